@@ -0,0 +1,95 @@
+//! Collector cost of a high-churn workload, short-lived tasks spawned,
+//! polled and closed in bursts, with full task records or aggregates only.
+//! Also prints the heap each mode is left holding once the burst is in.
+
+use bee_console::{BeeLayer, Builder};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use tokio::runtime::Runtime;
+use tracing_subscriber::layer::SubscriberExt;
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+/// Counts the bytes currently allocated.
+struct Counting;
+
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        LIVE.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOC: Counting = Counting;
+
+const MODES: [(&str, bool); 2] = [("full", false), ("aggregates_only", true)];
+
+fn builder(aggregates_only: bool) -> Builder {
+    BeeLayer::builder().aggregates_only(aggregates_only).unbounded_event_channel()
+}
+
+fn churn(tasks: usize) {
+    for job in 0..tasks {
+        let span = tracing::info_span!("job", job);
+        span.in_scope(|| {});
+        span.in_scope(|| {});
+    }
+}
+
+/// Runs `tasks` through a fresh collector, returning how long the burst took
+/// to send and for the collector to take in and shut down, and the heap
+/// it held once the burst was in.
+fn run(runtime: &Runtime, aggregates_only: bool, tasks: usize) -> (Duration, usize) {
+    let (layer, collector) = builder(aggregates_only).build();
+    let handle = collector.handle();
+
+    runtime.block_on(async {
+        let _default = tracing::subscriber::set_default(tracing_subscriber::registry().with(layer));
+        let collector = tokio::spawn(collector.run());
+        // Past the startup buffer, into the channel.
+        tokio::task::yield_now().await;
+
+        let before = LIVE.load(Ordering::Relaxed);
+        let started = Instant::now();
+        churn(tasks);
+        handle.debug_report(Duration::from_secs(60)).await;
+        let held = LIVE.load(Ordering::Relaxed).saturating_sub(before);
+
+        handle.shutdown().await.unwrap();
+        drop(collector.await.unwrap());
+        (started.elapsed(), held)
+    })
+}
+
+fn aggregates_only(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+
+    for &tasks in [1_000, 100_000].iter() {
+        for &(mode, enabled) in MODES.iter() {
+            let (_, held) = run(&runtime, enabled, tasks);
+            println!("{} tasks, {}: {} KiB held", tasks, mode, held / 1024);
+        }
+    }
+
+    let mut group = c.benchmark_group("high_churn");
+    for &(mode, enabled) in MODES.iter() {
+        group.bench_function(BenchmarkId::new(mode, 1_000), |b| {
+            b.iter_custom(|iters| (0..iters).map(|_| run(&runtime, enabled, 1_000).0).sum())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, aggregates_only);
+criterion_main!(benches);
@@ -0,0 +1,57 @@
+//! Cost of a span that re-enters itself deeply on every poll, with every
+//! entry and exit sent to the collector or only the outermost ones.
+
+use bee_console::BeeLayer;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use tokio::runtime::Runtime;
+use tracing_subscriber::layer::SubscriberExt;
+
+use std::time::{Duration, Instant};
+
+const POLLS: usize = 1_000;
+const DEPTH: usize = 8;
+
+fn reenter(span: &tracing::Span, depth: usize) {
+    if depth > 0 {
+        span.in_scope(|| reenter(span, depth - 1));
+    }
+}
+
+/// Polls one span through a fresh collector, returning how long the polls
+/// took to send and for the collector to take in and shut down.
+fn run(runtime: &Runtime, coalesce: bool) -> Duration {
+    let (layer, collector) = BeeLayer::builder().coalesce_reentrant(coalesce).unbounded_event_channel().build();
+    let handle = collector.handle();
+
+    runtime.block_on(async {
+        let _default = tracing::subscriber::set_default(tracing_subscriber::registry().with(layer));
+        let collector = tokio::spawn(collector.run());
+        // Past the startup buffer, into the channel.
+        tokio::task::yield_now().await;
+
+        let started = Instant::now();
+        let span = tracing::info_span!("task");
+        for _ in 0..POLLS {
+            reenter(&span, DEPTH);
+        }
+        drop(span);
+        handle.shutdown().await.unwrap();
+        collector.await.unwrap();
+        started.elapsed()
+    })
+}
+
+fn coalesce_reentrant(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+
+    let mut group = c.benchmark_group("reentrant_span");
+    for &(mode, coalesce) in [("every_entry", false), ("coalesced", true)].iter() {
+        group.bench_function(BenchmarkId::new(mode, DEPTH), |b| {
+            b.iter_custom(|iters| (0..iters).map(|_| run(&runtime, coalesce)).sum())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, coalesce_reentrant);
+criterion_main!(benches);
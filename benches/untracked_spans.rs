@@ -0,0 +1,30 @@
+//! Per-span overhead of spans from targets the layer doesn't track, checked
+//! by the layer for every span or left out by their callsite up front.
+
+use bee_console::BeeLayer;
+use criterion::{criterion_group, criterion_main, Criterion};
+use tracing_subscriber::{layer::SubscriberExt, Layer};
+
+fn untracked_spans(c: &mut Criterion) {
+    let mut group = c.benchmark_group("untracked_span");
+
+    let (layer, _collector) = BeeLayer::builder().include_targets(["my_app"]).build();
+    tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), || {
+        group.bench_function("checked_per_span", |b| {
+            b.iter(|| tracing::info_span!(target: "hyper::proto", "conn").in_scope(|| {}))
+        });
+    });
+
+    let (layer, _collector) = BeeLayer::builder().include_targets(["my_app"]).build();
+    let filter = layer.callsite_filter();
+    tracing::subscriber::with_default(tracing_subscriber::registry().with(layer.with_filter(filter)), || {
+        group.bench_function("callsite_filter", |b| {
+            b.iter(|| tracing::info_span!(target: "hyper::proto", "conn").in_scope(|| {}))
+        });
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, untracked_spans);
+criterion_main!(benches);
@@ -0,0 +1,797 @@
+use crate::{
+    callsite::{CallsiteLimit, CallsiteSelector},
+    census::AgeCensus,
+    collector::Collector,
+    config::EffectiveConfig,
+    degrade::ReportBudget,
+    event::EventId,
+    export::ExportConfig,
+    fields::FieldConfig,
+    filter::{CallsiteAllowlist, TOKIO_TASK_SPANS},
+    reducer::{ErasedReducer, Reducer, TaskReducer},
+    sampling::Sampler,
+    sender::{self, EventConsumer, EventSender, OverflowPolicy, Shared},
+    sink::{EventSink, SummarySink},
+    snapshot::SnapshotOrder,
+    subscriber::{BeeLayer, OnFormatError},
+    watchdog::WatchdogConfig,
+};
+
+use tracing_core::LevelFilter;
+#[cfg(feature = "json")]
+use tracing_subscriber::fmt::format::JsonFields;
+use tracing_subscriber::fmt::format::{DefaultFields, FormatFields};
+
+use std::{
+    sync::{atomic::AtomicU64, Arc},
+    time::Duration,
+};
+
+/// Configures and constructs a [`BeeLayer`].
+///
+/// Intervals and strides too small to work with, zero included, are raised
+/// to the smallest that do rather than rejected, here and when changed on a
+/// running collector through its [`CollectorHandle`](crate::CollectorHandle):
+/// flush intervals to [`Builder::MIN_TICK_INTERVAL`], and
+/// [`sample_one_in`](Builder::sample_one_in) and
+/// [`poll_sample_every`](Builder::poll_sample_every) to 1.
+pub struct Builder<F = DefaultFields> {
+    format: F,
+    fields: FieldConfig,
+    event_buffer_capacity: usize,
+    unbounded: bool,
+    startup_buffer_capacity: usize,
+    expected_tasks: Option<usize>,
+    tick_interval: Duration,
+    overflow_policy: OverflowPolicy,
+    watchdog: Option<WatchdogConfig>,
+    sinks: Vec<Box<dyn SummarySink>>,
+    export: ExportConfig,
+    callsite_limits: Vec<CallsiteLimit>,
+    order: SnapshotOrder,
+    event_sinks: Vec<Box<dyn EventSink>>,
+    on_format_error: OnFormatError,
+    coalesce_reentrant: bool,
+    include_targets: Option<Vec<String>>,
+    tasks_only: bool,
+    max_level: Option<LevelFilter>,
+    sample_one_in: Option<u64>,
+    track_filtered_traffic: bool,
+    align_ticks: bool,
+    instance: Option<String>,
+    transient_grace: Option<Duration>,
+    busy_loop_polls: Option<u64>,
+    max_counters: usize,
+    roll_up_counters: bool,
+    flamegraph: bool,
+    aggregates_only: bool,
+    strict: bool,
+    poll_sample_every: Option<u64>,
+    census_bounds: Option<Vec<Duration>>,
+    max_restarts: u32,
+    send_retries: u32,
+    lossy: bool,
+    replay: bool,
+    report_budget: Option<ReportBudget>,
+    reducers: Vec<Box<dyn ErasedReducer>>,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self {
+            format: Default::default(),
+            fields: Default::default(),
+            event_buffer_capacity: Self::EVENT_BUFFER_CAPACITY,
+            unbounded: false,
+            startup_buffer_capacity: Self::STARTUP_BUFFER_CAPACITY,
+            expected_tasks: None,
+            tick_interval: Duration::from_secs(Self::TICK_INTERVAL),
+            overflow_policy: Default::default(),
+            watchdog: None,
+            sinks: Vec::new(),
+            export: Default::default(),
+            callsite_limits: Vec::new(),
+            order: Default::default(),
+            event_sinks: Vec::new(),
+            on_format_error: Default::default(),
+            coalesce_reentrant: false,
+            include_targets: None,
+            tasks_only: false,
+            max_level: None,
+            sample_one_in: None,
+            track_filtered_traffic: false,
+            align_ticks: false,
+            instance: None,
+            transient_grace: None,
+            busy_loop_polls: None,
+            max_counters: Self::MAX_COUNTERS,
+            roll_up_counters: false,
+            flamegraph: false,
+            aggregates_only: false,
+            strict: false,
+            poll_sample_every: None,
+            census_bounds: None,
+            max_restarts: Self::MAX_RESTARTS,
+            send_retries: 0,
+            lossy: true,
+            replay: false,
+            report_budget: None,
+            reducers: Vec::new(),
+        }
+    }
+}
+
+impl Builder {
+    /// The shortest flush interval, which shorter ones are raised to.
+    pub const MIN_TICK_INTERVAL: Duration = Duration::from_millis(1);
+}
+
+impl<F> Builder<F> {
+    const TICK_INTERVAL: u64 = 1;
+    const STARTUP_BUFFER_CAPACITY: usize = 16 * 1024;
+    const EVENT_BUFFER_CAPACITY: usize = 100;
+    const WATCHDOG_STALL_FACTOR: u32 = 5;
+    const MAX_RESTARTS: u32 = 3;
+    const MAX_COUNTERS: usize = 16;
+
+    /// Sets how many events can be held back while the collector hasn't
+    /// started running yet, on top of the channel itself. Defaults to 16384.
+    pub fn startup_buffer_capacity(mut self, capacity: usize) -> Self {
+        self.startup_buffer_capacity = capacity;
+        self
+    }
+
+    /// Sizes the collector's task maps for `tasks` tasks kept at once, so
+    /// that a workload with a known steady-state task count doesn't make
+    /// them grow and rehash as it warms up. Only a hint; any number of tasks
+    /// is tracked either way.
+    pub fn expected_tasks(mut self, tasks: usize) -> Self {
+        self.expected_tasks = Some(tasks);
+        self
+    }
+
+    /// Sets the span field recording when a task was last woken, in
+    /// nanoseconds since the Unix epoch. Defaults to `task.woken_at`.
+    ///
+    /// When the field is recorded on a task span, the time from that wake to
+    /// the task's next poll is tracked as scheduling latency. Tasks that never
+    /// record it fall back to the idle gap between polls.
+    pub fn wake_field(mut self, name: impl Into<String>) -> Self {
+        self.fields.wake_field = Some(name.into());
+        self
+    }
+
+    /// Disables wake-to-poll latency tracking from span fields.
+    pub fn no_wake_field(mut self) -> Self {
+        self.fields.wake_field = None;
+        self
+    }
+
+    /// Parses the given numeric span field as the task's weight, used to
+    /// report busy time weighted by it. Values that aren't numbers are ignored.
+    pub fn weight_field(mut self, name: impl Into<String>) -> Self {
+        self.fields.weight_field = Some(name.into());
+        self
+    }
+
+    /// Keeps the value of the given span field on every task, so that tasks
+    /// can be grouped by it with [`Summary::aggregate_by_field`](crate::Summary::aggregate_by_field),
+    /// e.g. by tenant or request type.
+    pub fn group_by_field(mut self, name: impl Into<String>) -> Self {
+        self.fields.group_fields.push(name.into());
+        self
+    }
+
+    /// Adds up the polls and busy time of every span created with the same
+    /// value of the given field, e.g. `task.id`, in
+    /// [`Summary::stable_keys`](crate::Summary::stable_keys), so that a
+    /// logical task is followed across the spans standing for it over time.
+    ///
+    /// Totals are kept for every value seen, so the field should not take
+    /// unbounded values.
+    pub fn stable_key_field(mut self, name: impl Into<String>) -> Self {
+        self.fields.stable_key_field = Some(name.into());
+        self
+    }
+
+    /// Only tracks spans created with `bee.track = true`, e.g.
+    /// `info_span!("worker", bee.track = true)`, rather than every span.
+    /// Other spans send no events at all.
+    pub fn opt_in(mut self, enabled: bool) -> Self {
+        self.fields.opt_in = enabled;
+        self
+    }
+
+    /// Sets the span field holding the task's [`TaskKind`](crate::TaskKind).
+    /// Defaults to `kind`, which tokio sets on its task spans.
+    pub fn kind_field(mut self, name: impl Into<String>) -> Self {
+        self.fields.kind_field = Some(name.into());
+        self
+    }
+
+    /// Sets the numeric span field marking spans that monitor a queue and
+    /// holding its depth. Defaults to `queue.depth`.
+    ///
+    /// Spans declaring the field, even as `field::Empty`, are tracked as
+    /// queues rather than tasks: their depth is followed as it is recorded
+    /// on the span or on events inside it, and they are reported separately.
+    pub fn queue_field(mut self, name: impl Into<String>) -> Self {
+        self.fields.queue_field = Some(name.into());
+        self
+    }
+
+    /// Tracks every span as a task, however its fields are named.
+    pub fn no_queue_field(mut self) -> Self {
+        self.fields.queue_field = None;
+        self
+    }
+
+    /// Chooses whether spans whose fields fail to format are still tracked.
+    /// Defaults to [`OnFormatError::TrackEmpty`].
+    pub fn on_format_error(mut self, policy: OnFormatError) -> Self {
+        self.on_format_error = policy;
+        self
+    }
+
+    /// Only sends a span's outermost entry and exit on each thread, rather
+    /// than one event for every nested `in_scope` or re-entry. Stats come
+    /// out the same, with far fewer events sent for spans that re-enter
+    /// themselves, at the cost of keeping an entry count on every task span.
+    pub fn coalesce_reentrant(mut self, enabled: bool) -> Self {
+        self.coalesce_reentrant = enabled;
+        self
+    }
+
+    /// Only tracks spans whose target starts with one of `prefixes`, e.g.
+    /// `["my_app"]` to leave out the spans of libraries like `hyper`. Spans
+    /// from other targets send no events at all. Can be called again to add
+    /// more prefixes.
+    pub fn include_targets<I>(mut self, prefixes: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.include_targets.get_or_insert_with(Vec::new).extend(prefixes.into_iter().map(Into::into));
+        self
+    }
+
+    /// Only tracks the spans tokio opens for the tasks it spawns,
+    /// `runtime.spawn` and `runtime.blocking`, rather than every
+    /// instrumented function. Tokio only opens them when built with
+    /// `tokio_unstable`. Other spans send no events at all.
+    ///
+    /// Unlike a [`tasks_only`](crate::filter::tasks_only) filter, this
+    /// leaves every span to the other layers of the subscriber.
+    pub fn tasks_only(mut self, enabled: bool) -> Self {
+        self.tasks_only = enabled;
+        self
+    }
+
+    /// Only tracks spans at `level` or less verbose, e.g. `Level::DEBUG` to
+    /// leave out `TRACE` spans in hot loops. Spans left out send no events
+    /// at all, but stay enabled for the other layers of the subscriber.
+    pub fn max_level(mut self, level: impl Into<LevelFilter>) -> Self {
+        self.max_level = Some(level.into());
+        self
+    }
+
+    /// Only tracks every `n`th task spawned, for programs spawning too many
+    /// to track each one. The others send no events at all.
+    /// [`Summary::sampling`](crate::Summary::sampling) says how many were
+    /// tracked, to scale totals over them by. Spans monitoring queues are
+    /// always tracked.
+    pub fn sample_one_in(mut self, n: u64) -> Self {
+        self.sample_one_in = Some(n.max(1));
+        self
+    }
+
+    /// Counts the spans the layer doesn't track, and their entries and
+    /// exits, per callsite, and reports them under
+    /// [`Summary::filtered_traffic`](crate::Summary::filtered_traffic).
+    /// Spans filtered out before reaching the layer are only counted by a
+    /// filter [accounting](crate::filter::TasksOnly::account_to) to it.
+    pub fn track_filtered_traffic(mut self) -> Self {
+        self.track_filtered_traffic = true;
+        self
+    }
+
+    /// Chooses which event to discard when the collector falls behind.
+    /// Defaults to [`OverflowPolicy::DropNewest`].
+    pub fn overflow_policy(mut self, policy: OverflowPolicy) -> Self {
+        self.overflow_policy = policy;
+        self
+    }
+
+    /// Sets how many more times to try sending an event into a full channel
+    /// under [`OverflowPolicy::DropNewest`], spinning briefly in between,
+    /// before dropping it. This can ride out the collector falling behind
+    /// for a moment without ever blocking. Defaults to 0.
+    pub fn send_retries(mut self, retries: u32) -> Self {
+        self.send_retries = retries;
+        self
+    }
+
+    /// Whether to drop events the collector has no room for, which is the
+    /// default. With `lossy(false)`, sending into a full channel under
+    /// [`OverflowPolicy::DropNewest`] waits for the collector to make room
+    /// instead, slowing the instrumented program down rather than losing
+    /// events, e.g. for offline analysis.
+    ///
+    /// Waiting must never stop the collector from running, so where it
+    /// would the event is still dropped:
+    ///
+    /// - Outside of any tokio runtime, the sending thread blocks.
+    /// - On a worker of a multi-threaded runtime, the worker blocks in
+    ///   place, handing its other tasks to a new worker as
+    ///   [`block_in_place`](tokio::task::block_in_place) does.
+    /// - On a current-thread runtime, the thread waits only if the
+    ///   collector runs on another thread. If it runs on the same one, it
+    ///   couldn't drain the channel while the thread waits, so the event is
+    ///   dropped and counted as usual.
+    ///
+    /// Events sent before the collector runs are still buffered and dropped
+    /// as [`startup_buffer_capacity`](Self::startup_buffer_capacity)
+    /// describes.
+    pub fn lossy(mut self, lossy: bool) -> Self {
+        self.lossy = lossy;
+        self
+    }
+
+    /// Labels every summary with the process or runtime it came from, so
+    /// summaries from several of them can be told apart once merged.
+    pub fn instance(mut self, label: impl Into<String>) -> Self {
+        self.instance = Some(label.into());
+        self
+    }
+
+    /// Sets how often the collector flushes. Defaults to every second.
+    pub fn flush_interval(mut self, interval: Duration) -> Self {
+        self.tick_interval = interval.max(Builder::MIN_TICK_INTERVAL);
+        self
+    }
+
+    /// Sets how many events the channel to the collector holds before the
+    /// [`OverflowPolicy`] applies. Defaults to 100.
+    ///
+    /// # Panics
+    ///
+    /// If `capacity` is zero, as the channel couldn't hold any event.
+    pub fn event_buffer_capacity(mut self, capacity: usize) -> Self {
+        assert!(capacity > 0, "event buffer capacity must be at least 1");
+        self.event_buffer_capacity = capacity;
+        self
+    }
+
+    /// Sends events to the collector over an unbounded channel, so that none
+    /// are dropped however far behind it falls, at the cost of holding every
+    /// one of them until it catches up. Suits bursts of many more spawns than
+    /// the channel holds, e.g. at startup. Sending still never blocks.
+    ///
+    /// The [`event_buffer_capacity`](Self::event_buffer_capacity),
+    /// [`overflow_policy`](Self::overflow_policy),
+    /// [`send_retries`](Self::send_retries) and [`lossy`](Self::lossy) no
+    /// longer apply.
+    pub fn unbounded_event_channel(mut self) -> Self {
+        self.unbounded = true;
+        self
+    }
+
+    /// Formats span fields with `format` rather than [`DefaultFields`], e.g.
+    /// the formatter a `fmt` layer in the same subscriber uses, so that the
+    /// fields formatted by either are shared rather than formatted twice.
+    pub fn fields_format<F2>(self, format: F2) -> Builder<F2>
+    where
+        F2: for<'writer> FormatFields<'writer> + 'static,
+    {
+        Builder {
+            format,
+            fields: self.fields,
+            event_buffer_capacity: self.event_buffer_capacity,
+            unbounded: self.unbounded,
+            startup_buffer_capacity: self.startup_buffer_capacity,
+            expected_tasks: self.expected_tasks,
+            tick_interval: self.tick_interval,
+            overflow_policy: self.overflow_policy,
+            watchdog: self.watchdog,
+            sinks: self.sinks,
+            export: self.export,
+            callsite_limits: self.callsite_limits,
+            order: self.order,
+            event_sinks: self.event_sinks,
+            on_format_error: self.on_format_error,
+            coalesce_reentrant: self.coalesce_reentrant,
+            include_targets: self.include_targets,
+            tasks_only: self.tasks_only,
+            max_level: self.max_level,
+            sample_one_in: self.sample_one_in,
+            track_filtered_traffic: self.track_filtered_traffic,
+            align_ticks: self.align_ticks,
+            instance: self.instance,
+            transient_grace: self.transient_grace,
+            busy_loop_polls: self.busy_loop_polls,
+            max_counters: self.max_counters,
+            roll_up_counters: self.roll_up_counters,
+            flamegraph: self.flamegraph,
+            aggregates_only: self.aggregates_only,
+            strict: self.strict,
+            poll_sample_every: self.poll_sample_every,
+            census_bounds: self.census_bounds,
+            max_restarts: self.max_restarts,
+            send_retries: self.send_retries,
+            lossy: self.lossy,
+            replay: self.replay,
+            report_budget: self.report_budget,
+            reducers: self.reducers,
+        }
+    }
+
+    /// Formats span fields as a JSON object, e.g. `{"name":"worker","id":3}`,
+    /// for consumers that parse them. Spans without fields get `{}`.
+    #[cfg(feature = "json")]
+    pub fn json_fields(self) -> Builder<JsonFields> {
+        self.fields_format(JsonFields::new())
+    }
+
+    /// Flushes on wall-clock multiples of the flush interval, e.g. every
+    /// second on the second, rather than relative to when the collector
+    /// started, so that summaries from several processes line up.
+    pub fn align_ticks(mut self) -> Self {
+        self.align_ticks = true;
+        self
+    }
+
+    /// Sets how many times the collector restarts after panicking, starting
+    /// over with nothing collected, before it gives up. Defaults to 3.
+    ///
+    /// Panics in a single event or sink don't count, as they are caught
+    /// without restarting.
+    pub fn max_restarts(mut self, restarts: u32) -> Self {
+        self.max_restarts = restarts;
+        self
+    }
+
+    /// Starts a watchdog thread alongside the collector that warns on stderr
+    /// once the collector hasn't run for `stall_factor` flush intervals,
+    /// which usually means the runtime it shares with the application is blocked.
+    pub fn watchdog(mut self, stall_factor: u32) -> Self {
+        self.watchdog = Some(WatchdogConfig {
+            stall_factor,
+            dump_summary: false,
+        });
+        self
+    }
+
+    /// Has the watchdog also print the last summary the collector produced
+    /// when it reports a stall. Implies [`watchdog`](Self::watchdog) with a
+    /// factor of 5 if it wasn't enabled.
+    pub fn watchdog_dumps_summary(mut self) -> Self {
+        let watchdog = self.watchdog.get_or_insert(WatchdogConfig {
+            stall_factor: Self::WATCHDOG_STALL_FACTOR,
+            dump_summary: true,
+        });
+        watchdog.dump_summary = true;
+        self
+    }
+
+    /// Warns whenever more than `max_alive` tasks spawned from the selected
+    /// callsites are alive at once, e.g. a limit of 1 for a singleton
+    /// background worker. Each callsite is counted separately, and violations
+    /// are flagged in the summary. Warnings are logged at most once per
+    /// callsite per flush interval.
+    pub fn callsite_limit(mut self, selector: CallsiteSelector, max_alive: usize) -> Self {
+        self.callsite_limits.push(CallsiteLimit {
+            selector,
+            max_alive,
+        });
+        self
+    }
+
+    /// Drops tasks that close less than `grace` after they were created,
+    /// counting them as transient in the summary instead of listing them.
+    /// Keeps high-churn code paths from drowning out longer-lived tasks.
+    /// Tasks that exceeded their budget are always listed.
+    pub fn transient_grace(mut self, grace: Duration) -> Self {
+        self.transient_grace = Some(grace);
+        self
+    }
+
+    /// Flags tasks as busy looping once `polls` poll outcomes reported by
+    /// [`TrackFuture`](crate::TrackFuture) came back `Pending` without a
+    /// single `Ready`, and warns once for each.
+    pub fn busy_loop_polls(mut self, polls: u64) -> Self {
+        self.busy_loop_polls = Some(polls);
+        self
+    }
+
+    /// Sets how many distinct counters each task can have. Events inside a
+    /// task add to its counters through fields named `counter.<name>`, e.g.
+    /// `info!(counter.items = 3)`; once a task has `max` of them, further
+    /// names are ignored. Defaults to 16.
+    pub fn max_counters_per_task(mut self, max: usize) -> Self {
+        self.max_counters = max;
+        self
+    }
+
+    /// Sums task counters per callsite in
+    /// [`Summary::counter_rollup`](crate::Summary::counter_rollup), e.g. the
+    /// items processed by every task spawned from one place, including
+    /// tasks since closed and dropped.
+    pub fn roll_up_counters(mut self) -> Self {
+        self.roll_up_counters = true;
+        self
+    }
+
+    /// Folds the time each task spends polling into the stack of tasks it
+    /// was spawned inside of, kept in
+    /// [`Summary::stacks`](crate::Summary::stacks) for rendering as a
+    /// flamegraph. Costs a string per task and a map lookup per poll.
+    pub fn flamegraph(mut self) -> Self {
+        self.flamegraph = true;
+        self
+    }
+
+    /// Keeps no record per task, only counting tasks spawned and closed,
+    /// polls and busy time into [`Summary::counts`](crate::Summary::counts),
+    /// for the least overhead. Memory then grows with how many tasks are
+    /// polled at once rather than with how many were ever seen.
+    ///
+    /// Thread, queue, concurrency and poll duration aggregates are kept as
+    /// usual. Anything needing a task's record is not: the task table, kinds,
+    /// callsites, counters, budgets, wake and idle latencies, phases,
+    /// reducers, close events, replay and flamegraph stacks.
+    pub fn aggregates_only(mut self, enabled: bool) -> Self {
+        self.aggregates_only = enabled;
+        self
+    }
+
+    /// Checks that each task's events arrive in an order they could have
+    /// happened in: nothing before its spawn, no exit from a poll not
+    /// entered on that thread, and nothing after its close. Violations are
+    /// logged as errors and counted in
+    /// [`Summary::order_violations`](crate::Summary::order_violations).
+    ///
+    /// Events from different threads race each other through the channel,
+    /// so they can arrive out of order without anything being wrong with
+    /// the instrumentation; this is for finding out whether and how often
+    /// they do. Not checked when only counting.
+    pub fn strict(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+
+    /// Only records every `n`th poll into the poll duration histogram behind
+    /// [`Summary::poll_durations`](crate::Summary::poll_durations), cutting
+    /// its cost for tasks polled millions of times. Every poll is recorded
+    /// by default.
+    ///
+    /// Percentiles come out close to those of every poll as long as there
+    /// are plenty of samples, but rare outliers, and so the max and p99,
+    /// are more likely to be missed the larger `n` is. Polls are picked at
+    /// a fixed stride, so a workload whose slow polls recur with a period
+    /// of `n` can be over- or under-represented.
+    pub fn poll_sample_every(mut self, n: u64) -> Self {
+        self.poll_sample_every = Some(n.max(1));
+        self
+    }
+
+    /// Sets the bucket bounds of the age census in
+    /// [`Summary::census`](crate::Summary::census), each the exclusive upper
+    /// bound of a bucket, with one more bucket for anything older. Defaults
+    /// to [`AgeCensus::DEFAULT_BOUNDS`](crate::AgeCensus::DEFAULT_BOUNDS).
+    pub fn census_bounds(mut self, mut bounds: Vec<Duration>) -> Self {
+        bounds.sort();
+        bounds.dedup();
+        self.census_bounds = Some(bounds);
+        self
+    }
+
+    /// Sets the order tasks are listed in by snapshots and summaries.
+    /// Defaults to [`SnapshotOrder::Id`].
+    pub fn snapshot_order(mut self, order: SnapshotOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// Degrades the reports logged and sent to sinks on each flush whenever
+    /// producing them takes too much of the flush interval, e.g. on small
+    /// containers tracking thousands of tasks. Reports are never degraded
+    /// by default.
+    pub fn report_budget(mut self, budget: ReportBudget) -> Self {
+        self.report_budget = Some(budget);
+        self
+    }
+
+    /// Re-emits every task as it closes as a span on the
+    /// [`REPLAY_TARGET`](crate::REPLAY_TARGET) target, carrying its stats as
+    /// fields, so that other layers, e.g. one exporting to a tracing
+    /// backend, see a record of each task. The layer itself ignores them.
+    pub fn replay_spans(mut self) -> Self {
+        self.replay = true;
+        self
+    }
+
+    /// Adds a sink receiving the summary on every flush, on top of the
+    /// summary logged through `tracing`.
+    pub fn summary_sink(mut self, sink: impl SummarySink) -> Self {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+
+    /// Adds a sink receiving a copy of every raw event, in the order it was
+    /// sent, independently of the collector's aggregation, e.g. the sending
+    /// half of a channel to a recorder of your own. Sinks can be added any
+    /// number of times; each gets a copy of its own, and one that falls
+    /// behind drops events without holding up the others or the collector.
+    /// What each dropped is reported in [`Summary::event_sinks_dropped`](crate::Summary::event_sinks_dropped).
+    pub fn event_sink(mut self, sink: impl EventSink) -> Self {
+        self.event_sinks.push(Box::new(sink));
+        self
+    }
+
+    /// Adds a reducer accumulating a value of its own for every task from
+    /// the events about it, on top of the built-in stats.
+    pub fn task_reducer(mut self, reducer: impl TaskReducer) -> Self {
+        self.reducers.push(Box::new(Reducer::new(reducer)));
+        self
+    }
+
+    /// Sets the units sinks export durations and timestamps in, unless they
+    /// override them. Defaults to milliseconds and epoch milliseconds.
+    pub fn export_config(mut self, export: ExportConfig) -> Self {
+        self.export = export;
+        self
+    }
+
+    /// Builds the layer, to register with a subscriber, and the collector it
+    /// sends events to, to [run](Collector::run) on a task of its own.
+    pub fn build(self) -> (BeeLayer<F>, Collector) {
+        let capacity = (!self.unbounded).then_some(self.event_buffer_capacity);
+        let (tx, rx) = sender::event_channel(capacity);
+        // Nothing is ever evicted from an unbounded channel.
+        let overflow_policy = if self.unbounded { OverflowPolicy::DropNewest } else { self.overflow_policy };
+        let mut shared = Shared::new(self.startup_buffer_capacity, overflow_policy, self.event_buffer_capacity);
+        shared.config = EffectiveConfig {
+            instance: self.instance.clone(),
+            tick_interval: self.tick_interval,
+            align_ticks: self.align_ticks,
+            channel_capacity: capacity,
+            overflow_policy: self.overflow_policy,
+            send_retries: self.send_retries,
+            lossy: self.lossy,
+            startup_buffer_capacity: self.startup_buffer_capacity,
+            expected_tasks: self.expected_tasks,
+            summary_sinks: self.sinks.len(),
+            event_sinks: self.event_sinks.len(),
+            task_reducers: self.reducers.len(),
+            callsite_limits: self.callsite_limits.len(),
+            name_allowlist: None,
+            include_targets: self.include_targets.clone(),
+            tasks_only: self.tasks_only,
+            max_level: self.max_level,
+            opt_in: self.fields.opt_in,
+            sample_one_in: self.sample_one_in,
+            transient_grace: self.transient_grace,
+            busy_loop_polls: self.busy_loop_polls,
+            max_counters: self.max_counters,
+            roll_up_counters: self.roll_up_counters,
+            flamegraph: self.flamegraph,
+            aggregates_only: self.aggregates_only,
+            strict: self.strict,
+            poll_sample_every: self.poll_sample_every.unwrap_or(1),
+            census_bounds: self.census_bounds.clone().unwrap_or_else(|| AgeCensus::DEFAULT_BOUNDS.to_vec()),
+            max_restarts: self.max_restarts,
+            replay_spans: self.replay,
+            coalesce_reentrant: self.coalesce_reentrant,
+            report_budget: self.report_budget.as_ref().map(ReportBudget::max_fraction),
+            dropped_events: 0,
+        };
+        shared.set_tick_interval(self.tick_interval);
+        if self.track_filtered_traffic {
+            shared.filtered.enable();
+        }
+        let export = self.export;
+        shared.event_sinks = self
+            .event_sinks
+            .into_iter()
+            .map(|mut sink| {
+                sink.configure(&export);
+                EventConsumer::new(sink)
+            })
+            .collect();
+        shared.sampler = self.sample_one_in.map(Sampler::new);
+        shared.send_retries = self.send_retries;
+        shared.lossy = self.lossy;
+        let events = EventSender::new(tx, Arc::new(shared));
+        let mut collector = Collector::new(rx, &events, self.tick_interval);
+        if let Some(watchdog) = self.watchdog {
+            collector = collector.with_watchdog(watchdog);
+        }
+        if self.align_ticks {
+            collector = collector.with_aligned_ticks();
+        }
+        if let Some(tasks) = self.expected_tasks {
+            collector = collector.with_expected_tasks(tasks);
+        }
+        if let Some(grace) = self.transient_grace {
+            collector = collector.with_transient_grace(grace);
+        }
+        if let Some(polls) = self.busy_loop_polls {
+            collector = collector.with_busy_loop_polls(polls);
+        }
+        if let Some(n) = self.poll_sample_every {
+            collector = collector.with_poll_sample_every(n);
+        }
+        if self.replay {
+            collector = collector.with_replay();
+        }
+        if let Some(bounds) = self.census_bounds.clone() {
+            collector = collector.with_census_bounds(bounds);
+        }
+        if self.roll_up_counters {
+            collector = collector.with_counter_rollup();
+        }
+        if self.flamegraph {
+            collector = collector.with_stacks();
+        }
+        if self.aggregates_only {
+            collector = collector.with_counts_only();
+        }
+        if self.strict {
+            collector = collector.with_strict();
+        }
+        if let Some(budget) = self.report_budget {
+            collector = collector.with_report_budget(budget);
+        }
+        if let Some(instance) = self.instance {
+            collector = collector.with_instance(instance);
+        }
+        collector = collector
+            .with_max_restarts(self.max_restarts)
+            .with_max_counters(self.max_counters)
+            .with_reducers(self.reducers)
+            .with_order(self.order)
+            .with_callsite_limits(self.callsite_limits)
+            .with_sinks(self.sinks, self.export);
+
+        let layer = BeeLayer {
+            events,
+            format: Arc::new(self.format),
+            fields: self.fields,
+            on_format_error: self.on_format_error,
+            coalesce_reentrant: self.coalesce_reentrant,
+            source: EventId::FIRST_SPAN,
+            seq: AtomicU64::new(0),
+            callsites: CallsiteAllowlist::new(self.include_targets, self.tasks_only.then_some(TOKIO_TASK_SPANS), self.max_level).map(Arc::new),
+            handle: collector.handle(),
+        };
+
+        (layer, collector)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[should_panic(expected = "event buffer capacity must be at least 1")]
+    fn zero_event_buffer_capacity_panics() {
+        let _ = BeeLayer::builder().event_buffer_capacity(0);
+    }
+
+    #[test]
+    fn short_flush_intervals_are_raised_to_the_minimum() {
+        for &interval in [Duration::ZERO, Duration::from_micros(10)].iter() {
+            let (_layer, collector) = BeeLayer::builder().flush_interval(interval).build();
+            assert_eq!(collector.handle().config().tick_interval, Builder::MIN_TICK_INTERVAL);
+        }
+    }
+
+    #[test]
+    fn zero_strides_are_raised_to_one() {
+        let (_layer, collector) = BeeLayer::builder().sample_one_in(0).poll_sample_every(0).build();
+        let config = collector.handle().config();
+        assert_eq!(config.sample_one_in, Some(1));
+        assert_eq!(config.poll_sample_every, 1);
+    }
+}
@@ -0,0 +1,314 @@
+use crate::reporting::INTERNAL_TARGET;
+
+use tracing_core::{Level, Metadata};
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
+
+/// Where a task was spawned from, taken from its span's metadata.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Callsite {
+    pub target: &'static str,
+    pub name: &'static str,
+    /// The span's level, or `None` for callsites not taken from a span.
+    pub level: Option<Level>,
+    /// `None`, as is the line, for spans created with metadata that doesn't
+    /// say where it was declared.
+    pub file: Option<&'static str>,
+    pub line: Option<u32>,
+}
+
+impl Callsite {
+    pub fn new(target: &'static str, name: &'static str) -> Self {
+        Self {
+            target,
+            name,
+            level: None,
+            file: None,
+            line: None,
+        }
+    }
+
+    pub fn with_level(mut self, level: Level) -> Self {
+        self.level = Some(level);
+        self
+    }
+
+    pub fn with_location(mut self, file: &'static str, line: u32) -> Self {
+        self.file = Some(file);
+        self.line = Some(line);
+        self
+    }
+
+    pub(crate) fn from_metadata(metadata: &'static Metadata<'static>) -> Self {
+        Self {
+            target: metadata.target(),
+            name: metadata.name(),
+            level: Some(*metadata.level()),
+            file: metadata.file(),
+            line: metadata.line(),
+        }
+    }
+}
+
+impl Callsite {
+    /// `name@file:line`, or just the name if the location is unknown.
+    pub fn label(&self) -> String {
+        match (self.file, self.line) {
+            (Some(file), Some(line)) => format!("{}@{}:{}", self.name, file, line),
+            (Some(file), None) => format!("{}@{}", self.name, file),
+            _ => self.name.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Callsite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}::{}", self.target, self.name)?;
+
+        if let (Some(file), Some(line)) = (self.file, self.line) {
+            write!(f, " ({}:{})", file, line)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Picks out the callsites a [`Builder::callsite_limit`](crate::Builder::callsite_limit) applies to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CallsiteSelector {
+    /// Spans with the given target and name.
+    Name { target: String, name: String },
+    /// Spans declared at the given line. The file matches on whole path
+    /// components from the end, so `main.rs` matches `src/main.rs`.
+    Location { file: String, line: u32 },
+}
+
+impl CallsiteSelector {
+    pub fn name(target: impl Into<String>, name: impl Into<String>) -> Self {
+        Self::Name {
+            target: target.into(),
+            name: name.into(),
+        }
+    }
+
+    pub fn location(file: impl Into<String>, line: u32) -> Self {
+        Self::Location {
+            file: file.into(),
+            line,
+        }
+    }
+
+    fn matches(&self, callsite: &Callsite) -> bool {
+        match self {
+            Self::Name { target, name } => callsite.target == target && callsite.name == name,
+            Self::Location { file, line } => {
+                let file_matches = callsite
+                    .file
+                    .and_then(|path| path.strip_suffix(file.as_str()))
+                    .is_some_and(|rest| rest.is_empty() || rest.ends_with('/') || rest.ends_with('\\'));
+
+                file_matches && callsite.line == Some(*line)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct CallsiteLimit {
+    pub selector: CallsiteSelector,
+    pub max_alive: usize,
+}
+
+/// Tasks alive per callsite, as reported on each flush.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CallsiteSummary {
+    pub callsite: Callsite,
+    pub alive: usize,
+    /// The most tasks allowed alive at once, if a limit applies.
+    pub limit: Option<usize>,
+    /// Whether `limit` has ever been exceeded.
+    pub violated: bool,
+}
+
+/// Counters summed over every task spawned from one callsite, see
+/// [`Builder::roll_up_counters`](crate::Builder::roll_up_counters).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CallsiteCounters {
+    pub callsite: Callsite,
+    pub counters: BTreeMap<String, u64>,
+}
+
+#[derive(Default)]
+struct Site {
+    alive: usize,
+    limit: Option<usize>,
+    violated: bool,
+    /// Whether a violation was already reported this flush interval.
+    warned: bool,
+}
+
+/// Counts tasks alive per callsite, checking them against the configured limits.
+#[derive(Default)]
+pub(crate) struct CallsiteCounts {
+    limits: Vec<CallsiteLimit>,
+    sites: HashMap<Callsite, Site>,
+}
+
+impl CallsiteCounts {
+    pub fn new(limits: Vec<CallsiteLimit>) -> Self {
+        Self {
+            limits,
+            sites: HashMap::new(),
+        }
+    }
+
+    pub fn spawned(&mut self, callsite: Callsite) {
+        let limits = &self.limits;
+        let site = self.sites.entry(callsite).or_insert_with(|| Site {
+            // The strictest of the limits selecting this callsite.
+            limit: limits
+                .iter()
+                .filter(|limit| limit.selector.matches(&callsite))
+                .map(|limit| limit.max_alive)
+                .min(),
+            ..Default::default()
+        });
+
+        site.alive += 1;
+
+        let limit = match site.limit {
+            Some(limit) if site.alive > limit => limit,
+            _ => return,
+        };
+
+        site.violated = true;
+        if !site.warned {
+            site.warned = true;
+            tracing::warn!(
+                target: INTERNAL_TARGET,
+                callsite = %callsite,
+                alive = site.alive,
+                limit,
+                "Too many tasks alive from one callsite",
+            );
+        }
+    }
+
+    pub fn closed(&mut self, callsite: &Callsite) {
+        if let Some(site) = self.sites.get_mut(callsite) {
+            site.alive = site.alive.saturating_sub(1);
+        }
+    }
+
+    /// Lets each callsite report a violation again.
+    pub fn reset_warnings(&mut self) {
+        for site in self.sites.values_mut() {
+            site.warned = false;
+        }
+    }
+
+    /// Forgets every callsite, keeping the limits.
+    pub fn clear(&mut self) {
+        self.sites.clear();
+    }
+
+    /// Every callsite seen so far, most tasks alive first.
+    pub fn summary(&self) -> Vec<CallsiteSummary> {
+        let mut callsites: Vec<_> = self
+            .sites
+            .iter()
+            .map(|(callsite, site)| CallsiteSummary {
+                callsite: *callsite,
+                alive: site.alive,
+                limit: site.limit,
+                violated: site.violated,
+            })
+            .collect();
+        callsites.sort_by(|a, b| b.alive.cmp(&a.alive).then(a.callsite.cmp(&b.callsite)));
+
+        callsites
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limited(max_alive: usize) -> (CallsiteCounts, Callsite) {
+        let limit = CallsiteLimit {
+            selector: CallsiteSelector::location("main.rs", 7),
+            max_alive,
+        };
+        let callsite = Callsite::new("app", "worker").with_location("src/main.rs", 7);
+
+        (CallsiteCounts::new(vec![limit]), callsite)
+    }
+
+    fn site(counts: &CallsiteCounts) -> CallsiteSummary {
+        counts.summary()[0]
+    }
+
+    #[test]
+    fn limit_of_one_is_exceeded_by_a_second_task() {
+        let (mut counts, callsite) = limited(1);
+
+        counts.spawned(callsite);
+        assert!(!site(&counts).violated);
+        counts.spawned(callsite);
+        assert_eq!(site(&counts).alive, 2);
+        assert!(site(&counts).violated);
+
+        counts.closed(&callsite);
+        let recovered = site(&counts);
+        assert_eq!(recovered.alive, 1);
+        assert_eq!(recovered.limit, Some(1));
+        // Past violations are still reported once back under the limit.
+        assert!(recovered.violated);
+    }
+
+    #[test]
+    fn limit_of_n_warns_once_per_interval() {
+        let (mut counts, callsite) = limited(3);
+
+        for _ in 0..3 {
+            counts.spawned(callsite);
+        }
+        assert!(!site(&counts).violated);
+        counts.spawned(callsite);
+        counts.spawned(callsite);
+        assert!(site(&counts).violated);
+        assert!(counts.sites[&callsite].warned);
+
+        counts.closed(&callsite);
+        counts.closed(&callsite);
+        counts.reset_warnings();
+        assert_eq!(site(&counts).alive, 3);
+        assert!(!counts.sites[&callsite].warned);
+
+        counts.spawned(callsite);
+        assert!(counts.sites[&callsite].warned);
+    }
+
+    #[test]
+    fn strictest_limit_applies_and_others_are_unlimited() {
+        let mut counts = CallsiteCounts::new(vec![
+            CallsiteLimit {
+                selector: CallsiteSelector::name("app", "worker"),
+                max_alive: 5,
+            },
+            CallsiteLimit {
+                selector: CallsiteSelector::location("src/main.rs", 7),
+                max_alive: 2,
+            },
+        ]);
+
+        counts.spawned(Callsite::new("app", "worker").with_location("/src/main.rs", 7));
+        counts.spawned(Callsite::new("app", "other").with_location("src/domain.rs", 7));
+
+        let limits: Vec<_> = counts.summary().iter().map(|site| (site.callsite.name, site.limit)).collect();
+        assert_eq!(limits, [("other", None), ("worker", Some(2))]);
+    }
+}
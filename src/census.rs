@@ -0,0 +1,102 @@
+use std::{fmt, time::Duration};
+
+/// Active tasks counted by how old they are and by how long since they were
+/// last polled, for an at-a-glance read on whether old or stuck tasks are
+/// piling up.
+///
+/// Taken in a pass over the tasks kept each time a summary is produced, so
+/// it costs about as much as the task table, without listing any task.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AgeCensus {
+    /// Exclusive upper bounds of every bucket but the last, which holds
+    /// everything older. Set with
+    /// [`Builder::census_bounds`](crate::Builder::census_bounds).
+    pub bounds: Vec<Duration>,
+    /// Active tasks in each bucket by time since spawn, one more than
+    /// there are bounds.
+    pub by_age: Vec<u64>,
+    /// Active tasks in each bucket by time since their last poll ended, or
+    /// since spawn if they haven't been polled. Tasks being polled count as
+    /// not idle at all.
+    pub by_idle: Vec<u64>,
+}
+
+impl AgeCensus {
+    /// `<10ms`, `<100ms`, `<1s`, `<10s`, `<1m`, `<10m` and older.
+    pub const DEFAULT_BOUNDS: [Duration; 6] = [
+        Duration::from_millis(10),
+        Duration::from_millis(100),
+        Duration::from_secs(1),
+        Duration::from_secs(10),
+        Duration::from_secs(60),
+        Duration::from_secs(600),
+    ];
+
+    pub(crate) fn new(bounds: Vec<Duration>) -> Self {
+        let buckets = bounds.len() + 1;
+
+        Self {
+            bounds,
+            by_age: vec![0; buckets],
+            by_idle: vec![0; buckets],
+        }
+    }
+
+    pub(crate) fn record(&mut self, age: Duration, idle: Duration) {
+        let age = self.bucket(age);
+        let idle = self.bucket(idle);
+        self.by_age[age] += 1;
+        self.by_idle[idle] += 1;
+    }
+
+    fn bucket(&self, duration: Duration) -> usize {
+        self.bounds.iter().position(|bound| duration < *bound).unwrap_or(self.bounds.len())
+    }
+
+    /// A label for each bucket, e.g. `<10ms`, with the last one `>=10m`.
+    pub fn labels(&self) -> Vec<String> {
+        let mut labels: Vec<_> = self.bounds.iter().map(|bound| format!("<{}", short(*bound))).collect();
+        match self.bounds.last() {
+            Some(last) => labels.push(format!(">={}", short(*last))),
+            None => labels.push("any".into()),
+        }
+
+        labels
+    }
+
+    /// Active tasks counted.
+    pub fn tasks(&self) -> u64 {
+        self.by_age.iter().sum()
+    }
+}
+
+impl fmt::Display for AgeCensus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let labels = self.labels();
+        let line = |counts: &[u64]| {
+            labels
+                .iter()
+                .zip(counts)
+                .map(|(label, count)| format!("{} {}", label, count))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        write!(f, "Active tasks by age: {}; by idle time: {}", line(&self.by_age), line(&self.by_idle))
+    }
+}
+
+/// A bucket bound in the largest whole unit it fits.
+fn short(duration: Duration) -> String {
+    let millis = duration.as_millis();
+
+    if millis > 0 && millis.is_multiple_of(60_000) {
+        format!("{}m", millis / 60_000)
+    } else if millis > 0 && millis.is_multiple_of(1000) {
+        format!("{}s", millis / 1000)
+    } else if duration.subsec_nanos().is_multiple_of(1_000_000) {
+        format!("{}ms", millis)
+    } else {
+        format!("{:?}", duration)
+    }
+}
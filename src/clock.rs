@@ -0,0 +1,68 @@
+#[cfg(test)]
+use std::{cell::Cell, time::Duration};
+use std::{
+    sync::OnceLock,
+    time::{Instant, SystemTime},
+};
+
+/// A wall-clock time and the monotonic time it was taken at, against which
+/// the [`Instant`]s events are timed with are turned into wall-clock times.
+/// Taken once per process, as the first collector starts, so that the
+/// durations between events never depend on the wall clock, which NTP or a
+/// VM resuming can step backwards or forwards.
+#[derive(Clone, Copy)]
+struct Anchor {
+    instant: Instant,
+    system: SystemTime,
+}
+
+static ANCHOR: OnceLock<Anchor> = OnceLock::new();
+
+#[cfg(test)]
+thread_local! {
+    /// Replaces the anchor on the current thread, for tests moving the wall clock.
+    static MOVED_ANCHOR: Cell<Option<Anchor>> = const { Cell::new(None) };
+}
+
+fn anchor() -> &'static Anchor {
+    ANCHOR.get_or_init(|| Anchor {
+        instant: Instant::now(),
+        system: SystemTime::now(),
+    })
+}
+
+/// Takes the anchor now, unless it already was.
+pub(crate) fn init() {
+    anchor();
+}
+
+impl Anchor {
+    fn wall_time(&self, instant: Instant) -> SystemTime {
+        match instant.checked_duration_since(self.instant) {
+            Some(since) => self.system + since,
+            None => self.system - self.instant.duration_since(instant),
+        }
+    }
+}
+
+/// The wall-clock time `instant` happened at, as of the anchor.
+pub(crate) fn wall_time(instant: Instant) -> SystemTime {
+    #[cfg(test)]
+    if let Some(anchor) = MOVED_ANCHOR.with(Cell::get) {
+        return anchor.wall_time(instant);
+    }
+
+    anchor().wall_time(instant)
+}
+
+/// Steps the wall clock the current thread's wall-clock times are derived
+/// from back by `by`, as a collector re-reading it would see it.
+#[cfg(test)]
+pub(crate) fn step_back(by: Duration) {
+    let anchor = *anchor();
+    let anchor = Anchor {
+        system: anchor.system - by,
+        ..anchor
+    };
+    MOVED_ANCHOR.with(|moved| moved.set(Some(anchor)));
+}
@@ -0,0 +1,78 @@
+use crate::snapshot::TaskSnapshot;
+
+use tokio::sync::mpsc::{self, error::TrySendError, Receiver, Sender};
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Mutex,
+};
+
+/// Snapshots each close-events receiver can hold before further ones are
+/// dropped.
+pub const CLOSE_EVENTS_CAPACITY: usize = 1024;
+
+/// Receivers of every task's final snapshot as it closes, see
+/// [`CollectorHandle::close_events`](crate::CollectorHandle::close_events).
+pub(crate) struct CloseSubscribers {
+    /// `None` once the collector has stopped.
+    senders: Mutex<Option<Vec<Sender<TaskSnapshot>>>>,
+    /// Snapshots dropped because a receiver was full.
+    dropped: AtomicU64,
+}
+
+impl Default for CloseSubscribers {
+    fn default() -> Self {
+        Self {
+            senders: Mutex::new(Some(Vec::new())),
+            dropped: AtomicU64::new(0),
+        }
+    }
+}
+
+impl CloseSubscribers {
+    /// Returns a new receiver, which ends straight away if the collector has
+    /// already stopped.
+    pub fn subscribe(&self) -> Receiver<TaskSnapshot> {
+        let (tx, rx) = mpsc::channel(CLOSE_EVENTS_CAPACITY);
+        if let Some(senders) = &mut *self.senders.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) {
+            senders.push(tx);
+        }
+
+        rx
+    }
+
+    pub fn is_empty(&self) -> bool {
+        match &*self.senders.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) {
+            Some(senders) => senders.is_empty(),
+            None => true,
+        }
+    }
+
+    /// Sends `snapshot` to every receiver with room for it, forgetting the
+    /// ones that have been dropped.
+    pub fn publish(&self, snapshot: &TaskSnapshot) {
+        let mut senders = self.senders.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let senders = match &mut *senders {
+            Some(senders) => senders,
+            None => return,
+        };
+
+        senders.retain(|sender| match sender.try_send(snapshot.clone()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            Err(TrySendError::Closed(_)) => false,
+        });
+    }
+
+    /// Ends every receiver once the snapshots already sent are read.
+    pub fn close(&self) {
+        self.senders.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take();
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
@@ -1,125 +1,2804 @@
-use crate::event::Event;
+use crate::{
+    builder::Builder,
+    callsite::{Callsite, CallsiteCounters, CallsiteCounts, CallsiteLimit},
+    census::AgeCensus,
+    clock,
+    counts::TaskCounts,
+    degrade::{ReportBudget, ReportGovernor},
+    event::{Event, EventId},
+    export::ExportConfig,
+    fields::{self, Budget, FieldValue, TaskKind, TaskOutcome},
+    guard::FlushGuard,
+    logs::TaskLogs,
+    handle::{Command, CollectorHandle},
+    histogram::{CountHistogram, DurationHistogram},
+    queue::QueueTracker,
+    lifecycle::{FinalReport, ProfileReport, StartupReport},
+    reducer::ErasedReducer,
+    replay,
+    reporting::{self, INTERNAL_TARGET},
+    sampling::Sampler,
+    sender::{EventReceiver, EventSender, OverflowPolicy, Shared, WeakEventChannel},
+    sequence::SequenceTracker,
+    sink::SummarySink,
+    snapshot::{FollowedTask, SnapshotOrder, TaskPhases, TaskSnapshot},
+    stacks::{self, StackTracker},
+    stats::Stats,
+    startup::StartupStats,
+    summary::{AggregateStats, KindSummary, LatencySummary, PhaseSummary, Summary},
+    threads::ThreadTracker,
+    variants::FieldVariantCounts,
+    watchdog::{Watchdog, WatchdogConfig},
+};
 
 use futures::FutureExt;
-use tokio::sync::mpsc::Receiver;
-use tracing_core::span::Id;
+use tokio::{
+    sync::mpsc::{self, error::TryRecvError, Receiver, Sender},
+    time::{self, Interval, MissedTickBehavior},
+};
 
 use std::{
-    collections::HashMap,
-    time::{Duration, SystemTime},
+    collections::{BTreeMap, HashMap, HashSet},
+    io, mem,
+    panic::{self, AssertUnwindSafe},
+    sync::{atomic::Ordering, Arc},
+    task::Poll,
+    thread::Thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 #[derive(Default, Debug)]
 struct Task {
+    /// The `task.name` field, for tasks given one.
+    name: Option<String>,
     fields: String,
+    values: Vec<(&'static str, FieldValue)>,
+    callsite: Option<Callsite>,
+    kind: TaskKind,
+    budget: Budget,
+    weight: Option<f64>,
+    groups: Vec<(String, String)>,
+    overdue: bool,
+    busy_loop: bool,
+    woken_at: Option<SystemTime>,
+    outcome: Option<TaskOutcome>,
+    mem_bytes: Option<u64>,
+    mem_bytes_max: Option<u64>,
+    counters: BTreeMap<String, u64>,
+    /// The task's frame followed by its parents', if folding stacks.
+    stack: Option<String>,
+    /// The task it was spawned inside of, if that was tracked.
+    parent: Option<u64>,
+    /// The tasks spawned inside of it, oldest first.
+    children: Vec<u64>,
+    follows: Vec<FollowedTask>,
+    logs: TaskLogs,
+    /// The value of the stable key field the task was spawned with.
+    stable_key: Option<String>,
+    /// Polls per second, smoothed over flushes.
+    poll_rate: Option<f64>,
+    /// Polls as of the previous flush.
+    polls_at_flush: u64,
+    /// Whether the task was created by an event that beat its `Spawn` to
+    /// the collector, which is still to fill in what it was spawned with.
+    provisional: bool,
+    /// Flushes the task has stayed provisional across.
+    provisional_flushes: u32,
+    /// The threads the task is being polled on right now.
+    polling: Vec<ThreadPoll>,
     stats: Stats,
 }
 
-#[derive(Default, Debug)]
-struct Stats {
-    active: bool,
-    current_polls: u64,
-    created_at: Option<SystemTime>,
-    first_poll: Option<SystemTime>,
-    last_poll: Option<SystemTime>,
-    closed_at: Option<SystemTime>,
-    busy_time: Duration,
+/// A poll in progress on one thread.
+#[derive(Debug)]
+struct ThreadPoll {
+    thread: Thread,
+    /// Nested entries into the span on this thread.
+    depth: u64,
+    started: Instant,
 }
 
-impl Stats {
-    pub fn total_time(&self) -> Option<Duration> {
-        self.closed_at.and_then(|end| {
-            self.created_at.and_then(|start| {
-                end.duration_since(start).ok()
-            })
-        })
+impl Task {
+    /// Returns how far past its deadline the task ran, if it did.
+    fn deadline_overshoot(&self, now: Instant) -> Option<Duration> {
+        let deadline = self.budget.deadline?;
+        let lifetime = match self.stats.total_time() {
+            Some(total) => total,
+            None => self.stats.age(now)?,
+        };
+
+        lifetime.checked_sub(deadline).filter(|overshoot| *overshoot > Duration::ZERO)
+    }
+
+    fn polls_overshoot(&self) -> Option<u64> {
+        let budget = self.budget.polls?;
+        self.stats.polls.checked_sub(budget).filter(|overshoot| *overshoot > 0)
+    }
+
+    fn snapshot(&self, id: u64) -> TaskSnapshot {
+        TaskSnapshot {
+            id,
+            name: self.name.clone(),
+            fields: self.fields.clone(),
+            values: self.values.clone(),
+            callsite: self.callsite,
+            kind: self.kind,
+            stats: self.stats.clone(),
+            outcome: self.outcome,
+            mem_bytes: self.mem_bytes,
+            mem_bytes_max: self.mem_bytes_max,
+            budget: self.budget,
+            over_budget: self.overdue,
+            busy_loop: self.busy_loop,
+            weight: self.weight,
+            groups: self.groups.clone(),
+            counters: self.counters.clone(),
+            poll_rate: self.poll_rate.filter(|_| self.stats.active),
+            parent: self.parent,
+            children: self.children.clone(),
+            follows: self.follows.clone(),
+            logs: self.logs.clone(),
+            provisional: self.provisional,
+            reduced: Vec::new(),
+        }
     }
 }
 
+/// How much of each flush's poll rate goes into the smoothed rate.
+const POLL_RATE_SMOOTHING: f64 = 0.5;
+
+/// Aggregates the events a [`BeeLayer`](crate::BeeLayer) sends it into
+/// per-task state and summaries. Built alongside the layer by
+/// [`BeeLayer::pair`](crate::BeeLayer::pair) or
+/// [`Builder::build`](crate::Builder::build), and does nothing until
+/// [run](Self::run).
 pub struct Collector {
-    events: Receiver<Event>,
-    tasks: HashMap<Id, Task>,
+    events: EventReceiver,
+    commands: Receiver<Command>,
+    command_sender: Sender<Command>,
+    event_sender: WeakEventChannel,
+    shared: Arc<Shared>,
+    startup_stats: StartupStats,
+    /// Maps the ids events refer to onto the collector's own task ids, which
+    /// are never reused.
+    ids: HashMap<EventId, u64>,
+    tasks: HashMap<u64, Task>,
+    next_task_id: u64,
+    instance: Option<String>,
     tick_interval: Duration,
+    /// `None` if the channel is unbounded.
+    channel_capacity: Option<usize>,
+    /// When the collector started running.
+    started_at: Instant,
+    /// When the previous flush was taken, if one was.
+    last_flush: Option<Instant>,
+    /// Events dropped as of the previous flush.
+    dropped_at_flush: u64,
+    align_ticks: bool,
+    /// Numbers flushes, skipping ahead past any ticks that were missed.
+    seq: u64,
+    ticks_missed: u64,
+    order: SnapshotOrder,
+    watchdog: Option<WatchdogConfig>,
+    sinks: Vec<SinkSlot>,
+    export: ExportConfig,
+    governor: Option<ReportGovernor>,
+    /// How long producing the previous report took.
+    report_cost: Option<Duration>,
+    /// Panics caught while applying events or flushing sinks.
+    panics: u64,
+    restarts: u32,
+    max_restarts: u32,
+    /// Tasks closing sooner than this after they were created are only
+    /// counted, not kept.
+    transient_grace: Option<Duration>,
+    transient_tasks: u64,
+    /// Ids of tasks no longer kept, so that their late events and second
+    /// closes don't make provisional tasks of them. Ids retired before the
+    /// previous flush are moved to `retired_before`, and forgotten at the next.
+    retired: HashSet<EventId>,
+    retired_before: HashSet<EventId>,
+    /// Busy time of tasks no longer kept, per kind, so that busy time totals
+    /// never go down.
+    retired_busy_time: BTreeMap<TaskKind, Duration>,
+    /// Whether to replay closed tasks as spans.
+    replay: bool,
+    /// Tasks reporting this many poll outcomes without one being `Ready`
+    /// are flagged as busy looping.
+    busy_loop_polls: Option<u64>,
+    budget_violations: u64,
+    duplicate_closes: u64,
+    /// Whether to check events arrive in an order they could have happened in.
+    strict: bool,
+    order_violations: u64,
+    /// Where each source's event sequence numbers are up to.
+    sequences: SequenceTracker,
+    /// The tasks being polled, any of which may lose the end of its poll
+    /// when events are lost.
+    polling: HashSet<EventId>,
+    callsites: CallsiteCounts,
+    field_variants: FieldVariantCounts,
+    /// The most distinct counters kept per task.
+    max_counters: usize,
+    /// Counters summed per callsite, if rolled up.
+    counter_rollup: Option<BTreeMap<Callsite, BTreeMap<String, u64>>>,
+    /// Busy time folded by task stack, if building flamegraphs.
+    stacks: Option<StackTracker>,
+    /// Totals kept instead of tasks, if only counting.
+    counts: Option<TaskCounts>,
+    /// Totals per value of the stable key field, over every span with it.
+    stable_keys: BTreeMap<String, AggregateStats>,
+    scheduling_latency: DurationHistogram,
+    /// Time from spawn to first poll, recorded as soon as a task is first polled.
+    first_poll_delay: DurationHistogram,
+    idle_gaps: DurationHistogram,
+    /// A sample of how long each poll took on its thread.
+    poll_durations: DurationHistogram,
+    /// Records every `poll_sample_every`th poll into `poll_durations`.
+    poll_sample_every: u64,
+    /// Polls to skip before the next is sampled.
+    polls_until_sample: u64,
+    phases: PhaseHistograms,
+    threads: ThreadTracker,
+    queues: QueueTracker,
+    reducers: Vec<Box<dyn ErasedReducer>>,
+    concurrency: CountHistogram,
+    census_bounds: Vec<Duration>,
+}
+
+/// Tells producers the collector has stopped when dropped.
+struct StopGuard(Arc<Shared>);
+
+impl Drop for StopGuard {
+    fn drop(&mut self) {
+        self.0.stop_collecting();
+        self.0.close_events.close();
+    }
+}
+
+/// A summary sink, set aside once it has panicked too often.
+struct SinkSlot {
+    sink: Box<dyn SummarySink>,
+    panics: u32,
+}
+
+impl SinkSlot {
+    const MAX_PANICS: u32 = 3;
+
+    fn is_quarantined(&self) -> bool {
+        self.panics >= Self::MAX_PANICS
+    }
+}
+
+/// Distribution of each lifetime phase across all closed tasks.
+#[derive(Default)]
+struct PhaseHistograms {
+    scheduling: DurationHistogram,
+    busy: DurationHistogram,
+    idle: DurationHistogram,
+    cleanup: DurationHistogram,
+}
+
+impl PhaseHistograms {
+    fn record(&mut self, phases: TaskPhases) {
+        self.scheduling.record(phases.scheduling);
+        self.busy.record(phases.busy);
+        self.idle.record(phases.idle);
+        self.cleanup.record(phases.cleanup);
+    }
+
+    fn summary(&self) -> Option<PhaseSummary> {
+        Some(PhaseSummary {
+            scheduling: LatencySummary::from_histogram(&self.scheduling)?,
+            busy: LatencySummary::from_histogram(&self.busy)?,
+            idle: LatencySummary::from_histogram(&self.idle)?,
+            cleanup: LatencySummary::from_histogram(&self.cleanup)?,
+        })
+    }
 }
 
 impl Collector {
-    pub fn new(events: Receiver<Event>, tick_interval: Duration) -> Self {
+    pub(crate) fn new(events: EventReceiver, sender: &EventSender, tick_interval: Duration) -> Self {
+        let (command_sender, commands) = mpsc::channel(Self::COMMAND_CAPACITY);
+
         Self {
             events,
+            commands,
+            command_sender,
+            event_sender: sender.sender.downgrade(),
+            shared: sender.shared.clone(),
+            startup_stats: Default::default(),
+            ids: Default::default(),
             tasks: Default::default(),
+            next_task_id: 1,
+            instance: None,
             tick_interval,
+            channel_capacity: sender.sender.max_capacity(),
+            started_at: Instant::now(),
+            last_flush: None,
+            dropped_at_flush: 0,
+            align_ticks: false,
+            seq: 0,
+            ticks_missed: 0,
+            order: Default::default(),
+            watchdog: None,
+            sinks: Vec::new(),
+            export: Default::default(),
+            governor: None,
+            report_cost: None,
+            panics: 0,
+            restarts: 0,
+            max_restarts: 0,
+            transient_grace: None,
+            transient_tasks: 0,
+            retired: Default::default(),
+            retired_before: Default::default(),
+            retired_busy_time: BTreeMap::new(),
+            replay: false,
+            busy_loop_polls: None,
+            budget_violations: 0,
+            duplicate_closes: 0,
+            strict: false,
+            order_violations: 0,
+            sequences: Default::default(),
+            polling: Default::default(),
+            callsites: Default::default(),
+            field_variants: Default::default(),
+            max_counters: usize::MAX,
+            counter_rollup: None,
+            stacks: None,
+            counts: None,
+            stable_keys: BTreeMap::new(),
+            scheduling_latency: Default::default(),
+            first_poll_delay: Default::default(),
+            idle_gaps: Default::default(),
+            poll_durations: Default::default(),
+            poll_sample_every: 1,
+            polls_until_sample: 0,
+            phases: Default::default(),
+            threads: Default::default(),
+            queues: Default::default(),
+            reducers: Vec::new(),
+            concurrency: CountHistogram::new(CountHistogram::CONCURRENCY_BOUNDS),
+            census_bounds: AgeCensus::DEFAULT_BOUNDS.to_vec(),
+        }
+    }
+
+    const COMMAND_CAPACITY: usize = 16;
+
+    pub(crate) fn with_watchdog(mut self, config: WatchdogConfig) -> Self {
+        self.watchdog = Some(config);
+        self
+    }
+
+    pub(crate) fn with_instance(mut self, instance: String) -> Self {
+        self.instance = Some(instance);
+        self
+    }
+
+    /// Sizes the task maps for `tasks` tasks up front, so they don't grow
+    /// and rehash as the workload warms up.
+    pub(crate) fn with_expected_tasks(mut self, tasks: usize) -> Self {
+        self.ids.reserve(tasks);
+        self.tasks.reserve(tasks);
+        self
+    }
+
+    pub(crate) fn with_transient_grace(mut self, grace: Duration) -> Self {
+        self.transient_grace = Some(grace);
+        self
+    }
+
+    pub(crate) fn with_poll_sample_every(mut self, n: u64) -> Self {
+        self.poll_sample_every = n;
+        self
+    }
+
+    pub(crate) fn with_busy_loop_polls(mut self, polls: u64) -> Self {
+        self.busy_loop_polls = Some(polls);
+        self
+    }
+
+    pub(crate) fn with_reducers(mut self, reducers: Vec<Box<dyn ErasedReducer>>) -> Self {
+        self.reducers = reducers;
+        self
+    }
+
+    pub(crate) fn with_report_budget(mut self, budget: ReportBudget) -> Self {
+        self.governor = Some(ReportGovernor::new(budget));
+        self
+    }
+
+    pub(crate) fn with_replay(mut self) -> Self {
+        self.replay = true;
+        self
+    }
+
+    pub(crate) fn with_aligned_ticks(mut self) -> Self {
+        self.align_ticks = true;
+        self
+    }
+
+    pub(crate) fn with_order(mut self, order: SnapshotOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    pub(crate) fn with_callsite_limits(mut self, limits: Vec<CallsiteLimit>) -> Self {
+        self.callsites = CallsiteCounts::new(limits);
+        self
+    }
+
+    pub(crate) fn with_max_counters(mut self, max_counters: usize) -> Self {
+        self.max_counters = max_counters;
+        self
+    }
+
+    pub(crate) fn with_counter_rollup(mut self) -> Self {
+        self.counter_rollup = Some(BTreeMap::new());
+        self
+    }
+
+    pub(crate) fn with_stacks(mut self) -> Self {
+        self.stacks = Some(Default::default());
+        self
+    }
+
+    pub(crate) fn with_counts_only(mut self) -> Self {
+        self.counts = Some(Default::default());
+        self
+    }
+
+    pub(crate) fn with_strict(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+
+    pub(crate) fn with_census_bounds(mut self, bounds: Vec<Duration>) -> Self {
+        self.census_bounds = bounds;
+        self
+    }
+
+    pub(crate) fn with_max_restarts(mut self, max_restarts: u32) -> Self {
+        self.max_restarts = max_restarts;
+        self
+    }
+
+    pub(crate) fn with_sinks(mut self, sinks: Vec<Box<dyn SummarySink>>, export: ExportConfig) -> Self {
+        self.sinks = sinks.into_iter().map(|sink| SinkSlot { sink, panics: 0 }).collect();
+        self.export = export;
+        self
+    }
+
+    pub fn handle(&self) -> CollectorHandle {
+        CollectorHandle {
+            commands: self.command_sender.clone(),
+            events: self.event_sender.clone(),
+            shared: self.shared.clone(),
         }
     }
 
-    pub async fn run(mut self) {
-        let mut flush = tokio::time::interval(self.tick_interval); 
+    /// Runs until the event channel closes or the collector is shut down
+    /// through a [`CollectorHandle`], returning the final state of every task.
+    ///
+    /// The channel closes once the layer and every injector are dropped, at
+    /// which point the events still queued are applied and a final report
+    /// is made straight away, without waiting for the next flush.
+    ///
+    /// Panics applying an event or flushing a sink are caught and counted.
+    /// A panic anywhere else restarts the collector with everything it had
+    /// collected forgotten, up to the configured number of times.
+    pub async fn run(mut self) -> Vec<TaskSnapshot> {
+        // Dropped, and so marking the collector stopped, however this returns.
+        let _stopped = StopGuard(self.shared.clone());
+
+        for event in self.shared.startup.release(&mut self.events) {
+            self.apply(event);
+        }
+        self.startup_stats = self.shared.startup.stats();
+        self.shared.set_tick_interval(self.tick_interval);
+        self.shared.set_collector_thread();
+        self.shared.beat();
+        clock::init();
+        self.started_at = Instant::now();
+        self.report_startup();
+
+        // Dropped, and so stopped, whenever this future is.
+        let _watchdog = self.watchdog.and_then(|config| {
+            match Watchdog::spawn(config, self.shared.clone()) {
+                Ok(watchdog) => Some(watchdog),
+                Err(e) => {
+                    tracing::warn!(target: INTERNAL_TARGET, "Failed to start collector watchdog: {}", e);
+                    None
+                }
+            }
+        });
 
         loop {
-            let _ = flush.tick().await;
+            let payload = match AssertUnwindSafe(self.run_loop()).catch_unwind().await {
+                Ok(tasks) => return tasks,
+                Err(payload) => payload,
+            };
 
-            while let Some(event) = self.events.recv().now_or_never() {
-                match event {
-                    Some(event) => self.update(event),
-                    None => {
-                        tracing::debug!("Event channel closed, terminating collector");
-                        return;
+            if self.restarts >= self.max_restarts {
+                panic::resume_unwind(payload);
+            }
+
+            self.restarts += 1;
+            tracing::error!(
+                target: INTERNAL_TARGET,
+                "Collector panicked, restarting without its state ({} of {} restarts)",
+                self.restarts,
+                self.max_restarts,
+            );
+            self.restart();
+        }
+    }
+
+    /// Runs on a thread and runtime of its own until the returned guard is
+    /// dropped, which makes the final report, or until the event channel
+    /// closes. Suits short-lived programs, which may otherwise exit before
+    /// the first flush.
+    ///
+    /// Fails if the thread or its runtime can't be started.
+    pub fn spawn(self) -> io::Result<FlushGuard> {
+        FlushGuard::spawn(self)
+    }
+
+    /// Runs for `duration` of wall-clock time, then stops as if
+    /// [shut down](CollectorHandle::shutdown), returning every task seen.
+    /// Events sent after that are dropped.
+    ///
+    /// Stops early, with what it has, if the event channel closes first.
+    pub async fn profile_for(self, duration: Duration) -> ProfileReport {
+        let started_at = SystemTime::now();
+        let handle = self.handle();
+        let run = self.run();
+        tokio::pin!(run);
+
+        let tasks = tokio::select! {
+            tasks = &mut run => tasks,
+            _ = tokio::time::sleep(duration) => {
+                let (_, tasks) = tokio::join!(handle.shutdown(), run);
+                tasks
+            }
+        };
+
+        ProfileReport::new(started_at, tasks)
+    }
+
+    async fn run_loop(&mut self) -> Vec<TaskSnapshot> {
+        let mut flush = self.flush_interval(time::Instant::now());
+        let mut last_tick = None;
+
+        loop {
+            tokio::select! {
+                tick = flush.tick() => {
+                    self.shared.beat();
+
+                    if let Some(last_tick) = last_tick {
+                        let missed = self.missed_ticks(tick - last_tick);
+                        self.seq += missed;
+                        self.ticks_missed += missed;
+                    }
+                    last_tick = Some(tick);
+
+                    if !self.drain_events() {
+                        tracing::debug!(target: INTERNAL_TARGET, "Event channel closed, terminating collector");
+                        self.report_final();
+                        return self.snapshot();
+                    }
+
+                    self.check_deadlines();
+                    let report = self.governor.as_mut().is_none_or(ReportGovernor::should_report);
+                    if report {
+                        self.produce_metrics();
+                        self.callsites.reset_warnings();
+                    }
+                }
+                // The layer and every injector may be gone; if so, flush
+                // what they sent now rather than at the next tick.
+                _ = self.shared.senders_dropped.notified() => {
+                    if !self.drain_events() {
+                        tracing::debug!(target: INTERNAL_TARGET, "Event channel closed, terminating collector");
+                        self.report_final();
+                        return self.snapshot();
+                    }
+                }
+                // A sender is waiting for room, under `lossy(false)`.
+                _ = self.shared.channel_full.notified() => {
+                    self.shared.beat();
+                    if !self.drain_events() {
+                        tracing::debug!(target: INTERNAL_TARGET, "Event channel closed, terminating collector");
+                        self.report_final();
+                        return self.snapshot();
+                    }
+                }
+                Some(command) = self.commands.recv() => match command {
+                    Command::Shutdown(reply) => {
+                        self.drain_events();
+                        self.report_final();
+                        let report = self.snapshot();
+                        let _ = reply.send(report.clone());
+                        return report;
+                    }
+                    Command::Report(reply) => {
+                        self.drain_events();
+                        let _ = reply.send(self.summary());
                     }
+                    Command::SetTickInterval(interval) => {
+                        // Zero would make tokio's interval panic.
+                        self.tick_interval = interval.max(Builder::MIN_TICK_INTERVAL);
+                        self.shared.set_tick_interval(self.tick_interval);
+
+                        // The pending tick was scheduled for the old interval.
+                        flush = self.flush_interval(time::Instant::now() + self.tick_interval);
+                        last_tick = None;
+                    }
+                },
+            }
+        }
+    }
+
+    /// Ticks every `tick_interval` from `start`, or optionally on wall-clock
+    /// multiples of it so that summaries from several processes line up.
+    fn flush_interval(&self, start: time::Instant) -> Interval {
+        let start = if self.align_ticks {
+            let period = self.tick_interval.as_nanos().max(1);
+            let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+            let until_boundary = (period - since_epoch % period) as u64;
+
+            time::Instant::now() + Duration::from_nanos(until_boundary)
+        } else {
+            start
+        };
+
+        let mut interval = tokio::time::interval_at(start, self.tick_interval);
+        // Missed ticks are counted rather than caught up on in a burst.
+        interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        interval
+    }
+
+    /// How many ticks were skipped, given the time between two that weren't.
+    fn missed_ticks(&self, between: Duration) -> u64 {
+        let period = self.tick_interval.as_nanos().max(1);
+        let elapsed = between.as_nanos() + period / 2;
+
+        (elapsed / period).saturating_sub(1) as u64
+    }
+
+    /// Applies every queued event, returning `false` once the channel has closed.
+    fn drain_events(&mut self) -> bool {
+        let mut open = true;
+
+        // Producers can't see how long an unbounded channel is.
+        self.shared.peak_queue_len.fetch_max(self.events.len(), Ordering::Relaxed);
+        loop {
+            match self.events.try_recv() {
+                Ok(event) => self.apply(event),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    open = false;
+                    break;
+                }
+            };
+        }
+
+        // Under `DropOldest` everything sent after startup lands in the ring.
+        let shared = self.shared.clone();
+        if let Some(ring) = &shared.ring {
+            for event in ring.drain() {
+                self.apply(event);
+            }
+        }
+
+        open
+    }
+
+    fn snapshot(&self) -> Vec<TaskSnapshot> {
+        let mut tasks: Vec<_> = self
+            .tasks
+            .iter()
+            .map(|(id, task)| {
+                let mut snapshot = task.snapshot(*id);
+                snapshot.reduced = self.reducers.iter().filter_map(|reducer| reducer.value(*id)).collect();
+                snapshot
+            })
+            .collect();
+        self.order.sort(&mut tasks);
+
+        tasks
+    }
+
+    /// Looks up the live task an event refers to, along with its task id.
+    fn task_mut<'a>(ids: &HashMap<EventId, u64>, tasks: &'a mut HashMap<u64, Task>, id: &EventId) -> Option<(u64, &'a mut Task)> {
+        let task_id = *ids.get(id)?;
+        tasks.get_mut(&task_id).map(|task| (task_id, task))
+    }
+
+    /// Lists `child` among the children of `parent`, if it is still kept.
+    fn adopt(&mut self, parent: u64, child: u64) {
+        if let Some(parent) = self.tasks.get_mut(&parent) {
+            parent.children.push(child);
+        }
+    }
+
+    /// Reports an event that couldn't have happened after the ones before it
+    /// for its task: anything before the task's spawn, an exit from a poll
+    /// that wasn't entered on that thread, or anything after its close.
+    fn check_order(&mut self, event: &Event) {
+        let (kind, violation) = match event {
+            Event::Enter { id, .. }
+            | Event::Exit { id, .. }
+            | Event::Close { id, .. }
+            | Event::Record { id, .. }
+            | Event::FollowsFrom { id, .. }
+            | Event::Log { id, .. } => {
+                let kind = match event {
+                    Event::Enter { .. } => "Enter",
+                    Event::Exit { .. } => "Exit",
+                    Event::Close { .. } => "Close",
+                    Event::FollowsFrom { .. } => "FollowsFrom",
+                    Event::Log { .. } => "Log",
+                    _ => "Record",
                 };
+                let violation = match self.ids.get(id).map(|task_id| self.tasks.get(task_id)) {
+                    // Queues are checked no further.
+                    Some(None) => return,
+                    None if self.is_retired(id) => "arrived after its task was dropped",
+                    None => "arrived before its spawn",
+                    Some(Some(task)) if task.provisional => "arrived before its spawn",
+                    Some(Some(task)) if task.stats.closed_at.is_some() => "arrived after its close",
+                    Some(Some(task)) => match event {
+                        Event::Exit { thread, .. } if !task.polling.iter().any(|poll| poll.thread.id() == thread.id()) => {
+                            "ended a poll not entered on that thread"
+                        }
+                        _ => return,
+                    },
+                };
+                (kind, violation)
+            }
+            // Wakers can outlive their task, so their events may come after its close.
+            Event::Spawn { .. } | Event::Waker { .. } | Event::QueueOpen { .. } | Event::QueueClose { .. } => return,
+        };
+
+        self.order_violations += 1;
+        tracing::error!(target: INTERNAL_TARGET, "{} for task {} {}", kind, event.id().into_u64(), violation);
+    }
+
+    /// The totals kept for a task's stable key, if it has one.
+    fn key_stats<'a>(stable_keys: &'a mut BTreeMap<String, AggregateStats>, key: &Option<String>) -> Option<&'a mut AggregateStats> {
+        stable_keys.get_mut(key.as_deref()?)
+    }
+
+    /// Creates a provisional task for an event that reached the collector
+    /// before its task's `Spawn`, e.g. a wake sent from another thread right
+    /// after the spawn. The spawn fills it in once it arrives.
+    fn ensure_task(&mut self, id: EventId, time: Instant) {
+        if self.ids.contains_key(&id) {
+            return;
+        }
+
+        let task_id = self.next_task_id;
+        self.next_task_id += 1;
+
+        self.ids.insert(id, task_id);
+        self.tasks.insert(task_id, Task {
+            provisional: true,
+            stats: Stats {
+                created_at: Some(time),
+                active: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+    }
+
+    /// Whether the id belonged to a task no longer kept.
+    fn is_retired(&self, id: &EventId) -> bool {
+        self.retired.contains(id) || self.retired_before.contains(id)
+    }
+
+    /// Drops the provisional tasks still waiting on their `Spawn` a whole
+    /// flush later, whose spawn was most likely lost, and forgets the ids
+    /// retired before the previous flush.
+    fn expire_provisional(&mut self) {
+        self.retired_before = mem::take(&mut self.retired);
+
+        let mut expired = HashSet::new();
+        for (task_id, task) in self.tasks.iter_mut().filter(|(_, task)| task.provisional) {
+            if task.provisional_flushes > 0 {
+                expired.insert(*task_id);
+            } else {
+                task.provisional_flushes += 1;
+            }
+        }
+        if expired.is_empty() {
+            return;
+        }
+
+        for task_id in &expired {
+            if let Some(task) = self.tasks.remove(task_id) {
+                *self.retired_busy_time.entry(task.kind).or_default() += task.stats.busy_time;
+            }
+            for reducer in self.reducers.iter_mut() {
+                reducer.remove(*task_id);
+            }
+        }
+        let (retired, polling) = (&mut self.retired, &mut self.polling);
+        self.ids.retain(|id, task_id| {
+            if !expired.contains(task_id) {
+                return true;
+            }
+            retired.insert(*id);
+            polling.remove(id);
+            false
+        });
+
+        tracing::debug!(target: INTERNAL_TARGET, "Dropped {} provisional tasks whose spawn never arrived", expired.len());
+    }
+
+    /// Forgets every task and aggregate, keeping the configuration and the
+    /// counts of panics and restarts. Task ids carry on from where they were.
+    fn restart(&mut self) {
+        for task in self.tasks.values() {
+            *self.retired_busy_time.entry(task.kind).or_default() += task.stats.busy_time;
+        }
+        self.ids.clear();
+        self.tasks.clear();
+        self.budget_violations = 0;
+        self.duplicate_closes = 0;
+        self.order_violations = 0;
+        self.sequences.clear();
+        self.polling.clear();
+        self.transient_tasks = 0;
+        self.retired.clear();
+        self.retired_before.clear();
+        self.callsites.clear();
+        self.field_variants.clear();
+        if let Some(rollup) = &mut self.counter_rollup {
+            rollup.clear();
+        }
+        if let Some(stacks) = &mut self.stacks {
+            stacks.clear();
+        }
+        if let Some(counts) = &mut self.counts {
+            *counts = Default::default();
+        }
+        self.stable_keys.clear();
+        self.scheduling_latency = Default::default();
+        self.first_poll_delay = Default::default();
+        self.idle_gaps = Default::default();
+        self.poll_durations = Default::default();
+        self.phases = Default::default();
+        self.threads = Default::default();
+        self.queues = Default::default();
+        for reducer in self.reducers.iter_mut() {
+            reducer.clear();
+        }
+        self.concurrency = CountHistogram::new(CountHistogram::CONCURRENCY_BOUNDS);
+    }
+
+    /// Applies an event, dropping it if that panics.
+    fn apply(&mut self, event: Event) {
+        self.shared.observe_event(event.wall_time());
+        self.observe_seq(&event);
+
+        let applied = panic::catch_unwind(AssertUnwindSafe(|| {
+            if self.counts.is_some() {
+                self.count(event);
+            } else if self.reducers.is_empty() {
+                self.update(event);
+            } else {
+                let id = event.id();
+                self.update(event.clone());
+                self.reduce(&id, &event);
+            }
+        }));
+
+        if applied.is_err() {
+            self.panics += 1;
+            tracing::error!(target: INTERNAL_TARGET, "Collector panicked applying an event, dropping it");
+        }
+    }
+
+    /// Tracks the event's sequence number, marking the tasks that were being
+    /// polled when events went missing as approximate once they are lost.
+    fn observe_seq(&mut self, event: &Event) {
+        let id = event.id();
+        let polling = &self.polling;
+        let approximate = self.sequences.observe(&id, event.seq(), || {
+            polling.iter().filter(|polled| polled.source() == id.source()).copied().collect()
+        });
+        self.mark_approximate(approximate);
+    }
+
+    fn mark_approximate(&mut self, ids: Vec<EventId>) {
+        for id in ids {
+            if let Some((_, task)) = Self::task_mut(&self.ids, &mut self.tasks, &id) {
+                task.stats.approximate = true;
+            }
+        }
+    }
+
+    /// Adds an event to the totals kept when only counting. Queues are
+    /// tracked as usual; task fields and anything else kept per task are
+    /// ignored.
+    fn count(&mut self, event: Event) {
+        let counts = match &mut self.counts {
+            Some(counts) => counts,
+            None => return,
+        };
+
+        match event {
+            Event::Spawn { .. } => counts.spawned(),
+            Event::FollowsFrom { .. } | Event::Log { .. } | Event::Waker { .. } => {}
+            Event::Close { .. } => counts.closed(),
+            Event::Enter { id, time, thread, .. } => counts.enter(id, time, thread),
+            Event::Exit { id, time, thread, .. } => {
+                if let Some((thread, poll_time)) = counts.exit(id, time, &thread) {
+                    self.threads.record(&thread, poll_time);
+
+                    if self.polls_until_sample == 0 {
+                        self.poll_durations.record(poll_time);
+                        self.polls_until_sample = self.poll_sample_every;
+                    }
+                    self.polls_until_sample -= 1;
+                }
             }
+            Event::Record { id, time, updates, .. } => {
+                if let (Some(depth), Some(queue_id)) = (updates.queue_depth, self.ids.get(&id)) {
+                    self.queues.depth(*queue_id, depth, time);
+                }
+            }
+            event @ (Event::QueueOpen { .. } | Event::QueueClose { .. }) => self.update(event),
+        }
+    }
 
-            self.produce_metrics();
+    /// Runs the reducers over an event the task it refers to has been updated with.
+    fn reduce(&mut self, id: &EventId, event: &Event) {
+        let task_id = match self.ids.get(id) {
+            Some(task_id) => *task_id,
+            None => return,
+        };
+
+        match self.tasks.get(&task_id) {
+            Some(task) => {
+                for reducer in self.reducers.iter_mut() {
+                    reducer.reduce(task_id, event, &task.stats);
+                }
+            }
+            // Dropped as transient.
+            None => {
+                for reducer in self.reducers.iter_mut() {
+                    reducer.remove(task_id);
+                }
+            }
         }
     }
 
     fn update(&mut self, event: Event) {
+        if self.strict {
+            self.check_order(&event);
+        }
+
+        match &event {
+            // The span id may be recycled from a task no longer kept.
+            Event::Spawn { id, .. } => {
+                self.retired.remove(id);
+                self.retired_before.remove(id);
+            }
+            Event::Enter { id, time, .. }
+            | Event::Exit { id, time, .. }
+            | Event::Close { id, time, .. }
+            | Event::Record { id, time, .. }
+            | Event::FollowsFrom { id, time, .. }
+            | Event::Log { id, time, .. } => {
+                if self.is_retired(id) {
+                    if let Event::Close { .. } = event {
+                        self.duplicate_closes += 1;
+                    }
+                    return;
+                }
+                self.ensure_task(*id, *time);
+            }
+            _ => {}
+        }
+
         match event {
             Event::Spawn { 
                 id, 
                 time, 
                 fields,
+                attributes,
+                callsite,
+                parent,
+                ..
             } => {
-                let mut entry = &mut self.tasks.entry(id.clone()).or_default();
-                entry.fields = fields;
-                entry.stats.created_at = Some(time);
-                entry.stats.active = true;
+                let stack = self.stacks.as_ref().map(|_| {
+                    let name = attributes.name.as_deref().or(callsite.map(|callsite| callsite.name)).unwrap_or("task");
+                    let parent = parent
+                        .and_then(|parent| self.ids.get(&parent))
+                        .and_then(|parent| self.tasks.get(parent))
+                        .and_then(|parent| parent.stack.as_deref());
+
+                    match parent {
+                        Some(parent) => format!("{};{}", parent, stacks::frame(name)),
+                        None => stacks::frame(name),
+                    }
+                });
+
+                if let Some(callsite) = callsite {
+                    self.callsites.spawned(callsite);
+                    self.field_variants.spawned(callsite.target, &fields);
+                }
+                let parent = parent.and_then(|parent| self.ids.get(&parent)).copied();
+                let stable_keys = &mut self.stable_keys;
+                let key_stats = attributes.stable_key.as_ref().map(|key| stable_keys.entry(key.clone()).or_default());
+
+                // Events that beat the spawn here made a provisional task,
+                // which keeps its id and what it has accumulated.
+                if let Some((task_id, task)) = Self::task_mut(&self.ids, &mut self.tasks, &id) {
+                    if task.provisional {
+                        // Values recorded since its creation take the place
+                        // of the ones it was created with.
+                        if task.fields.is_empty() {
+                            task.fields = fields;
+                        }
+                        let recorded = mem::replace(&mut task.values, attributes.values);
+                        fields::merge_values(&mut task.values, recorded);
+                        task.name = attributes.name;
+                        task.callsite = callsite;
+                        task.kind = attributes.kind;
+                        task.budget = attributes.budget;
+                        task.weight = attributes.weight;
+                        task.groups = attributes.groups;
+                        task.stack = stack;
+                        task.stable_key = attributes.stable_key;
+                        task.parent = parent.filter(|parent| *parent != task_id);
+                        task.provisional = false;
+                        // Timestamps from other threads may run ahead of the
+                        // spawn's; the task can't have started after its first event.
+                        task.stats.created_at = task.stats.created_at.min(Some(time));
+
+                        if let Some(callsite) = &callsite {
+                            if !task.stats.active {
+                                self.callsites.closed(callsite);
+                            }
+                            if let Some(rollup) = &mut self.counter_rollup {
+                                let totals = rollup.entry(*callsite).or_default();
+                                for (name, value) in &task.counters {
+                                    let total = totals.entry(name.clone()).or_default();
+                                    *total = total.saturating_add(*value);
+                                }
+                            }
+                        }
+                        if let Some(key_stats) = key_stats {
+                            key_stats.tasks += 1;
+                            key_stats.active += task.stats.active as usize;
+                            key_stats.polls += task.stats.polls;
+                            key_stats.busy_time += task.stats.busy_time;
+                        }
+                        if let Some(parent) = parent.filter(|parent| *parent != task_id) {
+                            self.adopt(parent, task_id);
+                        }
+                        tracing::trace!(target: INTERNAL_TARGET, "Task {} spawned after its first event", task_id);
+                        return;
+                    }
+                }
+
+                if let Some(key_stats) = key_stats {
+                    key_stats.tasks += 1;
+                    key_stats.active += 1;
+                }
+
+                let task_id = self.next_task_id;
+                self.next_task_id += 1;
+
+                // A reused id starts a new task; the old one stays as it was.
+                self.ids.insert(id, task_id);
+                self.tasks.insert(task_id, Task {
+                    name: attributes.name,
+                    fields,
+                    values: attributes.values,
+                    callsite,
+                    kind: attributes.kind,
+                    budget: attributes.budget,
+                    weight: attributes.weight,
+                    groups: attributes.groups,
+                    stack,
+                    stable_key: attributes.stable_key,
+                    parent,
+                    stats: Stats {
+                        created_at: Some(time),
+                        active: true,
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                });
+                if let Some(parent) = parent {
+                    self.adopt(parent, task_id);
+                }
             }
-            Event::Enter { id, time } => {
-                let mut stats = &mut self.tasks.get_mut(&id).unwrap().stats;
+            Event::Enter { id, time, thread, .. } => {
+                let (_, task) = match Self::task_mut(&self.ids, &mut self.tasks, &id) {
+                    Some(task) => task,
+                    None => return,
+                };
+                let stats = &mut task.stats;
 
                 if stats.current_polls == 0 {
+                    self.polling.insert(id);
+
+                    // Prefer tokio's own waker events, then the instrumented
+                    // wake time; the gap since the previous poll ended is
+                    // only a proxy for either.
+                    if let Some(woken_at) = stats.woken_at.take() {
+                        let scheduled = time.saturating_duration_since(woken_at);
+                        stats.scheduled_time += scheduled;
+                        stats.scheduled_polls += 1;
+                        task.woken_at = None;
+                        self.scheduling_latency.record(scheduled);
+                    } else if let Some(woken_at) = task.woken_at.take() {
+                        self.scheduling_latency.record(clock::wall_time(time).duration_since(woken_at).unwrap_or_default());
+                    } else if let Some(last_exit) = stats.last_exit {
+                        self.idle_gaps.record(time.saturating_duration_since(last_exit));
+                    }
+
+                    stats.polls += 1;
                     stats.last_poll = Some(time);
-                    if stats.first_poll == None {
+                    stats.polled_on(thread.id());
+                    if let Some(key_stats) = Self::key_stats(&mut self.stable_keys, &task.stable_key) {
+                        key_stats.polls += 1;
+                    }
+                    if stats.first_poll.is_none() {
                         stats.first_poll = Some(time);
+
+                        if let Some(created_at) = stats.created_at {
+                            self.first_poll_delay.record(time.saturating_duration_since(created_at));
+                        }
                     }
                 }
 
                 stats.current_polls += 1;
+
+                match task.polling.iter_mut().find(|poll| poll.thread.id() == thread.id()) {
+                    Some(poll) => poll.depth += 1,
+                    None => {
+                        if let Some(stacks) = &mut self.stacks {
+                            stacks.poll_started(thread.id());
+                        }
+                        task.polling.push(ThreadPoll {
+                            thread,
+                            depth: 1,
+                            started: time,
+                        });
+                    }
+                }
             }
-            Event::Exit { id, time } => {
-                let mut stats = &mut self.tasks.get_mut(&id).unwrap().stats;
+            Event::Exit { id, time, thread, .. } => {
+                let task = match Self::task_mut(&self.ids, &mut self.tasks, &id) {
+                    Some((_, task)) if task.stats.current_polls > 0 => task,
+                    // The matching enter was lost; there is no poll to end.
+                    _ => return,
+                };
+                let stats = &mut task.stats;
                 stats.current_polls -= 1;
 
+                if let Some(index) = task.polling.iter().position(|poll| poll.thread.id() == thread.id()) {
+                    let poll = &mut task.polling[index];
+                    poll.depth -= 1;
+
+                    if poll.depth == 0 {
+                        let poll = task.polling.swap_remove(index);
+                        let poll_time = time.saturating_duration_since(poll.started);
+                        stats.poll_time += poll_time;
+                        self.threads.record(&poll.thread, poll_time);
+                        if let Some(stacks) = &mut self.stacks {
+                            stacks.poll_ended(poll.thread.id(), task.stack.as_deref(), poll_time);
+                        }
+
+                        if self.polls_until_sample == 0 {
+                            self.poll_durations.record(poll_time);
+                            self.polls_until_sample = self.poll_sample_every;
+                        }
+                        self.polls_until_sample -= 1;
+                    }
+                }
+
                 if stats.current_polls == 0 {
+                    self.polling.remove(&id);
                     if let Some(last_poll) = stats.last_poll {
-                        stats.busy_time += time.duration_since(last_poll).unwrap();
+                        let busy = time.saturating_duration_since(last_poll);
+                        stats.busy_time += busy;
+                        if let Some(key_stats) = Self::key_stats(&mut self.stable_keys, &task.stable_key) {
+                            key_stats.busy_time += busy;
+                        }
+                    }
+                    // Woken during the poll, it only became runnable now.
+                    if let Some(woken_at) = &mut stats.woken_at {
+                        *woken_at = (*woken_at).max(time);
                     }
+                    stats.last_exit = Some(time);
                 }
             }
-            Event::Close { id, time } => {
-                let mut stats = &mut self.tasks.get_mut(&id).unwrap().stats;
-                stats.active = false;
-                stats.closed_at = Some(time);
-            }
-        }
-    }
+            Event::Close { id, time, .. } => {
+                let (task_id, task) = match Self::task_mut(&self.ids, &mut self.tasks, &id) {
+                    Some(task) => task,
+                    None => return,
+                };
 
-    fn produce_metrics(&self) {
-        for task in self.tasks.iter() {
-            if task.1.stats.active {
-                tracing::info!("Task {} running", task.0.into_u64());
-            } else {
-                tracing::info!("Task {} inactive: total time {:?}", task.0.into_u64(), task.1.stats.total_time());
-            }
-        }
-    }
-}
\ No newline at end of file
+                // Recycled ids or misbehaving guards can close a span twice;
+                // only the first close counts.
+                if task.stats.closed_at.is_some() {
+                    self.duplicate_closes += 1;
+                    return;
+                }
+
+                task.stats.active = false;
+                task.stats.closed_at = Some(time);
+                // Its exit may have been lost.
+                self.polling.remove(&id);
+                if let Some(key_stats) = Self::key_stats(&mut self.stable_keys, &task.stable_key) {
+                    key_stats.active = key_stats.active.saturating_sub(1);
+                }
+                if !self.shared.close_events.is_empty() {
+                    self.shared.close_events.publish(&task.snapshot(task_id));
+                }
+                if let Some(callsite) = &task.callsite {
+                    self.callsites.closed(callsite);
+                }
+
+                if let Some(phases) = task.stats.phases() {
+                    self.phases.record(phases);
+                }
+
+                // A task already flagged as overdue while running has been counted.
+                if !task.overdue {
+                    let deadline = task.deadline_overshoot(time);
+                    let polls = task.polls_overshoot();
+
+                    if deadline.is_some() || polls.is_some() {
+                        task.overdue = true;
+                        self.budget_violations += 1;
+                    }
+
+                    if let Some(overshoot) = deadline {
+                        tracing::warn!(
+                            target: INTERNAL_TARGET,
+                            "Task {} completed {:?} past its deadline of {:?}",
+                            task_id,
+                            overshoot,
+                            task.budget.deadline.unwrap_or_default(),
+                        );
+                    }
+                    if let Some(overshoot) = polls {
+                        tracing::warn!(
+                            target: INTERNAL_TARGET,
+                            "Task {} was polled {} times more than its budget of {}",
+                            task_id,
+                            overshoot,
+                            task.budget.polls.unwrap_or_default(),
+                        );
+                    }
+                }
+
+                // Tasks over budget are kept so they can be looked into.
+                let transient = match (self.transient_grace, task.stats.total_time()) {
+                    (Some(grace), Some(lifetime)) => lifetime < grace && !task.overdue,
+                    _ => false,
+                };
+                if transient {
+                    self.transient_tasks += 1;
+                    *self.retired_busy_time.entry(task.kind).or_default() += task.stats.busy_time;
+                    let parent = self.tasks.remove(&task_id).and_then(|task| task.parent);
+                    if let Some(parent) = parent.and_then(|parent| self.tasks.get_mut(&parent)) {
+                        parent.children.retain(|child| *child != task_id);
+                    }
+                    self.ids.remove(&id);
+                    self.retired.insert(id);
+                } else if self.replay {
+                    replay::replay(&task.snapshot(task_id));
+                }
+            }
+            Event::QueueOpen {
+                id,
+                time,
+                name,
+                fields,
+                depth,
+                ..
+            } => {
+                let queue_id = self.next_task_id;
+                self.next_task_id += 1;
+
+                self.ids.insert(id, queue_id);
+                self.queues.opened(queue_id, name, fields, depth, time);
+            }
+            Event::FollowsFrom { id, follows, .. } => {
+                // Links to spans without a task are kept by their raw id.
+                let followed = match self.ids.get(&follows) {
+                    Some(task_id) if self.tasks.contains_key(task_id) => FollowedTask::Task(*task_id),
+                    _ => FollowedTask::Span(follows),
+                };
+                if let Some((_, task)) = Self::task_mut(&self.ids, &mut self.tasks, &id) {
+                    if !task.follows.contains(&followed) {
+                        task.follows.push(followed);
+                    }
+                }
+            }
+            Event::Log { id, level, message, .. } => {
+                if let Some((_, task)) = Self::task_mut(&self.ids, &mut self.tasks, &id) {
+                    task.logs.record(level, message);
+                }
+            }
+            Event::Waker { id, op, time, thread, .. } => {
+                // Only counted for tasks still kept; a waker can be dropped
+                // long after its task is gone.
+                if let Some((_, task)) = Self::task_mut(&self.ids, &mut self.tasks, &id) {
+                    task.stats.waker_op(op, time, thread.id());
+                }
+            }
+            Event::QueueClose { id, time, .. } => {
+                if let Some(queue_id) = self.ids.get(&id) {
+                    self.queues.closed(*queue_id, time);
+                }
+            }
+            Event::Record { id, time, updates, .. } => {
+                if let (Some(depth), Some(queue_id)) = (updates.queue_depth, self.ids.get(&id)) {
+                    self.queues.depth(*queue_id, depth, time);
+                }
+
+                let (task_id, task) = match Self::task_mut(&self.ids, &mut self.tasks, &id) {
+                    Some(task) => task,
+                    None => return,
+                };
+
+                match updates.poll {
+                    Some(Poll::Ready(())) => task.stats.ready_polls += 1,
+                    Some(Poll::Pending) => task.stats.pending_polls += 1,
+                    None => {}
+                }
+                if let Some(threshold) = self.busy_loop_polls {
+                    let stats = &task.stats;
+                    if !task.busy_loop && stats.ready_polls == 0 && stats.pending_polls >= threshold {
+                        task.busy_loop = true;
+                        tracing::warn!(
+                            target: INTERNAL_TARGET,
+                            "Task {} was polled {} times without making progress",
+                            task_id,
+                            stats.pending_polls,
+                        );
+                    }
+                }
+                if let Some(woken_at) = updates.woken_at {
+                    task.woken_at = Some(woken_at);
+                }
+                if let Some(fields) = updates.fields {
+                    task.fields = fields;
+                }
+                fields::merge_values(&mut task.values, updates.values);
+                if let Some(outcome) = updates.outcome {
+                    task.outcome = Some(outcome);
+                }
+                if let Some(mem_bytes) = updates.mem_bytes {
+                    task.mem_bytes = Some(mem_bytes);
+                    task.mem_bytes_max = task.mem_bytes_max.max(Some(mem_bytes));
+                }
+                for (name, value) in updates.counters {
+                    let full = task.counters.len() >= self.max_counters;
+                    match task.counters.get_mut(&name) {
+                        Some(counter) => *counter = counter.saturating_add(value),
+                        None if !full => {
+                            task.counters.insert(name.clone(), value);
+                        }
+                        None => {
+                            tracing::debug!(
+                                target: INTERNAL_TARGET,
+                                "Task {} already has {} counters, ignoring counter {}",
+                                task_id,
+                                self.max_counters,
+                                name,
+                            );
+                            continue;
+                        }
+                    }
+
+                    if let (Some(rollup), Some(callsite)) = (&mut self.counter_rollup, task.callsite) {
+                        let counter = rollup.entry(callsite).or_default().entry(name).or_default();
+                        *counter = counter.saturating_add(value);
+                    }
+                }
+            }
+        }
+    }
+
+    fn check_deadlines(&mut self) {
+        let now = Instant::now();
+
+        for (id, task) in self.tasks.iter_mut() {
+            if !task.stats.active || task.overdue {
+                continue;
+            }
+
+            if let Some(overshoot) = task.deadline_overshoot(now) {
+                task.overdue = true;
+                self.budget_violations += 1;
+
+                tracing::warn!(
+                    target: INTERNAL_TARGET,
+                    "Task {} still running {:?} past its deadline of {:?}",
+                    id,
+                    overshoot,
+                    task.budget.deadline.unwrap_or_default(),
+                );
+            }
+        }
+    }
+
+    /// Busy time of every weighted task, scaled by its weight.
+    fn weighted_busy_time(&self) -> Option<Duration> {
+        self.tasks
+            .values()
+            .filter_map(|task| {
+                let weighted = task.stats.busy_time.as_secs_f64() * task.weight?.max(0.0);
+                Some(Duration::try_from_secs_f64(weighted).unwrap_or(Duration::MAX))
+            })
+            .fold(None, |total, busy| Some(total.unwrap_or_default().saturating_add(busy)))
+    }
+
+    /// Counters summed per callsite, in callsite order, or empty if they
+    /// aren't rolled up.
+    fn counter_rollup(&self) -> Vec<CallsiteCounters> {
+        self.counter_rollup
+            .iter()
+            .flatten()
+            .map(|(callsite, counters)| CallsiteCounters {
+                callsite: *callsite,
+                counters: counters.clone(),
+            })
+            .collect()
+    }
+
+    /// Running tasks bucketed by age and by time since their last poll.
+    fn census(&self) -> AgeCensus {
+        let now = Instant::now();
+        let mut census = AgeCensus::new(self.census_bounds.clone());
+
+        for task in self.tasks.values().filter(|task| task.stats.active) {
+            census.record(
+                task.stats.age(now).unwrap_or_default(),
+                task.stats.idle(now).unwrap_or_default(),
+            );
+        }
+
+        census
+    }
+
+    /// Memory reported by every running task that reports it.
+    fn reported_mem_bytes(&self) -> Option<u64> {
+        self.tasks
+            .values()
+            .filter(|task| task.stats.active)
+            .filter_map(|task| task.mem_bytes)
+            .fold(None, |total, bytes| Some(total.unwrap_or(0u64).saturating_add(bytes)))
+    }
+
+    fn summary(&self) -> Summary {
+        let tasks = self.snapshot();
+
+        Summary {
+            instance: self.instance.clone(),
+            seq: self.seq,
+            flushed_at: None,
+            ticks_missed: self.ticks_missed,
+            name_allowlist: self.shared.allowlist().map(|names| {
+                let mut names: Vec<_> = names.iter().cloned().collect();
+                names.sort();
+                names
+            }),
+            kinds: KindSummary::collect(&tasks, &self.retired_busy_time),
+            threads: self.threads.summary(),
+            queues: self.queues.summary(),
+            tasks,
+            startup: self.startup_stats,
+            transient_tasks: self.transient_tasks,
+            budget_violations: self.budget_violations,
+            duplicate_closes: self.duplicate_closes,
+            order_violations: self.order_violations,
+            events_lost: self.sequences.lost,
+            event_gaps: self.sequences.gaps,
+            panics: self.panics,
+            quarantined_sinks: self.sinks.iter().filter(|slot| slot.is_quarantined()).count(),
+            restarts: self.restarts,
+            report_cost: self.report_cost,
+            degradation: self.governor.as_ref().map(|governor| governor.active().to_vec()).unwrap_or_default(),
+            peak_active: self.shared.peak_active(),
+            active_tasks: self.shared.active.load(Ordering::Relaxed),
+            active_concurrency: self.concurrency.distribution(),
+            census: self.census(),
+            callsites: self.callsites.summary(),
+            counter_rollup: self.counter_rollup(),
+            stacks: self.stacks.as_ref().map(StackTracker::summary).unwrap_or_default(),
+            counts: self.counts.as_ref().map(TaskCounts::summary),
+            sampling: self.shared.sampler.as_ref().map(Sampler::stats),
+            stable_keys: self.stable_keys.clone(),
+            field_variants: self.field_variants.summary(),
+            filtered_traffic: self.shared.filtered.summary(),
+            peak_queue_len: self.shared.peak_queue_len.load(Ordering::Relaxed),
+            last_event_at: self.shared.last_event_at(),
+            dropped_newest: self.shared.dropped_newest.total(),
+            dropped_by_kind: self.shared.dropped_newest.load(),
+            dropped_since_flush: self.shared.dropped_events().saturating_sub(self.dropped_at_flush),
+            dropped_oldest: self.shared.ring.as_ref().map(|ring| ring.dropped()),
+            event_sinks_dropped: self.shared.event_sinks_dropped(),
+            dropped_close_events: self.shared.close_events.dropped(),
+            weighted_busy_time: self.weighted_busy_time(),
+            reported_mem_bytes: self.reported_mem_bytes(),
+            wake_to_poll: LatencySummary::from_histogram(&self.scheduling_latency),
+            first_poll_delay: LatencySummary::from_histogram(&self.first_poll_delay),
+            idle_gaps: LatencySummary::from_histogram(&self.idle_gaps),
+            poll_durations: LatencySummary::from_histogram(&self.poll_durations),
+            phases: self.phases.summary(),
+        }
+    }
+
+    /// Updates every running task's poll rate with the polls since the
+    /// previous flush. Tasks created since are measured over the part of the
+    /// interval they were alive for.
+    fn update_poll_rates(&mut self, now: Instant) {
+        let interval_start = self.last_flush.unwrap_or(self.started_at);
+        self.last_flush = Some(now);
+
+        for task in self.tasks.values_mut().filter(|task| task.stats.active) {
+            let start = task.stats.created_at.map_or(interval_start, |created_at| created_at.max(interval_start));
+            let elapsed = now.saturating_duration_since(start).as_secs_f64();
+            let polls = task.stats.polls - task.polls_at_flush;
+            task.polls_at_flush = task.stats.polls;
+            if elapsed <= 0.0 {
+                continue;
+            }
+
+            let rate = polls as f64 / elapsed;
+            task.poll_rate = Some(match task.poll_rate {
+                Some(previous) => previous + POLL_RATE_SMOOTHING * (rate - previous),
+                None => rate,
+            });
+        }
+    }
+
+    /// Takes the summary for a flush, starting a new flush interval.
+    fn flush_summary(&mut self) -> Summary {
+        self.seq += 1;
+        let approximate = self.sequences.flush(false);
+        self.mark_approximate(approximate);
+        self.expire_provisional();
+        self.concurrency.record(self.shared.active.load(Ordering::Relaxed) as u64);
+        self.update_poll_rates(Instant::now());
+
+        let mut summary = self.summary();
+        summary.flushed_at = Some(SystemTime::now());
+        self.dropped_at_flush += summary.dropped_since_flush;
+        self.threads.reset();
+        self.queues.reset();
+
+        summary
+    }
+
+    fn produce_metrics(&mut self) {
+        let started = Instant::now();
+
+        let mut summary = self.flush_summary();
+        if self.governor.as_ref().is_some_and(ReportGovernor::aggregates_only) {
+            summary.tasks = Vec::new();
+        }
+
+        reporting::in_metrics_span(|| {
+            for line in summary.to_string().lines() {
+                tracing::info!("{}", line);
+            }
+
+            self.each_sink(|sink, export| sink.flush(&summary, export));
+        });
+        self.shared.publish_summary(summary);
+
+        let cost = started.elapsed();
+        self.report_cost = Some(cost);
+        if let Some(governor) = &mut self.governor {
+            governor.observe(cost, self.tick_interval);
+        }
+    }
+
+    fn report_startup(&mut self) {
+        let report = StartupReport {
+            version: env!("CARGO_PKG_VERSION"),
+            instance: self.instance.clone(),
+            started_at: clock::wall_time(self.started_at),
+            tick_interval: self.tick_interval,
+            channel_capacity: self.channel_capacity,
+            overflow_policy: match self.shared.ring {
+                Some(_) => OverflowPolicy::DropOldest,
+                None => OverflowPolicy::DropNewest,
+            },
+            summary_sinks: self.sinks.len(),
+            event_sinks: self.shared.event_sinks.len(),
+            name_allowlist: self.shared.allowlist().map(|names| {
+                let mut names: Vec<_> = names.iter().cloned().collect();
+                names.sort();
+                names
+            }),
+            transient_grace: self.transient_grace,
+        };
+
+        reporting::in_metrics_span(|| {
+            tracing::info!("{}", report);
+            self.each_sink(|sink, export| sink.start(&report, export));
+        });
+    }
+
+    /// Reports on the whole run in place of a last flush. Every task is
+    /// included, however reports were degraded.
+    fn report_final(&mut self) {
+        // Nothing more can arrive to fill the gaps left.
+        let approximate = self.sequences.flush(true);
+        self.mark_approximate(approximate);
+        let summary = self.flush_summary();
+        let report = FinalReport::new(clock::wall_time(self.started_at), self.next_task_id - 1, summary);
+
+        reporting::in_metrics_span(|| {
+            for line in report.to_string().lines() {
+                tracing::info!("{}", line);
+            }
+
+            self.each_sink(|sink, export| sink.finish(&report, export));
+        });
+        self.shared.publish_summary(report.summary);
+    }
+
+    /// Calls every sink that isn't quarantined, catching and counting panics.
+    fn each_sink(&mut self, mut call: impl FnMut(&mut dyn SummarySink, &ExportConfig)) {
+        for (index, slot) in self.sinks.iter_mut().enumerate() {
+            if slot.is_quarantined() {
+                continue;
+            }
+
+            let export = &self.export;
+            if panic::catch_unwind(AssertUnwindSafe(|| call(slot.sink.as_mut(), export))).is_err() {
+                self.panics += 1;
+                slot.panics += 1;
+
+                if slot.is_quarantined() {
+                    tracing::error!(target: INTERNAL_TARGET, "Summary sink {} panicked {} times, no longer flushing it", index, slot.panics);
+                } else {
+                    tracing::error!(target: INTERNAL_TARGET, "Summary sink {} panicked", index);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Running;
+    use crate::{
+        fields::{FieldUpdates, TaskAttributes},
+        waker::WakerOp,
+        BeeLayer,
+    };
+
+    use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+
+    use std::{
+        fmt,
+        sync::{Arc, Mutex},
+    };
+
+    fn spawn(id: EventId, fields: &str) -> Event {
+        Event::spawn(id, fields.into(), Default::default(), None, None)
+    }
+
+    fn spawn_with(id: EventId, attributes: TaskAttributes) -> Event {
+        Event::spawn(id, String::new(), attributes, None, None)
+    }
+
+    /// Moves the event to `when`, for scripting timings.
+    fn at(mut event: Event, when: Instant) -> Event {
+        match &mut event {
+            Event::Spawn { time, .. }
+            | Event::Enter { time, .. }
+            | Event::Exit { time, .. }
+            | Event::Close { time, .. }
+            | Event::Record { time, .. }
+            | Event::FollowsFrom { time, .. }
+            | Event::Log { time, .. }
+            | Event::Waker { time, .. }
+            | Event::QueueOpen { time, .. }
+            | Event::QueueClose { time, .. } => *time = when,
+        }
+        event
+    }
+
+    /// Moves a poll event to `on`, for scripting threads.
+    fn on(mut event: Event, on: &Thread) -> Event {
+        if let Event::Enter { thread, .. } | Event::Exit { thread, .. } | Event::Waker { thread, .. } = &mut event {
+            *thread = on.clone();
+        }
+        event
+    }
+
+    fn named_thread(name: &str) -> Thread {
+        std::thread::Builder::new().name(name.into()).spawn(std::thread::current).unwrap().join().unwrap()
+    }
+
+    fn task(collector: &Collector, id: u64) -> TaskSnapshot {
+        collector.snapshot().into_iter().find(|task| task.id == id).unwrap()
+    }
+
+    #[test]
+    fn wake_before_spawn_upgrades_the_provisional_task() {
+        let (_layer, mut collector) = BeeLayer::pair();
+        let id = EventId::external(1);
+
+        let wake = FieldUpdates {
+            woken_at: Some(SystemTime::now()),
+            ..Default::default()
+        };
+        collector.apply(Event::record(id, wake));
+        assert!(collector.snapshot()[0].provisional);
+
+        collector.apply(spawn(id, "job=1"));
+        collector.apply(Event::enter(id));
+        collector.apply(Event::exit(id));
+
+        let tasks = collector.snapshot();
+        assert_eq!(tasks.len(), 1);
+        assert!(!tasks[0].provisional);
+        assert_eq!(tasks[0].fields, "job=1");
+        assert_eq!(tasks[0].stats.polls(), 1);
+        assert_eq!(collector.scheduling_latency.len(), 1);
+    }
+
+    #[test]
+    fn late_events_for_dropped_transient_tasks_are_ignored() {
+        let (_layer, mut collector) = BeeLayer::builder().transient_grace(Duration::from_secs(60)).build();
+        let id = EventId::external(1);
+
+        collector.apply(spawn(id, ""));
+        collector.apply(Event::enter(id));
+        collector.apply(Event::exit(id));
+        collector.apply(Event::close(id));
+        collector.apply(Event::close(id));
+        collector.apply(Event::enter(id));
+        collector.apply(Event::log(id, tracing::Level::INFO, None));
+
+        let summary = collector.summary();
+        assert!(summary.tasks.is_empty());
+        assert_eq!(summary.transient_tasks, 1);
+        assert_eq!(summary.duplicate_closes, 1);
+
+        // Once the id is reused by a new span, it is tracked again.
+        collector.apply(spawn(id, ""));
+        assert_eq!(collector.snapshot().len(), 1);
+    }
+
+    #[test]
+    fn followed_tasks_that_are_gone_keep_their_span_id() {
+        let (_layer, mut collector) = BeeLayer::builder().transient_grace(Duration::from_secs(60)).build();
+        let [gone, kept, untracked, follower] = [1, 2, 3, 4].map(EventId::external);
+
+        collector.apply(spawn(gone, "gone"));
+        collector.apply(Event::close(gone));
+        collector.apply(spawn(kept, "kept"));
+        collector.apply(spawn(follower, "follower"));
+        for followed in [gone, kept, untracked, gone].iter() {
+            collector.apply(Event::follows_from(follower, *followed));
+        }
+
+        let tasks = collector.snapshot();
+        let task = |fields: &str| tasks.iter().find(|task| task.fields == fields).unwrap();
+        assert_eq!(task("follower").follows, [
+            FollowedTask::Span(gone),
+            FollowedTask::Task(task("kept").id),
+            FollowedTask::Span(untracked),
+        ]);
+    }
+
+    #[test]
+    fn recorded_fields_for_unknown_spans_never_make_a_task() {
+        let (_layer, mut collector) = BeeLayer::pair();
+        let recorded = FieldUpdates {
+            fields: Some("peer=\"10.0.0.1:443\"".into()),
+            ..Default::default()
+        };
+
+        // Only held on to in case its spawn is late.
+        collector.apply(Event::record(EventId::external(1), recorded.clone()));
+        assert!(collector.snapshot()[0].provisional);
+        collector.flush_summary();
+        collector.flush_summary();
+        assert!(collector.snapshot().is_empty());
+
+        collector.apply(spawn(EventId::external(2), "peer=none"));
+        collector.apply(Event::record(EventId::external(2), recorded));
+        assert_eq!(collector.snapshot()[0].fields, "peer=\"10.0.0.1:443\"");
+    }
+
+    #[test]
+    fn orphaned_tasks_are_shown_at_the_top_level() {
+        let (_layer, mut collector) = BeeLayer::builder().transient_grace(Duration::from_secs(60)).build();
+        let [closed, kept, untracked] = [1, 2, 3].map(EventId::external);
+        let spawn_in = |id, fields: &str, parent| Event::spawn(EventId::external(id), fields.into(), Default::default(), None, Some(parent));
+
+        collector.apply(spawn(closed, "parent=closed"));
+        collector.apply(spawn(kept, "parent=kept"));
+        collector.apply(spawn_in(4, "child=of_closed", closed));
+        collector.apply(spawn_in(5, "child=of_untracked", untracked));
+        collector.apply(spawn_in(6, "child=of_kept", kept));
+        collector.apply(Event::close(closed));
+
+        let summary = collector.summary();
+        assert_eq!(summary.tasks.len(), 4);
+        let shown = summary.to_string();
+        let indent = |fields: &str| {
+            let line = shown.lines().find(|line| line.contains(fields)).unwrap();
+            line.len() - line.trim_start().len()
+        };
+        assert_eq!(indent("child=of_closed"), 0);
+        assert_eq!(indent("child=of_untracked"), 0);
+        assert_eq!(indent("parent=kept"), 0);
+        assert_eq!(indent("child=of_kept"), 2);
+    }
+
+    #[test]
+    fn provisional_tasks_expire_without_their_spawn() {
+        let (_layer, mut collector) = BeeLayer::pair();
+        let id = EventId::external(1);
+
+        collector.apply(Event::enter(id));
+        collector.flush_summary();
+        assert_eq!(collector.snapshot().len(), 1);
+
+        collector.flush_summary();
+        assert!(collector.snapshot().is_empty());
+
+        collector.apply(Event::exit(id));
+        assert!(collector.snapshot().is_empty());
+    }
+
+    #[test]
+    fn budgets_flag_tasks_overdue_while_running_or_at_completion() {
+        let (_layer, mut collector) = BeeLayer::pair();
+        let now = Instant::now();
+        let budget = || TaskAttributes {
+            budget: Budget {
+                deadline: Some(Duration::from_secs(1)),
+                polls: None,
+            },
+            ..Default::default()
+        };
+        let (on_time, running, late) = (EventId::external(1), EventId::external(2), EventId::external(3));
+
+        collector.apply(at(spawn_with(on_time, budget()), now - Duration::from_millis(500)));
+        collector.apply(at(Event::close(on_time), now));
+        assert!(!task(&collector, 1).over_budget);
+
+        collector.apply(at(spawn_with(running, budget()), now - Duration::from_secs(2)));
+        collector.check_deadlines();
+        let overdue = task(&collector, 2);
+        assert!(overdue.over_budget);
+        assert!(overdue.stats.is_active());
+        // Already counted while running.
+        collector.apply(Event::close(running));
+
+        collector.apply(at(spawn_with(late, budget()), now - Duration::from_secs(3)));
+        collector.apply(at(Event::close(late), now - Duration::from_secs(1)));
+        assert!(task(&collector, 3).over_budget);
+
+        assert_eq!(collector.summary().budget_violations, 2);
+    }
+
+    #[test]
+    fn wake_field_measures_the_wake_to_poll_latency() {
+        let (_layer, mut collector) = BeeLayer::pair();
+        let id = EventId::external(1);
+        let woken = Instant::now();
+
+        collector.apply(at(spawn(id, ""), woken));
+        let wake = FieldUpdates {
+            woken_at: Some(clock::wall_time(woken)),
+            ..Default::default()
+        };
+        collector.apply(at(Event::record(id, wake), woken));
+        collector.apply(at(Event::enter(id), woken + Duration::from_millis(20)));
+        collector.apply(at(Event::exit(id), woken + Duration::from_millis(21)));
+
+        let latency = collector.summary().wake_to_poll.unwrap();
+        assert_eq!(latency.samples, 1);
+        // Histograms keep about three significant digits.
+        assert!(latency.max >= Duration::from_millis(20) && latency.max < Duration::from_millis(21));
+    }
+
+    #[test]
+    fn phases_add_up_to_the_task_lifetime() {
+        let (_layer, mut collector) = BeeLayer::pair();
+        let id = EventId::external(1);
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+
+        collector.apply(at(spawn(id, ""), start));
+        collector.apply(at(Event::enter(id), ms(10)));
+        collector.apply(at(Event::exit(id), ms(15)));
+        collector.apply(at(Event::enter(id), ms(25)));
+        collector.apply(at(Event::exit(id), ms(27)));
+        collector.apply(at(Event::close(id), ms(40)));
+
+        let task = task(&collector, 1);
+        let phases = task.stats.phases().unwrap();
+        assert_eq!(phases.scheduling, Duration::from_millis(10));
+        assert_eq!(phases.busy, Duration::from_millis(7));
+        assert_eq!(phases.idle, Duration::from_millis(10));
+        assert_eq!(phases.cleanup, Duration::from_millis(13));
+        assert_eq!(phases.scheduling + phases.busy + phases.idle + phases.cleanup, task.total_time().unwrap());
+    }
+
+    #[test]
+    fn weighted_busy_time_scales_each_task_by_its_weight() {
+        let (_layer, mut collector) = BeeLayer::pair();
+        let start = Instant::now();
+
+        for (n, weight, busy) in [(1, Some(2.0), 10), (2, Some(0.5), 40), (3, None, 100)] {
+            let id = EventId::external(n);
+            let attributes = TaskAttributes {
+                weight,
+                ..Default::default()
+            };
+            collector.apply(at(spawn_with(id, attributes), start));
+            collector.apply(at(Event::enter(id), start));
+            collector.apply(at(Event::exit(id), start + Duration::from_millis(busy)));
+        }
+
+        // 2 * 10ms + 0.5 * 40ms, leaving out the unweighted task.
+        assert_eq!(collector.summary().weighted_busy_time, Some(Duration::from_millis(40)));
+    }
+
+    #[test]
+    fn second_close_is_only_counted() {
+        let (_layer, mut collector) = BeeLayer::pair();
+        let mut closes = collector.handle().close_events();
+        let id = EventId::external(1);
+        let start = Instant::now();
+
+        collector.apply(at(spawn(id, ""), start));
+        collector.apply(at(Event::close(id), start + Duration::from_millis(10)));
+        collector.apply(at(Event::close(id), start + Duration::from_millis(20)));
+
+        assert_eq!(closes.try_recv().unwrap().id, 1);
+        assert!(closes.try_recv().is_err());
+        let summary = collector.summary();
+        assert_eq!(summary.duplicate_closes, 1);
+        assert_eq!(summary.tasks[0].total_time(), Some(Duration::from_millis(10)));
+    }
+
+    #[test]
+    fn snapshots_of_unchanged_tasks_keep_their_order() {
+        let (_layer, mut collector) = BeeLayer::pair();
+        let start = Instant::now();
+
+        for n in 1..=50 {
+            let id = EventId::external(n);
+            collector.apply(at(spawn(id, ""), start));
+            collector.apply(at(Event::enter(id), start));
+            collector.apply(at(Event::exit(id), start + Duration::from_millis(n % 3)));
+        }
+
+        let ids = |collector: &Collector| collector.snapshot().iter().map(|task| task.id).collect::<Vec<_>>();
+        let first = ids(&collector);
+        assert_eq!(first, (1..=50).collect::<Vec<_>>());
+        assert_eq!(ids(&collector), first);
+
+        // Ties in busy time fall back on the id.
+        collector.order = SnapshotOrder::BusyTime;
+        let busiest = ids(&collector);
+        assert_eq!(busiest[..3], [2, 5, 8]);
+        assert_eq!(ids(&collector), busiest);
+    }
+
+    #[test]
+    fn busy_time_is_split_by_thread() {
+        let (_layer, mut collector) = BeeLayer::pair();
+        let (a, b) = (named_thread("worker-a"), named_thread("worker-b"));
+        let (first, second) = (EventId::external(1), EventId::external(2));
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+
+        collector.apply(at(spawn(first, ""), start));
+        collector.apply(at(spawn(second, ""), start));
+        collector.apply(on(at(Event::enter(first), ms(0)), &a));
+        collector.apply(on(at(Event::exit(first), ms(10)), &a));
+        collector.apply(on(at(Event::enter(second), ms(0)), &b));
+        collector.apply(on(at(Event::exit(second), ms(30)), &b));
+        collector.apply(on(at(Event::enter(first), ms(30)), &b));
+        collector.apply(on(at(Event::exit(first), ms(35)), &b));
+
+        let threads: Vec<_> = collector.summary().threads.into_iter().map(|thread| (thread.label(), thread.busy_time)).collect();
+        assert_eq!(threads, [
+            ("worker-b".to_string(), Duration::from_millis(35)),
+            ("worker-a".to_string(), Duration::from_millis(10)),
+        ]);
+        assert_eq!(task(&collector, 1).stats.migrations(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn flushes_are_numbered_and_timestamped() {
+        let flushes = Arc::new(Mutex::new(Vec::new()));
+        let seen = flushes.clone();
+        let (_layer, collector) = BeeLayer::builder()
+            .flush_interval(Duration::from_millis(100))
+            .summary_sink(move |summary: &Summary| seen.lock().unwrap().push((summary.seq, summary.flushed_at, summary.ticks_missed)))
+            .build();
+        let handle = collector.handle();
+        let collector = tokio::spawn(collector.run());
+
+        tokio::time::sleep(Duration::from_millis(1050)).await;
+        handle.shutdown().await.unwrap();
+        collector.await.unwrap();
+
+        let flushes = flushes.lock().unwrap();
+        assert!(flushes.len() >= 10);
+        assert!(flushes.windows(2).all(|pair| pair[1].0 == pair[0].0 + 1));
+        assert!(flushes.iter().all(|&(_, flushed_at, missed)| flushed_at.is_some() && missed == 0));
+    }
+
+    #[test]
+    fn skipped_ticks_are_counted() {
+        let (_layer, collector) = BeeLayer::builder().flush_interval(Duration::from_millis(100)).build();
+        assert_eq!(collector.missed_ticks(Duration::from_millis(100)), 0);
+        assert_eq!(collector.missed_ticks(Duration::from_millis(120)), 0);
+        assert_eq!(collector.missed_ticks(Duration::from_millis(300)), 2);
+        assert_eq!(collector.missed_ticks(Duration::from_millis(290)), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn aligned_ticks_fall_on_wall_clock_boundaries() {
+        let period = Duration::from_secs(1);
+        let (_layer, collector) = BeeLayer::builder().flush_interval(period).align_ticks().build();
+
+        let (start, wall) = (time::Instant::now(), SystemTime::now());
+        let mut interval = collector.flush_interval(start);
+        for _ in 0..3 {
+            let tick = interval.tick().await;
+            let at = (wall + (tick - start)).duration_since(UNIX_EPOCH).unwrap();
+            // Wall time moves on while the tokio clock is paused, if only by
+            // a little.
+            let off = at.as_nanos() % period.as_nanos();
+            assert!(!(50_000_000..=950_000_000).contains(&off), "{:?} off a boundary", off);
+        }
+
+        let (_layer, collector) = BeeLayer::builder().flush_interval(period).build();
+        assert_eq!(collector.flush_interval(start).tick().await, start);
+    }
+
+    #[test]
+    fn first_poll_delays_are_aggregated_across_tasks() {
+        let (_layer, mut collector) = BeeLayer::pair();
+        let start = Instant::now();
+
+        for (id, delay_ms) in [(1, 1), (2, 2), (3, 3), (4, 4), (5, 100)] {
+            let id = EventId::external(id);
+            let first_poll = start + Duration::from_millis(delay_ms);
+            collector.apply(at(spawn(id, ""), start));
+            collector.apply(at(Event::enter(id), first_poll));
+            collector.apply(at(Event::exit(id), first_poll));
+            // Only the first poll counts.
+            collector.apply(at(Event::enter(id), first_poll + Duration::from_millis(500)));
+            collector.apply(at(Event::exit(id), first_poll + Duration::from_millis(500)));
+        }
+
+        let delay = collector.summary().first_poll_delay.unwrap();
+        assert_eq!(delay.samples, 5);
+        assert!((Duration::from_micros(2990)..=Duration::from_micros(3010)).contains(&delay.p50));
+        assert!((Duration::from_micros(99_900)..=Duration::from_micros(100_100)).contains(&delay.p99));
+        assert_eq!(delay.max, delay.p99);
+    }
+
+    #[test]
+    fn active_count_is_sampled_at_each_flush() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let (layer, mut collector) = BeeLayer::pair();
+        let _default = tracing::subscriber::set_default(tracing_subscriber::registry().with(layer));
+
+        let mut spans = vec![tracing::info_span!("task")];
+        collector.flush_summary();
+        spans.extend((0..2).map(|_| tracing::info_span!("task")));
+        collector.flush_summary();
+        spans.clear();
+        collector.flush_summary();
+        spans.extend((0..6).map(|_| tracing::info_span!("task")));
+        let summary = collector.flush_summary();
+
+        let concurrency = &summary.active_concurrency;
+        assert_eq!((concurrency.count, concurrency.sum), (4, 10));
+        assert_eq!(concurrency.buckets[..5], [(0, 1), (1, 2), (2, 2), (5, 3), (10, 4)]);
+
+        let prometheus = ExportConfig::default().to_prometheus(&summary);
+        assert!(prometheus.contains("task_active_concurrency_bucket{le=\"5\"} 3\n"));
+        assert!(prometheus.contains("task_active_concurrency_bucket{le=\"+Inf\"} 4\n"));
+        assert!(prometheus.contains("task_active_concurrency_sum 10\n"));
+        assert!(prometheus.contains("task_active_concurrency_current 6\n"));
+    }
+
+    #[test]
+    fn transient_tasks_are_only_counted() {
+        let (_layer, mut collector) = BeeLayer::builder().transient_grace(Duration::from_millis(10)).build();
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+
+        for (id, lifetime_ms) in [(1, 1), (2, 9), (3, 50), (4, 2)] {
+            let id = EventId::external(id);
+            collector.apply(at(spawn(id, ""), start));
+            collector.apply(at(Event::enter(id), start));
+            collector.apply(at(Event::exit(id), ms(1)));
+            collector.apply(at(Event::close(id), ms(lifetime_ms)));
+        }
+        // Still running, however young.
+        collector.apply(at(spawn(EventId::external(5), ""), start));
+
+        let summary = collector.summary();
+        let mut kept: Vec<_> = summary.tasks.iter().map(|task| task.id).collect();
+        kept.sort_unstable();
+        assert_eq!(kept, [3, 5]);
+        assert_eq!(summary.transient_tasks, 3);
+        // Their busy time still counts towards the running total.
+        assert_eq!(summary.kinds[0].busy_time_total, Duration::from_millis(4));
+        assert_eq!(summary.kinds[0].busy_time, Duration::from_millis(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn panicking_sink_is_counted_and_quarantined() {
+        let flushes = Arc::new(Mutex::new(Vec::new()));
+        let seen = flushes.clone();
+        let running = Running::start(BeeLayer::builder()
+            .flush_interval(Duration::from_secs(1))
+            .summary_sink(|_: &Summary| panic!("sink failed"))
+            .summary_sink(move |summary: &Summary| {
+                seen.lock().unwrap().push((summary.tasks.len(), summary.panics, summary.quarantined_sinks))
+            })
+            .build());
+
+        for _ in 0..5 {
+            tracing::info_span!("task").in_scope(|| {});
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+        assert_eq!(running.finish().await.len(), 5);
+
+        let flushes = flushes.lock().unwrap();
+        // Each flush counts the panics of the ones before it.
+        assert_eq!(flushes[..4], [(1, 0, 0), (2, 1, 0), (3, 2, 0), (4, 3, 1)]);
+        assert!(flushes[4..].iter().all(|&flush| flush == (5, 3, 1)));
+    }
+
+    #[test]
+    fn reducer_counts_enters_after_a_cutoff() {
+        use crate::{reducer::TaskReducer, Stats};
+
+        struct PollsAfter(Instant);
+
+        impl TaskReducer for PollsAfter {
+            type Value = u64;
+
+            fn reduce(&mut self, polls: &mut u64, event: &Event, _stats: &Stats) {
+                if let Event::Enter { time, .. } = event {
+                    *polls += (*time >= self.0) as u64;
+                }
+            }
+        }
+
+        let start = Instant::now();
+        let cutoff = start + Duration::from_secs(10);
+        let (_layer, mut collector) = BeeLayer::builder().task_reducer(PollsAfter(cutoff)).build();
+        let (first, second) = (EventId::external(1), EventId::external(2));
+
+        collector.apply(at(spawn(first, ""), start));
+        collector.apply(at(spawn(second, ""), start));
+        for secs in [1, 5, 10, 12, 20] {
+            let time = start + Duration::from_secs(secs);
+            collector.apply(at(Event::enter(first), time));
+            collector.apply(at(Event::exit(first), time));
+        }
+        collector.apply(at(Event::enter(second), start));
+        collector.apply(at(Event::exit(second), start));
+
+        let first = task(&collector, 1);
+        assert_eq!(first.reduced::<u64>(), Some(&3));
+        assert_eq!(first.stats.polls(), 5);
+        assert_eq!(task(&collector, 2).reduced::<u64>(), Some(&0));
+    }
+
+    #[test]
+    fn overlapping_polls_count_once_towards_busy_time() {
+        let (_layer, mut collector) = BeeLayer::pair();
+        let (a, b) = (named_thread("poller-a"), named_thread("poller-b"));
+        let id = EventId::external(1);
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+
+        collector.apply(at(spawn(id, ""), start));
+        collector.apply(on(at(Event::enter(id), ms(0)), &a));
+        collector.apply(on(at(Event::enter(id), ms(10)), &b));
+        collector.apply(on(at(Event::exit(id), ms(30)), &a));
+        collector.apply(on(at(Event::exit(id), ms(50)), &b));
+        // Nested on one thread.
+        collector.apply(on(at(Event::enter(id), ms(60)), &a));
+        collector.apply(on(at(Event::enter(id), ms(65)), &a));
+        collector.apply(on(at(Event::exit(id), ms(70)), &a));
+        collector.apply(on(at(Event::exit(id), ms(80)), &a));
+        collector.apply(at(Event::close(id), ms(100)));
+
+        let stats = task(&collector, 1).stats;
+        assert_eq!(stats.busy_time(), Duration::from_millis(70));
+        assert_eq!(stats.poll_time(), Duration::from_millis(90));
+        assert!(stats.busy_time() <= stats.total_time().unwrap());
+    }
+
+    #[test]
+    fn busy_seconds_counter_only_increases() {
+        let (_layer, mut collector) = BeeLayer::builder().transient_grace(Duration::from_millis(50)).build();
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+        let poll = |collector: &mut Collector, id, from, to| {
+            collector.apply(at(Event::enter(EventId::external(id)), ms(from)));
+            collector.apply(at(Event::exit(EventId::external(id)), ms(to)));
+        };
+        let scrape = |collector: &Collector| -> f64 {
+            let prometheus = ExportConfig::default().to_prometheus(&collector.summary());
+            let line = prometheus.lines().find(|line| line.starts_with("task_busy_seconds_total{")).unwrap();
+            line.rsplit(' ').next().unwrap().parse().unwrap()
+        };
+
+        collector.apply(at(spawn(EventId::external(1), ""), start));
+        poll(&mut collector, 1, 0, 10);
+        let mut scrapes = vec![scrape(&collector)];
+
+        // Dropped as transient, with its busy time carried over.
+        collector.apply(at(spawn(EventId::external(2), ""), ms(100)));
+        poll(&mut collector, 2, 100, 105);
+        collector.apply(at(Event::close(EventId::external(2)), ms(110)));
+        scrapes.push(scrape(&collector));
+
+        // Restarting forgets every task, but not their busy time.
+        collector.restart();
+        scrapes.push(scrape(&collector));
+
+        collector.apply(at(spawn(EventId::external(3), ""), ms(200)));
+        poll(&mut collector, 3, 200, 201);
+        scrapes.push(scrape(&collector));
+
+        assert_eq!(collector.snapshot().len(), 1);
+        assert_eq!(scrapes, [0.010, 0.015, 0.015, 0.016]);
+    }
+
+    #[test]
+    fn last_event_at_follows_the_latest_event() {
+        let (_layer, mut collector) = BeeLayer::pair();
+        let id = EventId::external(1);
+        let start = Instant::now();
+        assert_eq!(collector.summary().last_event_at, None);
+
+        collector.apply(at(spawn(id, ""), start));
+        let first = collector.summary().last_event_at.unwrap();
+        // Nothing arriving leaves it where it was.
+        collector.flush_summary();
+        assert_eq!(collector.summary().last_event_at, Some(first));
+
+        collector.apply(at(Event::enter(id), start + Duration::from_secs(5)));
+        let second = collector.summary().last_event_at.unwrap();
+        let advanced = second.duration_since(first).unwrap();
+        assert!((Duration::from_micros(4_999_999)..=Duration::from_micros(5_000_001)).contains(&advanced));
+    }
+
+    #[test]
+    fn one_in_n_polls_is_sampled() {
+        let durations = |every| {
+            let (_layer, mut collector) = BeeLayer::builder().poll_sample_every(every).build();
+            let id = EventId::external(1);
+            let start = Instant::now();
+            collector.apply(at(spawn(id, ""), start));
+            // Polls of 1µs to 1ms, in turn.
+            for micros in 1..=1000 {
+                let time = start + Duration::from_millis(micros);
+                collector.apply(at(Event::enter(id), time));
+                collector.apply(at(Event::exit(id), time + Duration::from_micros(micros)));
+            }
+            collector.summary().poll_durations.unwrap()
+        };
+
+        let all = durations(1);
+        let sampled = durations(10);
+        assert_eq!(all.samples, 1000);
+        assert_eq!(sampled.samples, 100);
+        for (sampled, all) in [(sampled.p50, all.p50), (sampled.p99, all.p99)] {
+            let error = sampled.as_secs_f64() / all.as_secs_f64() - 1.0;
+            assert!(error.abs() < 0.05, "sampled {:?}, all {:?}", sampled, all);
+        }
+    }
+
+    /// A fixed run of three tasks, the first spawning the other two.
+    fn script(collector: &mut Collector, start: Instant) {
+        let worker = named_thread("worker");
+        let ms = |ms| start + Duration::from_millis(ms);
+
+        for id in 1..=3 {
+            let event_id = EventId::external(id);
+            let parent = (id != 1).then(|| EventId::external(1));
+            let spawned = at(Event::spawn(event_id, format!("job={}", id), Default::default(), None, parent), ms(id));
+            collector.apply(spawned);
+        }
+        for id in 1..=3 {
+            let event_id = EventId::external(id);
+            for poll in 0..id {
+                let from = 10 * id + 3 * poll;
+                collector.apply(on(at(Event::enter(event_id), ms(from)), &worker));
+                collector.apply(on(at(Event::exit(event_id), ms(from + poll + 1)), &worker));
+            }
+            if id == 2 {
+                collector.apply(at(Event::log(event_id, tracing::Level::WARN, Some("slow".into())), ms(25)));
+            }
+            collector.apply(at(Event::close(event_id), ms(40 + id)));
+        }
+    }
+
+    /// Makes epoch timestamps relative to `start`, so that they are the same
+    /// on every run.
+    fn relative_timestamps(json: &mut serde_json::Value, start_ms: u64) {
+        match json {
+            serde_json::Value::Number(n) if n.as_u64().is_some_and(|n| n >= start_ms) => {
+                *json = (n.as_u64().unwrap() - start_ms).into();
+            }
+            serde_json::Value::Array(values) => values.iter_mut().for_each(|value| relative_timestamps(value, start_ms)),
+            serde_json::Value::Object(map) => map.values_mut().for_each(|value| relative_timestamps(value, start_ms)),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn canonical_json_is_byte_stable() {
+        let start = Instant::now();
+        let run = || {
+            let (_layer, mut collector) = BeeLayer::pair();
+            script(&mut collector, start);
+            collector.summary().canonical_json()
+        };
+
+        // Each collector's maps are seeded differently, so their iteration
+        // order differs between the two runs.
+        assert_eq!(run(), run());
+    }
+
+    #[test]
+    fn canonical_json_matches_the_golden_file() {
+        let (_layer, mut collector) = BeeLayer::pair();
+        let start = Instant::now();
+        script(&mut collector, start);
+
+        let start_ms = clock::wall_time(start).duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+        let mut json = serde_json::from_str(&collector.summary().canonical_json()).unwrap();
+        relative_timestamps(&mut json, start_ms);
+        let json = serde_json::to_string_pretty(&json).unwrap();
+
+        assert_eq!(json.trim(), include_str!("testdata/canonical_summary.json").trim());
+    }
+
+    #[test]
+    fn presized_collector_tracks_the_same_tasks() {
+        let start = Instant::now();
+        let run = |collector: Collector| {
+            let mut collector = collector;
+            let capacity = collector.tasks.capacity();
+            for n in 0..5_000 {
+                let id = EventId::external(n);
+                collector.apply(at(spawn(id, &format!("n={}", n)), start));
+                collector.apply(at(Event::enter(id), start + Duration::from_millis(n % 7)));
+                collector.apply(at(Event::exit(id), start + Duration::from_millis(n % 7 + n % 3)));
+                if n % 2 == 0 {
+                    collector.apply(at(Event::close(id), start + Duration::from_millis(10)));
+                }
+            }
+            let tasks: Vec<_> = collector
+                .snapshot()
+                .into_iter()
+                .map(|task| (task.id, task.fields, task.stats.polls(), task.stats.busy_time(), task.stats.is_active()))
+                .collect();
+            (capacity, tasks)
+        };
+
+        let (_layer, collector) = BeeLayer::pair();
+        let (unsized_capacity, grown) = run(collector);
+        let (_layer, collector) = BeeLayer::pair();
+        let (presized_capacity, presized) = run(collector.with_expected_tasks(10_000));
+
+        assert!(unsized_capacity < 10_000);
+        assert!(presized_capacity >= 10_000);
+        assert_eq!(presized.len(), 5_000);
+        assert_eq!(presized.iter().filter(|task| task.4).count(), 2_500);
+        assert_eq!(presized, grown);
+    }
+
+    #[test]
+    fn census_buckets_tasks_by_age_and_idle_time() {
+        let ago = |ms| Instant::now().checked_sub(Duration::from_millis(ms)).unwrap();
+        let script = |collector: &mut Collector| {
+            // Spawned at, polled until, if ever.
+            for (n, &(spawned, polled)) in [(0, None), (50, Some(40)), (2_000, Some(0)), (700_000, Some(30_000))].iter().enumerate() {
+                let id = EventId::external(n as u64);
+                collector.apply(at(spawn(id, ""), ago(spawned)));
+                if let Some(polled) = polled {
+                    collector.apply(at(Event::enter(id), ago(polled + 1)));
+                    collector.apply(at(Event::exit(id), ago(polled)));
+                }
+            }
+            // Old, but closed.
+            let closed = EventId::external(9);
+            collector.apply(at(spawn(closed, ""), ago(900_000)));
+            collector.apply(at(Event::close(closed), ago(800_000)));
+        };
+
+        let (_layer, mut collector) = BeeLayer::pair();
+        script(&mut collector);
+        let census = collector.summary().census;
+        assert_eq!(census.by_age, [1, 1, 0, 1, 0, 0, 1]);
+        assert_eq!(census.by_idle, [2, 1, 0, 0, 1, 0, 0]);
+        assert_eq!(
+            census.to_string(),
+            "Active tasks by age: <10ms 1, <100ms 1, <1s 0, <10s 1, <1m 0, <10m 0, >=10m 1; \
+             by idle time: <10ms 2, <100ms 1, <1s 0, <10s 0, <1m 1, <10m 0, >=10m 0",
+        );
+
+        let (_layer, collector) = BeeLayer::pair();
+        let mut collector = collector.with_census_bounds(vec![Duration::from_secs(1), Duration::from_secs(60)]);
+        script(&mut collector);
+        let census = collector.summary().census;
+        assert_eq!(census.labels(), ["<1s", "<1m", ">=1m"]);
+        assert_eq!(census.by_age, [2, 1, 1]);
+        assert_eq!(census.by_idle, [3, 1, 0]);
+    }
+
+    #[test]
+    fn nested_polls_fold_into_collapsed_stacks() {
+        let (_layer, collector) = BeeLayer::pair();
+        let mut collector = collector.with_stacks();
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+        let worker = named_thread("worker");
+
+        let tasks = [(1, "root", None), (2, "child", Some(1)), (3, "leaf", Some(2)), (4, "other;task", Some(1))];
+        for &(id, name, parent) in tasks.iter() {
+            let attributes = TaskAttributes {
+                name: Some(name.into()),
+                ..Default::default()
+            };
+            let spawned = Event::spawn(EventId::external(id), String::new(), attributes, None, parent.map(EventId::external));
+            collector.apply(at(spawned, start));
+        }
+
+        // root polls child, which polls leaf; then root polls the other task.
+        for &(id, entered) in [(1, 0), (2, 10), (3, 15)].iter() {
+            collector.apply(on(at(Event::enter(EventId::external(id)), ms(entered)), &worker));
+        }
+        for &(id, exited) in [(3, 25), (2, 40), (1, 50)].iter() {
+            collector.apply(on(at(Event::exit(EventId::external(id)), ms(exited)), &worker));
+        }
+        collector.apply(on(at(Event::enter(EventId::external(4)), ms(60)), &worker));
+        collector.apply(on(at(Event::exit(EventId::external(4)), ms(65)), &worker));
+
+        assert_eq!(
+            collector.summary().stacks.to_string(),
+            "root 20000000\nroot;child 20000000\nroot;child;leaf 10000000\nroot;other:task 5000000\n",
+        );
+    }
+
+    #[test]
+    fn counting_only_matches_the_full_aggregates() {
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+        let (a, b) = (named_thread("worker-a"), named_thread("worker-b"));
+        let mut events = Vec::new();
+        for n in 0..200 {
+            let id = EventId::external(n);
+            let thread = if n % 2 == 0 { &a } else { &b };
+            events.push(at(spawn(id, "job"), ms(n)));
+            for poll in 0..n % 4 {
+                let entered = n + 10 * poll;
+                events.push(on(at(Event::enter(id), ms(entered)), thread));
+                // Re-entered while already being polled.
+                if n % 5 == 0 {
+                    events.push(on(at(Event::enter(id), ms(entered + 1)), thread));
+                    events.push(on(at(Event::exit(id), ms(entered + 2)), thread));
+                }
+                events.push(on(at(Event::exit(id), ms(entered + 3 + n % 3)), thread));
+            }
+            if n % 7 != 0 {
+                events.push(at(Event::close(id), ms(n + 50)));
+            }
+        }
+        // Polled on both threads at once, counting once towards busy time.
+        let shared = EventId::external(1000);
+        events.push(at(spawn(shared, "shared"), ms(0)));
+        events.push(on(at(Event::enter(shared), ms(10)), &a));
+        events.push(on(at(Event::enter(shared), ms(15)), &b));
+        events.push(on(at(Event::exit(shared), ms(20)), &a));
+        events.push(on(at(Event::exit(shared), ms(30)), &b));
+
+        let (_layer, mut full) = BeeLayer::pair();
+        let (_layer, counting) = BeeLayer::pair();
+        let mut counting = counting.with_counts_only();
+        for event in events {
+            full.apply(event.clone());
+            counting.apply(event);
+        }
+
+        let full = full.summary();
+        let counted = counting.summary();
+        assert_eq!(counted.counts, Some(AggregateStats {
+            tasks: full.tasks.len(),
+            active: full.tasks.iter().filter(|task| task.stats.is_active()).count(),
+            polls: full.tasks.iter().map(|task| task.stats.polls()).sum(),
+            busy_time: full.tasks.iter().map(|task| task.stats.busy_time()).sum(),
+        }));
+        assert_eq!(counted.poll_durations, full.poll_durations);
+        let busy = |summary: &Summary| summary.threads.iter().map(|thread| (thread.label(), thread.busy_time)).collect::<Vec<_>>();
+        assert_eq!(busy(&counted), busy(&full));
+        assert!(counted.tasks.is_empty());
+        assert!(counting.tasks.is_empty() && counting.ids.is_empty());
+    }
+
+    /// Keeps the message of every error the crate logs about itself.
+    struct InternalErrors(Arc<Mutex<Vec<String>>>);
+
+    impl<S: tracing::Subscriber> Layer<S> for InternalErrors {
+        fn on_event(&self, event: &tracing::Event<'_>, _ctx: Context<'_, S>) {
+            struct Message(String);
+
+            impl tracing::field::Visit for Message {
+                fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn fmt::Debug) {
+                    if field.name() == "message" {
+                        self.0 = format!("{:?}", value);
+                    }
+                }
+            }
+
+            let metadata = event.metadata();
+            if metadata.target() == INTERNAL_TARGET && *metadata.level() == tracing::Level::ERROR {
+                let mut message = Message(String::new());
+                event.record(&mut message);
+                self.0.lock().unwrap().push(message.0);
+            }
+        }
+    }
+
+    #[test]
+    fn strict_mode_reports_events_out_of_causal_order() {
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+        let (a, b) = (named_thread("worker-a"), named_thread("worker-b"));
+        let (one, two) = (EventId::external(1), EventId::external(2));
+        let events = vec![
+            on(at(Event::enter(one), ms(0)), &a),
+            at(spawn(one, ""), ms(1)),
+            on(at(Event::enter(one), ms(2)), &a),
+            on(at(Event::exit(one), ms(3)), &b),
+            on(at(Event::exit(one), ms(4)), &a),
+            at(Event::close(one), ms(5)),
+            at(Event::log(one, tracing::Level::INFO, None), ms(6)),
+            // In order.
+            at(spawn(two, ""), ms(0)),
+            on(at(Event::enter(two), ms(1)), &a),
+            on(at(Event::exit(two), ms(2)), &a),
+            at(Event::close(two), ms(3)),
+        ];
+
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let subscriber = tracing_subscriber::registry().with(InternalErrors(errors.clone()));
+        let (_layer, strict) = BeeLayer::pair();
+        let mut strict = strict.with_strict();
+        let (_layer, mut lenient) = BeeLayer::pair();
+        tracing::subscriber::with_default(subscriber, || {
+            for event in events {
+                strict.apply(event.clone());
+                lenient.apply(event);
+            }
+        });
+
+        assert_eq!(strict.summary().order_violations, 3);
+        assert_eq!(lenient.summary().order_violations, 0);
+        assert_eq!(*errors.lock().unwrap(), [
+            "Enter for task 1 arrived before its spawn",
+            "Exit for task 1 ended a poll not entered on that thread",
+            "Log for task 1 arrived after its close",
+        ]);
+    }
+
+    #[test]
+    fn poll_rate_converges_to_a_steady_rate() {
+        let (_layer, mut collector) = BeeLayer::pair();
+        let start = Instant::now();
+        collector.started_at = start;
+        let secs = |secs: f64| start + Duration::from_secs_f64(secs);
+        let (steady, late) = (EventId::external(1), EventId::external(2));
+        let poll = |collector: &mut Collector, id, at_secs| {
+            collector.apply(at(Event::enter(id), secs(at_secs)));
+            collector.apply(at(Event::exit(id), secs(at_secs)));
+        };
+        let rates = |collector: &Collector| {
+            let snapshot = collector.snapshot();
+            (snapshot[0].poll_rate.unwrap(), snapshot[1].poll_rate.unwrap())
+        };
+
+        collector.apply(at(spawn(steady, ""), start));
+        // Slow to start with; then 20 polls a second.
+        for n in 0..5 {
+            poll(&mut collector, steady, n as f64 * 0.2);
+        }
+        // Alive for the second half of the interval only, at 20 a second.
+        collector.apply(at(spawn(late, ""), secs(0.5)));
+        for n in 0..10 {
+            poll(&mut collector, late, 0.5 + n as f64 * 0.05);
+        }
+        collector.update_poll_rates(secs(1.0));
+        let (first, late_rate) = rates(&collector);
+        assert!((first - 5.0).abs() < 1e-9, "{}", first);
+        assert!((late_rate - 20.0).abs() < 1e-9, "{}", late_rate);
+
+        let mut previous = first;
+        for flush in 1..20 {
+            for n in 0..20 {
+                poll(&mut collector, steady, flush as f64 + n as f64 * 0.05);
+                poll(&mut collector, late, flush as f64 + n as f64 * 0.05);
+            }
+            collector.update_poll_rates(secs(flush as f64 + 1.0));
+
+            let (rate, late_rate) = rates(&collector);
+            assert!(rate > previous && rate <= 20.0, "{} after {}", rate, previous);
+            assert!((late_rate - 20.0).abs() < 1e-9);
+            previous = rate;
+        }
+        assert!((previous - 20.0).abs() < 0.1, "{}", previous);
+
+        collector.apply(at(Event::close(late), secs(20.0)));
+        assert_eq!(collector.snapshot()[1].poll_rate, None);
+    }
+
+    #[test]
+    fn scheduled_time_runs_from_the_first_wake_to_the_next_poll() {
+        let (_layer, mut collector) = BeeLayer::pair();
+        let id = EventId::external(1);
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+
+        collector.apply(at(spawn(id, ""), start));
+        collector.apply(at(Event::waker(id, WakerOp::Wake), ms(10)));
+        collector.apply(at(Event::waker(id, WakerOp::WakeByRef), ms(12)));
+        collector.apply(at(Event::enter(id), ms(30)));
+        collector.apply(at(Event::exit(id), ms(31)));
+        collector.apply(at(Event::waker(id, WakerOp::Wake), ms(40)));
+        collector.apply(at(Event::enter(id), ms(45)));
+        collector.apply(at(Event::exit(id), ms(46)));
+        // Not woken, so not scheduled either.
+        collector.apply(at(Event::enter(id), ms(60)));
+        collector.apply(at(Event::exit(id), ms(61)));
+
+        let stats = task(&collector, 1).stats;
+        assert_eq!(stats.wakes(), 3);
+        assert_eq!(stats.scheduled_time(), Duration::from_millis(25));
+        assert_eq!(stats.mean_scheduled_time(), Some(Duration::from_micros(12_500)));
+    }
+
+    #[test]
+    fn wakes_from_another_thread_mid_poll_are_scheduled_from_its_end() {
+        let (_layer, mut collector) = BeeLayer::pair();
+        let id = EventId::external(1);
+        let elsewhere = named_thread("elsewhere");
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+
+        collector.apply(at(spawn(id, ""), start));
+        collector.apply(at(Event::enter(id), ms(10)));
+        collector.apply(at(on(Event::waker(id, WakerOp::Wake), &elsewhere), ms(12)));
+        collector.apply(at(Event::exit(id), ms(15)));
+        collector.apply(at(Event::enter(id), ms(22)));
+        collector.apply(at(Event::exit(id), ms(23)));
+
+        let stats = task(&collector, 1).stats;
+        assert_eq!((stats.wakes(), stats.self_wakes()), (1, 0));
+        assert_eq!(stats.scheduled_time(), Duration::from_millis(7));
+        assert_eq!(stats.mean_scheduled_time(), Some(Duration::from_millis(7)));
+    }
+
+    #[test]
+    fn nested_entries_on_one_thread_are_no_migration() {
+        let (_layer, mut collector) = BeeLayer::pair();
+        let id = EventId::external(1);
+
+        collector.apply(spawn(id, ""));
+        for _ in 0..3 {
+            collector.apply(Event::enter(id));
+            collector.apply(Event::enter(id));
+            collector.apply(Event::exit(id));
+            collector.apply(Event::exit(id));
+        }
+
+        let stats = task(&collector, 1).stats;
+        assert_eq!((stats.polls(), stats.threads(), stats.migrations()), (3, 1, 0));
+    }
+
+    #[test]
+    fn only_wakes_from_inside_the_tasks_own_poll_are_self_wakes() {
+        let (_layer, mut collector) = BeeLayer::pair();
+        let (busy, other) = (EventId::external(1), EventId::external(2));
+        let elsewhere = named_thread("elsewhere");
+
+        collector.apply(spawn(busy, ""));
+        collector.apply(spawn(other, ""));
+        collector.apply(Event::enter(busy));
+        collector.apply(Event::waker(busy, WakerOp::WakeByRef));
+        collector.apply(Event::waker(other, WakerOp::Wake));
+        // The task being polled, but woken by another thread.
+        collector.apply(on(Event::waker(busy, WakerOp::Wake), &elsewhere));
+        collector.apply(Event::exit(busy));
+        collector.apply(Event::waker(busy, WakerOp::Wake));
+
+        let busy = task(&collector, 1).stats;
+        assert_eq!((busy.self_wakes(), busy.wakes()), (1, 3));
+        assert_eq!(busy.self_wake_ratio(), Some(1.0 / 3.0));
+        let other = task(&collector, 2).stats;
+        assert_eq!((other.self_wakes(), other.wakes()), (0, 1));
+    }
+
+    #[test]
+    fn lost_events_mark_the_tasks_polled_across_the_gap() {
+        let (_layer, mut collector) = BeeLayer::pair();
+        let (polled, idle, late) = (EventId::external(1), EventId::external(2), EventId::external(3));
+
+        collector.apply(spawn(polled, "").with_seq(0));
+        collector.apply(spawn(idle, "").with_seq(1));
+        collector.apply(Event::enter(polled).with_seq(2));
+        // 3 and 4 never arrive.
+        collector.apply(Event::exit(polled).with_seq(5));
+        // 6 arrives after 7, which loses nothing.
+        collector.apply(Event::close(idle).with_seq(7));
+        collector.apply(spawn(late, "").with_seq(6));
+
+        // Still in flight, as far as the collector can tell.
+        let summary = collector.flush_summary();
+        assert_eq!((summary.events_lost, summary.event_gaps), (0, 0));
+        assert!(summary.tasks.iter().all(|task| !task.stats.is_approximate()));
+
+        let summary = collector.flush_summary();
+        assert_eq!((summary.events_lost, summary.event_gaps), (2, 1));
+        assert!(task(&collector, 1).stats.is_approximate());
+        assert!(!task(&collector, 2).stats.is_approximate());
+        assert!(!task(&collector, 3).stats.is_approximate());
+    }
+
+    #[test]
+    fn wall_clock_stepping_back_mid_poll_leaves_the_timings_alone() {
+        let (_layer, mut collector) = BeeLayer::pair();
+        let id = EventId::external(1);
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+
+        collector.apply(at(spawn(id, ""), start));
+        collector.apply(at(Event::enter(id), ms(10)));
+        // NTP steps the wall clock back an hour, mid-poll.
+        clock::step_back(Duration::from_secs(3600));
+        collector.apply(at(Event::exit(id), ms(15)));
+        collector.apply(at(Event::close(id), ms(20)));
+
+        let stats = task(&collector, 1).stats;
+        assert_eq!(stats.busy_time(), Duration::from_millis(5));
+        let (created, closed) = (stats.created_at().unwrap(), stats.closed_at().unwrap());
+        assert_eq!(closed.duration_since(created).unwrap(), Duration::from_millis(20));
+        assert!(stats.first_poll().unwrap() <= stats.last_poll_ended().unwrap());
+    }
+}
@@ -0,0 +1,153 @@
+use crate::{
+    census::AgeCensus,
+    export::{float, ExportConfig, Row},
+    sender::OverflowPolicy,
+};
+
+use serde_json::Value;
+use tracing_core::LevelFilter;
+
+use std::time::Duration;
+
+/// The settings a collector runs with, as they are right now: what it was
+/// built with, plus any changes made through its
+/// [`CollectorHandle`](crate::CollectorHandle) since.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EffectiveConfig {
+    pub instance: Option<String>,
+    /// How often the collector flushes, including changes made with
+    /// [`CollectorHandle::set_tick_interval`](crate::CollectorHandle::set_tick_interval).
+    pub tick_interval: Duration,
+    pub align_ticks: bool,
+    /// How many events the channel to the collector holds, or `None` if it
+    /// is [unbounded](crate::Builder::unbounded_event_channel).
+    pub channel_capacity: Option<usize>,
+    pub overflow_policy: OverflowPolicy,
+    pub send_retries: u32,
+    /// Whether events are dropped rather than waited on room for when the
+    /// channel is full.
+    pub lossy: bool,
+    pub startup_buffer_capacity: usize,
+    pub expected_tasks: Option<usize>,
+    pub summary_sinks: usize,
+    pub event_sinks: usize,
+    pub task_reducers: usize,
+    pub callsite_limits: usize,
+    /// Names of the tasks tracked, or `None` if every task is, including
+    /// changes made with
+    /// [`CollectorHandle::set_name_allowlist`](crate::CollectorHandle::set_name_allowlist).
+    pub name_allowlist: Option<Vec<String>>,
+    /// Prefixes of the targets spans are tracked from, or `None` if every
+    /// target is.
+    pub include_targets: Option<Vec<String>>,
+    /// Whether only tokio's task spans are tracked.
+    pub tasks_only: bool,
+    /// The most verbose level spans are tracked at, or `None` for every level.
+    pub max_level: Option<LevelFilter>,
+    /// Whether only spans created with `bee.track = true` are tracked.
+    pub opt_in: bool,
+    /// Every this many tasks, one is tracked, if only some are.
+    pub sample_one_in: Option<u64>,
+    pub transient_grace: Option<Duration>,
+    pub busy_loop_polls: Option<u64>,
+    pub max_counters: usize,
+    pub roll_up_counters: bool,
+    pub flamegraph: bool,
+    pub aggregates_only: bool,
+    pub strict: bool,
+    pub poll_sample_every: u64,
+    pub census_bounds: Vec<Duration>,
+    pub max_restarts: u32,
+    pub replay_spans: bool,
+    pub coalesce_reentrant: bool,
+    /// The fraction of each flush interval reports may take before they are
+    /// degraded, if budgeted.
+    pub report_budget: Option<f64>,
+    /// Events dropped so far, from the startup buffer or evicted from a
+    /// full queue.
+    pub dropped_events: u64,
+}
+
+impl EffectiveConfig {
+    pub fn to_json(&self, export: &ExportConfig) -> Value {
+        let mut row = Row::new();
+        row.push(("instance".into(), self.instance.clone().map(Value::from).unwrap_or(Value::Null)));
+        export.duration(&mut row, "tick_interval", Some(self.tick_interval));
+        row.push(("align_ticks".into(), self.align_ticks.into()));
+        row.push(("channel_capacity".into(), self.channel_capacity.map(Value::from).unwrap_or(Value::Null)));
+        row.push(("overflow_policy".into(), format!("{:?}", self.overflow_policy).into()));
+        row.push(("send_retries".into(), self.send_retries.into()));
+        row.push(("lossy".into(), self.lossy.into()));
+        row.push(("startup_buffer_capacity".into(), self.startup_buffer_capacity.into()));
+        row.push(("expected_tasks".into(), self.expected_tasks.map(Value::from).unwrap_or(Value::Null)));
+        row.push(("summary_sinks".into(), self.summary_sinks.into()));
+        row.push(("event_sinks".into(), self.event_sinks.into()));
+        row.push(("task_reducers".into(), self.task_reducers.into()));
+        row.push(("callsite_limits".into(), self.callsite_limits.into()));
+        row.push(("name_allowlist".into(), self.name_allowlist.clone().map(Value::from).unwrap_or(Value::Null)));
+        row.push(("include_targets".into(), self.include_targets.clone().map(Value::from).unwrap_or(Value::Null)));
+        row.push(("tasks_only".into(), self.tasks_only.into()));
+        row.push(("max_level".into(), self.max_level.map(|level| Value::from(level.to_string())).unwrap_or(Value::Null)));
+        row.push(("opt_in".into(), self.opt_in.into()));
+        row.push(("sample_one_in".into(), self.sample_one_in.map(Value::from).unwrap_or(Value::Null)));
+        export.duration(&mut row, "transient_grace", self.transient_grace);
+        row.push(("busy_loop_polls".into(), self.busy_loop_polls.map(Value::from).unwrap_or(Value::Null)));
+        row.push(("max_counters".into(), self.max_counters.into()));
+        row.push(("roll_up_counters".into(), self.roll_up_counters.into()));
+        row.push(("flamegraph".into(), self.flamegraph.into()));
+        row.push(("aggregates_only".into(), self.aggregates_only.into()));
+        row.push(("strict".into(), self.strict.into()));
+        row.push(("poll_sample_every".into(), self.poll_sample_every.into()));
+        row.push((
+            "census_buckets".into(),
+            AgeCensus::new(self.census_bounds.clone()).labels().into(),
+        ));
+        row.push(("max_restarts".into(), self.max_restarts.into()));
+        row.push(("replay_spans".into(), self.replay_spans.into()));
+        row.push(("coalesce_reentrant".into(), self.coalesce_reentrant.into()));
+        row.push(("report_budget".into(), self.report_budget.map(float).unwrap_or(Value::Null)));
+        row.push(("dropped_events".into(), self.dropped_events.into()));
+
+        Value::Object(row.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{BeeLayer, Builder};
+
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn config_reflects_changes_made_through_the_handle() {
+        let (_layer, collector) = BeeLayer::builder()
+            .flush_interval(Duration::from_secs(1))
+            .poll_sample_every(10)
+            .summary_sink(|_: &crate::Summary| {})
+            .build();
+        let handle = collector.handle();
+        let collector = tokio::spawn(collector.run());
+
+        let config = handle.config();
+        assert_eq!(config.tick_interval, Duration::from_secs(1));
+        assert_eq!(config.poll_sample_every, 10);
+        assert_eq!(config.summary_sinks, 1);
+        assert_eq!(config.name_allowlist, None);
+
+        handle.set_tick_interval(Duration::from_millis(200)).await.unwrap();
+        handle.set_name_allowlist(Some(vec!["flusher".into()]));
+        let config = handle.config();
+        assert_eq!(config.tick_interval, Duration::from_millis(200));
+        assert_eq!(config.name_allowlist, Some(vec!["flusher".to_string()]));
+
+        // Raised to the shortest interval the collector flushes at.
+        handle.set_tick_interval(Duration::ZERO).await.unwrap();
+        assert_eq!(handle.config().tick_interval, Builder::MIN_TICK_INTERVAL);
+
+        let report = handle.debug_report(Duration::from_secs(5)).await;
+        assert_eq!(report.config.name_allowlist, Some(vec!["flusher".to_string()]));
+
+        handle.shutdown().await.unwrap();
+        collector.await.unwrap();
+    }
+}
@@ -0,0 +1,94 @@
+use crate::{event::EventId, summary::AggregateStats};
+
+use std::{
+    collections::HashMap,
+    thread::Thread,
+    time::{Duration, Instant},
+};
+
+/// Totals kept instead of a record per task when the collector
+/// [only counts](crate::Builder::aggregates_only). Only the tasks being
+/// polled right now are held on to, so memory follows how many polls run at
+/// once rather than how many tasks were ever seen.
+#[derive(Default)]
+pub(crate) struct TaskCounts {
+    spawned: u64,
+    closed: u64,
+    polls: u64,
+    busy_time: Duration,
+    polling: HashMap<EventId, Polling>,
+}
+
+/// A task being polled, on one thread or more.
+struct Polling {
+    /// Entries into the task not yet exited, across every thread.
+    current_polls: u64,
+    /// When the task was entered while not being polled anywhere.
+    started: Instant,
+    /// The threads the task is being polled on, with how many times it is
+    /// entered on each and since when.
+    threads: Vec<(Thread, u64, Instant)>,
+}
+
+impl TaskCounts {
+    pub fn spawned(&mut self) {
+        self.spawned += 1;
+    }
+
+    pub fn closed(&mut self) {
+        self.closed += 1;
+    }
+
+    pub fn enter(&mut self, id: EventId, time: Instant, thread: Thread) {
+        let polling = self.polling.entry(id).or_insert_with(|| Polling {
+            current_polls: 0,
+            started: time,
+            threads: Vec::new(),
+        });
+        if polling.current_polls == 0 {
+            self.polls += 1;
+        }
+        polling.current_polls += 1;
+
+        match polling.threads.iter_mut().find(|(polled_on, ..)| polled_on.id() == thread.id()) {
+            Some((_, depth, _)) => *depth += 1,
+            None => polling.threads.push((thread, 1, time)),
+        }
+    }
+
+    /// Returns the thread and length of the poll the exit ends on that
+    /// thread, if it ends one.
+    pub fn exit(&mut self, id: EventId, time: Instant, thread: &Thread) -> Option<(Thread, Duration)> {
+        // The matching enter was lost; there is no poll to end.
+        let polling = self.polling.get_mut(&id)?;
+        polling.current_polls -= 1;
+
+        let mut ended = None;
+        if let Some(index) = polling.threads.iter().position(|(polled_on, ..)| polled_on.id() == thread.id()) {
+            let (_, depth, started) = &mut polling.threads[index];
+            *depth -= 1;
+
+            if *depth == 0 {
+                let started = *started;
+                let (thread, ..) = polling.threads.swap_remove(index);
+                ended = Some((thread, time.saturating_duration_since(started)));
+            }
+        }
+
+        if polling.current_polls == 0 {
+            self.busy_time += time.saturating_duration_since(polling.started);
+            self.polling.remove(&id);
+        }
+
+        ended
+    }
+
+    pub fn summary(&self) -> AggregateStats {
+        AggregateStats {
+            tasks: self.spawned as usize,
+            active: self.spawned.saturating_sub(self.closed) as usize,
+            polls: self.polls,
+            busy_time: self.busy_time,
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use crate::reporting::INTERNAL_TARGET;
+
+use std::{fmt, time::Duration};
+
+/// One step down from a full report, taken when producing reports costs
+/// too much. See [`ReportBudget`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Degradation {
+    /// Leave the per-task rows out of flushed summaries.
+    AggregatesOnly,
+    /// Only report on every `n`th flush.
+    Stretch(u32),
+}
+
+impl fmt::Display for Degradation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AggregatesOnly => f.write_str("aggregates only"),
+            Self::Stretch(n) => write!(f, "every {} flushes", n),
+        }
+    }
+}
+
+/// How much of each flush interval producing the report may take before
+/// reports are degraded, and the steps to degrade them by.
+///
+/// Each time a report takes longer than `max_fraction` of the interval it
+/// covers, the next step on the ladder is taken, on top of the steps before
+/// it. Once a report takes less than half of that, the last step is undone.
+/// Summaries requested through a [`CollectorHandle`](crate::CollectorHandle)
+/// are always complete.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReportBudget {
+    max_fraction: f64,
+    ladder: Vec<Degradation>,
+}
+
+impl ReportBudget {
+    /// Degrades reports taking more than `max_fraction` of the flush
+    /// interval, first to aggregates only, then to every second and every
+    /// fourth flush.
+    pub fn new(max_fraction: f64) -> Self {
+        Self {
+            max_fraction,
+            ladder: vec![Degradation::AggregatesOnly, Degradation::Stretch(2), Degradation::Stretch(4)],
+        }
+    }
+
+    pub fn max_fraction(&self) -> f64 {
+        self.max_fraction
+    }
+
+    /// Replaces the steps taken, in order.
+    pub fn ladder(mut self, ladder: Vec<Degradation>) -> Self {
+        self.ladder = ladder;
+        self
+    }
+}
+
+/// Walks up and down a [`ReportBudget`]'s ladder as report costs come in.
+pub(crate) struct ReportGovernor {
+    budget: ReportBudget,
+    /// Steps of the ladder currently taken.
+    level: usize,
+    /// Flushes since the last report.
+    skipped: u32,
+}
+
+impl ReportGovernor {
+    pub fn new(budget: ReportBudget) -> Self {
+        Self {
+            budget,
+            level: 0,
+            skipped: 0,
+        }
+    }
+
+    pub fn active(&self) -> &[Degradation] {
+        &self.budget.ladder[..self.level]
+    }
+
+    pub fn aggregates_only(&self) -> bool {
+        self.active().contains(&Degradation::AggregatesOnly)
+    }
+
+    /// Flushes each report covers.
+    pub fn stretch(&self) -> u32 {
+        self.active()
+            .iter()
+            .filter_map(|step| match step {
+                Degradation::Stretch(n) => Some(*n),
+                Degradation::AggregatesOnly => None,
+            })
+            .max()
+            .unwrap_or(1)
+            .max(1)
+    }
+
+    /// Called on every flush, returning whether to report on this one.
+    pub fn should_report(&mut self) -> bool {
+        self.skipped += 1;
+        if self.skipped < self.stretch() {
+            return false;
+        }
+
+        self.skipped = 0;
+        true
+    }
+
+    /// Takes a step up or down the ladder given how long the last report took.
+    pub fn observe(&mut self, cost: Duration, interval: Duration) {
+        let covered = interval.as_secs_f64() * self.stretch() as f64;
+        if covered <= 0.0 {
+            return;
+        }
+        let fraction = cost.as_secs_f64() / covered;
+
+        if fraction > self.budget.max_fraction && self.level < self.budget.ladder.len() {
+            self.level += 1;
+            tracing::warn!(
+                target: INTERNAL_TARGET,
+                "Reporting took {:?}, degrading reports to {}",
+                cost,
+                self.budget.ladder[self.level - 1],
+            );
+        } else if fraction < self.budget.max_fraction / 2.0 && self.level > 0 {
+            self.level -= 1;
+            tracing::info!(target: INTERNAL_TARGET, "Reporting took {:?}, restoring reports", cost);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERVAL: Duration = Duration::from_secs(1);
+
+    /// Reports on the next `flushes` flushes, returning which were reported.
+    fn reports(governor: &mut ReportGovernor, flushes: usize) -> Vec<bool> {
+        (0..flushes).map(|_| governor.should_report()).collect()
+    }
+
+    #[test]
+    fn costly_reports_degrade_and_cheap_ones_recover() {
+        let mut governor = ReportGovernor::new(ReportBudget::new(0.1));
+        assert!(governor.active().is_empty());
+        assert_eq!(reports(&mut governor, 3), [true, true, true]);
+
+        // Within budget, or over half of it, nothing changes.
+        governor.observe(Duration::from_millis(80), INTERVAL);
+        assert!(governor.active().is_empty());
+
+        governor.observe(Duration::from_millis(200), INTERVAL);
+        assert!(governor.aggregates_only());
+        assert_eq!(governor.stretch(), 1);
+
+        governor.observe(Duration::from_millis(200), INTERVAL);
+        assert_eq!(governor.active(), [Degradation::AggregatesOnly, Degradation::Stretch(2)]);
+        assert_eq!(reports(&mut governor, 4), [false, true, false, true]);
+
+        // Costs are measured against the flushes a report covers.
+        governor.observe(Duration::from_millis(300), INTERVAL);
+        assert_eq!(governor.stretch(), 4);
+        assert_eq!(reports(&mut governor, 8), [false, false, false, true, false, false, false, true]);
+
+        // The top of the ladder is as far as it goes.
+        governor.observe(Duration::from_secs(4), INTERVAL);
+        assert_eq!(governor.active().len(), 3);
+
+        // A fiftieth of four flushes is under half the budget.
+        governor.observe(Duration::from_millis(80), INTERVAL);
+        assert_eq!(governor.stretch(), 2);
+        governor.observe(Duration::from_millis(10), INTERVAL);
+        governor.observe(Duration::from_millis(10), INTERVAL);
+        assert!(governor.active().is_empty());
+        assert_eq!(reports(&mut governor, 2), [true, true]);
+    }
+
+    #[test]
+    fn ladder_can_be_replaced() {
+        let budget = ReportBudget::new(0.5).ladder(vec![Degradation::Stretch(3)]);
+        let mut governor = ReportGovernor::new(budget);
+
+        governor.observe(Duration::from_millis(600), INTERVAL);
+        assert!(!governor.aggregates_only());
+        assert_eq!(governor.stretch(), 3);
+        assert_eq!(reports(&mut governor, 3), [false, false, true]);
+    }
+}
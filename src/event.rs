@@ -1,54 +1,328 @@
-use tracing_core::span::Id;
+use crate::{
+    callsite::Callsite,
+    clock,
+    fields::{FieldUpdates, TaskAttributes},
+    waker::WakerOp,
+};
 
-use std::time::SystemTime;
+use tracing_core::{span::Id, Level};
 
+use std::{
+    thread::{self, Thread},
+    time::{Instant, SystemTime},
+};
+
+/// Identifies the task an [`Event`] refers to.
+///
+/// Ids are namespaced by the source that produced them, so span ids coming
+/// from a [`BeeLayer`](crate::BeeLayer) never collide with the ids an
+/// [`EventInjector`](crate::EventInjector) is given, nor with span ids from
+/// a clone of the layer installed in another registry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct EventId {
+    source: u32,
+    id: u64,
+}
+
+impl EventId {
+    const EXTERNAL: u32 = 0;
+    /// The source of the first layer built. Its clones count up from here.
+    pub(crate) const FIRST_SPAN: u32 = 1;
+
+    pub(crate) fn span(source: u32, id: &Id) -> Self {
+        Self {
+            source,
+            id: id.into_u64(),
+        }
+    }
+
+    pub(crate) fn external(id: u64) -> Self {
+        Self {
+            source: Self::EXTERNAL,
+            id,
+        }
+    }
+
+    /// The id as given by its source, e.g. the span id.
+    pub fn into_u64(&self) -> u64 {
+        self.id
+    }
+
+    /// Whether the id was handed in through an [`EventInjector`](crate::EventInjector).
+    pub fn is_external(&self) -> bool {
+        self.source == Self::EXTERNAL
+    }
+
+    /// The source that produced the id, see [`Event::seq`].
+    pub(crate) fn source(&self) -> u32 {
+        self.source
+    }
+}
+
+/// A task lifecycle event, as sent to the collector.
+#[non_exhaustive]
+#[derive(Clone, Debug)]
 pub enum Event {
+    #[non_exhaustive]
     Spawn {
-        id: Id,
-        time: SystemTime,
+        id: EventId,
+        seq: u64,
+        time: Instant,
         fields: String,
+        attributes: TaskAttributes,
+        callsite: Option<Callsite>,
+        /// The innermost task the task was spawned inside of, if any.
+        parent: Option<EventId>,
     },
+    #[non_exhaustive]
     Enter {
-        id: Id,
-        time: SystemTime,
+        id: EventId,
+        seq: u64,
+        time: Instant,
+        /// The thread the task is being polled on.
+        thread: Thread,
     },
+    #[non_exhaustive]
     Exit {
-        id: Id,
-        time: SystemTime,
+        id: EventId,
+        seq: u64,
+        time: Instant,
+        /// The thread the task was being polled on.
+        thread: Thread,
     },
+    #[non_exhaustive]
     Close {
-        id: Id,
-        time: SystemTime,
+        id: EventId,
+        seq: u64,
+        time: Instant,
+    },
+    #[non_exhaustive]
+    Record {
+        id: EventId,
+        seq: u64,
+        time: Instant,
+        updates: FieldUpdates,
+    },
+    /// The task was marked as following from another, e.g. with
+    /// [`Span::follows_from`](tracing::Span::follows_from).
+    #[non_exhaustive]
+    FollowsFrom {
+        id: EventId,
+        seq: u64,
+        time: Instant,
+        follows: EventId,
+    },
+    /// A `tracing` event was logged inside the task.
+    #[non_exhaustive]
+    Log {
+        id: EventId,
+        seq: u64,
+        time: Instant,
+        level: Level,
+        /// The event's message, only kept for warnings and errors.
+        message: Option<String>,
+    },
+    /// Something was done with the task's waker, as logged by tokio when
+    /// built with `tokio_unstable`.
+    #[non_exhaustive]
+    Waker {
+        id: EventId,
+        seq: u64,
+        time: Instant,
+        op: WakerOp,
+        /// The thread the waker was used on, which tells a task waking
+        /// itself from inside its poll apart from another waking it.
+        thread: Thread,
+    },
+    /// A span monitoring a queue rather than a task was created.
+    #[non_exhaustive]
+    QueueOpen {
+        id: EventId,
+        seq: u64,
+        time: Instant,
+        name: String,
+        fields: String,
+        depth: Option<u64>,
+    },
+    #[non_exhaustive]
+    QueueClose {
+        id: EventId,
+        seq: u64,
+        time: Instant,
     },
 }
 
 impl Event {
-    pub fn spawn(id: Id, fields: String) -> Self {
+    pub(crate) fn spawn(
+        id: EventId,
+        fields: String,
+        attributes: TaskAttributes,
+        callsite: Option<Callsite>,
+        parent: Option<EventId>,
+    ) -> Self {
         Self::Spawn {
             id,
-            time: SystemTime::now(),
+            seq: 0,
+            time: Instant::now(),
             fields,
+            attributes,
+            callsite,
+            parent,
         }
     }
 
-    pub fn enter(id: Id) -> Self {
+    pub(crate) fn enter(id: EventId) -> Self {
         Self::Enter {
             id,
-            time: SystemTime::now(),
+            seq: 0,
+            time: Instant::now(),
+            thread: thread::current(),
         }
     }
 
-    pub fn exit(id: Id) -> Self {
+    pub(crate) fn exit(id: EventId) -> Self {
         Self::Exit {
             id,
-            time: SystemTime::now(),
+            seq: 0,
+            time: Instant::now(),
+            thread: thread::current(),
         }
     }
 
-    pub fn close(id: Id) -> Self {
+    pub(crate) fn close(id: EventId) -> Self {
         Self::Close {
             id,
-            time: SystemTime::now(),
+            seq: 0,
+            time: Instant::now(),
+        }
+    }
+
+    pub(crate) fn record(id: EventId, updates: FieldUpdates) -> Self {
+        Self::Record {
+            id,
+            seq: 0,
+            time: Instant::now(),
+            updates,
+        }
+    }
+
+    pub(crate) fn follows_from(id: EventId, follows: EventId) -> Self {
+        Self::FollowsFrom {
+            id,
+            seq: 0,
+            time: Instant::now(),
+            follows,
+        }
+    }
+
+    pub(crate) fn log(id: EventId, level: Level, message: Option<String>) -> Self {
+        Self::Log {
+            id,
+            seq: 0,
+            time: Instant::now(),
+            level,
+            message,
+        }
+    }
+
+    pub(crate) fn waker(id: EventId, op: WakerOp) -> Self {
+        Self::Waker {
+            id,
+            seq: 0,
+            time: Instant::now(),
+            op,
+            thread: thread::current(),
         }
     }
-}
\ No newline at end of file
+
+    pub(crate) fn queue_open(id: EventId, name: String, fields: String, depth: Option<u64>) -> Self {
+        Self::QueueOpen {
+            id,
+            seq: 0,
+            time: Instant::now(),
+            name,
+            fields,
+            depth,
+        }
+    }
+
+    pub(crate) fn queue_close(id: EventId) -> Self {
+        Self::QueueClose {
+            id,
+            seq: 0,
+            time: Instant::now(),
+        }
+    }
+
+    /// When the event happened, on the monotonic clock durations between
+    /// events are measured with.
+    pub fn time(&self) -> Instant {
+        match self {
+            Self::Spawn { time, .. }
+            | Self::Enter { time, .. }
+            | Self::Exit { time, .. }
+            | Self::Close { time, .. }
+            | Self::Record { time, .. }
+            | Self::FollowsFrom { time, .. }
+            | Self::Log { time, .. }
+            | Self::Waker { time, .. }
+            | Self::QueueOpen { time, .. }
+            | Self::QueueClose { time, .. } => *time,
+        }
+    }
+
+    /// When the event happened, on the wall clock.
+    pub fn wall_time(&self) -> SystemTime {
+        clock::wall_time(self.time())
+    }
+
+    /// The task or queue this event is about.
+    pub fn id(&self) -> EventId {
+        match self {
+            Self::Spawn { id, .. }
+            | Self::Enter { id, .. }
+            | Self::Exit { id, .. }
+            | Self::Close { id, .. }
+            | Self::Record { id, .. }
+            | Self::FollowsFrom { id, .. }
+            | Self::Log { id, .. }
+            | Self::Waker { id, .. }
+            | Self::QueueOpen { id, .. }
+            | Self::QueueClose { id, .. } => *id,
+        }
+    }
+
+    /// The event's place among those sent by its source, counting up from
+    /// zero. Gaps mean events were lost on the way to the collector.
+    pub fn seq(&self) -> u64 {
+        match self {
+            Self::Spawn { seq, .. }
+            | Self::Enter { seq, .. }
+            | Self::Exit { seq, .. }
+            | Self::Close { seq, .. }
+            | Self::Record { seq, .. }
+            | Self::FollowsFrom { seq, .. }
+            | Self::Log { seq, .. }
+            | Self::Waker { seq, .. }
+            | Self::QueueOpen { seq, .. }
+            | Self::QueueClose { seq, .. } => *seq,
+        }
+    }
+
+    /// Stamps the event with its sequence number, as it is sent.
+    pub(crate) fn with_seq(mut self, next: u64) -> Self {
+        match &mut self {
+            Self::Spawn { seq, .. }
+            | Self::Enter { seq, .. }
+            | Self::Exit { seq, .. }
+            | Self::Close { seq, .. }
+            | Self::Record { seq, .. }
+            | Self::FollowsFrom { seq, .. }
+            | Self::Log { seq, .. }
+            | Self::Waker { seq, .. }
+            | Self::QueueOpen { seq, .. }
+            | Self::QueueClose { seq, .. } => *seq = next,
+        }
+        self
+    }
+}
@@ -0,0 +1,968 @@
+use crate::{
+    callsite::Callsite,
+    clock,
+    event::Event,
+    fields::FieldValue,
+    logs::TaskLogs,
+    snapshot::{FollowedTask, TaskSnapshot},
+    summary::{LatencySummary, Summary},
+};
+
+use serde_json::{Map, Number, Value};
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// The version of the JSON layout written by [`ExportConfig`], included in
+/// every summary as `schema_version`.
+///
+/// It is bumped whenever a field is renamed or removed, or changes meaning or
+/// unit. Adding a field does not bump it, so consumers should ignore fields
+/// they don't know.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Decimal places ratios and averages are rounded to, so that their JSON is
+/// the same on every platform and run.
+const FLOAT_DECIMALS: i32 = 6;
+
+/// Decimal places fractional seconds are rounded to: nanoseconds, the
+/// precision durations are measured in.
+const SECS_DECIMALS: i32 = 9;
+
+/// The unit durations are written in. Field names carry the unit as a suffix
+/// (`busy_s`, `busy_ms`, `busy_us`, `busy_ns`), so a consumer expecting a
+/// different unit fails to find the field rather than misreading it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DurationUnit {
+    /// Fractional seconds.
+    SecsF64,
+    #[default]
+    Millis,
+    Micros,
+    Nanos,
+}
+
+impl DurationUnit {
+    fn suffix(&self) -> &'static str {
+        match self {
+            Self::SecsF64 => "s",
+            Self::Millis => "ms",
+            Self::Micros => "us",
+            Self::Nanos => "ns",
+        }
+    }
+
+    fn value(&self, duration: Duration) -> Value {
+        match self {
+            Self::SecsF64 => fixed(duration.as_secs_f64(), SECS_DECIMALS),
+            Self::Millis => saturating_u64(duration.as_millis()).into(),
+            Self::Micros => saturating_u64(duration.as_micros()).into(),
+            Self::Nanos => saturating_u64(duration.as_nanos()).into(),
+        }
+    }
+}
+
+/// How wall-clock timestamps are written. Integer formats are suffixed like
+/// durations (`created_at_ms`); RFC 3339 strings keep the bare name.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TimestampFormat {
+    #[default]
+    EpochMillis,
+    EpochMicros,
+    Rfc3339,
+}
+
+impl TimestampFormat {
+    fn key(&self, name: &str) -> String {
+        match self {
+            Self::EpochMillis => format!("{}_ms", name),
+            Self::EpochMicros => format!("{}_us", name),
+            Self::Rfc3339 => name.to_string(),
+        }
+    }
+
+    fn value(&self, time: SystemTime) -> Value {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+        match self {
+            Self::EpochMillis => saturating_u64(since_epoch.as_millis()).into(),
+            Self::EpochMicros => saturating_u64(since_epoch.as_micros()).into(),
+            Self::Rfc3339 => rfc3339(since_epoch).into(),
+        }
+    }
+}
+
+/// Units used when exporting summaries, shared by every output format.
+///
+/// The crate has no HTTP output of its own: to post summaries, render them
+/// with [`to_json`](Self::to_json) from a
+/// [`SummarySink`](crate::SummarySink) wrapping the client of your choice.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ExportConfig {
+    pub duration_unit: DurationUnit,
+    pub timestamp_format: TimestampFormat,
+}
+
+impl ExportConfig {
+    pub fn new(duration_unit: DurationUnit, timestamp_format: TimestampFormat) -> Self {
+        Self {
+            duration_unit,
+            timestamp_format,
+        }
+    }
+
+    pub(crate) fn duration(&self, row: &mut Row, name: &str, duration: Option<Duration>) {
+        let value = duration.map(|duration| self.duration_unit.value(duration)).unwrap_or(Value::Null);
+        row.push((format!("{}_{}", name, self.duration_unit.suffix()), value));
+    }
+
+    pub(crate) fn timestamp(&self, row: &mut Row, name: &str, time: Option<SystemTime>) {
+        let value = time.map(|time| self.timestamp_format.value(time)).unwrap_or(Value::Null);
+        row.push((self.timestamp_format.key(name), value));
+    }
+
+    pub(crate) fn latency(&self, latency: Option<LatencySummary>) -> Value {
+        let latency = match latency {
+            Some(latency) => latency,
+            None => return Value::Null,
+        };
+
+        let mut row = Row::new();
+        self.duration(&mut row, "p50", Some(latency.p50));
+        self.duration(&mut row, "p99", Some(latency.p99));
+        self.duration(&mut row, "max", Some(latency.max));
+        row.push(("samples".into(), latency.samples.into()));
+
+        Value::Object(row.into_iter().collect())
+    }
+
+    /// A task as a flat list of columns, in a fixed order.
+    pub(crate) fn task_row(&self, task: &TaskSnapshot) -> Vec<(String, Value)> {
+        let mut row = Row::new();
+        let phases = task.phases();
+
+        row.push(("id".into(), task.id.into()));
+        row.push(("fields".into(), task.fields.clone().into()));
+        row.push(("kind".into(), task.kind.as_str().into()));
+        row.push(("active".into(), task.stats.is_active().into()));
+        row.push(("polls".into(), task.stats.polls().into()));
+        row.push(("ready_polls".into(), task.stats.ready_polls().into()));
+        row.push(("pending_polls".into(), task.stats.pending_polls().into()));
+        row.push((
+            "ready_ratio".into(),
+            task.stats.ready_ratio().map(float).unwrap_or(Value::Null),
+        ));
+        row.push(("busy_loop".into(), task.busy_loop.into()));
+        self.duration(&mut row, "busy", Some(task.stats.busy_time()));
+        self.duration(&mut row, "poll_time", Some(task.stats.poll_time()));
+        self.duration(&mut row, "total", task.total_time());
+        self.timestamp(&mut row, "created_at", task.stats.created_at());
+        self.timestamp(&mut row, "first_poll", task.stats.first_poll());
+        self.timestamp(&mut row, "last_poll", task.stats.last_poll());
+        self.timestamp(&mut row, "last_poll_ended", task.stats.last_poll_ended());
+        self.timestamp(&mut row, "closed_at", task.stats.closed_at());
+        self.duration(&mut row, "scheduling", phases.map(|phases| phases.scheduling));
+        self.duration(&mut row, "idle", phases.map(|phases| phases.idle));
+        self.duration(&mut row, "cleanup", phases.map(|phases| phases.cleanup));
+        row.push(("outcome".into(), task.outcome.map(|outcome| outcome.as_str().into()).unwrap_or(Value::Null)));
+        row.push(("mem_bytes".into(), task.mem_bytes.map(Value::from).unwrap_or(Value::Null)));
+        row.push(("mem_bytes_max".into(), task.mem_bytes_max.map(Value::from).unwrap_or(Value::Null)));
+        self.duration(&mut row, "deadline", task.budget.deadline);
+        row.push(("budget_polls".into(), task.budget.polls.map(Value::from).unwrap_or(Value::Null)));
+        row.push(("over_budget".into(), task.over_budget.into()));
+        row.push(("weight".into(), task.weight.map(float).unwrap_or(Value::Null)));
+
+        row
+    }
+
+    /// A task as a JSON object: its columns, plus its callsite, counters,
+    /// poll rate and whether it is provisional.
+    pub fn task_json(&self, task: &TaskSnapshot) -> Value {
+        let mut map: Map<String, Value> = self.task_row(task).into_iter().collect();
+        map.insert("name".into(), task.name.clone().map(Value::from).unwrap_or(Value::Null));
+        map.insert("callsite".into(), task.callsite.as_ref().map(callsite_json).unwrap_or(Value::Null));
+        map.insert("values".into(), values_json(&task.values));
+        map.insert("counters".into(), counters_json(&task.counters));
+        map.insert("poll_rate".into(), task.poll_rate.map(float).unwrap_or(Value::Null));
+        map.insert("wakes".into(), task.stats.wakes().into());
+        map.insert("waker_clones".into(), task.stats.waker_clones().into());
+        map.insert("waker_drops".into(), task.stats.waker_drops().into());
+        map.insert("self_wakes".into(), task.stats.self_wakes().into());
+        map.insert("self_wake_ratio".into(), task.stats.self_wake_ratio().map(float).unwrap_or(Value::Null));
+        let mut scheduled = Row::new();
+        self.duration(&mut scheduled, "scheduled", Some(task.stats.scheduled_time()));
+        self.duration(&mut scheduled, "mean_scheduled", task.stats.mean_scheduled_time());
+        map.extend(scheduled);
+        map.insert("threads".into(), task.stats.threads().into());
+        map.insert("migrations".into(), task.stats.migrations().into());
+        map.insert("parent".into(), task.parent.map(Value::from).unwrap_or(Value::Null));
+        map.insert("children".into(), task.children.clone().into());
+        map.insert("follows".into(), task.follows.iter().map(followed_json).collect());
+        map.insert("logs".into(), logs_json(&task.logs));
+        map.insert("provisional".into(), task.provisional.into());
+        map.insert("approximate".into(), task.stats.is_approximate().into());
+
+        Value::Object(map)
+    }
+
+    /// A raw event, with its kind under `event`.
+    pub fn event_json(&self, event: &Event) -> Value {
+        let id = event.id();
+        let mut row = Row::new();
+
+        row.push(("id".into(), id.into_u64().into()));
+        row.push(("external".into(), id.is_external().into()));
+        row.push(("seq".into(), event.seq().into()));
+
+        match event {
+            Event::Spawn {
+                time,
+                fields,
+                attributes,
+                callsite,
+                parent,
+                ..
+            } => {
+                row.push(("event".into(), "spawn".into()));
+                self.timestamp(&mut row, "time", Some(clock::wall_time(*time)));
+                row.push(("fields".into(), fields.clone().into()));
+                row.push(("kind".into(), attributes.kind.as_str().into()));
+                row.push(("callsite".into(), callsite.map(|callsite| callsite.to_string().into()).unwrap_or(Value::Null)));
+                row.push(("parent".into(), parent.map(|parent| parent.into_u64().into()).unwrap_or(Value::Null)));
+            }
+            Event::Enter { time, thread, .. } => {
+                row.push(("event".into(), "enter".into()));
+                self.timestamp(&mut row, "time", Some(clock::wall_time(*time)));
+                row.push(("thread".into(), thread.name().map(Value::from).unwrap_or_else(|| format!("{:?}", thread.id()).into())));
+            }
+            Event::Exit { time, thread, .. } => {
+                row.push(("event".into(), "exit".into()));
+                self.timestamp(&mut row, "time", Some(clock::wall_time(*time)));
+                row.push(("thread".into(), thread.name().map(Value::from).unwrap_or_else(|| format!("{:?}", thread.id()).into())));
+            }
+            Event::Close { time, .. } => {
+                row.push(("event".into(), "close".into()));
+                self.timestamp(&mut row, "time", Some(clock::wall_time(*time)));
+            }
+            Event::Record { time, updates, .. } => {
+                row.push(("event".into(), "record".into()));
+                self.timestamp(&mut row, "time", Some(clock::wall_time(*time)));
+                self.timestamp(&mut row, "woken_at", updates.woken_at);
+                row.push(("outcome".into(), updates.outcome.map(|outcome| outcome.as_str().into()).unwrap_or(Value::Null)));
+                row.push(("mem_bytes".into(), updates.mem_bytes.map(Value::from).unwrap_or(Value::Null)));
+                row.push(("fields".into(), updates.fields.clone().map(Value::from).unwrap_or(Value::Null)));
+                row.push((
+                    "poll".into(),
+                    updates
+                        .poll
+                        .map(|poll| if poll.is_ready() { "ready" } else { "pending" }.into())
+                        .unwrap_or(Value::Null),
+                ));
+                row.push(("queue_depth".into(), updates.queue_depth.map(Value::from).unwrap_or(Value::Null)));
+            }
+            Event::FollowsFrom { time, follows, .. } => {
+                row.push(("event".into(), "follows_from".into()));
+                self.timestamp(&mut row, "time", Some(clock::wall_time(*time)));
+                row.push(("follows".into(), follows.into_u64().into()));
+                row.push(("follows_external".into(), follows.is_external().into()));
+            }
+            Event::Log { time, level, message, .. } => {
+                row.push(("event".into(), "log".into()));
+                self.timestamp(&mut row, "time", Some(clock::wall_time(*time)));
+                row.push(("level".into(), level.as_str().into()));
+                row.push(("message".into(), message.clone().map(Value::from).unwrap_or(Value::Null)));
+            }
+            Event::Waker { time, op, thread, .. } => {
+                row.push(("event".into(), "waker".into()));
+                self.timestamp(&mut row, "time", Some(clock::wall_time(*time)));
+                row.push(("op".into(), op.as_str().into()));
+                row.push(("thread".into(), thread.name().map(Value::from).unwrap_or_else(|| format!("{:?}", thread.id()).into())));
+            }
+            Event::QueueOpen {
+                time,
+                name,
+                fields,
+                depth,
+                ..
+            } => {
+                row.push(("event".into(), "queue_open".into()));
+                self.timestamp(&mut row, "time", Some(clock::wall_time(*time)));
+                row.push(("name".into(), name.clone().into()));
+                row.push(("fields".into(), fields.clone().into()));
+                row.push(("queue_depth".into(), depth.map(Value::from).unwrap_or(Value::Null)));
+            }
+            Event::QueueClose { time, .. } => {
+                row.push(("event".into(), "queue_close".into()));
+                self.timestamp(&mut row, "time", Some(clock::wall_time(*time)));
+            }
+        }
+
+        Value::Object(row.into_iter().collect())
+    }
+
+    /// Everything in the summary except the task table.
+    pub(crate) fn aggregates_json(&self, summary: &Summary) -> Map<String, Value> {
+        let mut map = Map::new();
+
+        let mut row = Row::new();
+        row.push(("schema_version".into(), SCHEMA_VERSION.into()));
+        row.push(("instance".into(), summary.instance.clone().map(Value::from).unwrap_or(Value::Null)));
+        row.push(("seq".into(), summary.seq.into()));
+        self.timestamp(&mut row, "flushed_at", summary.flushed_at);
+        self.timestamp(&mut row, "last_event_at", summary.last_event_at);
+        row.push(("ticks_missed".into(), summary.ticks_missed.into()));
+        row.push(("name_allowlist".into(), summary.name_allowlist.clone().map(Value::from).unwrap_or(Value::Null)));
+        map.extend(row);
+
+        let mut startup = Map::new();
+        startup.insert("buffered".into(), summary.startup.buffered.into());
+        startup.insert("dropped".into(), summary.startup.dropped.into());
+        map.insert("startup".into(), Value::Object(startup));
+
+        map.insert("budget_violations".into(), summary.budget_violations.into());
+        map.insert("duplicate_closes".into(), summary.duplicate_closes.into());
+        map.insert("order_violations".into(), summary.order_violations.into());
+        map.insert("events_lost".into(), summary.events_lost.into());
+        map.insert("event_gaps".into(), summary.event_gaps.into());
+        map.insert("transient_tasks".into(), summary.transient_tasks.into());
+        map.insert("panics".into(), summary.panics.into());
+        map.insert("quarantined_sinks".into(), summary.quarantined_sinks.into());
+        map.insert("restarts".into(), summary.restarts.into());
+        map.insert(
+            "degradation".into(),
+            summary.degradation.iter().map(|step| Value::from(step.to_string())).collect(),
+        );
+        map.insert("peak_queue_len".into(), summary.peak_queue_len.into());
+        map.insert("dropped_newest".into(), summary.dropped_newest.into());
+        map.insert("dropped_by_kind".into(), summary.dropped_by_kind.iter().map(|(kind, dropped)| (kind.to_string(), Value::from(dropped))).collect());
+        map.insert("dropped_since_flush".into(), summary.dropped_since_flush.into());
+        map.insert("dropped_oldest".into(), summary.dropped_oldest.map(Value::from).unwrap_or(Value::Null));
+        map.insert("event_sinks_dropped".into(), summary.event_sinks_dropped.clone().into());
+        map.insert("dropped_close_events".into(), summary.dropped_close_events.into());
+
+        map.insert(
+            "callsites".into(),
+            summary
+                .callsites
+                .iter()
+                .map(|callsite| {
+                    let mut map = Map::new();
+                    map.insert("target".into(), callsite.callsite.target.into());
+                    map.insert("name".into(), callsite.callsite.name.into());
+                    map.insert("file".into(), callsite.callsite.file.map(Value::from).unwrap_or(Value::Null));
+                    map.insert("line".into(), callsite.callsite.line.map(Value::from).unwrap_or(Value::Null));
+                    map.insert("alive".into(), callsite.alive.into());
+                    map.insert("limit".into(), callsite.limit.map(Value::from).unwrap_or(Value::Null));
+                    map.insert("violated".into(), callsite.violated.into());
+                    Value::Object(map)
+                })
+                .collect(),
+        );
+
+        map.insert(
+            "field_variants".into(),
+            summary
+                .field_variants
+                .iter()
+                .map(|variants| {
+                    let mut map = Map::new();
+                    map.insert("target".into(), variants.target.into());
+                    map.insert("variants".into(), variants.variants.into());
+                    map.insert("saturated".into(), variants.saturated.into());
+                    Value::Object(map)
+                })
+                .collect(),
+        );
+
+        map.insert(
+            "counter_rollup".into(),
+            summary
+                .counter_rollup
+                .iter()
+                .map(|rollup| {
+                    let mut map = Map::new();
+                    map.insert("target".into(), rollup.callsite.target.into());
+                    map.insert("name".into(), rollup.callsite.name.into());
+                    map.insert("file".into(), rollup.callsite.file.map(Value::from).unwrap_or(Value::Null));
+                    map.insert("line".into(), rollup.callsite.line.map(Value::from).unwrap_or(Value::Null));
+                    map.insert("counters".into(), counters_json(&rollup.counters));
+                    Value::Object(map)
+                })
+                .collect(),
+        );
+
+        map.insert(
+            "stacks".into(),
+            summary
+                .stacks
+                .stacks
+                .iter()
+                .map(|(stack, busy)| {
+                    let mut row = Row::new();
+                    row.push(("stack".into(), stack.clone().into()));
+                    self.duration(&mut row, "busy", Some(*busy));
+                    Value::Object(row.into_iter().collect())
+                })
+                .collect(),
+        );
+
+        map.insert(
+            "filtered_traffic".into(),
+            summary
+                .filtered_traffic
+                .iter()
+                .map(|filtered| {
+                    let mut map = Map::new();
+                    map.insert("target".into(), filtered.callsite.target.into());
+                    map.insert("name".into(), filtered.callsite.name.into());
+                    map.insert("file".into(), filtered.callsite.file.map(Value::from).unwrap_or(Value::Null));
+                    map.insert("line".into(), filtered.callsite.line.map(Value::from).unwrap_or(Value::Null));
+                    map.insert("spans".into(), filtered.spans.into());
+                    map.insert("events".into(), filtered.events.into());
+                    Value::Object(map)
+                })
+                .collect(),
+        );
+
+        map.insert(
+            "kinds".into(),
+            Value::Object(
+                summary
+                    .kinds
+                    .iter()
+                    .map(|kind| {
+                        let mut row = Row::new();
+                        row.push(("tasks".into(), kind.tasks.into()));
+                        row.push(("active".into(), kind.active.into()));
+                        row.push(("polls".into(), kind.polls.into()));
+                        self.duration(&mut row, "busy", Some(kind.busy_time));
+                        self.duration(&mut row, "busy_total", Some(kind.busy_time_total));
+                        (kind.kind.as_str().to_string(), Value::Object(row.into_iter().collect()))
+                    })
+                    .collect(),
+            ),
+        );
+
+        map.insert(
+            "threads".into(),
+            summary
+                .threads
+                .iter()
+                .map(|thread| {
+                    let mut row = Row::new();
+                    row.push(("thread".into(), thread.label().into()));
+                    self.duration(&mut row, "busy", Some(thread.busy_time));
+                    row.push(("utilization".into(), float(thread.utilization)));
+                    Value::Object(row.into_iter().collect())
+                })
+                .collect(),
+        );
+
+        map.insert(
+            "queues".into(),
+            summary
+                .queues
+                .iter()
+                .map(|queue| {
+                    let mut row: Row = vec![
+                        ("id".into(), queue.id.into()),
+                        ("name".into(), queue.name.clone().into()),
+                        ("fields".into(), queue.fields.clone().into()),
+                        ("depth".into(), queue.depth.into()),
+                        ("max_depth".into(), queue.max_depth.into()),
+                        ("avg_depth".into(), float(queue.avg_depth)),
+                    ];
+                    self.timestamp(&mut row, "opened_at", Some(queue.opened_at));
+                    self.timestamp(&mut row, "closed_at", queue.closed_at);
+                    Value::Object(row.into_iter().collect())
+                })
+                .collect(),
+        );
+
+        let mut concurrency = Map::new();
+        concurrency.insert(
+            "buckets".into(),
+            summary
+                .active_concurrency
+                .buckets
+                .iter()
+                .map(|(le, count)| {
+                    let mut bucket = Map::new();
+                    bucket.insert("le".into(), (*le).into());
+                    bucket.insert("count".into(), (*count).into());
+                    Value::Object(bucket)
+                })
+                .collect(),
+        );
+        concurrency.insert("sum".into(), summary.active_concurrency.sum.into());
+        concurrency.insert("count".into(), summary.active_concurrency.count.into());
+        map.insert("active_concurrency".into(), Value::Object(concurrency));
+
+        let counts = summary.counts.map(|counts| {
+            let mut row = Row::new();
+            row.push(("tasks".into(), counts.tasks.into()));
+            row.push(("active".into(), counts.active.into()));
+            row.push(("polls".into(), counts.polls.into()));
+            self.duration(&mut row, "busy", Some(counts.busy_time));
+            Value::Object(row.into_iter().collect())
+        });
+        map.insert("counts".into(), counts.unwrap_or(Value::Null));
+        let sampling = summary.sampling.map(|sampling| {
+            let mut row = Map::new();
+            row.insert("one_in".into(), sampling.one_in.into());
+            row.insert("seen".into(), sampling.seen.into());
+            row.insert("sampled".into(), sampling.sampled.into());
+            row.insert("scale".into(), float(sampling.scale()));
+            Value::Object(row)
+        });
+        map.insert("sampling".into(), sampling.unwrap_or(Value::Null));
+
+        map.insert(
+            "stable_keys".into(),
+            Value::Object(
+                summary
+                    .stable_keys
+                    .iter()
+                    .map(|(key, stats)| {
+                        let mut row = Row::new();
+                        row.push(("tasks".into(), stats.tasks.into()));
+                        row.push(("active".into(), stats.active.into()));
+                        row.push(("polls".into(), stats.polls.into()));
+                        self.duration(&mut row, "busy", Some(stats.busy_time));
+                        (key.clone(), Value::Object(row.into_iter().collect()))
+                    })
+                    .collect(),
+            ),
+        );
+
+        let census = &summary.census;
+        map.insert(
+            "census".into(),
+            census
+                .labels()
+                .into_iter()
+                .zip(census.by_age.iter().zip(&census.by_idle))
+                .map(|(bucket, (by_age, by_idle))| {
+                    let mut map = Map::new();
+                    map.insert("bucket".into(), bucket.into());
+                    map.insert("by_age".into(), (*by_age).into());
+                    map.insert("by_idle".into(), (*by_idle).into());
+                    Value::Object(map)
+                })
+                .collect(),
+        );
+
+        let mut row = Row::new();
+        row.push(("active_tasks".into(), summary.active_tasks.into()));
+        row.push(("peak_active".into(), summary.peak_active.map(|peak| peak.count.into()).unwrap_or(Value::Null)));
+        self.timestamp(&mut row, "peak_active_at", summary.peak_active.map(|peak| peak.at));
+        self.duration(&mut row, "weighted_busy", summary.weighted_busy_time);
+        self.duration(&mut row, "report_cost", summary.report_cost);
+        row.push(("reported_mem_bytes".into(), summary.reported_mem_bytes.map(Value::from).unwrap_or(Value::Null)));
+        map.extend(row);
+
+        map.insert("wake_to_poll".into(), self.latency(summary.wake_to_poll));
+        map.insert("first_poll_delay".into(), self.latency(summary.first_poll_delay));
+        map.insert("idle_gaps".into(), self.latency(summary.idle_gaps));
+        map.insert("poll_durations".into(), self.latency(summary.poll_durations));
+        map.insert(
+            "phases".into(),
+            match summary.phases {
+                Some(phases) => {
+                    let mut map = Map::new();
+                    map.insert("scheduling".into(), self.latency(Some(phases.scheduling)));
+                    map.insert("busy".into(), self.latency(Some(phases.busy)));
+                    map.insert("idle".into(), self.latency(Some(phases.idle)));
+                    map.insert("cleanup".into(), self.latency(Some(phases.cleanup)));
+                    Value::Object(map)
+                }
+                None => Value::Null,
+            },
+        );
+
+        map
+    }
+
+    pub fn summary_json(&self, summary: &Summary) -> Value {
+        let mut map = self.aggregates_json(summary);
+        map.insert("tasks".into(), summary.tasks.iter().map(|task| self.task_json(task)).collect());
+
+        Value::Object(map)
+    }
+
+    /// The summary as a single JSON object.
+    pub fn to_json(&self, summary: &Summary) -> String {
+        self.summary_json(summary).to_string()
+    }
+
+    /// One JSON object per task, newline-delimited.
+    pub fn to_jsonl(&self, tasks: &[TaskSnapshot]) -> String {
+        let mut out = String::new();
+
+        for task in tasks {
+            out.push_str(&self.task_json(task).to_string());
+            out.push('\n');
+        }
+
+        out
+    }
+
+    /// The task table as CSV, with a header row.
+    pub fn to_csv(&self, tasks: &[TaskSnapshot]) -> String {
+        let mut out = String::new();
+        let header = self.task_row(&TaskSnapshot::default());
+
+        out.push_str(&header.iter().map(|(key, _)| key.as_str()).collect::<Vec<_>>().join(","));
+        out.push('\n');
+
+        for task in tasks {
+            let row = self.task_row(task);
+            let cells: Vec<_> = row.iter().map(|(_, value)| csv_cell(value)).collect();
+
+            out.push_str(&cells.join(","));
+            out.push('\n');
+        }
+
+        out
+    }
+
+    /// Process-wide metrics in the Prometheus text exposition format.
+    pub fn to_prometheus(&self, summary: &Summary) -> String {
+        let mut out = String::new();
+        let concurrency = &summary.active_concurrency;
+
+        prometheus_header(&mut out, "task_active_concurrency", "histogram", "Tasks active at each flush.");
+        for (le, count) in &concurrency.buckets {
+            let _ = writeln!(out, "task_active_concurrency_bucket{{le=\"{}\"}} {}", le, count);
+        }
+        let _ = writeln!(out, "task_active_concurrency_bucket{{le=\"+Inf\"}} {}", concurrency.count);
+        let _ = writeln!(out, "task_active_concurrency_sum {}", concurrency.sum);
+        let _ = writeln!(out, "task_active_concurrency_count {}", concurrency.count);
+
+        prometheus_header(&mut out, "task_active_concurrency_current", "gauge", "Tasks active now.");
+        let _ = writeln!(out, "task_active_concurrency_current {}", summary.active_tasks);
+
+        let census = &summary.census;
+        prometheus_header(&mut out, "task_age_census", "gauge", "Running tasks by time since spawn.");
+        for (bucket, count) in census.labels().iter().zip(&census.by_age) {
+            let _ = writeln!(out, "task_age_census{{age=\"{}\"}} {}", bucket, count);
+        }
+        prometheus_header(&mut out, "task_idle_census", "gauge", "Running tasks by time since their last poll.");
+        for (bucket, count) in census.labels().iter().zip(&census.by_idle) {
+            let _ = writeln!(out, "task_idle_census{{idle=\"{}\"}} {}", bucket, count);
+        }
+
+        if let Some(peak) = summary.peak_active {
+            prometheus_header(&mut out, "task_active_peak", "gauge", "The most tasks ever active at once.");
+            let _ = writeln!(out, "task_active_peak {}", peak.count);
+        }
+
+        prometheus_header(&mut out, "task_budget_violations_total", "counter", "Tasks that ran over their budget.");
+        let _ = writeln!(out, "task_budget_violations_total {}", summary.budget_violations);
+
+        prometheus_header(&mut out, "task_busy_seconds_total", "counter", "Time spent polling tasks of each kind.");
+        for kind in &summary.kinds {
+            let _ = writeln!(
+                out,
+                "task_busy_seconds_total{{kind=\"{}\"}} {:.9}",
+                kind.kind.as_str(),
+                kind.busy_time_total.as_secs_f64(),
+            );
+        }
+
+        if !summary.queues.is_empty() {
+            prometheus_header(&mut out, "queue_depth", "gauge", "Depth last recorded on each queue span.");
+            for queue in &summary.queues {
+                let _ = writeln!(out, "queue_depth{{queue=\"{}\",id=\"{}\"}} {}", escape_label(&queue.name), queue.id, queue.depth);
+            }
+            prometheus_header(&mut out, "queue_depth_max", "gauge", "Greatest depth ever recorded on each queue span.");
+            for queue in &summary.queues {
+                let _ = writeln!(out, "queue_depth_max{{queue=\"{}\",id=\"{}\"}} {}", escape_label(&queue.name), queue.id, queue.max_depth);
+            }
+        }
+
+        if !summary.threads.is_empty() {
+            prometheus_header(&mut out, "thread_busy_ratio", "gauge", "Fraction of the last flush interval spent polling.");
+            for thread in &summary.threads {
+                let _ = writeln!(
+                    out,
+                    "thread_busy_ratio{{thread=\"{}\"}} {:.6}",
+                    escape_label(&thread.label()),
+                    thread.utilization,
+                );
+            }
+        }
+
+        out
+    }
+
+    /// The task table in InfluxDB line protocol, one `task` point per task.
+    pub fn to_line_protocol(&self, tasks: &[TaskSnapshot]) -> String {
+        let mut out = String::new();
+
+        for task in tasks {
+            let fields: Vec<_> = self
+                .task_row(task)
+                .into_iter()
+                .filter(|(key, _)| key != "id")
+                .filter_map(|(key, value)| Some(format!("{}={}", escape_key(&key), line_protocol_value(&value)?)))
+                .collect();
+
+            let _ = writeln!(out, "task,id={} {}", task.id, fields.join(","));
+        }
+
+        out
+    }
+}
+
+pub(crate) type Row = Vec<(String, Value)>;
+
+fn saturating_u64(value: u128) -> u64 {
+    value.min(u64::MAX as u128) as u64
+}
+
+/// A callsite as a JSON object with its `target`, `name`, `level`, `file`
+/// and `line`.
+fn callsite_json(callsite: &Callsite) -> Value {
+    let mut map = Map::new();
+    map.insert("target".into(), callsite.target.into());
+    map.insert("name".into(), callsite.name.into());
+    map.insert("level".into(), callsite.level.map(|level| Value::from(level.as_str())).unwrap_or(Value::Null));
+    map.insert("file".into(), callsite.file.map(Value::from).unwrap_or(Value::Null));
+    map.insert("line".into(), callsite.line.map(Value::from).unwrap_or(Value::Null));
+
+    Value::Object(map)
+}
+
+/// A ratio or average, rounded to [`FLOAT_DECIMALS`].
+pub(crate) fn float(value: f64) -> Value {
+    fixed(value, FLOAT_DECIMALS)
+}
+
+/// `value` rounded to `decimals` places, or null if it isn't finite.
+fn fixed(value: f64, decimals: i32) -> Value {
+    let scale = 10f64.powi(decimals);
+    Number::from_f64((value * scale).round() / scale).map(Value::Number).unwrap_or(Value::Null)
+}
+
+/// Recorded field values, keeping numbers and bools as JSON ones.
+fn values_json(values: &[(&'static str, FieldValue)]) -> Value {
+    let values = values.iter().map(|(name, value)| {
+        let value = match value {
+            FieldValue::I64(value) => (*value).into(),
+            FieldValue::U64(value) => (*value).into(),
+            FieldValue::Bool(value) => (*value).into(),
+            FieldValue::F64(value) => float(*value),
+            FieldValue::Str(value) | FieldValue::Debug(value) => value.clone().into(),
+        };
+        (name.to_string(), value)
+    });
+
+    Value::Object(values.collect())
+}
+
+/// `{"task": id}` for a tracked task, `{"span": id}` for a raw span id.
+fn followed_json(followed: &FollowedTask) -> Value {
+    let mut map = Map::new();
+    match followed {
+        FollowedTask::Task(id) => map.insert("task".into(), (*id).into()),
+        FollowedTask::Span(id) => map.insert("span".into(), id.into_u64().into()),
+    };
+
+    Value::Object(map)
+}
+
+fn logs_json(logs: &TaskLogs) -> Value {
+    let mut map = Map::new();
+    map.insert("trace".into(), logs.trace.into());
+    map.insert("debug".into(), logs.debug.into());
+    map.insert("info".into(), logs.info.into());
+    map.insert("warn".into(), logs.warn.into());
+    map.insert("error".into(), logs.error.into());
+    map.insert("last_warn".into(), logs.last_warn.clone().map(Value::from).unwrap_or(Value::Null));
+    map.insert("last_error".into(), logs.last_error.clone().map(Value::from).unwrap_or(Value::Null));
+
+    Value::Object(map)
+}
+
+fn counters_json(counters: &BTreeMap<String, u64>) -> Value {
+    Value::Object(counters.iter().map(|(name, value)| (name.clone(), (*value).into())).collect())
+}
+
+fn csv_cell(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) if s.contains(&[',', '"', '\n', '\r'][..]) => format!("\"{}\"", s.replace('"', "\"\"")),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn prometheus_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn escape_key(key: &str) -> String {
+    key.replace(',', "\\,").replace('=', "\\=").replace(' ', "\\ ")
+}
+
+fn line_protocol_value(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::Bool(b) => Some(b.to_string()),
+        Value::Number(n) if n.is_f64() => Some(n.to_string()),
+        Value::Number(n) => Some(format!("{}i", n)),
+        Value::String(s) => Some(format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))),
+        _ => None,
+    }
+}
+
+/// Formats a time since the Unix epoch as an RFC 3339 UTC timestamp with
+/// microsecond precision.
+pub(crate) fn rfc3339(since_epoch: Duration) -> String {
+    let secs = since_epoch.as_secs();
+    let days = (secs / 86_400) as i64;
+    let (hour, minute, second) = (secs % 86_400 / 3600, secs % 3600 / 60, secs % 60);
+
+    // Days to civil date, from Howard Hinnant's `civil_from_days`.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+        year,
+        month,
+        day,
+        hour,
+        minute,
+        second,
+        since_epoch.subsec_micros(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::Stats;
+
+    use std::time::Instant;
+
+    /// Checks that every task format renders `value` under `key`.
+    fn assert_rendered(export: ExportConfig, task: &TaskSnapshot, key: &str, value: &Value) {
+        let tasks = std::slice::from_ref(task);
+
+        let jsonl: Value = serde_json::from_str(export.to_jsonl(tasks).trim_end()).unwrap();
+        assert_eq!(&jsonl[key], value, "JSONL {:?}", export);
+
+        let csv = export.to_csv(tasks);
+        let mut lines = csv.lines().map(|line| line.split(',').collect::<Vec<_>>());
+        let (header, row) = (lines.next().unwrap(), lines.next().unwrap());
+        let column = header.iter().position(|name| *name == key).unwrap_or_else(|| panic!("CSV {:?} has no {}", export, key));
+        assert_eq!(row[column], csv_cell(value), "CSV {:?}", export);
+
+        let line = export.to_line_protocol(tasks);
+        let field = format!("{}={}", key, line_protocol_value(value).unwrap());
+        let (_, fields) = line.trim_end().split_once(' ').unwrap();
+        assert!(fields.split(',').any(|rendered| rendered == field), "line protocol {:?} has no {}: {}", export, field, line);
+    }
+
+    #[test]
+    fn every_task_format_names_and_scales_durations_by_unit() {
+        let task = TaskSnapshot {
+            stats: Stats {
+                busy_time: Duration::from_micros(1500),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let cases = [
+            (DurationUnit::SecsF64, "busy_s", Value::from(0.0015)),
+            (DurationUnit::Millis, "busy_ms", Value::from(1)),
+            (DurationUnit::Micros, "busy_us", Value::from(1500)),
+            (DurationUnit::Nanos, "busy_ns", Value::from(1_500_000)),
+        ];
+        for (unit, key, value) in cases {
+            assert_rendered(ExportConfig::new(unit, TimestampFormat::default()), &task, key, &value);
+        }
+    }
+
+    #[test]
+    fn every_task_format_names_and_formats_timestamps_by_format() {
+        let created = Instant::now();
+        let task = TaskSnapshot {
+            stats: Stats {
+                created_at: Some(created),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let since_epoch = clock::wall_time(created).duration_since(UNIX_EPOCH).unwrap();
+
+        let cases = [
+            (TimestampFormat::EpochMillis, "created_at_ms", Value::from(since_epoch.as_millis() as u64)),
+            (TimestampFormat::EpochMicros, "created_at_us", Value::from(since_epoch.as_micros() as u64)),
+            (TimestampFormat::Rfc3339, "created_at", Value::from(rfc3339(since_epoch))),
+        ];
+        for (format, key, value) in cases {
+            assert_rendered(ExportConfig::new(DurationUnit::default(), format), &task, key, &value);
+        }
+    }
+
+    #[test]
+    fn durations_are_named_and_scaled_by_unit() {
+        let task = TaskSnapshot {
+            stats: Stats {
+                busy_time: Duration::from_micros(1500),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let cases = [
+            (DurationUnit::SecsF64, "busy_s", Value::from(0.0015)),
+            (DurationUnit::Millis, "busy_ms", Value::from(1)),
+            (DurationUnit::Micros, "busy_us", Value::from(1500)),
+            (DurationUnit::Nanos, "busy_ns", Value::from(1_500_000)),
+        ];
+        for (unit, key, value) in cases {
+            let json = ExportConfig::new(unit, TimestampFormat::default()).task_json(&task);
+            assert_eq!(json[key], value, "{:?}", unit);
+            // `busy_loop` and the busy time in this unit only.
+            assert_eq!(json.as_object().unwrap().keys().filter(|name| name.starts_with("busy_")).count(), 2);
+        }
+    }
+
+    #[test]
+    fn timestamps_are_named_and_formatted_by_format() {
+        let summary = Summary {
+            flushed_at: Some(UNIX_EPOCH + Duration::from_micros(1_700_000_000_123_456)),
+            ..Default::default()
+        };
+
+        let cases = [
+            (TimestampFormat::EpochMillis, "flushed_at_ms", Value::from(1_700_000_000_123u64)),
+            (TimestampFormat::EpochMicros, "flushed_at_us", Value::from(1_700_000_000_123_456u64)),
+            (TimestampFormat::Rfc3339, "flushed_at", Value::from("2023-11-14T22:13:20.123456Z")),
+        ];
+        for (format, key, value) in cases {
+            let json = ExportConfig::new(DurationUnit::default(), format).aggregates_json(&summary);
+            assert_eq!(json[key], value, "{:?}", format);
+        }
+    }
+}
@@ -0,0 +1,520 @@
+use crate::reporting::INTERNAL_TARGET;
+
+use tracing_core::{
+    field::{Field, FieldSet, Visit},
+    span::Attributes,
+};
+
+use std::{
+    fmt,
+    task::Poll,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+pub(crate) const DEADLINE_FIELD: &str = "task.deadline_ms";
+pub(crate) const BUDGET_POLLS_FIELD: &str = "task.budget_polls";
+pub(crate) const WAKE_FIELD: &str = "task.woken_at";
+pub(crate) const OUTCOME_FIELD: &str = "task.outcome";
+pub(crate) const KIND_FIELD: &str = "kind";
+pub(crate) const MEM_FIELD: &str = "task.mem_bytes";
+pub(crate) const POLL_FIELD: &str = "task.poll";
+pub(crate) const NAME_FIELD: &str = "task.name";
+pub(crate) const QUEUE_DEPTH_FIELD: &str = "queue.depth";
+pub(crate) const COUNTER_PREFIX: &str = "counter.";
+pub(crate) const TRACK_FIELD: &str = "bee.track";
+
+/// Names of the span fields the layer extracts typed values from.
+#[derive(Clone, Debug)]
+pub(crate) struct FieldConfig {
+    /// Field holding the time the task was last woken, in nanoseconds since
+    /// the Unix epoch.
+    pub wake_field: Option<String>,
+    /// Numeric field holding the task's relative weight.
+    pub weight_field: Option<String>,
+    /// Field holding the kind of task, as tokio reports it.
+    pub kind_field: Option<String>,
+    /// Numeric field marking a span as monitoring a queue, holding its depth.
+    pub queue_field: Option<String>,
+    /// Fields whose values tasks can be grouped by.
+    pub group_fields: Vec<String>,
+    /// Field identifying the logical task a span stands for.
+    pub stable_key_field: Option<String>,
+    /// Whether only spans with [`TRACK_FIELD`] set to `true` are tracked.
+    pub opt_in: bool,
+}
+
+impl Default for FieldConfig {
+    fn default() -> Self {
+        Self {
+            wake_field: Some(WAKE_FIELD.to_string()),
+            weight_field: None,
+            kind_field: Some(KIND_FIELD.to_string()),
+            queue_field: Some(QUEUE_DEPTH_FIELD.to_string()),
+            group_fields: Vec::new(),
+            stable_key_field: None,
+            opt_in: false,
+        }
+    }
+}
+
+impl FieldConfig {
+    fn is_wake_field(&self, field: &Field) -> bool {
+        self.wake_field.as_deref() == Some(field.name())
+    }
+
+    fn is_weight_field(&self, field: &Field) -> bool {
+        self.weight_field.as_deref() == Some(field.name())
+    }
+
+    fn is_kind_field(&self, field: &Field) -> bool {
+        self.kind_field.as_deref() == Some(field.name())
+    }
+
+    fn is_queue_field(&self, field: &Field) -> bool {
+        self.queue_field.as_deref() == Some(field.name())
+    }
+
+    fn is_group_field(&self, field: &Field) -> bool {
+        self.group_fields.iter().any(|name| name == field.name())
+    }
+
+    fn is_stable_key_field(&self, field: &Field) -> bool {
+        self.stable_key_field.as_deref() == Some(field.name())
+    }
+
+    /// Whether spans with these fields monitor a queue rather than a task.
+    /// The depth field only needs to be declared, e.g. as `field::Empty`.
+    pub fn is_queue(&self, fields: &FieldSet) -> bool {
+        match &self.queue_field {
+            Some(name) => fields.field(name).is_some(),
+            None => false,
+        }
+    }
+
+    /// Whether a span with these fields is tracked as far as opting in goes.
+    pub fn opts_in(&self, attrs: &Attributes<'_>) -> bool {
+        if !self.opt_in {
+            return true;
+        }
+        // Spans without the field at all needn't be visited.
+        if attrs.metadata().fields().field(TRACK_FIELD).is_none() {
+            return false;
+        }
+
+        let mut visitor = TrackVisitor(false);
+        attrs.record(&mut visitor);
+        visitor.0
+    }
+
+    /// Whether a [`RecordVisitor`] would pick anything up from these fields.
+    pub fn has_updates(&self, fields: &FieldSet) -> bool {
+        fields
+            .iter()
+            .any(|field| {
+                self.is_wake_field(&field)
+                    || self.is_queue_field(&field)
+                    || matches!(field.name(), OUTCOME_FIELD | MEM_FIELD | POLL_FIELD)
+                    || field.name().starts_with(COUNTER_PREFIX)
+            })
+    }
+}
+
+/// Expectations declared on a task span at spawn time, e.g.
+/// `info_span!("batch_flush", task.deadline_ms = 500)`.
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct Budget {
+    pub deadline: Option<Duration>,
+    pub polls: Option<u64>,
+}
+
+impl Budget {
+    pub fn is_empty(&self) -> bool {
+        self.deadline.is_none() && self.polls.is_none()
+    }
+}
+
+/// Typed values extracted from a span's fields when it was created.
+#[non_exhaustive]
+#[derive(Clone, Default, Debug)]
+pub struct TaskAttributes {
+    pub budget: Budget,
+    pub weight: Option<f64>,
+    pub kind: TaskKind,
+    /// The `task.name` field, as set by tokio on named tasks.
+    pub name: Option<String>,
+    /// The depth a queue span was created with.
+    pub queue_depth: Option<u64>,
+    /// Values of the fields registered with
+    /// [`Builder::group_by_field`](crate::Builder::group_by_field), by field name.
+    pub groups: Vec<(String, String)>,
+    /// The value of the field set with
+    /// [`Builder::stable_key_field`](crate::Builder::stable_key_field).
+    pub stable_key: Option<String>,
+    /// Every field the span was created with a value for, in order.
+    pub values: Vec<(&'static str, FieldValue)>,
+}
+
+/// A span field's value, as recorded.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FieldValue {
+    I64(i64),
+    U64(u64),
+    Bool(bool),
+    F64(f64),
+    Str(String),
+    /// A value recorded with `?` or `%`, formatted.
+    Debug(String),
+}
+
+impl fmt::Display for FieldValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::I64(value) => write!(f, "{}", value),
+            Self::U64(value) => write!(f, "{}", value),
+            Self::Bool(value) => write!(f, "{}", value),
+            Self::F64(value) => write!(f, "{}", value),
+            Self::Str(value) => write!(f, "{:?}", value),
+            Self::Debug(value) => f.write_str(value),
+        }
+    }
+}
+
+/// What kind of work a task is, as tagged by tokio on its task spans.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum TaskKind {
+    /// A future spawned with `tokio::spawn`.
+    Task,
+    /// A future spawned with `spawn_local`.
+    Local,
+    /// A closure run on the blocking pool with `spawn_blocking`.
+    Blocking,
+    /// A future run with `block_on`.
+    BlockOn,
+    /// The span didn't say, or said something unrecognised.
+    #[default]
+    Unknown,
+}
+
+impl TaskKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Task => "task",
+            Self::Local => "local",
+            Self::Blocking => "blocking",
+            Self::BlockOn => "block_on",
+            Self::Unknown => "unknown",
+        }
+    }
+
+    fn parse(value: &str) -> Self {
+        match value.trim_matches('"') {
+            "task" => Self::Task,
+            "local" => Self::Local,
+            "blocking" => Self::Blocking,
+            "block_on" => Self::BlockOn,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+impl fmt::Display for TaskKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Looks for [`TRACK_FIELD`] set to `true`.
+struct TrackVisitor(bool);
+
+impl Visit for TrackVisitor {
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        if field.name() == TRACK_FIELD {
+            self.0 = value;
+        }
+    }
+
+    fn record_debug(&mut self, _field: &Field, _value: &dyn fmt::Debug) {}
+}
+
+pub(crate) struct SpawnVisitor<'a> {
+    config: &'a FieldConfig,
+    pub attributes: TaskAttributes,
+}
+
+impl<'a> SpawnVisitor<'a> {
+    pub fn new(config: &'a FieldConfig) -> Self {
+        Self {
+            config,
+            attributes: Default::default(),
+        }
+    }
+
+    fn record_budget(&mut self, field: &Field, value: u64) {
+        match field.name() {
+            DEADLINE_FIELD => self.attributes.budget.deadline = Some(Duration::from_millis(value)),
+            BUDGET_POLLS_FIELD => self.attributes.budget.polls = Some(value),
+            _ => {}
+        }
+    }
+
+    fn record_depth(&mut self, field: &Field, value: u64) {
+        if self.config.is_queue_field(field) {
+            self.attributes.queue_depth = Some(value);
+        }
+    }
+
+    fn record_weight(&mut self, field: &Field, value: f64) {
+        if self.config.is_weight_field(field) && value.is_finite() {
+            self.attributes.weight = Some(value);
+        }
+    }
+
+    fn record_group(&mut self, field: &Field, value: impl fmt::Display) {
+        if self.config.is_group_field(field) {
+            self.attributes.groups.push((field.name().to_string(), value.to_string()));
+        }
+        if self.config.is_stable_key_field(field) {
+            self.attributes.stable_key = Some(value.to_string());
+        }
+    }
+}
+
+impl Visit for SpawnVisitor<'_> {
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.record_budget(field, value);
+        self.record_depth(field, value);
+        self.record_weight(field, value as f64);
+        self.record_group(field, value);
+        self.attributes.values.push((field.name(), FieldValue::U64(value)));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        if value >= 0 {
+            self.record_budget(field, value as u64);
+            self.record_depth(field, value as u64);
+        }
+        self.record_weight(field, value as f64);
+        self.record_group(field, value);
+        self.attributes.values.push((field.name(), FieldValue::I64(value)));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.record_weight(field, value);
+        self.record_group(field, value);
+        self.attributes.values.push((field.name(), FieldValue::F64(value)));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.record_group(field, value);
+        self.attributes.values.push((field.name(), FieldValue::Bool(value)));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if self.config.is_weight_field(field) {
+            if let Ok(value) = value.trim().parse() {
+                self.record_weight(field, value);
+            }
+        }
+        self.record_group(field, value);
+        if self.config.is_kind_field(field) {
+            self.attributes.kind = TaskKind::parse(value);
+        }
+        if field.name() == NAME_FIELD {
+            self.attributes.name = Some(value.to_string());
+        }
+        self.attributes.values.push((field.name(), FieldValue::Str(value.to_string())));
+    }
+
+    // Tokio records the kind and name with `%`, which arrives here.
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        let value = format!("{:?}", value);
+        if self.config.is_kind_field(field) {
+            self.attributes.kind = TaskKind::parse(&value);
+        }
+        if field.name() == NAME_FIELD {
+            self.attributes.name = Some(value.clone());
+        }
+        self.record_group(field, &value);
+        self.attributes.values.push((field.name(), FieldValue::Debug(value)));
+    }
+}
+
+/// How a task spawned through [`spawn_tracked`](crate::spawn_tracked) ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TaskOutcome {
+    /// The future ran to completion.
+    Completed,
+    /// The future panicked while being polled.
+    Panicked,
+    /// The future was dropped before completing, e.g. because it was aborted.
+    Dropped,
+}
+
+impl TaskOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Completed => "ok",
+            Self::Panicked => "panicked",
+            Self::Dropped => "dropped",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "ok" => Some(Self::Completed),
+            "panicked" => Some(Self::Panicked),
+            "dropped" => Some(Self::Dropped),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for TaskOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Typed values extracted from a span's fields after it was created.
+#[non_exhaustive]
+#[derive(Clone, Default, Debug)]
+pub struct FieldUpdates {
+    pub woken_at: Option<SystemTime>,
+    pub outcome: Option<TaskOutcome>,
+    /// Memory the task reports holding, in bytes.
+    pub mem_bytes: Option<u64>,
+    /// What a poll inside the task returned, as reported by
+    /// [`TrackFuture`](crate::TrackFuture).
+    pub poll: Option<Poll<()>>,
+    /// The depth of a queue span.
+    pub queue_depth: Option<u64>,
+    /// Amounts to add to the task's counters, from `counter.<name>` fields.
+    pub counters: Vec<(String, u64)>,
+    /// The span's fields formatted again with the values recorded on it.
+    pub fields: Option<String>,
+    /// The values recorded on the span, replacing any it had by the same name.
+    pub values: Vec<(&'static str, FieldValue)>,
+}
+
+impl FieldUpdates {
+    pub fn is_empty(&self) -> bool {
+        self.woken_at.is_none()
+            && self.outcome.is_none()
+            && self.mem_bytes.is_none()
+            && self.poll.is_none()
+            && self.queue_depth.is_none()
+            && self.counters.is_empty()
+            && self.fields.is_none()
+            && self.values.is_empty()
+    }
+}
+
+/// Sets each of `recorded` in `values`, in place of a value of the same name.
+pub(crate) fn merge_values(values: &mut Vec<(&'static str, FieldValue)>, recorded: Vec<(&'static str, FieldValue)>) {
+    for (name, value) in recorded {
+        match values.iter_mut().find(|(existing, _)| *existing == name) {
+            Some((_, existing)) => *existing = value,
+            None => values.push((name, value)),
+        }
+    }
+}
+
+pub(crate) struct RecordVisitor<'a> {
+    config: &'a FieldConfig,
+    /// Whether to keep every value, as for `Span::record`, rather than only
+    /// the ones updating the task, as for events.
+    keep_values: bool,
+    pub updates: FieldUpdates,
+}
+
+impl<'a> RecordVisitor<'a> {
+    pub fn new(config: &'a FieldConfig) -> Self {
+        Self {
+            config,
+            keep_values: false,
+            updates: Default::default(),
+        }
+    }
+
+    pub fn keeping_values(config: &'a FieldConfig) -> Self {
+        Self {
+            keep_values: true,
+            ..Self::new(config)
+        }
+    }
+
+    /// Picks up the updates numeric fields make.
+    fn update_u64(&mut self, field: &Field, value: u64) {
+        if self.config.is_wake_field(field) {
+            self.updates.woken_at = UNIX_EPOCH.checked_add(Duration::from_nanos(value));
+        }
+        if field.name() == MEM_FIELD {
+            self.updates.mem_bytes = Some(value);
+        }
+        if self.config.is_queue_field(field) {
+            self.updates.queue_depth = Some(value);
+        }
+        if let Some(name) = field.name().strip_prefix(COUNTER_PREFIX) {
+            self.updates.counters.push((name.to_string(), value));
+        }
+    }
+
+    fn keep(&mut self, field: &Field, value: impl FnOnce() -> FieldValue) {
+        if self.keep_values {
+            self.updates.values.push((field.name(), value()));
+        }
+    }
+
+    /// Counters only count up by whole amounts.
+    fn ignore_counter(&self, field: &Field) {
+        if field.name().starts_with(COUNTER_PREFIX) {
+            tracing::debug!(target: INTERNAL_TARGET, "Ignoring counter {} without a non-negative integer value", field.name());
+        }
+    }
+}
+
+impl Visit for RecordVisitor<'_> {
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.keep(field, || FieldValue::U64(value));
+        self.update_u64(field, value);
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.keep(field, || FieldValue::I64(value));
+        if value >= 0 {
+            self.update_u64(field, value as u64);
+        } else {
+            self.ignore_counter(field);
+        }
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.keep(field, || FieldValue::F64(value));
+        self.ignore_counter(field);
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.keep(field, || FieldValue::Bool(value));
+        self.ignore_counter(field);
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.keep(field, || FieldValue::Str(value.to_string()));
+        match field.name() {
+            OUTCOME_FIELD => self.updates.outcome = TaskOutcome::parse(value),
+            POLL_FIELD => {
+                self.updates.poll = match value {
+                    "ready" => Some(Poll::Ready(())),
+                    "pending" => Some(Poll::Pending),
+                    _ => None,
+                }
+            }
+            _ => self.ignore_counter(field),
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.keep(field, || FieldValue::Debug(format!("{:?}", value)));
+        self.ignore_counter(field);
+    }
+}
@@ -0,0 +1,474 @@
+use crate::{callsite::Callsite, reporting, spawn::POLL_TARGET, waker::WAKER_TARGET};
+
+use tracing_core::{callsite::Identifier, subscriber::Interest, LevelFilter, Metadata};
+use tracing_subscriber::layer::{Context, Filter};
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+};
+
+/// Names of the spans tokio creates for spawned tasks when built with `tokio_unstable`.
+pub const TOKIO_TASK_SPANS: &[&str] = &["runtime.spawn", "runtime.blocking"];
+
+/// Returns a per-layer filter that only lets tokio task spans through,
+/// along with the events that update the tasks they happen in: waker
+/// operations and poll outcomes, plus logs and counters no more verbose
+/// than the [maximum level](TasksOnly::with_max_level).
+///
+/// ```ignore
+/// registry()
+///     .with(bee_layer.with_filter(filter::tasks_only()))
+///     .with(fmt_layer)
+///     .init();
+/// ```
+pub fn tasks_only() -> TasksOnly {
+    TasksOnly {
+        names: TOKIO_TASK_SPANS.iter().map(|name| name.to_string()).collect(),
+        targets: Vec::new(),
+        max_level: None,
+        traffic: None,
+    }
+}
+
+/// A [`Filter`] matching task spans by name, plus any extra span names or
+/// target prefixes configured by the user. Waker and poll events always
+/// match, other events only up to the maximum level.
+#[derive(Clone, Debug)]
+pub struct TasksOnly {
+    names: Vec<String>,
+    targets: Vec<String>,
+    max_level: Option<LevelFilter>,
+    traffic: Option<FilteredTraffic>,
+}
+
+impl TasksOnly {
+    /// Also match spans with the given name.
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.names.push(name.into());
+        self
+    }
+
+    /// Also match every span whose target starts with the given prefix.
+    pub fn with_target(mut self, prefix: impl Into<String>) -> Self {
+        self.targets.push(prefix.into());
+        self
+    }
+
+    /// Reject spans and events more verbose than `level`, other than
+    /// tokio's waker events and the layer's own poll events.
+    pub fn with_max_level(mut self, level: impl Into<LevelFilter>) -> Self {
+        self.max_level = Some(level.into());
+        self
+    }
+
+    /// Counts the spans and events filtered out per callsite into `traffic`,
+    /// usually [`BeeLayer::filtered_traffic`](crate::BeeLayer::filtered_traffic),
+    /// so they show up in its summaries.
+    ///
+    /// Filtered out callsites are then asked about every time they are hit
+    /// rather than once, which costs a map lookup and an atomic increment.
+    pub fn account_to(mut self, traffic: FilteredTraffic) -> Self {
+        traffic.enable();
+        self.traffic = Some(traffic);
+        self
+    }
+
+    fn matches(&self, meta: &Metadata<'_>) -> bool {
+        if meta.is_event() && (meta.target() == WAKER_TARGET || meta.target() == POLL_TARGET) {
+            return true;
+        }
+
+        if let Some(max_level) = self.max_level {
+            if *meta.level() > max_level {
+                return false;
+            }
+        }
+
+        meta.is_event()
+            || self.names.iter().any(|name| name == meta.name())
+            || self.targets.iter().any(|prefix| meta.target().starts_with(prefix.as_str()))
+    }
+}
+
+impl<S> Filter<S> for TasksOnly {
+    fn enabled(&self, meta: &Metadata<'_>, _cx: &Context<'_, S>) -> bool {
+        if self.matches(meta) {
+            return true;
+        }
+
+        if let Some(traffic) = &self.traffic {
+            traffic.count(&meta.callsite(), meta.is_span());
+        }
+        false
+    }
+
+    fn callsite_enabled(&self, meta: &'static Metadata<'static>) -> Interest {
+        if self.matches(meta) {
+            return Interest::always();
+        }
+
+        match &self.traffic {
+            Some(traffic) => {
+                traffic.register(meta);
+                Interest::sometimes()
+            }
+            None => Interest::never(),
+        }
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        // Waker and poll events are logged at trace level.
+        self.max_level.map(|level| level.max(LevelFilter::TRACE))
+    }
+}
+
+/// Spans and events kept from the collector, by a [`TasksOnly`] filter
+/// accounting to it or by the layer itself, counted per callsite.
+///
+/// Clones share their counts.
+#[derive(Clone, Default)]
+pub struct FilteredTraffic {
+    inner: Arc<FilteredInner>,
+}
+
+#[derive(Default)]
+struct FilteredInner {
+    enabled: AtomicBool,
+    callsites: RwLock<HashMap<Identifier, Arc<FilteredCounts>>>,
+}
+
+struct FilteredCounts {
+    callsite: Callsite,
+    spans: AtomicU64,
+    events: AtomicU64,
+}
+
+impl fmt::Debug for FilteredTraffic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FilteredTraffic").field("callsites", &self.summary()).finish()
+    }
+}
+
+/// What was filtered out of one callsite, as reported on each flush.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FilteredCallsite {
+    pub callsite: Callsite,
+    /// Spans created and not tracked.
+    pub spans: u64,
+    /// Events, and entries and exits of untracked spans.
+    pub events: u64,
+}
+
+impl FilteredTraffic {
+    pub(crate) fn enable(&self) {
+        self.inner.enabled.store(true, Ordering::Relaxed);
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.inner.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Makes room for a callsite's counts ahead of counting into them.
+    pub(crate) fn register(&self, meta: &'static Metadata<'static>) {
+        let mut callsites = self.inner.callsites.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        callsites.entry(meta.callsite()).or_insert_with(|| {
+            Arc::new(FilteredCounts {
+                callsite: Callsite::from_metadata(meta),
+                spans: AtomicU64::new(0),
+                events: AtomicU64::new(0),
+            })
+        });
+    }
+
+    /// Counts a span or event from a registered callsite.
+    pub(crate) fn count(&self, callsite: &Identifier, is_span: bool) {
+        let callsites = self.inner.callsites.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        if let Some(counts) = callsites.get(callsite) {
+            let counter = if is_span { &counts.spans } else { &counts.events };
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Counts a span or event, registering its callsite if needed.
+    pub(crate) fn record(&self, meta: &'static Metadata<'static>, is_span: bool) {
+        let registered = {
+            let callsites = self.inner.callsites.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+            callsites.contains_key(&meta.callsite())
+        };
+        if !registered {
+            self.register(meta);
+        }
+
+        self.count(&meta.callsite(), is_span);
+    }
+
+    /// Every callsite anything was filtered out of, most filtered first.
+    pub fn summary(&self) -> Vec<FilteredCallsite> {
+        let callsites = self.inner.callsites.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let mut summary: Vec<_> = callsites
+            .values()
+            .map(|counts| FilteredCallsite {
+                callsite: counts.callsite,
+                spans: counts.spans.load(Ordering::Relaxed),
+                events: counts.events.load(Ordering::Relaxed),
+            })
+            .filter(|filtered| filtered.spans + filtered.events > 0)
+            .collect();
+        summary.sort_by(|a, b| (b.spans + b.events).cmp(&(a.spans + a.events)).then(a.callsite.cmp(&b.callsite)));
+
+        summary
+    }
+}
+
+/// The callsites the layer tracks spans from, by target prefix, set with
+/// [`Builder::include_targets`](crate::Builder::include_targets), by span
+/// name, set with [`Builder::tasks_only`](crate::Builder::tasks_only), and
+/// by level, set with [`Builder::max_level`](crate::Builder::max_level).
+/// Each callsite's decision is cached, so they are only compared the first
+/// time a callsite opens a span.
+pub(crate) struct CallsiteAllowlist {
+    prefixes: Option<Vec<String>>,
+    names: Option<&'static [&'static str]>,
+    max_level: Option<LevelFilter>,
+    callsites: RwLock<HashMap<Identifier, bool>>,
+}
+
+impl CallsiteAllowlist {
+    /// Returns `None` if every callsite is allowed.
+    pub fn new(
+        prefixes: Option<Vec<String>>,
+        names: Option<&'static [&'static str]>,
+        max_level: Option<LevelFilter>,
+    ) -> Option<Self> {
+        if prefixes.is_none() && names.is_none() && max_level.is_none() {
+            return None;
+        }
+
+        Some(Self {
+            prefixes,
+            names,
+            max_level,
+            callsites: Default::default(),
+        })
+    }
+
+    pub fn allows(&self, meta: &Metadata<'_>) -> bool {
+        let cached = self.callsites.read().unwrap_or_else(|poisoned| poisoned.into_inner()).get(&meta.callsite()).copied();
+        if let Some(allowed) = cached {
+            return allowed;
+        }
+
+        let allowed = self.prefixes.as_ref().is_none_or(|prefixes| {
+            prefixes.iter().any(|prefix| meta.target().starts_with(prefix.as_str()))
+        }) && self.names.is_none_or(|names| names.contains(&meta.name()))
+            && self.max_level.is_none_or(|max_level| *meta.level() <= max_level);
+        let mut callsites = self.callsites.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+        callsites.insert(meta.callsite(), allowed);
+
+        allowed
+    }
+}
+
+/// A per-layer [`Filter`] leaving out the spans a layer doesn't track by
+/// their callsite, see [`BeeLayer::callsite_filter`](crate::BeeLayer::callsite_filter).
+///
+/// Events are let through wherever they come from, as they update the
+/// tasks they happen in.
+#[derive(Clone)]
+pub struct CallsiteFilter {
+    pub(crate) callsites: Option<Arc<CallsiteAllowlist>>,
+    pub(crate) traffic: Option<FilteredTraffic>,
+}
+
+impl fmt::Debug for CallsiteFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CallsiteFilter").field("allowlist", &self.callsites.is_some()).finish()
+    }
+}
+
+impl CallsiteFilter {
+    fn allows(&self, meta: &Metadata<'_>) -> bool {
+        if reporting::is_own_target(meta.target()) {
+            return false;
+        }
+
+        meta.is_event() || self.callsites.as_ref().is_none_or(|callsites| callsites.allows(meta))
+    }
+}
+
+impl<S> Filter<S> for CallsiteFilter {
+    fn enabled(&self, meta: &Metadata<'_>, _cx: &Context<'_, S>) -> bool {
+        if self.allows(meta) {
+            return true;
+        }
+
+        if let Some(traffic) = &self.traffic {
+            traffic.count(&meta.callsite(), meta.is_span());
+        }
+        false
+    }
+
+    fn callsite_enabled(&self, meta: &'static Metadata<'static>) -> Interest {
+        if self.allows(meta) {
+            return Interest::always();
+        }
+
+        // Counting what is filtered out means being asked every time.
+        match &self.traffic {
+            Some(traffic) => {
+                traffic.register(meta);
+                Interest::sometimes()
+            }
+            None => Interest::never(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Running;
+    use crate::BeeLayer;
+
+    use tracing::Level;
+
+    use tracing_subscriber::{fmt::format::FmtSpan, layer::SubscriberExt, Layer};
+
+    use std::{
+        io::{self, Write},
+        sync::Mutex,
+        time::Duration,
+    };
+
+    /// Collects what a fmt layer writes.
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn tasks_only_lets_waker_events_through_past_the_max_level() {
+        let (layer, collector) = BeeLayer::pair();
+        let filter = tasks_only().with_name("job").with_max_level(Level::INFO);
+        let subscriber = tracing_subscriber::registry().with(layer.with_filter(filter));
+        let running = Running::under(subscriber, collector);
+
+        let span = tracing::info_span!("job");
+        let id = span.id().unwrap().into_u64();
+        span.in_scope(|| {
+            tracing::info!("kept");
+            tracing::trace!("too verbose");
+        });
+        tracing::trace!(target: WAKER_TARGET, op = "waker.wake", task.id = id);
+        tracing::trace!(target: WAKER_TARGET, op = "waker.clone", task.id = id);
+        tracing::trace!(target: WAKER_TARGET, op = "waker.drop", task.id = id);
+
+        let tasks = running.finish().await;
+
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].stats.wakes(), 1);
+        assert_eq!(tasks[0].stats.waker_clones(), 1);
+        assert_eq!(tasks[0].stats.waker_drops(), 1);
+        assert_eq!(tasks[0].logs.total(), 1);
+    }
+
+    #[test]
+    fn max_level_hint_only_rises_for_waker_and_poll_events() {
+        let hint = |filter: TasksOnly| Filter::<tracing_subscriber::Registry>::max_level_hint(&filter);
+        assert_eq!(hint(tasks_only()), None);
+        assert_eq!(hint(tasks_only().with_max_level(Level::INFO)), Some(LevelFilter::TRACE));
+    }
+
+    #[tokio::test]
+    async fn fmt_sees_every_span_while_the_layer_tracks_tasks() {
+        let output = Capture::default();
+        let writer = output.clone();
+        let fmt = tracing_subscriber::fmt::layer()
+            .with_span_events(FmtSpan::NEW)
+            .with_ansi(false)
+            .with_writer(move || writer.clone());
+
+        let (layer, collector) = BeeLayer::pair();
+        let subscriber = tracing_subscriber::registry().with(layer.with_filter(tasks_only())).with(fmt);
+        let running = Running::under(subscriber, collector);
+
+        tracing::info_span!("runtime.spawn").in_scope(|| {});
+        tracing::info_span!("request").in_scope(|| {});
+        tracing::info_span!("query").in_scope(|| {});
+
+        let tasks = running.finish().await;
+
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].callsite.unwrap().name, "runtime.spawn");
+        let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        for name in ["runtime.spawn", "request", "query"] {
+            assert!(output.contains(&format!("INFO {}: bee_console::filter::tests: new", name)), "{} missing", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn callsite_filter_leaves_out_untracked_callsites_for_the_layer_alone() {
+        let output = Capture::default();
+        let writer = output.clone();
+        let fmt = tracing_subscriber::fmt::layer()
+            .with_span_events(FmtSpan::NEW)
+            .with_ansi(false)
+            .with_writer(move || writer.clone());
+
+        let (layer, collector) = BeeLayer::builder().include_targets(["my_app"]).build();
+        let filter = layer.callsite_filter();
+        let subscriber = tracing_subscriber::registry().with(layer.with_filter(filter)).with(fmt);
+        let running = Running::under(subscriber, collector);
+
+        tracing::info_span!(target: "hyper::proto", "conn").in_scope(|| {});
+        tracing::info_span!(target: "my_app", "handler").in_scope(|| {});
+
+        let tasks = running.finish().await;
+
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].callsite.unwrap().name, "handler");
+        let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        assert!(output.contains("INFO conn: hyper::proto: new"), "{}", output);
+        assert!(output.contains("INFO handler: my_app: new"), "{}", output);
+    }
+
+    #[tokio::test]
+    async fn filtered_traffic_is_attributed_to_its_callsite() {
+        let running = Running::start(BeeLayer::builder().include_targets(["my_app"]).track_filtered_traffic().build());
+
+        for _ in 0..3 {
+            let span = tracing::info_span!(target: "hyper::proto", "conn");
+            span.in_scope(|| {});
+            span.in_scope(|| {});
+        }
+        tracing::info_span!(target: "my_app", "handler").in_scope(|| {});
+
+        let summary = running.handle.debug_report(Duration::from_secs(5)).await.summary;
+        assert_eq!(summary.tasks.len(), 1);
+        let filtered: Vec<_> = summary
+            .filtered_traffic
+            .iter()
+            .map(|filtered| (filtered.callsite.target, filtered.callsite.name, filtered.spans, filtered.events))
+            .collect();
+        // Each poll is an enter and an exit.
+        assert_eq!(filtered, [("hyper::proto", "conn", 3, 12)]);
+
+        running.finish().await;
+    }
+}
@@ -0,0 +1,151 @@
+use crate::{collector::Collector, reporting::INTERNAL_TARGET};
+
+use tokio::sync::oneshot;
+
+use std::{
+    io,
+    sync::mpsc::{self, RecvTimeoutError},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// Runs a collector on a thread of its own, shutting it down with a final
+/// drain and flush when dropped, e.g. at the end of `main`. Returned by
+/// [`Collector::spawn`].
+///
+/// Without it, programs that exit before the collector's first flush never
+/// see a report. Dropping the guard waits for the final report, for up to
+/// [`FlushGuard::TIMEOUT`] unless [set otherwise](FlushGuard::timeout).
+/// The collector runs on a runtime of its own, so this is safe from inside
+/// or outside of an async context, on any runtime.
+#[must_use = "dropping the guard shuts the collector down straight away"]
+pub struct FlushGuard {
+    stop: Option<oneshot::Sender<()>>,
+    /// Disconnects once the collector's thread is done.
+    done: mpsc::Receiver<()>,
+    thread: Option<JoinHandle<()>>,
+    timeout: Duration,
+}
+
+impl FlushGuard {
+    /// How long dropping the guard waits for the final report by default.
+    pub const TIMEOUT: Duration = Duration::from_secs(2);
+
+    pub(crate) fn spawn(collector: Collector) -> io::Result<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_time().build()?;
+        let (stop, stopped) = oneshot::channel();
+        let (done, done_rx) = mpsc::channel::<()>();
+
+        let thread = thread::Builder::new().name("bee-console-collector".into()).spawn(move || {
+            let _done = done;
+            let handle = collector.handle();
+
+            runtime.block_on(async move {
+                let run = collector.run();
+                tokio::pin!(run);
+
+                tokio::select! {
+                    _ = &mut run => {}
+                    _ = stopped => {
+                        let _ = tokio::join!(handle.shutdown(), run);
+                    }
+                }
+            });
+        })?;
+
+        Ok(Self {
+            stop: Some(stop),
+            done: done_rx,
+            thread: Some(thread),
+            timeout: Self::TIMEOUT,
+        })
+    }
+
+    /// A guard with no collector behind it, returned by
+    /// [`NoopCollector::spawn`](crate::NoopCollector::spawn).
+    pub(crate) fn noop() -> Self {
+        let (_, done) = mpsc::channel();
+
+        Self {
+            stop: None,
+            done,
+            thread: None,
+            timeout: Self::TIMEOUT,
+        }
+    }
+
+    /// Sets how long dropping the guard waits for the final report.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+impl Drop for FlushGuard {
+    fn drop(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            // Fails only if the collector already stopped by itself.
+            let _ = stop.send(());
+        }
+
+        match self.done.recv_timeout(self.timeout) {
+            Err(RecvTimeoutError::Disconnected) | Ok(()) => {
+                if let Some(thread) = self.thread.take() {
+                    let _ = thread.join();
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                tracing::warn!(
+                    target: INTERNAL_TARGET,
+                    "Collector did not finish its final report within {:?}, no longer waiting for it",
+                    self.timeout,
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{BeeLayer, Summary};
+
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use std::sync::{Arc, Mutex};
+
+    /// A short-lived program: spawns a few tasks and returns well before
+    /// the first flush. Returns every summary the sink received.
+    fn short_lived_program() -> Vec<Summary> {
+        let summaries = Arc::new(Mutex::new(Vec::new()));
+        let sink = summaries.clone();
+        let (layer, collector) = BeeLayer::builder()
+            .summary_sink(move |summary: &Summary| sink.lock().unwrap().push(summary.clone()))
+            .build();
+        let _guard = collector.spawn().unwrap();
+        let _default = tracing::subscriber::set_default(tracing_subscriber::registry().with(layer));
+
+        for job in 0..5 {
+            tracing::info_span!("job", job).in_scope(|| {});
+        }
+
+        drop(_default);
+        drop(_guard);
+        Arc::try_unwrap(summaries).unwrap().into_inner().unwrap()
+    }
+
+    fn assert_complete(summaries: &[Summary]) {
+        let last = summaries.last().expect("a final summary");
+        assert_eq!(last.tasks.len(), 5);
+        assert!(last.tasks.iter().all(|task| task.stats.polls() == 1 && task.stats.closed_at().is_some()));
+    }
+
+    #[test]
+    fn dropping_the_guard_reports_a_program_that_returns_straight_away() {
+        assert_complete(&short_lived_program());
+    }
+
+    #[tokio::test]
+    async fn dropping_the_guard_inside_a_runtime_does_not_panic() {
+        assert_complete(&short_lived_program());
+    }
+}
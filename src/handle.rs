@@ -0,0 +1,307 @@
+use crate::{
+    builder::Builder,
+    config::EffectiveConfig,
+    inject::EventInjector,
+    sender::{EventSender, Shared, WeakEventChannel},
+    report::{CollectorHealth, DebugReport},
+    snapshot::TaskSnapshot,
+    summary::Summary,
+};
+
+use tokio::sync::{
+    mpsc::{self, Receiver},
+    oneshot,
+};
+
+use std::{
+    error::Error,
+    fmt,
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, SystemTime},
+};
+
+pub(crate) enum Command {
+    Shutdown(oneshot::Sender<Vec<TaskSnapshot>>),
+    Report(oneshot::Sender<Summary>),
+    SetTickInterval(Duration),
+}
+
+/// Controls a running collector from elsewhere in the program.
+#[derive(Clone)]
+pub struct CollectorHandle {
+    pub(crate) commands: mpsc::Sender<Command>,
+    pub(crate) events: WeakEventChannel,
+    pub(crate) shared: Arc<Shared>,
+}
+
+impl CollectorHandle {
+    /// Returns an injector feeding events straight into the collector, or
+    /// `None` if its event channel has already closed.
+    ///
+    /// The injector keeps the channel open for as long as it lives.
+    pub fn injector(&self) -> Option<EventInjector> {
+        let sender = self.events.upgrade()?;
+
+        Some(EventInjector {
+            events: EventSender::new(sender, self.shared.clone()),
+        })
+    }
+
+    /// Captures everything the collector knows in one [`DebugReport`].
+    ///
+    /// The collector is asked for a fresh summary, but if it doesn't answer
+    /// within `timeout`, e.g. because the runtime it runs on is blocked, the
+    /// summary from its last flush is used instead and the report is marked
+    /// as stale.
+    pub async fn debug_report(&self, timeout: Duration) -> DebugReport {
+        let (tx, rx) = oneshot::channel();
+
+        let fresh = match self.commands.try_send(Command::Report(tx)) {
+            Ok(()) => tokio::time::timeout(timeout, rx).await.ok().and_then(Result::ok),
+            Err(_) => None,
+        };
+        let stale = fresh.is_none();
+
+        let summary = match fresh {
+            Some(summary) => summary,
+            None => self.shared.last_summary().map(|summary| (*summary).clone()).unwrap_or_default(),
+        };
+
+        DebugReport {
+            generated_at: SystemTime::now(),
+            stale,
+            config: self.shared.effective_config(),
+            collector: CollectorHealth {
+                running: !self.commands.is_closed(),
+                since_heartbeat: self.shared.since_heartbeat(),
+                stalls: self.shared.stalls.load(Ordering::Relaxed),
+                active_tasks: self.shared.active.load(Ordering::Relaxed),
+                last_event_at: self.shared.last_event_at(),
+            },
+            summary,
+        }
+    }
+
+    /// Returns a receiver yielding each task's final snapshot as it closes,
+    /// for forwarding completed tasks somewhere as they finish rather than
+    /// once per flush.
+    ///
+    /// Each call returns a new receiver seeing every task closed from then
+    /// on, transient ones included. A receiver holds up to
+    /// [`CLOSE_EVENTS_CAPACITY`](crate::CLOSE_EVENTS_CAPACITY) snapshots; while it is full, further
+    /// snapshots are dropped for it and counted in
+    /// [`Summary::dropped_close_events`]. The collector never waits for a
+    /// receiver. Receivers end once the collector stops.
+    pub fn close_events(&self) -> Receiver<TaskSnapshot> {
+        self.shared.close_events.subscribe()
+    }
+
+    /// The settings the collector runs with right now, including changes
+    /// made through this handle. Doesn't wait for the collector.
+    pub fn config(&self) -> EffectiveConfig {
+        self.shared.effective_config()
+    }
+
+    /// Changes how often the collector flushes, keeping everything it has
+    /// collected so far. The next flush happens one new interval from when
+    /// the collector picks up the change. Raised to
+    /// [`Builder::MIN_TICK_INTERVAL`](crate::Builder::MIN_TICK_INTERVAL) if
+    /// shorter, as on the [`Builder`](crate::Builder).
+    pub async fn set_tick_interval(&self, interval: Duration) -> Result<(), CollectorClosed> {
+        self.commands
+            .send(Command::SetTickInterval(interval))
+            .await
+            .map_err(|_| CollectorClosed)?;
+        // Reported by `config` from now on, before the collector picks it up.
+        self.shared.set_tick_interval(interval.max(Builder::MIN_TICK_INTERVAL));
+
+        Ok(())
+    }
+
+    /// Only tracks tasks spawned from now on whose name is in `names`, or
+    /// every task again if `names` is `None`, which is the default.
+    ///
+    /// A task's name is its `task.name` field, as set by tokio on named
+    /// tasks, or else the name of its span. Tasks already tracked carry on
+    /// being tracked until they close. Summaries list the allowlist while it
+    /// is set.
+    pub fn set_name_allowlist(&self, names: Option<Vec<String>>) {
+        self.shared.set_allowlist(names.map(|names| names.into_iter().collect()));
+    }
+
+    /// Stops the collector and returns the final state of every task it knows about.
+    ///
+    /// All events queued before this call are applied first. The handle is
+    /// consumed; other clones of it will fail with [`CollectorClosed`] afterwards.
+    pub async fn shutdown(self) -> Result<Vec<TaskSnapshot>, CollectorClosed> {
+        let (tx, rx) = oneshot::channel();
+
+        self.commands
+            .send(Command::Shutdown(tx))
+            .await
+            .map_err(|_| CollectorClosed)?;
+
+        rx.await.map_err(|_| CollectorClosed)
+    }
+}
+
+/// The collector has already stopped running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CollectorClosed;
+
+impl fmt::Display for CollectorClosed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("collector is no longer running")
+    }
+}
+
+impl Error for CollectorClosed {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Running;
+    use crate::{BeeLayer, Summary};
+
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use std::sync::{Arc, Mutex};
+
+    #[tokio::test]
+    async fn shutdown_returns_every_task_closed() {
+        let (layer, collector) = BeeLayer::pair();
+        let handle = collector.handle();
+        let _default = tracing::subscriber::set_default(tracing_subscriber::registry().with(layer));
+        tokio::spawn(collector.run());
+
+        for job in 0..5 {
+            tracing::info_span!("job", job).in_scope(|| {});
+        }
+
+        let tasks = handle.shutdown().await.unwrap();
+        assert_eq!(tasks.len(), 5);
+        for task in tasks {
+            assert!(!task.stats.is_active());
+            assert!(task.stats.closed_at().is_some());
+            assert_eq!(task.stats.polls(), 1);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn close_events_yield_each_final_snapshot() {
+        let running = Running::start(BeeLayer::pair());
+        let mut closes = running.handle.close_events();
+        let mut lagging = running.handle.close_events();
+
+        for job in 0..3 {
+            let span = tracing::info_span!("job", job);
+            for _ in 0..=job {
+                span.in_scope(|| {});
+            }
+        }
+        for job in 0..3 {
+            let task = closes.recv().await.unwrap();
+            assert_eq!(task.fields, format!("job={}", job));
+            assert_eq!(task.stats.polls(), job + 1);
+            assert!(task.stats.closed_at().is_some());
+        }
+
+        // `closes` keeps up, a flush at a time, while `lagging` is never read.
+        for batch in 0..crate::CLOSE_EVENTS_CAPACITY / 8 {
+            for job in 0..8 {
+                tracing::info_span!("job", job = batch * 8 + job).in_scope(|| {});
+            }
+            for _ in 0..8 {
+                closes.recv().await.unwrap();
+            }
+        }
+        assert_eq!(running.handle.debug_report(Duration::from_secs(5)).await.summary.dropped_close_events, 3);
+
+        running.finish().await;
+        assert!(closes.recv().await.is_none());
+        assert_eq!(lagging.recv().await.unwrap().fields, "job=0");
+        let mut read = 1;
+        while lagging.recv().await.is_some() {
+            read += 1;
+        }
+        assert_eq!(read, crate::CLOSE_EVENTS_CAPACITY);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn flushes_follow_a_changed_interval() {
+        let flushes = Arc::new(Mutex::new(Vec::new()));
+        let seen = flushes.clone();
+        let running = Running::start(BeeLayer::builder()
+            .flush_interval(Duration::from_secs(1))
+            .summary_sink(move |summary: &Summary| seen.lock().unwrap().push((tokio::time::Instant::now(), summary.tasks.len())))
+            .build());
+        let span = tracing::info_span!("kept");
+
+        tokio::time::sleep(Duration::from_millis(3500)).await;
+        running.handle.set_tick_interval(Duration::from_millis(100)).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(1050)).await;
+        assert_eq!(running.handle.config().tick_interval, Duration::from_millis(100));
+
+        drop(span);
+        running.finish().await;
+
+        let flushes = flushes.lock().unwrap();
+        let gaps: Vec<_> = flushes.windows(2).map(|pair| pair[1].0 - pair[0].0).collect();
+        let slow = gaps.iter().take_while(|&&gap| gap == Duration::from_secs(1)).count();
+        assert_eq!(slow, 3);
+        // The first flush comes one new interval after the change, and the
+        // last as the collector shuts down.
+        assert_eq!(gaps[slow], Duration::from_millis(600));
+        let fast = &gaps[slow + 1..gaps.len() - 1];
+        assert_eq!(fast.len(), 9);
+        assert!(fast.iter().all(|&gap| gap == Duration::from_millis(100)), "{:?}", gaps);
+        // The task spawned before the change is still known after it.
+        assert!(flushes.iter().all(|&(_, tasks)| tasks == 1));
+    }
+
+    #[tokio::test]
+    async fn allowlist_only_stops_new_tasks_outside_it() {
+        let running = Running::start(BeeLayer::pair());
+
+        let existing = tracing::info_span!("flusher", n = 1);
+        running.handle.set_name_allowlist(Some(vec!["conn-handler".into()]));
+        tracing::info_span!("conn-handler", n = 2).in_scope(|| {});
+        tracing::info_span!("flusher", n = 3).in_scope(|| {});
+        existing.in_scope(|| {});
+        drop(existing);
+
+        let report = running.handle.debug_report(Duration::from_secs(5)).await;
+        assert_eq!(report.summary.name_allowlist, Some(vec!["conn-handler".to_string()]));
+
+        running.handle.set_name_allowlist(None);
+        tracing::info_span!("flusher", n = 4).in_scope(|| {});
+        let report = running.handle.debug_report(Duration::from_secs(5)).await;
+        assert_eq!(report.summary.name_allowlist, None);
+
+        let mut tasks = running.finish().await;
+        tasks.sort_by(|a, b| a.fields.cmp(&b.fields));
+        let fields: Vec<_> = tasks.iter().map(|task| task.fields.as_str()).collect();
+        assert_eq!(fields, ["n=1", "n=2", "n=4"]);
+        // The task tracked before the allowlist ran to completion.
+        assert_eq!(tasks[0].stats.polls(), 1);
+        assert!(tasks[0].stats.closed_at().is_some());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn zero_tick_interval_is_raised_to_the_minimum() {
+        let flushes = Arc::new(Mutex::new(0));
+        let counted = flushes.clone();
+        let running = Running::start(BeeLayer::builder()
+            .summary_sink(move |_: &Summary| *counted.lock().unwrap() += 1)
+            .build());
+
+        running.handle.set_tick_interval(Duration::ZERO).await.unwrap();
+        assert_eq!(running.handle.config().tick_interval, Builder::MIN_TICK_INTERVAL);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let flushed = *flushes.lock().unwrap();
+        assert!((9..=11).contains(&flushed), "{} flushes", flushed);
+
+        // Still running, rather than panicked in `tokio::time::interval`.
+        running.finish().await;
+    }
+}
@@ -0,0 +1,93 @@
+use crate::summary::CountDistribution;
+
+use hdrhistogram::Histogram;
+
+use std::time::Duration;
+
+/// A latency histogram with nanosecond resolution.
+#[derive(Debug)]
+pub(crate) struct DurationHistogram {
+    inner: Histogram<u64>,
+}
+
+impl Default for DurationHistogram {
+    fn default() -> Self {
+        Self {
+            inner: Histogram::new(3).expect("valid significant figures"),
+        }
+    }
+}
+
+impl DurationHistogram {
+    pub fn record(&mut self, duration: Duration) {
+        let nanos = duration.as_nanos().min(u64::MAX as u128) as u64;
+
+        // `saturating_record` clamps to the current range instead of growing
+        // it, so only fall back to it if resizing fails.
+        if self.inner.record(nanos).is_err() {
+            self.inner.saturating_record(nanos);
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        self.inner.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    pub fn percentile(&self, quantile: f64) -> Duration {
+        Duration::from_nanos(self.inner.value_at_quantile(quantile))
+    }
+
+    pub fn max(&self) -> Duration {
+        Duration::from_nanos(self.inner.max())
+    }
+}
+
+/// Counts samples into fixed buckets, for values such as the number of
+/// active tasks where exported bucket bounds should stay stable.
+#[derive(Debug)]
+pub(crate) struct CountHistogram {
+    bounds: &'static [u64],
+    /// Samples per bucket, plus one for anything above the last bound.
+    counts: Vec<u64>,
+    sum: u64,
+}
+
+impl CountHistogram {
+    pub const CONCURRENCY_BOUNDS: &'static [u64] = &[0, 1, 2, 5, 10, 20, 50, 100, 200, 500, 1000, 2000, 5000, 10_000];
+
+    pub fn new(bounds: &'static [u64]) -> Self {
+        Self {
+            bounds,
+            counts: vec![0; bounds.len() + 1],
+            sum: 0,
+        }
+    }
+
+    pub fn record(&mut self, value: u64) {
+        let bucket = self.bounds.iter().position(|bound| value <= *bound).unwrap_or(self.bounds.len());
+        self.counts[bucket] += 1;
+        self.sum = self.sum.saturating_add(value);
+    }
+
+    pub fn distribution(&self) -> CountDistribution {
+        let mut cumulative = 0;
+
+        CountDistribution {
+            buckets: self
+                .bounds
+                .iter()
+                .zip(&self.counts)
+                .map(|(bound, count)| {
+                    cumulative += count;
+                    (*bound, cumulative)
+                })
+                .collect(),
+            sum: self.sum,
+            count: self.counts.iter().sum(),
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use crate::{
+    callsite::Callsite,
+    event::{Event, EventId},
+    fields::{FieldUpdates, TaskAttributes},
+    sender::EventSender,
+};
+
+/// Describes a task reported through an [`EventInjector`].
+#[non_exhaustive]
+#[derive(Clone, Debug, Default)]
+pub struct TaskMeta {
+    pub id: u64,
+    pub fields: String,
+    pub attributes: TaskAttributes,
+    pub callsite: Option<Callsite>,
+    /// The id of the injected task this one was spawned inside of.
+    pub parent: Option<u64>,
+}
+
+impl TaskMeta {
+    pub fn new(id: u64) -> Self {
+        Self {
+            id,
+            ..Default::default()
+        }
+    }
+
+    /// Sets the task's formatted fields, shown alongside its stats.
+    pub fn with_fields(mut self, fields: impl Into<String>) -> Self {
+        self.fields = fields.into();
+        self
+    }
+
+    pub fn with_attributes(mut self, attributes: TaskAttributes) -> Self {
+        self.attributes = attributes;
+        self
+    }
+
+    /// Sets where the task was spawned from, so it counts towards that
+    /// callsite's alive tasks.
+    pub fn with_callsite(mut self, callsite: Callsite) -> Self {
+        self.callsite = Some(callsite);
+        self
+    }
+
+    /// Sets the injected task this one runs inside of, making it the
+    /// parent frame in [flamegraph stacks](crate::Builder::flamegraph).
+    pub fn with_parent(mut self, parent: u64) -> Self {
+        self.parent = Some(parent);
+        self
+    }
+}
+
+/// Feeds task lifecycle events to the collector without going through
+/// `tracing`, e.g. from a non-tokio executor's own instrumentation.
+///
+/// Ids are chosen by the caller and live in their own namespace, so they
+/// can't collide with span ids reported by a [`BeeLayer`](crate::BeeLayer).
+/// Events are timestamped with the same clock the layer uses.
+#[derive(Clone)]
+pub struct EventInjector {
+    pub(crate) events: EventSender,
+}
+
+impl EventInjector {
+    fn send(&self, event: Event) {
+        self.events.send(event.with_seq(self.events.shared.next_external_seq()));
+    }
+
+    pub fn task_spawned(&self, meta: TaskMeta) {
+        self.send(Event::spawn(
+            EventId::external(meta.id),
+            meta.fields,
+            meta.attributes,
+            meta.callsite,
+            meta.parent.map(EventId::external),
+        ));
+    }
+
+    pub fn poll_started(&self, id: u64) {
+        self.send(Event::enter(EventId::external(id)));
+    }
+
+    pub fn poll_ended(&self, id: u64) {
+        self.send(Event::exit(EventId::external(id)));
+    }
+
+    pub fn task_closed(&self, id: u64) {
+        self.send(Event::close(EventId::external(id)));
+    }
+
+    pub fn fields_recorded(&self, id: u64, updates: FieldUpdates) {
+        self.send(Event::record(EventId::external(id), updates));
+    }
+
+    /// Links task `id` to the task `follows` it follows from.
+    pub fn task_follows_from(&self, id: u64, follows: u64) {
+        self.send(Event::follows_from(EventId::external(id), EventId::external(follows)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BeeLayer, Summary};
+
+    use std::sync::{Arc, Mutex};
+
+    #[tokio::test]
+    async fn injected_events_alone_produce_a_summary() {
+        let summaries = Arc::new(Mutex::new(Vec::new()));
+        let flushed = summaries.clone();
+        // The layer is never installed.
+        let (_layer, collector) = BeeLayer::builder()
+            .summary_sink(move |summary: &Summary| flushed.lock().unwrap().push(summary.clone()))
+            .build();
+        let handle = collector.handle();
+        let injector = handle.injector().unwrap();
+        let collector = tokio::spawn(collector.run());
+
+        injector.task_spawned(TaskMeta::new(1).with_fields("job=1"));
+        injector.task_spawned(TaskMeta::new(2).with_parent(1));
+        for id in [1, 2] {
+            injector.poll_started(id);
+            injector.poll_ended(id);
+        }
+        injector.task_closed(2);
+        drop(injector);
+
+        handle.shutdown().await.unwrap();
+        let tasks = collector.await.unwrap();
+        assert_eq!(tasks.len(), 2);
+        assert_eq!(tasks[0].fields, "job=1");
+        assert!(tasks[0].stats.is_active());
+        assert_eq!(tasks[0].children, [tasks[1].id]);
+        assert_eq!(tasks[1].parent, Some(tasks[0].id));
+        assert!(!tasks[1].stats.is_active());
+        assert!(tasks.iter().all(|task| task.stats.polls() == 1));
+
+        let summary = summaries.lock().unwrap().pop().unwrap();
+        assert_eq!(summary.tasks.len(), 2);
+        assert_eq!(summary.active_tasks, 1);
+        assert_eq!(summary.events_lost, 0);
+    }
+}
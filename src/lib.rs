@@ -1,5 +1,101 @@
+mod builder;
+mod callsite;
+mod census;
+mod clock;
+mod close;
 mod collector;
+mod config;
+mod counts;
+mod degrade;
 mod event;
+mod export;
+mod fields;
+pub mod filter;
+mod guard;
+mod handle;
+mod histogram;
+mod inject;
+mod lifecycle;
+#[cfg(feature = "log")]
+mod log_sink;
+mod logs;
+mod merge;
+mod noop;
+mod queue;
+mod reducer;
+mod replay;
+mod reporting;
+mod report;
+mod rotate;
+mod sampling;
+mod sender;
+mod sequence;
+mod sink;
+mod snapshot;
+mod spawn;
+mod stacks;
+mod startup;
+mod stats;
 mod subscriber;
+mod summary;
+#[cfg(test)]
+mod testing;
+mod threads;
+mod variants;
+mod waker;
+mod watchdog;
 
-pub use subscriber::BeeLayer;
\ No newline at end of file
+pub use builder::Builder;
+pub use census::AgeCensus;
+pub use callsite::{Callsite, CallsiteCounters, CallsiteSelector, CallsiteSummary};
+pub use close::CLOSE_EVENTS_CAPACITY;
+pub use collector::Collector;
+pub use config::EffectiveConfig;
+pub use degrade::{Degradation, ReportBudget};
+pub use event::{Event, EventId};
+pub use export::{DurationUnit, ExportConfig, TimestampFormat, SCHEMA_VERSION};
+pub use fields::{Budget, FieldUpdates, FieldValue, TaskAttributes, TaskKind, TaskOutcome};
+pub use guard::FlushGuard;
+pub use handle::{CollectorClosed, CollectorHandle};
+pub use inject::{EventInjector, TaskMeta};
+pub use lifecycle::{FinalReport, ProfileReport, StartupReport};
+#[cfg(feature = "log")]
+pub use log_sink::{LogFormat, LogSummarySink};
+pub use logs::TaskLogs;
+pub use merge::{InstanceTask, MergedSummary};
+pub use noop::{NoopBeeLayer, NoopCollector};
+pub use queue::QueueSummary;
+pub use reducer::TaskReducer;
+pub use replay::REPLAY_TARGET;
+pub use reporting::{INTERNAL_TARGET, METRICS_TARGET};
+pub use report::{CollectorHealth, DebugReport};
+pub use rotate::RotatingFile;
+pub use sampling::SampleStats;
+pub use sender::{DroppedEvents, OverflowPolicy};
+pub use sink::{EventSink, JsonSummarySink, JsonlEventSink, SummarySink};
+pub use snapshot::{FollowedTask, SnapshotOrder, TaskPhases, TaskSnapshot};
+pub use spawn::{spawn_tracked, spawn_tracked_on, TrackFuture};
+pub use stacks::CollapsedStacks;
+pub use startup::StartupStats;
+pub use stats::Stats;
+pub use subscriber::{BeeLayer, OnFormatError};
+pub use summary::{AggregateStats, CountDistribution, KindSummary, LatencySummary, PeakActive, PhaseSummary, Summary};
+pub use threads::ThreadSummary;
+pub use variants::FieldVariants;
+pub use waker::WakerOp;
+/// The layer to install: [`BeeLayer`], or [`NoopBeeLayer`] when built with
+/// the `disabled` feature.
+#[cfg(not(feature = "disabled"))]
+pub type TaskLayer = BeeLayer;
+/// The layer to install: [`BeeLayer`], or [`NoopBeeLayer`] when built with
+/// the `disabled` feature.
+#[cfg(feature = "disabled")]
+pub type TaskLayer = NoopBeeLayer;
+/// The collector paired with [`TaskLayer`]: [`Collector`], or
+/// [`NoopCollector`] when built with the `disabled` feature.
+#[cfg(not(feature = "disabled"))]
+pub type TaskCollector = Collector;
+/// The collector paired with [`TaskLayer`]: [`Collector`], or
+/// [`NoopCollector`] when built with the `disabled` feature.
+#[cfg(feature = "disabled")]
+pub type TaskCollector = NoopCollector;
@@ -0,0 +1,386 @@
+use crate::{
+    export::{ExportConfig, Row},
+    histogram::DurationHistogram,
+    sender::OverflowPolicy,
+    snapshot::TaskSnapshot,
+    summary::{LatencySummary, Summary},
+};
+
+use serde_json::{Map, Value};
+
+use std::{
+    fmt,
+    time::{Duration, SystemTime},
+};
+
+/// The configuration a collector started with, reported once as it starts
+/// running so that captures describe how they were taken.
+#[derive(Clone, Debug)]
+pub struct StartupReport {
+    /// The version of this crate.
+    pub version: &'static str,
+    pub instance: Option<String>,
+    pub started_at: SystemTime,
+    pub tick_interval: Duration,
+    /// `None` if the channel is unbounded.
+    pub channel_capacity: Option<usize>,
+    pub overflow_policy: OverflowPolicy,
+    pub summary_sinks: usize,
+    pub event_sinks: usize,
+    /// Names of the tasks tracked, or `None` if every task is.
+    pub name_allowlist: Option<Vec<String>>,
+    pub transient_grace: Option<Duration>,
+}
+
+impl StartupReport {
+    pub fn to_json(&self, export: &ExportConfig) -> Value {
+        let mut row = Row::new();
+        row.push(("version".into(), self.version.into()));
+        if let Some(instance) = &self.instance {
+            row.push(("instance".into(), instance.as_str().into()));
+        }
+        export.timestamp(&mut row, "started_at", Some(self.started_at));
+        export.duration(&mut row, "tick_interval", Some(self.tick_interval));
+        row.push(("channel_capacity".into(), self.channel_capacity.map(Value::from).unwrap_or(Value::Null)));
+        row.push(("overflow_policy".into(), format!("{:?}", self.overflow_policy).into()));
+        row.push(("summary_sinks".into(), self.summary_sinks.into()));
+        row.push(("event_sinks".into(), self.event_sinks.into()));
+        row.push((
+            "name_allowlist".into(),
+            self.name_allowlist.as_ref().map_or(Value::Null, |names| names.iter().map(|name| name.as_str()).collect()),
+        ));
+        export.duration(&mut row, "transient_grace", self.transient_grace);
+
+        let mut map = Map::new();
+        map.insert("startup".into(), Value::Object(row.into_iter().collect()));
+
+        Value::Object(map)
+    }
+}
+
+impl fmt::Display for StartupReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "bee-console {} starting", self.version)?;
+        if let Some(instance) = &self.instance {
+            write!(f, " for {}", instance)?;
+        }
+        write!(f, ": flushing every {:?}, ", self.tick_interval)?;
+        match self.channel_capacity {
+            Some(capacity) => write!(f, "channel capacity {} ({:?})", capacity, self.overflow_policy)?,
+            None => f.write_str("unbounded channel")?,
+        }
+        write!(f, ", {} summary sinks, {} event sinks", self.summary_sinks, self.event_sinks)?;
+        match &self.name_allowlist {
+            Some(names) => write!(f, ", tracking only {}", names.join(", "))?,
+            None => f.write_str(", tracking every task")?,
+        }
+        if let Some(grace) = self.transient_grace {
+            write!(f, ", counting tasks closed within {:?} as transient", grace)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The whole run summarized once the collector stops, the last thing it
+/// reports.
+#[derive(Clone, Debug)]
+pub struct FinalReport {
+    pub started_at: SystemTime,
+    pub ended_at: SystemTime,
+    /// Every task tracked over the run, including transient ones and ones
+    /// lost to restarts.
+    pub tasks: u64,
+    /// Time spent polling every task tracked over the run.
+    pub busy_time: Duration,
+    /// Lifetimes of the closed tasks still kept.
+    pub lifetimes: Option<LatencySummary>,
+    /// Busy time of every task still kept.
+    pub busy_times: Option<LatencySummary>,
+    pub budget_violations: u64,
+    /// Tasks flagged as busy looping.
+    pub busy_loops: usize,
+    /// Ticks the collector missed because it was stalled.
+    pub ticks_missed: u64,
+    /// Events dropped from the startup buffer or evicted from a full queue.
+    pub dropped_events: u64,
+    /// The final summary, with every task kept.
+    pub summary: Summary,
+}
+
+impl FinalReport {
+    /// How many of the busiest tasks are listed.
+    pub const TOP: usize = 10;
+
+    pub(crate) fn new(started_at: SystemTime, tasks: u64, summary: Summary) -> Self {
+        let mut lifetimes = DurationHistogram::default();
+        let mut busy_times = DurationHistogram::default();
+        for task in &summary.tasks {
+            if let Some(lifetime) = task.total_time() {
+                lifetimes.record(lifetime);
+            }
+            busy_times.record(task.stats.busy_time());
+        }
+
+        Self {
+            started_at,
+            ended_at: SystemTime::now(),
+            tasks,
+            busy_time: summary.kinds.iter().map(|kind| kind.busy_time_total).sum(),
+            lifetimes: LatencySummary::from_histogram(&lifetimes),
+            busy_times: LatencySummary::from_histogram(&busy_times),
+            budget_violations: summary.budget_violations,
+            busy_loops: summary.tasks.iter().filter(|task| task.busy_loop).count(),
+            ticks_missed: summary.ticks_missed,
+            dropped_events: summary.startup.dropped + summary.dropped_newest + summary.dropped_oldest.unwrap_or(0),
+            summary,
+        }
+    }
+
+    /// Wall-clock time the run covered.
+    pub fn duration(&self) -> Duration {
+        self.ended_at.duration_since(self.started_at).unwrap_or_default()
+    }
+
+    /// The report as a JSON object with the sections `final`, `aggregates`
+    /// and `top_tasks`.
+    pub fn to_json(&self, export: &ExportConfig) -> Value {
+        let mut row = Row::new();
+        export.timestamp(&mut row, "started_at", Some(self.started_at));
+        export.timestamp(&mut row, "ended_at", Some(self.ended_at));
+        export.duration(&mut row, "duration", Some(self.duration()));
+        row.push(("tasks".into(), self.tasks.into()));
+        export.duration(&mut row, "busy", Some(self.busy_time));
+        row.push(("lifetime".into(), export.latency(self.lifetimes)));
+        row.push(("busy_per_task".into(), export.latency(self.busy_times)));
+        row.push(("budget_violations".into(), self.budget_violations.into()));
+        row.push(("busy_loops".into(), self.busy_loops.into()));
+        row.push(("ticks_missed".into(), self.ticks_missed.into()));
+        row.push(("dropped_events".into(), self.dropped_events.into()));
+
+        let mut map = Map::new();
+        map.insert("final".into(), Value::Object(row.into_iter().collect()));
+        map.insert("aggregates".into(), Value::Object(export.aggregates_json(&self.summary)));
+        map.insert(
+            "top_tasks".into(),
+            self.summary.busiest(Self::TOP).map(|task| export.task_json(task)).collect(),
+        );
+
+        Value::Object(map)
+    }
+}
+
+impl fmt::Display for FinalReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Run over after {:?}: {} tasks, busy {:?}",
+            self.duration(),
+            self.tasks,
+            self.busy_time,
+        )?;
+        if let Some(latency) = self.lifetimes {
+            writeln!(
+                f,
+                "Task lifetimes: p50 {:?}, p99 {:?}, max {:?} ({} samples)",
+                latency.p50, latency.p99, latency.max, latency.samples,
+            )?;
+        }
+        if let Some(latency) = self.busy_times {
+            writeln!(
+                f,
+                "Busy time per task: p50 {:?}, p99 {:?}, max {:?} ({} samples)",
+                latency.p50, latency.p99, latency.max, latency.samples,
+            )?;
+        }
+        writeln!(
+            f,
+            "{} budget violations, {} busy loops, {} ticks missed, {} events dropped",
+            self.budget_violations, self.busy_loops, self.ticks_missed, self.dropped_events,
+        )?;
+        for task in self.summary.busiest(Self::TOP) {
+            writeln!(f, "{}", task)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// What a collector [profiling](crate::Collector::profile_for) for a fixed
+/// window saw.
+#[derive(Clone, Debug)]
+pub struct ProfileReport {
+    pub started_at: SystemTime,
+    pub ended_at: SystemTime,
+    tasks: Vec<TaskSnapshot>,
+}
+
+impl ProfileReport {
+    pub(crate) fn new(started_at: SystemTime, tasks: Vec<TaskSnapshot>) -> Self {
+        Self {
+            started_at,
+            ended_at: SystemTime::now(),
+            tasks,
+        }
+    }
+
+    /// Every task seen over the window, including the ones still open at
+    /// its end, which are [active](crate::Stats::is_active).
+    pub fn tasks(&self) -> &[TaskSnapshot] {
+        &self.tasks
+    }
+
+    /// The tasks still open as the window ended.
+    pub fn open_tasks(&self) -> impl Iterator<Item = &TaskSnapshot> {
+        self.tasks.iter().filter(|task| task.stats.is_active())
+    }
+
+    pub fn into_tasks(self) -> Vec<TaskSnapshot> {
+        self.tasks
+    }
+
+    /// Wall-clock time the window covered.
+    pub fn duration(&self) -> Duration {
+        self.ended_at.duration_since(self.started_at).unwrap_or_default()
+    }
+}
+
+impl fmt::Display for ProfileReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Profiled for {:?}: {} tasks, {} still open",
+            self.duration(),
+            self.tasks.len(),
+            self.open_tasks().count(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Running;
+    use crate::{sink::SummarySink, BeeLayer};
+
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use std::{
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    #[derive(Clone, Default)]
+    struct Reports(Arc<Mutex<(Option<StartupReport>, Option<FinalReport>)>>);
+
+    impl SummarySink for Reports {
+        fn flush(&mut self, _summary: &Summary, _export: &ExportConfig) {}
+
+        fn start(&mut self, report: &StartupReport, _export: &ExportConfig) {
+            self.0.lock().unwrap().0 = Some(report.clone());
+        }
+
+        fn finish(&mut self, report: &FinalReport, _export: &ExportConfig) {
+            self.0.lock().unwrap().1 = Some(report.clone());
+        }
+    }
+
+    #[tokio::test]
+    async fn final_report_totals_match_the_tasks() {
+        let reports = Reports::default();
+        let running = Running::start(BeeLayer::builder()
+            .flush_interval(Duration::from_millis(250))
+            .summary_sink(reports.clone())
+            .build());
+
+        for job in 0..12u64 {
+            let span = tracing::info_span!("job", job);
+            for _ in 0..=job % 3 {
+                span.in_scope(|| thread::sleep(Duration::from_millis(job % 4)));
+            }
+        }
+        let tasks = running.finish().await;
+
+        let (startup, report) = reports.0.lock().unwrap().clone();
+        let startup = startup.expect("startup report");
+        assert_eq!(startup.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(startup.tick_interval, Duration::from_millis(250));
+        assert_eq!(startup.summary_sinks, 1);
+
+        let report = report.expect("final report");
+        assert_eq!(report.tasks, 12);
+        assert_eq!(report.busy_time, tasks.iter().map(|task| task.stats.busy_time()).sum());
+        assert_eq!(report.lifetimes.unwrap().samples, 12);
+        assert_eq!(report.busy_times.unwrap().samples, 12);
+        assert_eq!(report.dropped_events, 0);
+        assert!(report.duration() > Duration::ZERO);
+
+        let json = report.to_json(&ExportConfig::default());
+        assert_eq!(json["final"]["tasks"], 12);
+        let top = json["top_tasks"].as_array().unwrap();
+        assert_eq!(top.len(), FinalReport::TOP);
+        // Three polls of 3ms each.
+        assert_eq!(top[0]["fields"], "job=11");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn profile_returns_the_tasks_seen_in_its_window() {
+        let (layer, collector) = BeeLayer::pair();
+        let _default = tracing::subscriber::set_default(tracing_subscriber::registry().with(layer));
+        let started = tokio::time::Instant::now();
+        let profile = tokio::spawn(collector.profile_for(Duration::from_secs(30)));
+
+        tracing::info_span!("done").in_scope(|| {});
+        let open = tracing::info_span!("open");
+        open.in_scope(|| {});
+        open.in_scope(|| {});
+
+        let report = profile.await.unwrap();
+        assert_eq!(started.elapsed(), Duration::from_secs(30));
+
+        assert!(report.started_at <= report.ended_at);
+        let tasks: Vec<_> = report.tasks().iter().map(|task| (task.callsite.unwrap().name, task.stats.polls(), task.stats.closed_at().is_some())).collect();
+        assert_eq!(tasks, [("done", 1, true), ("open", 2, false)]);
+        let open: Vec<_> = report.open_tasks().map(|task| task.callsite.unwrap().name).collect();
+        assert_eq!(open, ["open"]);
+    }
+
+    /// Queues 1000 tasks' events without the collector draining any, then
+    /// stops it by dropping the layer or by shutting it down.
+    async fn stop_with_events_queued(shutdown: bool) -> (Vec<TaskSnapshot>, FinalReport) {
+        let reports = Reports::default();
+        let (layer, collector) = BeeLayer::builder()
+            .flush_interval(Duration::from_secs(3600))
+            .event_buffer_capacity(10_000)
+            .summary_sink(reports.clone())
+            .build();
+        let handle = collector.handle();
+        let default = tracing::subscriber::set_default(tracing_subscriber::registry().with(layer));
+        let collector = tokio::spawn(collector.run());
+        // Past the startup buffer, into the channel.
+        tokio::task::yield_now().await;
+
+        for job in 0..1000 {
+            tracing::info_span!("job", job).in_scope(|| {});
+        }
+        if shutdown {
+            handle.shutdown().await.unwrap();
+        }
+        drop(default);
+        let tasks = collector.await.unwrap();
+
+        let report = reports.0.lock().unwrap().1.clone().expect("final report");
+        (tasks, report)
+    }
+
+    #[tokio::test]
+    async fn stopping_keeps_the_events_still_queued() {
+        for &shutdown in [false, true].iter() {
+            let (tasks, report) = stop_with_events_queued(shutdown).await;
+            assert_eq!(tasks.len(), 1000);
+            assert!(tasks.iter().all(|task| task.stats.polls() == 1 && task.stats.closed_at().is_some()));
+            assert_eq!(report.tasks, 1000);
+            assert_eq!(report.dropped_events, 0);
+        }
+    }
+}
@@ -0,0 +1,214 @@
+use crate::{
+    export::ExportConfig,
+    lifecycle::{FinalReport, StartupReport},
+    sink::SummarySink,
+    summary::Summary,
+};
+
+use log::Level;
+use serde_json::Value;
+
+/// How a [`LogSummarySink`] formats what it logs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// The same lines the collector logs through `tracing`, one record each.
+    #[default]
+    Table,
+    /// One JSON object per record, as exported by [`ExportConfig`].
+    Json,
+}
+
+/// Emits each summary through the `log` facade, for pipelines that only
+/// collect `log` records. Enabled by the `log` feature.
+///
+/// The aggregates are logged at `Info`, and the busiest tasks after them at
+/// `Debug`, one record per task. Nothing is formatted for a level `log` has
+/// disabled, so a sink below `log::max_level()` costs next to nothing.
+pub struct LogSummarySink {
+    target: String,
+    format: LogFormat,
+    top: usize,
+    export: Option<ExportConfig>,
+}
+
+impl Default for LogSummarySink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LogSummarySink {
+    const DEFAULT_TARGET: &'static str = "bee_console";
+    const DEFAULT_TOP: usize = 10;
+
+    pub fn new() -> Self {
+        Self {
+            target: Self::DEFAULT_TARGET.to_string(),
+            format: Default::default(),
+            top: Self::DEFAULT_TOP,
+            export: None,
+        }
+    }
+
+    /// Sets the target records are logged on. Defaults to `bee_console`.
+    pub fn target(mut self, target: impl Into<String>) -> Self {
+        self.target = target.into();
+        self
+    }
+
+    pub fn format(mut self, format: LogFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// How many of the busiest tasks to log at `Debug`. Defaults to 10.
+    pub fn top(mut self, n: usize) -> Self {
+        self.top = n;
+        self
+    }
+
+    /// Overrides the units set on the builder for this sink, in
+    /// [`LogFormat::Json`].
+    pub fn export_config(mut self, export: ExportConfig) -> Self {
+        self.export = Some(export);
+        self
+    }
+
+    fn enabled(&self, level: Level) -> bool {
+        log::log_enabled!(target: self.target.as_str(), level)
+    }
+
+    /// Logs each line of `text` as a record of its own.
+    fn log_lines(&self, level: Level, text: &str) {
+        for line in text.lines() {
+            log::log!(target: self.target.as_str(), level, "{}", line);
+        }
+    }
+
+    fn log_json(&self, level: Level, json: &Value) {
+        log::log!(target: self.target.as_str(), level, "{}", json);
+    }
+}
+
+impl SummarySink for LogSummarySink {
+    fn flush(&mut self, summary: &Summary, export: &ExportConfig) {
+        let export = self.export.as_ref().unwrap_or(export);
+
+        if self.enabled(Level::Info) {
+            match self.format {
+                LogFormat::Table => self.log_lines(Level::Info, &summary.aggregates().to_string()),
+                LogFormat::Json => self.log_json(Level::Info, &Value::Object(export.aggregates_json(summary))),
+            }
+        }
+
+        if self.top > 0 && self.enabled(Level::Debug) {
+            for task in summary.busiest(self.top) {
+                match self.format {
+                    LogFormat::Table => self.log_lines(Level::Debug, &task.to_string()),
+                    LogFormat::Json => self.log_json(Level::Debug, &export.task_json(task)),
+                }
+            }
+        }
+    }
+
+    fn start(&mut self, report: &StartupReport, export: &ExportConfig) {
+        if !self.enabled(Level::Info) {
+            return;
+        }
+
+        match self.format {
+            LogFormat::Table => self.log_lines(Level::Info, &report.to_string()),
+            LogFormat::Json => self.log_json(Level::Info, &report.to_json(self.export.as_ref().unwrap_or(export))),
+        }
+    }
+
+    fn finish(&mut self, report: &FinalReport, export: &ExportConfig) {
+        if !self.enabled(Level::Info) {
+            return;
+        }
+
+        match self.format {
+            LogFormat::Table => self.log_lines(Level::Info, &report.to_string()),
+            LogFormat::Json => self.log_json(Level::Info, &report.to_json(self.export.as_ref().unwrap_or(export))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Running;
+    use crate::BeeLayer;
+
+    use log::{LevelFilter, Log, Metadata, Record};
+
+    use std::{sync::Mutex, time::Duration};
+
+    /// Keeps every record logged on `bee_test`, the one target the tests log
+    /// on, as the logger is global.
+    struct Capture(Mutex<Vec<(Level, String)>>);
+
+    impl Log for Capture {
+        fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+            metadata.target() == "bee_test"
+        }
+
+        fn log(&self, record: &Record<'_>) {
+            if self.enabled(record.metadata()) {
+                self.0.lock().unwrap().push((record.level(), record.args().to_string()));
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    static CAPTURE: Capture = Capture(Mutex::new(Vec::new()));
+
+    fn captured() -> Vec<(Level, String)> {
+        std::mem::take(&mut *CAPTURE.0.lock().unwrap())
+    }
+
+    async fn summary_of_three_jobs() -> Summary {
+        let running = Running::start(BeeLayer::pair());
+
+        for job in 0..3 {
+            tracing::info_span!("job", job).in_scope(|| {});
+        }
+
+        let summary = running.handle.debug_report(Duration::from_secs(5)).await.summary;
+        running.finish().await;
+        summary
+    }
+
+    #[tokio::test]
+    async fn summaries_are_logged_through_the_log_facade() {
+        log::set_logger(&CAPTURE).unwrap();
+        let summary = summary_of_three_jobs().await;
+        let export = ExportConfig::default();
+
+        log::set_max_level(LevelFilter::Debug);
+        LogSummarySink::new().target("bee_test").top(2).flush(&summary, &export);
+        let records = captured();
+        let header: Vec<_> = records.iter().take_while(|(level, _)| *level == Level::Info).map(|(_, line)| line.clone()).collect();
+        assert_eq!(header.join("\n"), summary.aggregates().to_string().trim_end());
+        let tasks = &records[header.len()..];
+        assert_eq!(tasks.len(), 2);
+        assert!(tasks.iter().all(|(level, line)| *level == Level::Debug && line.contains("fields: job=")), "{:?}", tasks);
+
+        LogSummarySink::new().target("bee_test").format(LogFormat::Json).flush(&summary, &export);
+        let records = captured();
+        assert_eq!(records.len(), 4);
+        let aggregates: Value = serde_json::from_str(&records[0].1).unwrap();
+        assert_eq!(aggregates["schema_version"], 1);
+        let task: Value = serde_json::from_str(&records[1].1).unwrap();
+        assert!(task["fields"].as_str().unwrap().starts_with("job="));
+
+        // Tasks are only logged at `Debug`, and nothing once `log` is off.
+        log::set_max_level(LevelFilter::Info);
+        LogSummarySink::new().target("bee_test").flush(&summary, &export);
+        assert!(captured().iter().all(|(level, _)| *level == Level::Info));
+        log::set_max_level(LevelFilter::Off);
+        LogSummarySink::new().target("bee_test").flush(&summary, &export);
+        assert!(captured().is_empty());
+    }
+}
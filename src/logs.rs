@@ -0,0 +1,92 @@
+use tracing_core::{
+    field::{Field, Visit},
+    Level,
+};
+
+use std::fmt;
+
+/// The events logged inside a task, counted by level, with the message of
+/// the last warning and error.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TaskLogs {
+    pub trace: u64,
+    pub debug: u64,
+    pub info: u64,
+    pub warn: u64,
+    pub error: u64,
+    pub last_warn: Option<String>,
+    pub last_error: Option<String>,
+}
+
+impl TaskLogs {
+    pub fn total(&self) -> u64 {
+        self.trace + self.debug + self.info + self.warn + self.error
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total() == 0
+    }
+
+    pub(crate) fn record(&mut self, level: Level, message: Option<String>) {
+        match level {
+            Level::TRACE => self.trace += 1,
+            Level::DEBUG => self.debug += 1,
+            Level::INFO => self.info += 1,
+            Level::WARN => {
+                self.warn += 1;
+                self.last_warn = message.or(self.last_warn.take());
+            }
+            Level::ERROR => {
+                self.error += 1;
+                self.last_error = message.or(self.last_error.take());
+            }
+        }
+    }
+}
+
+/// Displays as `logs=12 warnings=1 errors=3 last_error="connection reset"`,
+/// leaving out levels nothing was logged at.
+impl fmt::Display for TaskLogs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "logs={}", self.total())?;
+        if self.warn > 0 {
+            write!(f, " warnings={}", self.warn)?;
+        }
+        if self.error > 0 {
+            write!(f, " errors={}", self.error)?;
+        }
+        if let Some(message) = &self.last_warn {
+            write!(f, " last_warn={:?}", message)?;
+        }
+        if let Some(message) = &self.last_error {
+            write!(f, " last_error={:?}", message)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether the message of an event at `level` is kept.
+pub(crate) fn keeps_message(level: &Level) -> bool {
+    *level <= Level::WARN
+}
+
+/// Picks the `message` field out of an event.
+#[derive(Default)]
+pub(crate) struct MessageVisitor {
+    pub message: Option<String>,
+}
+
+impl Visit for MessageVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = Some(value.to_string());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.message = Some(format!("{:?}", value));
+        }
+    }
+}
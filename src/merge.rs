@@ -0,0 +1,431 @@
+use crate::{
+    callsite::{Callsite, CallsiteCounters, CallsiteSummary},
+    census::AgeCensus,
+    variants::FieldVariants,
+    filter::FilteredCallsite,
+    fields::TaskKind,
+    sampling::SampleStats,
+    sender::DroppedEvents,
+    snapshot::TaskSnapshot,
+    stacks::CollapsedStacks,
+    startup::StartupStats,
+    summary::{AggregateStats, CountDistribution, KindSummary, Summary},
+};
+
+use std::{
+    collections::BTreeMap,
+    time::{Duration, SystemTime},
+};
+
+/// Summaries from several processes combined into one, e.g. by a central
+/// aggregator receiving them from every replica.
+///
+/// Counters and gauges are summed. Summaries don't carry the histograms
+/// behind their percentiles, so these sections are left out:
+///
+/// - the latency percentiles: `wake_to_poll`, `first_poll_delay`,
+///   `idle_gaps`, `poll_durations` and `phases`;
+/// - `peak_active` and `peak_queue_len`, as peaks reached at different
+///   times don't add up;
+/// - the per-thread `threads` and per-span `queues`, whose ids only mean
+///   something within their own process;
+/// - what describes a single flush or collector: `seq`, `flushed_at`,
+///   `ticks_missed`, `name_allowlist`, `quarantined_sinks`, `report_cost`
+///   and `degradation`.
+///
+/// An instance whose collector [restarted](Summary::restarts) counts from
+/// the restart only, so adding it in undercounts; it is listed under
+/// [`reset_instances`](Self::reset_instances).
+#[derive(Clone, Debug, Default)]
+pub struct MergedSummary {
+    /// How many summaries went into the merge.
+    pub instances: usize,
+    /// The instances whose collector restarted, starting its counts over,
+    /// labelled as in [`InstanceTask::instance`].
+    pub reset_instances: Vec<String>,
+    pub startup: StartupStats,
+    pub transient_tasks: u64,
+    pub budget_violations: u64,
+    pub duplicate_closes: u64,
+    pub order_violations: u64,
+    pub events_lost: u64,
+    pub event_gaps: u64,
+    pub panics: u64,
+    pub restarts: u32,
+    pub dropped_newest: u64,
+    pub dropped_by_kind: DroppedEvents,
+    pub dropped_since_flush: u64,
+    pub dropped_oldest: Option<u64>,
+    pub dropped_close_events: u64,
+    /// Events dropped by every event sink of every instance.
+    pub event_sinks_dropped: u64,
+    /// The latest event processed by any instance.
+    pub last_event_at: Option<SystemTime>,
+    pub active_tasks: usize,
+    /// Tasks active at each flush, across every instance. `None` if the
+    /// instances used different buckets, which can't be added together.
+    pub active_concurrency: Option<CountDistribution>,
+    /// Running tasks by age across every instance. `None` if the instances
+    /// used different buckets.
+    pub census: Option<AgeCensus>,
+    pub weighted_busy_time: Option<Duration>,
+    pub reported_mem_bytes: Option<u64>,
+    /// Per-callsite counts, merged by callsite. A limit is reported as
+    /// violated if it was on any instance.
+    pub callsites: Vec<CallsiteSummary>,
+    /// Task counters summed per callsite over every instance, in callsite order.
+    pub counter_rollup: Vec<CallsiteCounters>,
+    /// Field variants per target, from the instance that saw the most, as
+    /// the variants instances share can't be told apart.
+    pub field_variants: Vec<FieldVariants>,
+    /// Busy time by task stack, summed over every instance.
+    pub stacks: CollapsedStacks,
+    /// Totals summed over the instances that only count, if any do.
+    pub counts: Option<AggregateStats>,
+    pub sampling: Option<SampleStats>,
+    /// Totals per stable key, summed over every instance.
+    pub stable_keys: BTreeMap<String, AggregateStats>,
+    /// What was kept from each instance's collector, merged by callsite.
+    pub filtered_traffic: Vec<FilteredCallsite>,
+    pub kinds: Vec<KindSummary>,
+    /// Every task, tagged with the instance it came from.
+    pub tasks: Vec<InstanceTask>,
+}
+
+/// A task from one of the summaries in a [`MergedSummary`].
+#[derive(Clone, Debug)]
+pub struct InstanceTask {
+    /// The summary's [`instance`](Summary::instance) label, or its position
+    /// in the merge if it had none, e.g. `#2`.
+    pub instance: String,
+    pub task: TaskSnapshot,
+}
+
+impl Summary {
+    /// Combines summaries from several processes into one.
+    pub fn merge<'a>(summaries: impl IntoIterator<Item = &'a Summary>) -> MergedSummary {
+        let mut merged = MergedSummary {
+            active_concurrency: Some(Default::default()),
+            census: Some(Default::default()),
+            ..Default::default()
+        };
+        let mut callsites: BTreeMap<Callsite, CallsiteSummary> = BTreeMap::new();
+        let mut kinds: BTreeMap<TaskKind, KindSummary> = BTreeMap::new();
+        let mut filtered_traffic: BTreeMap<Callsite, FilteredCallsite> = BTreeMap::new();
+        let mut field_variants: BTreeMap<&'static str, FieldVariants> = BTreeMap::new();
+        let mut counter_rollup: BTreeMap<Callsite, BTreeMap<String, u64>> = BTreeMap::new();
+
+        for (index, summary) in summaries.into_iter().enumerate() {
+            merged.instances += 1;
+            merged.startup.buffered += summary.startup.buffered;
+            merged.startup.dropped += summary.startup.dropped;
+            merged.transient_tasks += summary.transient_tasks;
+            merged.budget_violations += summary.budget_violations;
+            merged.duplicate_closes += summary.duplicate_closes;
+            merged.order_violations += summary.order_violations;
+            merged.events_lost += summary.events_lost;
+            merged.event_gaps += summary.event_gaps;
+            merged.panics += summary.panics;
+            merged.restarts += summary.restarts;
+            let instance = summary.instance.clone().unwrap_or_else(|| format!("#{}", index));
+            if summary.restarts > 0 {
+                merged.reset_instances.push(instance.clone());
+            }
+            merged.dropped_newest += summary.dropped_newest;
+            merged.dropped_by_kind.merge(&summary.dropped_by_kind);
+            merged.dropped_since_flush += summary.dropped_since_flush;
+            merged.dropped_oldest = add(merged.dropped_oldest, summary.dropped_oldest);
+            merged.dropped_close_events += summary.dropped_close_events;
+            merged.event_sinks_dropped += summary.event_sinks_dropped.iter().sum::<u64>();
+            merged.last_event_at = merged.last_event_at.max(summary.last_event_at);
+            merged.active_tasks += summary.active_tasks;
+            merged.weighted_busy_time = add(merged.weighted_busy_time, summary.weighted_busy_time);
+            merged.reported_mem_bytes = add(merged.reported_mem_bytes, summary.reported_mem_bytes);
+
+            merged.active_concurrency = merged
+                .active_concurrency
+                .and_then(|total| merge_distributions(total, &summary.active_concurrency));
+            merged.census = merged.census.and_then(|total| merge_census(total, &summary.census));
+
+            for callsite in &summary.callsites {
+                callsites
+                    .entry(callsite.callsite)
+                    .and_modify(|total| {
+                        total.alive += callsite.alive;
+                        total.limit = match (total.limit, callsite.limit) {
+                            (Some(a), Some(b)) => Some(a.min(b)),
+                            (a, b) => a.or(b),
+                        };
+                        total.violated |= callsite.violated;
+                    })
+                    .or_insert(*callsite);
+            }
+
+            for variants in &summary.field_variants {
+                field_variants
+                    .entry(variants.target)
+                    .and_modify(|most| {
+                        if variants.variants > most.variants {
+                            *most = variants.clone();
+                        }
+                    })
+                    .or_insert_with(|| variants.clone());
+            }
+
+            merged.stacks.merge(&summary.stacks);
+            if let Some(sampling) = &summary.sampling {
+                merged.sampling.get_or_insert_with(Default::default).merge(sampling);
+            }
+            if let Some(counts) = &summary.counts {
+                add_stats(merged.counts.get_or_insert_with(Default::default), counts);
+            }
+            for (key, stats) in &summary.stable_keys {
+                add_stats(merged.stable_keys.entry(key.clone()).or_default(), stats);
+            }
+
+            for rollup in &summary.counter_rollup {
+                let totals = counter_rollup.entry(rollup.callsite).or_default();
+                for (name, value) in &rollup.counters {
+                    let total = totals.entry(name.clone()).or_default();
+                    *total = total.saturating_add(*value);
+                }
+            }
+
+            for filtered in &summary.filtered_traffic {
+                filtered_traffic
+                    .entry(filtered.callsite)
+                    .and_modify(|total| {
+                        total.spans += filtered.spans;
+                        total.events += filtered.events;
+                    })
+                    .or_insert(*filtered);
+            }
+
+            for kind in &summary.kinds {
+                kinds
+                    .entry(kind.kind)
+                    .and_modify(|total| {
+                        total.tasks += kind.tasks;
+                        total.active += kind.active;
+                        total.polls += kind.polls;
+                        total.busy_time += kind.busy_time;
+                        total.busy_time_total += kind.busy_time_total;
+                    })
+                    .or_insert(*kind);
+            }
+
+            merged.tasks.extend(summary.tasks.iter().map(|task| InstanceTask {
+                instance: instance.clone(),
+                task: task.clone(),
+            }));
+        }
+
+        merged.callsites = callsites.into_values().collect();
+        merged.callsites.sort_by(|a, b| b.alive.cmp(&a.alive).then(a.callsite.cmp(&b.callsite)));
+        merged.field_variants = field_variants.into_values().collect();
+        merged.field_variants.sort_by(|a, b| b.variants.cmp(&a.variants).then(a.target.cmp(b.target)));
+        merged.counter_rollup = counter_rollup
+            .into_iter()
+            .map(|(callsite, counters)| CallsiteCounters { callsite, counters })
+            .collect();
+        merged.filtered_traffic = filtered_traffic.into_values().collect();
+        merged.filtered_traffic.sort_by(|a, b| (b.spans + b.events).cmp(&(a.spans + a.events)).then(a.callsite.cmp(&b.callsite)));
+        merged.kinds = kinds.into_values().collect();
+        merged.reset_instances.sort_unstable();
+
+        merged
+    }
+}
+
+fn add<T: std::ops::Add<Output = T>>(total: Option<T>, value: Option<T>) -> Option<T> {
+    match (total, value) {
+        (Some(total), Some(value)) => Some(total + value),
+        (total, value) => total.or(value),
+    }
+}
+
+/// Adds two distributions bucket by bucket, if their buckets line up. A
+/// distribution without samples takes on the other's buckets.
+fn merge_distributions(total: CountDistribution, other: &CountDistribution) -> Option<CountDistribution> {
+    if total.count == 0 {
+        return Some(other.clone());
+    }
+    if other.count == 0 {
+        return Some(total);
+    }
+
+    let bounds_match = total.buckets.len() == other.buckets.len()
+        && total.buckets.iter().zip(&other.buckets).all(|(a, b)| a.0 == b.0);
+    if !bounds_match {
+        return None;
+    }
+
+    Some(CountDistribution {
+        buckets: total
+            .buckets
+            .iter()
+            .zip(&other.buckets)
+            .map(|((le, a), (_, b))| (*le, a + b))
+            .collect(),
+        sum: total.sum + other.sum,
+        count: total.count + other.count,
+    })
+}
+
+fn add_stats(total: &mut AggregateStats, stats: &AggregateStats) {
+    total.tasks += stats.tasks;
+    total.active += stats.active;
+    total.polls += stats.polls;
+    total.busy_time += stats.busy_time;
+}
+
+/// Adds two censuses bucket by bucket, if their bounds match. An empty census
+/// takes on the other's bounds.
+fn merge_census(total: AgeCensus, other: &AgeCensus) -> Option<AgeCensus> {
+    if total.by_age.is_empty() {
+        return Some(other.clone());
+    }
+    if total.bounds != other.bounds {
+        return None;
+    }
+
+    let add = |a: &[u64], b: &[u64]| a.iter().zip(b).map(|(a, b)| a + b).collect();
+    Some(AgeCensus {
+        by_age: add(&total.by_age, &other.by_age),
+        by_idle: add(&total.by_idle, &other.by_idle),
+        bounds: total.bounds,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(instance: &str, scale: u64) -> Summary {
+        Summary {
+            instance: Some(instance.into()),
+            transient_tasks: scale,
+            events_lost: 2 * scale,
+            panics: scale % 2,
+            active_tasks: scale as usize,
+            dropped_oldest: Some(scale),
+            weighted_busy_time: Some(Duration::from_millis(10 * scale)),
+            active_concurrency: CountDistribution {
+                buckets: vec![(1, scale), (10, 2 * scale)],
+                sum: 5 * scale,
+                count: 3 * scale,
+            },
+            kinds: vec![KindSummary {
+                kind: TaskKind::Task,
+                tasks: scale as usize,
+                active: 1,
+                polls: 4 * scale,
+                busy_time: Duration::from_millis(scale),
+                busy_time_total: Duration::from_millis(2 * scale),
+            }],
+            stable_keys: BTreeMap::from([(
+                format!("key-{}", scale % 2),
+                AggregateStats {
+                    tasks: 1,
+                    active: 0,
+                    polls: scale,
+                    busy_time: Duration::from_millis(scale),
+                },
+            )]),
+            tasks: vec![TaskSnapshot {
+                id: 1,
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    /// What a merge adds up, leaving out the tasks, whose order follows the
+    /// summaries'.
+    fn aggregates(merged: &MergedSummary) -> String {
+        format!(
+            "{} {:?} {} {} {} {} {:?} {:?} {:?} {:?} {:?}",
+            merged.instances,
+            merged.reset_instances,
+            merged.transient_tasks,
+            merged.events_lost,
+            merged.panics,
+            merged.active_tasks,
+            merged.dropped_oldest,
+            merged.weighted_busy_time,
+            merged.active_concurrency,
+            merged.kinds,
+            merged.stable_keys,
+        )
+    }
+
+    #[test]
+    fn merge_of_one_keeps_its_totals() {
+        let one = summary("a", 3);
+        let merged = Summary::merge([&one]);
+
+        assert_eq!(merged.instances, 1);
+        assert_eq!(merged.transient_tasks, one.transient_tasks);
+        assert_eq!(merged.events_lost, one.events_lost);
+        assert_eq!(merged.panics, one.panics);
+        assert_eq!(merged.active_tasks, one.active_tasks);
+        assert_eq!(merged.dropped_oldest, one.dropped_oldest);
+        assert_eq!(merged.weighted_busy_time, one.weighted_busy_time);
+        assert_eq!(merged.active_concurrency.as_ref(), Some(&one.active_concurrency));
+        assert_eq!(merged.kinds, one.kinds);
+        assert_eq!(merged.stable_keys, one.stable_keys);
+        assert_eq!(merged.tasks.len(), 1);
+        assert_eq!(merged.tasks[0].instance, "a");
+    }
+
+    #[test]
+    fn merge_is_order_independent() {
+        let (a, b, c) = (summary("a", 1), summary("b", 2), summary("c", 5));
+        let orders = [[&a, &b, &c], [&a, &c, &b], [&b, &a, &c], [&b, &c, &a], [&c, &a, &b], [&c, &b, &a]];
+
+        let expected = aggregates(&Summary::merge(orders[0]));
+        for order in orders {
+            let merged = Summary::merge(order);
+            assert_eq!(aggregates(&merged), expected);
+
+            let mut instances: Vec<_> = merged.tasks.iter().map(|task| task.instance.as_str()).collect();
+            instances.sort_unstable();
+            assert_eq!(instances, ["a", "b", "c"]);
+        }
+
+        let merged = Summary::merge([&a, &b, &c]);
+        assert_eq!(merged.transient_tasks, 8);
+        assert_eq!(merged.active_concurrency.unwrap().buckets, [(1, 8), (10, 16)]);
+    }
+
+    #[test]
+    fn unlabelled_tasks_are_told_apart_by_position() {
+        let (mut a, mut b) = (summary("a", 1), summary("b", 1));
+        a.instance = None;
+        b.instance = None;
+
+        let instances: Vec<_> = Summary::merge([&a, &b]).tasks.into_iter().map(|task| task.instance).collect();
+        assert_eq!(instances, ["#0", "#1"]);
+    }
+
+    #[test]
+    fn distributions_with_different_buckets_are_flagged() {
+        let a = summary("a", 1);
+        let mut b = summary("b", 1);
+        b.active_concurrency.buckets = vec![(2, 1), (20, 2)];
+
+        assert!(Summary::merge([&a, &b]).active_concurrency.is_none());
+    }
+
+    #[test]
+    fn restarted_instances_are_flagged() {
+        let (a, mut b, mut c) = (summary("a", 1), summary("b", 1), summary("c", 1));
+        b.restarts = 1;
+        c.restarts = 2;
+
+        let merged = Summary::merge([&c, &a, &b]);
+        assert_eq!(merged.reset_instances, ["b", "c"]);
+        assert_eq!(merged.restarts, 3);
+        assert!(Summary::merge([&a]).reset_instances.is_empty());
+    }
+}
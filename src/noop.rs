@@ -0,0 +1,85 @@
+use crate::{guard::FlushGuard, handle::CollectorHandle, snapshot::TaskSnapshot};
+
+use tracing_core::subscriber::Subscriber;
+use tracing_subscriber::{registry::LookupSpan, Layer};
+
+use std::io;
+
+/// A stand-in for [`BeeLayer`](crate::BeeLayer) that tracks nothing, for
+/// builds where instrumentation has to cost nothing.
+///
+/// It has no collector and no channel, and every callback is empty. It offers
+/// the same [`pair`](Self::pair) and [`handle`](Self::handle) as `BeeLayer`,
+/// so [`TaskLayer`](crate::TaskLayer) and
+/// [`TaskCollector`](crate::TaskCollector) can switch between the two with
+/// the `disabled` feature.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoopBeeLayer;
+
+impl NoopBeeLayer {
+    pub fn pair() -> (Self, NoopCollector) {
+        (Self, NoopCollector)
+    }
+
+    /// Always `None`; there is no collector to control.
+    pub fn handle(&self) -> Option<CollectorHandle> {
+        None
+    }
+
+    pub fn active_count(&self) -> usize {
+        0
+    }
+
+    /// Always `false`; nothing is collected.
+    pub fn is_collecting(&self) -> bool {
+        false
+    }
+
+    pub fn peak_queue_len(&self) -> usize {
+        0
+    }
+}
+
+/// The collector paired with a [`NoopBeeLayer`], with nothing to collect.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoopCollector;
+
+impl NoopCollector {
+    /// Returns no tasks straight away.
+    pub async fn run(self) -> Vec<TaskSnapshot> {
+        Vec::new()
+    }
+
+    /// Returns a guard with nothing to flush, without starting a thread.
+    pub fn spawn(self) -> io::Result<FlushGuard> {
+        Ok(FlushGuard::noop())
+    }
+}
+
+impl<S> Layer<S> for NoopBeeLayer where S: Subscriber + for<'a> LookupSpan<'a> {}
+
+#[cfg(all(test, feature = "disabled"))]
+mod tests {
+    use crate::{TaskCollector, TaskLayer};
+
+    use tracing_subscriber::layer::SubscriberExt;
+
+    fn assert_layer_bounds<L: Clone + Send + Sync + 'static>(_: &L) {}
+
+    #[tokio::test]
+    async fn disabled_layer_records_nothing() {
+        let (layer, collector): (TaskLayer, TaskCollector) = TaskLayer::pair();
+        assert_layer_bounds(&layer);
+        assert!(layer.handle().is_none());
+
+        let subscriber = tracing_subscriber::registry().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("task");
+            span.in_scope(|| tracing::info!("polled"));
+        });
+
+        assert_eq!(layer.active_count(), 0);
+        assert!(!layer.is_collecting());
+        assert!(collector.run().await.is_empty());
+    }
+}
@@ -0,0 +1,218 @@
+use crate::clock;
+
+use std::{
+    collections::HashMap,
+    fmt,
+    time::{Instant, SystemTime},
+};
+
+/// A span monitoring a queue or channel, as reported on each flush.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueueSummary {
+    pub id: u64,
+    /// The span's name.
+    pub name: String,
+    pub fields: String,
+    /// The depth last recorded.
+    pub depth: u64,
+    /// The greatest depth ever recorded.
+    pub max_depth: u64,
+    /// The depth averaged over time since the previous flush.
+    pub avg_depth: f64,
+    pub opened_at: SystemTime,
+    pub closed_at: Option<SystemTime>,
+}
+
+impl QueueSummary {
+    pub fn is_open(&self) -> bool {
+        self.closed_at.is_none()
+    }
+}
+
+impl fmt::Display for QueueSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Queue {} {}{}: depth {}, max {}, average {:.1}",
+            self.id,
+            self.name,
+            if self.is_open() { "" } else { " (closed)" },
+            self.depth,
+            self.max_depth,
+            self.avg_depth,
+        )
+    }
+}
+
+struct Queue {
+    name: String,
+    fields: String,
+    depth: u64,
+    max_depth: u64,
+    /// Depth integrated over time since `tracked_since`, in depth-seconds.
+    area: f64,
+    /// The start of the current interval, or when the queue was opened if later.
+    tracked_since: Instant,
+    /// When the depth last changed, or the queue closed.
+    changed_at: Instant,
+    opened_at: Instant,
+    closed_at: Option<Instant>,
+}
+
+impl Queue {
+    /// Accounts for the time spent at the current depth up to `now`.
+    fn advance(&mut self, now: Instant) {
+        // Updates can arrive slightly out of order from different threads.
+        let now = now.max(self.changed_at);
+        self.area += self.depth as f64 * now.saturating_duration_since(self.changed_at).as_secs_f64();
+        self.changed_at = now;
+    }
+
+    fn avg_depth(&self, now: Instant) -> f64 {
+        // Closed queues stopped counting when they closed.
+        let (area, end) = match self.closed_at {
+            Some(_) => (self.area, self.changed_at),
+            None => {
+                let current = self.depth as f64 * now.saturating_duration_since(self.changed_at).as_secs_f64();
+                (self.area + current, now)
+            }
+        };
+
+        let tracked = end.saturating_duration_since(self.tracked_since);
+        if tracked.is_zero() {
+            return self.depth as f64;
+        }
+
+        area / tracked.as_secs_f64()
+    }
+}
+
+/// Depth of every queue span, with a time-weighted average per flush interval.
+#[derive(Default)]
+pub(crate) struct QueueTracker {
+    queues: HashMap<u64, Queue>,
+}
+
+impl QueueTracker {
+    pub fn opened(&mut self, id: u64, name: String, fields: String, depth: Option<u64>, time: Instant) {
+        let depth = depth.unwrap_or(0);
+
+        self.queues.insert(id, Queue {
+            name,
+            fields,
+            depth,
+            max_depth: depth,
+            area: 0.0,
+            tracked_since: time,
+            changed_at: time,
+            opened_at: time,
+            closed_at: None,
+        });
+    }
+
+    pub fn depth(&mut self, id: u64, depth: u64, time: Instant) {
+        if let Some(queue) = self.open_mut(id) {
+            queue.advance(time);
+            queue.depth = depth;
+            queue.max_depth = queue.max_depth.max(depth);
+        }
+    }
+
+    pub fn closed(&mut self, id: u64, time: Instant) {
+        if let Some(queue) = self.open_mut(id) {
+            queue.advance(time);
+            queue.closed_at = Some(time);
+        }
+    }
+
+    fn open_mut(&mut self, id: u64) -> Option<&mut Queue> {
+        self.queues.get_mut(&id).filter(|queue| queue.closed_at.is_none())
+    }
+
+    /// Reports every queue, with averages over the interval since the last flush.
+    pub fn summary(&self) -> Vec<QueueSummary> {
+        let now = Instant::now();
+
+        let mut queues: Vec<_> = self
+            .queues
+            .iter()
+            .map(|(id, queue)| QueueSummary {
+                id: *id,
+                name: queue.name.clone(),
+                fields: queue.fields.clone(),
+                depth: queue.depth,
+                max_depth: queue.max_depth,
+                avg_depth: queue.avg_depth(now),
+                opened_at: clock::wall_time(queue.opened_at),
+                closed_at: queue.closed_at.map(clock::wall_time),
+            })
+            .collect();
+        queues.sort_by_key(|queue| queue.id);
+
+        queues
+    }
+
+    /// Starts a new interval, forgetting queues that closed during the last one.
+    pub fn reset(&mut self) {
+        let now = Instant::now();
+
+        self.queues.retain(|_, queue| queue.closed_at.is_none());
+        for queue in self.queues.values_mut() {
+            queue.advance(now);
+            queue.area = 0.0;
+            queue.tracked_since = now;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Running;
+    use crate::BeeLayer;
+
+    use std::time::Duration;
+
+    #[test]
+    fn average_depth_is_weighted_by_time() {
+        let mut queues = QueueTracker::default();
+        let start = Instant::now();
+        let secs = |secs| start + Duration::from_secs(secs);
+
+        queues.opened(1, "jobs".into(), String::new(), None, start);
+        queues.depth(1, 4, secs(1));
+        queues.depth(1, 2, secs(3));
+        queues.closed(1, secs(4));
+        // Ignored once closed.
+        queues.depth(1, 9, secs(5));
+
+        let queue = &queues.summary()[0];
+        assert_eq!((queue.depth, queue.max_depth), (2, 4));
+        assert_eq!(queue.avg_depth, 2.5);
+        assert!(!queue.is_open());
+
+        queues.reset();
+        assert!(queues.summary().is_empty());
+    }
+
+    #[tokio::test]
+    async fn spans_with_the_queue_field_are_tracked_as_queues() {
+        let running = Running::start(BeeLayer::builder().queue_field("backlog").build());
+
+        let queue = tracing::info_span!("jobs", backlog = 1u64);
+        queue.record("backlog", 7u64);
+        queue.record("backlog", 3u64);
+        queue.in_scope(|| {});
+        tracing::info_span!("task").in_scope(|| {});
+
+        let summary = running.handle.debug_report(Duration::from_secs(5)).await.summary;
+        assert_eq!(summary.queues.len(), 1);
+        let jobs = &summary.queues[0];
+        assert_eq!((jobs.name.as_str(), jobs.depth, jobs.max_depth), ("jobs", 3, 7));
+        assert!(jobs.is_open());
+        assert_eq!(summary.tasks.len(), 1);
+
+        drop(queue);
+        assert_eq!(running.finish().await.len(), 1);
+    }
+}
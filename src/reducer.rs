@@ -0,0 +1,73 @@
+use crate::{event::Event, stats::Stats};
+
+use std::{any::Any, collections::HashMap, sync::Arc};
+
+/// Accumulates a value of its own per task from the raw event stream, for
+/// metrics the collector doesn't compute itself.
+///
+/// Added with [`Builder::task_reducer`](crate::Builder::task_reducer), and
+/// read back from snapshots with [`TaskSnapshot::reduced`](crate::TaskSnapshot::reduced).
+///
+/// ```ignore
+/// struct PollsAfter(Instant);
+///
+/// impl TaskReducer for PollsAfter {
+///     type Value = u64;
+///
+///     fn reduce(&mut self, polls: &mut u64, event: &Event, _stats: &Stats) {
+///         if let Event::Enter { time, .. } = event {
+///             *polls += (*time >= self.0) as u64;
+///         }
+///     }
+/// }
+/// ```
+pub trait TaskReducer: Send + Sync + 'static {
+    type Value: Default + Clone + Send + Sync + 'static;
+
+    /// Called with every event about a task, after the collector has applied
+    /// it to the task's `stats`. Each task starts with the default value.
+    fn reduce(&mut self, value: &mut Self::Value, event: &Event, stats: &Stats);
+}
+
+/// A [`TaskReducer`] with its value type erased, so that reducers of
+/// different types can sit side by side.
+pub(crate) trait ErasedReducer: Send + Sync {
+    fn reduce(&mut self, task_id: u64, event: &Event, stats: &Stats);
+    fn value(&self, task_id: u64) -> Option<Arc<dyn Any + Send + Sync>>;
+    fn remove(&mut self, task_id: u64);
+    fn clear(&mut self);
+}
+
+pub(crate) struct Reducer<R: TaskReducer> {
+    reducer: R,
+    values: HashMap<u64, R::Value>,
+}
+
+impl<R: TaskReducer> Reducer<R> {
+    pub fn new(reducer: R) -> Self {
+        Self {
+            reducer,
+            values: HashMap::new(),
+        }
+    }
+}
+
+impl<R: TaskReducer> ErasedReducer for Reducer<R> {
+    fn reduce(&mut self, task_id: u64, event: &Event, stats: &Stats) {
+        let value = self.values.entry(task_id).or_default();
+        self.reducer.reduce(value, event, stats);
+    }
+
+    fn value(&self, task_id: u64) -> Option<Arc<dyn Any + Send + Sync>> {
+        let value = self.values.get(&task_id)?.clone();
+        Some(Arc::new(value))
+    }
+
+    fn remove(&mut self, task_id: u64) {
+        self.values.remove(&task_id);
+    }
+
+    fn clear(&mut self) {
+        self.values.clear();
+    }
+}
@@ -0,0 +1,134 @@
+use crate::snapshot::TaskSnapshot;
+
+use std::{
+    cell::Cell,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The target completed tasks are replayed on, see
+/// [`Builder::replay_spans`](crate::Builder::replay_spans).
+pub const REPLAY_TARGET: &str = "bee_console::replay";
+
+thread_local! {
+    static REPLAYING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Whether the current thread is replaying a task, in which case the layer
+/// ignores the spans it sees rather than tracking its own replays.
+pub(crate) fn is_replaying() -> bool {
+    REPLAYING.with(Cell::get)
+}
+
+/// Clears the flag again, even if a layer panics on the replayed span.
+struct Replaying {
+    was_replaying: bool,
+}
+
+impl Drop for Replaying {
+    fn drop(&mut self) {
+        REPLAYING.with(|replaying| replaying.set(self.was_replaying));
+    }
+}
+
+/// Emits a closed task as a span of its own on [`REPLAY_TARGET`], entered and
+/// exited straight away, with the task's stats as fields.
+///
+/// `tracing` spans can't be given explicit start and end times, so the span
+/// itself takes no time; the task's own times are recorded as fields.
+pub(crate) fn replay(task: &TaskSnapshot) {
+    let micros = |time: Option<SystemTime>| {
+        time.and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|since_epoch| since_epoch.as_micros() as u64)
+    };
+
+    let _replaying = Replaying {
+        was_replaying: REPLAYING.with(|replaying| replaying.replace(true)),
+    };
+
+    let span = tracing::info_span!(
+        target: REPLAY_TARGET,
+        "task",
+        task.id = task.id,
+        task.name = task.name.as_deref(),
+        task.fields = %task.fields,
+        task.kind = task.kind.as_str(),
+        task.callsite = task.callsite.map(|callsite| callsite.to_string()),
+        task.outcome = task.outcome.map(|outcome| outcome.as_str()),
+        polls = task.stats.polls(),
+        busy_us = task.stats.busy_time().as_micros() as u64,
+        total_us = task.total_time().map(|total| total.as_micros() as u64),
+        created_at_us = micros(task.stats.created_at()),
+        closed_at_us = micros(task.stats.closed_at()),
+    );
+    span.in_scope(|| {});
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BeeLayer;
+
+    use tracing::{span, Subscriber};
+    use tracing_subscriber::{
+        layer::{Context, SubscriberExt},
+        Layer,
+    };
+
+    use std::{
+        panic,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    /// Counts the replayed spans it sees, panicking on them if asked to.
+    struct Replays {
+        seen: Arc<AtomicUsize>,
+        panic: bool,
+    }
+
+    impl<S: Subscriber> Layer<S> for Replays {
+        fn on_new_span(&self, attrs: &span::Attributes<'_>, _id: &span::Id, _ctx: Context<'_, S>) {
+            if attrs.metadata().target() == REPLAY_TARGET {
+                self.seen.fetch_add(1, Ordering::Relaxed);
+                assert!(!self.panic, "downstream layer panicked");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn downstream_layers_see_one_span_per_task() {
+        let seen = Arc::new(AtomicUsize::new(0));
+        let (layer, collector) = BeeLayer::builder().replay_spans().build();
+        let handle = collector.handle();
+        let subscriber = tracing_subscriber::registry().with(layer).with(Replays {
+            seen: seen.clone(),
+            panic: false,
+        });
+        let _default = tracing::subscriber::set_default(subscriber);
+        let collector = tokio::spawn(collector.run());
+
+        for job in 0..3 {
+            tracing::info_span!("job", job).in_scope(|| {});
+        }
+
+        handle.shutdown().await.unwrap();
+        let tasks = collector.await.unwrap();
+        assert_eq!(tasks.len(), 3);
+        assert_eq!(seen.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn replaying_is_cleared_when_a_layer_panics() {
+        let subscriber = tracing_subscriber::registry().with(Replays {
+            seen: Default::default(),
+            panic: true,
+        });
+        let _default = tracing::subscriber::set_default(subscriber);
+
+        let replayed = panic::catch_unwind(|| replay(&TaskSnapshot::default()));
+        assert!(replayed.is_err());
+        assert!(!is_replaying());
+    }
+}
@@ -0,0 +1,139 @@
+use crate::{
+    config::EffectiveConfig,
+    export::{ExportConfig, Row},
+    summary::Summary,
+};
+
+use serde_json::{Map, Value};
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Whether the collector looked healthy when a [`DebugReport`] was taken.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CollectorHealth {
+    /// Whether the collector was still accepting commands.
+    pub running: bool,
+    /// Time since the collector last flushed.
+    pub since_heartbeat: Duration,
+    /// Times the [watchdog](crate::Builder::watchdog) reported the collector
+    /// as stalled.
+    pub stalls: u64,
+    /// Tasks spawned and not yet closed, as counted by the producers.
+    pub active_tasks: usize,
+    /// When the latest event the collector processed happened.
+    pub last_event_at: Option<SystemTime>,
+}
+
+/// Everything the collector knows, captured at once for incident debugging.
+#[derive(Clone, Debug)]
+pub struct DebugReport {
+    pub generated_at: SystemTime,
+    /// Whether `summary` is from the collector's last flush rather than
+    /// taken for this report, because the collector didn't respond.
+    pub stale: bool,
+    pub collector: CollectorHealth,
+    pub config: EffectiveConfig,
+    pub summary: Summary,
+}
+
+impl DebugReport {
+    /// The report as a JSON object with the sections `generated_at`, `stale`,
+    /// `collector`, `config`, `aggregates`, `flags` and `tasks`.
+    pub fn to_json(&self, export: &ExportConfig) -> Value {
+        let mut map = Map::new();
+
+        let mut row = Row::new();
+        export.timestamp(&mut row, "generated_at", Some(self.generated_at));
+        row.push(("stale".into(), self.stale.into()));
+        map.extend(row);
+
+        let mut collector = Row::new();
+        collector.push(("running".into(), self.collector.running.into()));
+        export.duration(&mut collector, "since_heartbeat", Some(self.collector.since_heartbeat));
+        collector.push(("stalls".into(), self.collector.stalls.into()));
+        collector.push(("active_tasks".into(), self.collector.active_tasks.into()));
+        export.timestamp(&mut collector, "last_event_at", self.collector.last_event_at);
+        map.insert("collector".into(), Value::Object(collector.into_iter().collect()));
+        map.insert("config".into(), self.config.to_json(export));
+
+        map.insert("aggregates".into(), Value::Object(export.aggregates_json(&self.summary)));
+
+        let mut flags = Map::new();
+        flags.insert(
+            "over_budget".into(),
+            self.summary.tasks.iter().filter(|task| task.over_budget).map(|task| task.id).collect(),
+        );
+        map.insert("flags".into(), Value::Object(flags));
+
+        map.insert("tasks".into(), self.summary.tasks.iter().map(|task| export.task_json(task)).collect());
+
+        Value::Object(map)
+    }
+
+    pub fn to_pretty_json(&self, export: &ExportConfig) -> String {
+        serde_json::to_string_pretty(&self.to_json(export)).unwrap_or_default()
+    }
+
+    /// Writes the report as pretty JSON to a file in `dir` named after the
+    /// time it was generated, e.g. `bee-report-1700000000000.json`,
+    /// returning the file's path.
+    pub fn write_to_dir(&self, dir: impl AsRef<Path>, export: &ExportConfig) -> io::Result<PathBuf> {
+        let millis = self.generated_at.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+        let path = dir.as_ref().join(format!("bee-report-{}.json", millis));
+
+        fs::write(&path, self.to_pretty_json(export))?;
+
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Running;
+    use crate::BeeLayer;
+
+    /// Whether `object` has a key starting with `key`, as duration and
+    /// timestamp keys are suffixed with their unit.
+    fn has(object: &Value, key: &str) -> bool {
+        object.as_object().unwrap().keys().any(|name| name.starts_with(key))
+    }
+
+    #[tokio::test]
+    async fn report_has_every_section() {
+        let running = Running::start(BeeLayer::pair());
+
+        let span = tracing::info_span!("job");
+        span.in_scope(|| {});
+        let report = running.handle.debug_report(Duration::from_secs(5)).await;
+        assert!(!report.stale);
+
+        let export = ExportConfig::default();
+        let json: Value = serde_json::from_str(&report.to_pretty_json(&export)).unwrap();
+        for section in ["generated_at", "stale", "collector", "config", "aggregates", "flags", "tasks"] {
+            assert!(has(&json, section), "missing {}", section);
+        }
+        for key in ["running", "since_heartbeat", "stalls", "active_tasks", "last_event_at"] {
+            assert!(has(&json["collector"], key), "missing collector.{}", key);
+        }
+        assert_eq!(json["collector"]["running"], true);
+        assert_eq!(json["collector"]["active_tasks"], 1);
+        assert!(has(&json["config"], "tick_interval"));
+        assert!(has(&json["aggregates"], "events_lost"));
+        assert_eq!(json["flags"]["over_budget"], Value::Array(Vec::new()));
+        assert_eq!(json["tasks"].as_array().unwrap().len(), 1);
+        assert_eq!(json["tasks"][0]["polls"], 1);
+
+        let path = report.write_to_dir(std::env::temp_dir(), &export).unwrap();
+        let written: Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(written, json);
+
+        drop(span);
+        running.finish().await;
+    }
+}
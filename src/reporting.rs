@@ -0,0 +1,95 @@
+use std::cell::Cell;
+
+/// The target of the `task_metrics` span the collector logs its reports and
+/// flushes its sinks in, so that downstream layers can filter on it.
+pub const METRICS_TARGET: &str = "bee_console::metrics";
+
+/// The target of the diagnostics the layer and collector log about
+/// themselves, e.g. dropped events or sinks failing. The layer ignores
+/// events on it, so that they never count towards the task they happen in.
+pub const INTERNAL_TARGET: &str = "bee_console::internal";
+
+/// Whether spans and events on `target` are the crate's own doing.
+pub(crate) fn is_own_target(target: &str) -> bool {
+    target == METRICS_TARGET || target == INTERNAL_TARGET
+}
+
+thread_local! {
+    static REPORTING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Whether the current thread is inside the collector's metrics span, in
+/// which case the layer ignores the spans it sees rather than tracking the
+/// collector's own reporting.
+pub(crate) fn is_reporting() -> bool {
+    REPORTING.with(Cell::get)
+}
+
+/// Clears the flag again, even if reporting panics.
+struct Reporting {
+    was_reporting: bool,
+}
+
+impl Drop for Reporting {
+    fn drop(&mut self) {
+        REPORTING.with(|reporting| reporting.set(self.was_reporting));
+    }
+}
+
+/// Runs `report` inside a `task_metrics` span on [`METRICS_TARGET`].
+pub(crate) fn in_metrics_span<R>(report: impl FnOnce() -> R) -> R {
+    let _reporting = Reporting {
+        was_reporting: REPORTING.with(|reporting| reporting.replace(true)),
+    };
+    let _span = tracing::info_span!(target: METRICS_TARGET, "task_metrics").entered();
+
+    report()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Running;
+    use crate::{BeeLayer, Summary};
+
+    use tracing::Subscriber;
+    use tracing_subscriber::{layer::{Context, SubscriberExt}, registry::LookupSpan, Layer};
+
+    use std::sync::{Arc, Mutex};
+
+    /// An event's target, and the target and name of each span it was in.
+    type Scope = (String, Vec<(&'static str, &'static str)>);
+
+    /// Records the scope of every event.
+    struct Scopes(Arc<Mutex<Vec<Scope>>>);
+
+    impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for Scopes {
+        fn on_event(&self, event: &tracing::Event<'_>, ctx: Context<'_, S>) {
+            let spans = ctx.event_scope(event).map(|scope| scope.map(|span| (span.metadata().target(), span.name())).collect()).unwrap_or_default();
+            self.0.lock().unwrap().push((event.metadata().target().to_string(), spans));
+        }
+    }
+
+    #[tokio::test]
+    async fn reports_are_logged_in_an_untracked_metrics_span() {
+        let scopes = Arc::new(Mutex::new(Vec::new()));
+        let (layer, collector) = BeeLayer::builder()
+            // A sink's own spans are part of the reporting, and as untracked.
+            .summary_sink(|_: &Summary| tracing::info_span!("export").in_scope(|| {}))
+            .build();
+        let subscriber = tracing_subscriber::registry().with(layer).with(Scopes(scopes.clone()));
+        let running = Running::under(subscriber, collector);
+
+        tracing::info_span!("job").in_scope(|| tracing::info!("working"));
+        let tasks = running.finish().await;
+
+        let names: Vec<_> = tasks.iter().map(|task| task.callsite.unwrap().name).collect();
+        assert_eq!(names, ["job"]);
+
+        let scopes = scopes.lock().unwrap();
+        let reports: Vec<_> = scopes.iter().filter(|(target, _)| target == "bee_console::collector").collect();
+        assert!(reports.len() > 1);
+        assert!(reports.iter().all(|(_, spans)| spans == &[(METRICS_TARGET, "task_metrics")]), "{:?}", reports);
+        assert!(scopes.contains(&("bee_console::reporting::tests".to_string(), vec![("bee_console::reporting::tests", "job")])));
+    }
+}
@@ -0,0 +1,279 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+/// A file that rotates once it grows past a size limit, for sinks writing
+/// long captures.
+///
+/// On rotation `capture.jsonl` moves to `capture.jsonl.1`, which moves to
+/// `capture.jsonl.2` and so on, keeping at most `max_files` old files. Files
+/// are only rotated on [`flush`](Write::flush), which sinks call after each
+/// whole record, and only by renaming, so a reader opening any of the paths
+/// sees a complete file.
+pub struct RotatingFile {
+    path: PathBuf,
+    max_file_size: u64,
+    max_files: usize,
+    #[cfg(feature = "zstd")]
+    compress: bool,
+    output: Option<Output>,
+}
+
+impl RotatingFile {
+    const DEFAULT_MAX_FILE_SIZE: u64 = 64 * 1024 * 1024;
+    const DEFAULT_MAX_FILES: usize = 5;
+
+    /// Writes to `path`, appending to it if it already exists. The file is
+    /// opened on the first write.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            max_file_size: Self::DEFAULT_MAX_FILE_SIZE,
+            max_files: Self::DEFAULT_MAX_FILES,
+            #[cfg(feature = "zstd")]
+            compress: false,
+            output: None,
+        }
+    }
+
+    /// Rotates once the file on disk reaches `bytes`. Defaults to 64 MiB.
+    pub fn max_file_size(mut self, bytes: u64) -> Self {
+        self.max_file_size = bytes;
+        self
+    }
+
+    /// How many rotated files to keep besides the current one. Defaults to 5.
+    pub fn max_files(mut self, count: usize) -> Self {
+        self.max_files = count;
+        self
+    }
+
+    /// Compresses each file as a zstd stream. Frames are finished on rotation
+    /// and when the writer is dropped, so every rotated file decompresses on
+    /// its own. The size limit applies to the compressed size.
+    #[cfg(feature = "zstd")]
+    pub fn compress(mut self, compress: bool) -> Self {
+        self.compress = compress;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        path.into()
+    }
+
+    fn output(&mut self) -> io::Result<&mut Output> {
+        if self.output.is_none() {
+            let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+            let written = file.metadata()?.len();
+            let file = CountingFile { file, written };
+
+            #[cfg(feature = "zstd")]
+            let output = if self.compress {
+                Output::Zstd(zstd::stream::write::Encoder::new(file, 0)?)
+            } else {
+                Output::Plain(BufWriter::new(file))
+            };
+            #[cfg(not(feature = "zstd"))]
+            let output = Output::Plain(BufWriter::new(file));
+
+            self.output = Some(output);
+        }
+
+        Ok(self.output.as_mut().expect("output was just opened"))
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if let Some(output) = self.output.take() {
+            output.finish()?;
+        }
+
+        if self.max_files == 0 {
+            return fs::remove_file(&self.path);
+        }
+
+        for index in (1..self.max_files).rev() {
+            match fs::rename(self.rotated_path(index), self.rotated_path(index + 1)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+
+        fs::rename(&self.path, self.rotated_path(1))
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output()?.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let output = match self.output.as_mut() {
+            Some(output) => output,
+            None => return Ok(()),
+        };
+        output.flush()?;
+
+        if output.written() >= self.max_file_size {
+            self.rotate()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for RotatingFile {
+    fn drop(&mut self) {
+        if let Some(output) = self.output.take() {
+            let _ = output.finish();
+        }
+    }
+}
+
+enum Output {
+    Plain(BufWriter<CountingFile>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::write::Encoder<'static, CountingFile>),
+}
+
+impl Output {
+    /// Bytes on disk, as of the last flush.
+    fn written(&self) -> u64 {
+        match self {
+            Self::Plain(writer) => writer.get_ref().written,
+            #[cfg(feature = "zstd")]
+            Self::Zstd(encoder) => encoder.get_ref().written,
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        let mut file = match self {
+            Self::Plain(writer) => writer.into_inner().map_err(io::IntoInnerError::into_error)?,
+            #[cfg(feature = "zstd")]
+            Self::Zstd(encoder) => encoder.finish()?,
+        };
+
+        file.flush()
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Plain(writer) => writer.write(buf),
+            #[cfg(feature = "zstd")]
+            Self::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Plain(writer) => writer.flush(),
+            #[cfg(feature = "zstd")]
+            Self::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+struct CountingFile {
+    file: File,
+    written: u64,
+}
+
+impl Write for CountingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.file.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::process;
+
+    /// An empty directory of its own for each test.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("bee-rotate-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// The files `file` wrote, oldest first.
+    fn written(file: &RotatingFile) -> Vec<PathBuf> {
+        let mut paths: Vec<_> = (1..).map(|index| file.rotated_path(index)).take_while(|path| path.exists()).collect();
+        paths.reverse();
+        paths.push(file.path().to_owned());
+        paths
+    }
+
+    #[test]
+    fn files_rotate_past_the_size_limit() {
+        let dir = scratch_dir("plain");
+        let mut file = RotatingFile::new(dir.join("capture.log")).max_file_size(64).max_files(2);
+        for line in 0..30 {
+            writeln!(file, "line {:02}", line).unwrap();
+            file.flush().unwrap();
+        }
+        let paths = written(&file);
+        drop(file);
+
+        assert_eq!(paths.len(), 3);
+        let kept: String = paths.iter().map(|path| fs::read_to_string(path).unwrap()).collect();
+        let lines: Vec<_> = kept.lines().collect();
+        // Rotated files hold whole lines, and only the oldest are gone.
+        let first = 30 - lines.len();
+        assert!(first > 0);
+        let expected: Vec<_> = (first..30).map(|line| format!("line {:02}", line)).collect();
+        assert_eq!(lines, expected);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn compressed_files_decompress_on_their_own() {
+        use crate::{
+            event::{Event, EventId},
+            sink::{EventSink, JsonlEventSink},
+        };
+
+        let dir = scratch_dir("zstd");
+        let file = RotatingFile::new(dir.join("capture.jsonl")).max_file_size(200).max_files(100).compress(true);
+        let sink = JsonlEventSink::new(file);
+        for id in 0..50 {
+            sink.send(&Event::close(EventId::external(id)));
+        }
+        let file = sink.into_inner();
+        let paths = written(&file);
+        drop(file);
+
+        assert!(paths.len() > 2);
+        let mut ids = Vec::new();
+        for path in paths {
+            let decompressed = zstd::decode_all(File::open(path).unwrap()).unwrap();
+            for line in String::from_utf8(decompressed).unwrap().lines() {
+                let event: serde_json::Value = serde_json::from_str(line).unwrap();
+                ids.push(event["id"].as_u64().unwrap());
+            }
+        }
+        assert_eq!(ids, (0..50).collect::<Vec<_>>());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,86 @@
+use std::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// How many tasks were tracked out of how many were spawned, when only
+/// [one in every few](crate::Builder::sample_one_in) is.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SampleStats {
+    /// Every this many tasks, one is tracked.
+    pub one_in: u64,
+    /// Tasks spawned, tracked or not.
+    pub seen: u64,
+    /// Tasks tracked.
+    pub sampled: u64,
+}
+
+impl SampleStats {
+    /// How much to scale totals over the tracked tasks by, e.g. polls or
+    /// busy time, to estimate them over every task.
+    pub fn scale(&self) -> f64 {
+        match self.sampled {
+            0 => 0.0,
+            sampled => self.seen as f64 / sampled as f64,
+        }
+    }
+
+    /// Estimates a total over every task from one over the tracked tasks.
+    pub fn extrapolate(&self, sampled_total: f64) -> f64 {
+        sampled_total * self.scale()
+    }
+
+    /// Adds the counts of `other`. Counts from instances sampling at
+    /// different rates still scale correctly, but `one_in` keeps its value.
+    pub fn merge(&mut self, other: &SampleStats) {
+        if self.one_in == 0 {
+            self.one_in = other.one_in;
+        }
+        self.seen += other.seen;
+        self.sampled += other.sampled;
+    }
+}
+
+impl fmt::Display for SampleStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Sampled {} of {} tasks (1 in {}), totals over them scale by {:.1}",
+            self.sampled,
+            self.seen,
+            self.one_in,
+            self.scale(),
+        )
+    }
+}
+
+/// Picks every `one_in`th task to track. Counting rather than drawing at
+/// random keeps runs repeatable.
+pub(crate) struct Sampler {
+    one_in: u64,
+    seen: AtomicU64,
+}
+
+impl Sampler {
+    pub fn new(one_in: u64) -> Self {
+        Self {
+            one_in,
+            seen: AtomicU64::new(0),
+        }
+    }
+
+    /// Whether to track the task being spawned.
+    pub fn sample(&self) -> bool {
+        self.seen.fetch_add(1, Ordering::Relaxed).is_multiple_of(self.one_in)
+    }
+
+    pub fn stats(&self) -> SampleStats {
+        let seen = self.seen.load(Ordering::Relaxed);
+
+        SampleStats {
+            one_in: self.one_in,
+            seen,
+            sampled: seen.div_ceil(self.one_in),
+        }
+    }
+}
@@ -0,0 +1,837 @@
+use crate::{
+    close::CloseSubscribers,
+    config::EffectiveConfig,
+    event::{Event, EventId},
+    filter::FilteredTraffic,
+    reporting::INTERNAL_TARGET,
+    sampling::Sampler,
+    sink::EventSink,
+    startup::StartupBuffer,
+    summary::{PeakActive, Summary},
+};
+
+use tokio::{
+    runtime::{Handle, RuntimeFlavor},
+    sync::{
+        mpsc::{
+            error::{TryRecvError, TrySendError},
+            Permit, Receiver, Sender, UnboundedReceiver, UnboundedSender, WeakSender, WeakUnboundedSender,
+        },
+        Notify,
+    },
+    task,
+};
+
+use std::{
+    collections::{HashSet, VecDeque},
+    fmt, hint, mem,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    thread::{self, ThreadId},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Events dropped with the collector's channel full, by kind.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DroppedEvents {
+    pub spawn: u64,
+    pub enter: u64,
+    pub exit: u64,
+    pub close: u64,
+    pub record: u64,
+    pub follows_from: u64,
+    pub log: u64,
+    pub waker: u64,
+    /// Queue spans opening and closing.
+    pub queue: u64,
+}
+
+impl DroppedEvents {
+    fn kinds(&self) -> [(&'static str, u64); 9] {
+        [
+            ("spawn", self.spawn),
+            ("enter", self.enter),
+            ("exit", self.exit),
+            ("close", self.close),
+            ("record", self.record),
+            ("follows_from", self.follows_from),
+            ("log", self.log),
+            ("waker", self.waker),
+            ("queue", self.queue),
+        ]
+    }
+
+    pub fn total(&self) -> u64 {
+        self.kinds().iter().map(|(_, dropped)| dropped).sum()
+    }
+
+    pub fn merge(&mut self, other: &DroppedEvents) {
+        self.spawn += other.spawn;
+        self.enter += other.enter;
+        self.exit += other.exit;
+        self.close += other.close;
+        self.record += other.record;
+        self.follows_from += other.follows_from;
+        self.log += other.log;
+        self.waker += other.waker;
+        self.queue += other.queue;
+    }
+
+    /// Each kind anything was dropped of, with how many.
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, u64)> {
+        IntoIterator::into_iter(self.kinds()).filter(|(_, dropped)| *dropped > 0)
+    }
+}
+
+/// Displays as e.g. `spawn 3, enter 40`, leaving out kinds with none dropped.
+impl fmt::Display for DroppedEvents {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kinds: Vec<_> = self.iter().map(|(kind, dropped)| format!("{} {}", kind, dropped)).collect();
+        f.write_str(&kinds.join(", "))
+    }
+}
+
+/// Counts behind [`DroppedEvents`], one per kind in the order of its fields.
+#[derive(Default)]
+pub(crate) struct DroppedCounts([AtomicU64; 9]);
+
+impl DroppedCounts {
+    /// Which count an event goes towards.
+    fn slot(event: &Event) -> usize {
+        match event {
+            Event::Spawn { .. } => 0,
+            Event::Enter { .. } => 1,
+            Event::Exit { .. } => 2,
+            Event::Close { .. } => 3,
+            Event::Record { .. } => 4,
+            Event::FollowsFrom { .. } => 5,
+            Event::Log { .. } => 6,
+            Event::Waker { .. } => 7,
+            Event::QueueOpen { .. } | Event::QueueClose { .. } => 8,
+        }
+    }
+
+    fn count(&self, slot: usize) {
+        self.0[slot].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn total(&self) -> u64 {
+        self.0.iter().map(|count| count.load(Ordering::Relaxed)).sum()
+    }
+
+    pub fn load(&self) -> DroppedEvents {
+        let [spawn, enter, exit, close, record, follows_from, log, waker, queue] = self.0.each_ref().map(|count| count.load(Ordering::Relaxed));
+
+        DroppedEvents {
+            spawn,
+            enter,
+            exit,
+            close,
+            record,
+            follows_from,
+            log,
+            waker,
+            queue,
+        }
+    }
+}
+
+/// What to do with an event when the collector's queue is full.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Discard the event being sent. This is the default.
+    #[default]
+    DropNewest,
+    /// Discard the oldest queued event to make room, keeping recent state
+    /// fresh at the cost of taking a lock on every send.
+    DropOldest,
+}
+
+/// State shared between every producer of events and the collector.
+pub(crate) struct Shared {
+    pub startup: StartupBuffer,
+    /// Tasks spawned and not yet closed, as seen by the producers.
+    pub active: AtomicUsize,
+    /// Cleared once the collector stops, after which events are no longer sent.
+    collecting: AtomicBool,
+    /// The most tasks ever active at once. Mirrors `peak` so producers only
+    /// take the lock when setting a new high-water mark.
+    peak_count: AtomicUsize,
+    peak: Mutex<Option<PeakActive>>,
+    /// The most events ever waiting for the collector at once.
+    pub peak_queue_len: AtomicUsize,
+    /// Events discarded because the channel was full, under
+    /// [`OverflowPolicy::DropNewest`].
+    pub dropped_newest: DroppedCounts,
+    /// Replaces the channel once the collector runs, under [`OverflowPolicy::DropOldest`].
+    pub ring: Option<EventRing>,
+    /// When the collector last ran, in milliseconds since `created`.
+    heartbeat: AtomicU64,
+    /// Times the watchdog reported the collector as stalled.
+    pub stalls: AtomicU64,
+    /// When the latest event the collector processed happened, in
+    /// microseconds since the epoch, or 0 if it hasn't processed any.
+    last_event_at: AtomicU64,
+    /// How often the collector flushes, in nanoseconds.
+    tick_interval: AtomicU64,
+    created: Instant,
+    /// The most recent summary, readable without going through the collector.
+    summary: Mutex<Option<Arc<Summary>>>,
+    /// Receive a copy of every event sent.
+    pub event_sinks: Vec<EventConsumer>,
+    /// Extra attempts at sending into a full channel under
+    /// [`OverflowPolicy::DropNewest`] before the event is dropped.
+    pub send_retries: u32,
+    /// Whether events are dropped rather than waited on room for when the
+    /// channel is full, see [`Builder::lossy`](crate::Builder::lossy).
+    pub lossy: bool,
+    /// The thread the collector runs on, if it runs on a current-thread
+    /// runtime, where nothing may wait for it to make room.
+    collector_thread: Mutex<Option<ThreadId>>,
+    /// Woken as a sender starts waiting for room, for the collector to
+    /// drain its channel rather than wait for its next tick.
+    pub channel_full: Notify,
+    /// Names of the tasks to track, or `None` to track every task.
+    allowlist: RwLock<Option<Arc<HashSet<String>>>>,
+    /// Whether an allowlist was ever set, so spans may have been skipped.
+    /// Until then producers don't need to look for skipped spans.
+    allowlist_used: AtomicBool,
+    /// Whether a span on one of the crate's own targets was opened outside
+    /// its reporting, and so skipped. Until then producers don't need to
+    /// look for it either.
+    own_spans_skipped: AtomicBool,
+    /// The [`EventId`] source to give the next clone of the layer.
+    next_span_source: AtomicU32,
+    /// The sequence number of the next event injected, shared by every
+    /// [`EventInjector`](crate::EventInjector) as they share a source.
+    next_external_seq: AtomicU64,
+    /// Spans and events kept from the collector, once enabled.
+    pub filtered: FilteredTraffic,
+    /// The settings the layer was built with, see [`Shared::effective_config`].
+    pub config: EffectiveConfig,
+    /// Receive each task's final snapshot as it closes.
+    pub close_events: CloseSubscribers,
+    /// Picks the tasks to track, if only some are.
+    pub sampler: Option<Sampler>,
+    /// Woken as senders are dropped, for the collector to check whether
+    /// its channel has closed rather than wait for its next tick.
+    pub senders_dropped: Notify,
+}
+
+impl Shared {
+    pub fn new(startup_buffer_capacity: usize, policy: OverflowPolicy, capacity: usize) -> Self {
+        Self {
+            startup: StartupBuffer::new(startup_buffer_capacity),
+            active: AtomicUsize::new(0),
+            collecting: AtomicBool::new(true),
+            peak_count: AtomicUsize::new(0),
+            peak: Mutex::new(None),
+            peak_queue_len: AtomicUsize::new(0),
+            dropped_newest: Default::default(),
+            ring: match policy {
+                OverflowPolicy::DropNewest => None,
+                OverflowPolicy::DropOldest => Some(EventRing::new(capacity)),
+            },
+            heartbeat: AtomicU64::new(0),
+            stalls: AtomicU64::new(0),
+            last_event_at: AtomicU64::new(0),
+            tick_interval: AtomicU64::new(0),
+            created: Instant::now(),
+            summary: Mutex::new(None),
+            event_sinks: Vec::new(),
+            send_retries: 0,
+            lossy: true,
+            collector_thread: Mutex::new(None),
+            channel_full: Notify::new(),
+            allowlist: RwLock::new(None),
+            allowlist_used: AtomicBool::new(false),
+            own_spans_skipped: AtomicBool::new(false),
+            next_span_source: AtomicU32::new(EventId::FIRST_SPAN + 1),
+            next_external_seq: AtomicU64::new(0),
+            filtered: Default::default(),
+            config: Default::default(),
+            close_events: Default::default(),
+            sampler: None,
+            senders_dropped: Notify::new(),
+        }
+    }
+
+    pub fn is_collecting(&self) -> bool {
+        self.collecting.load(Ordering::Relaxed)
+    }
+
+    /// Records that the collector has stopped, returning whether it was
+    /// still thought to be running.
+    pub fn stop_collecting(&self) -> bool {
+        self.collecting.swap(false, Ordering::Relaxed)
+    }
+
+    /// Records the thread the collector is running on, if it is one that
+    /// can't make room in the channel while a sender on it waits.
+    pub fn set_collector_thread(&self) {
+        let flavor = Handle::try_current().map(|handle| handle.runtime_flavor());
+        let thread = matches!(flavor, Ok(RuntimeFlavor::CurrentThread)).then(|| thread::current().id());
+
+        *self.collector_thread.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = thread;
+    }
+
+    /// Whether the collector runs on the current thread, which is then not
+    /// allowed to wait for it.
+    fn on_collector_thread(&self) -> bool {
+        let collector_thread = *self.collector_thread.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        collector_thread == Some(thread::current().id())
+    }
+
+    /// Raises the high-water mark if `active` tasks are more than ever before.
+    fn observe_active(&self, active: usize) {
+        if active <= self.peak_count.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let mut peak = self.peak.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if active > self.peak_count.load(Ordering::Relaxed) {
+            self.peak_count.store(active, Ordering::Relaxed);
+            *peak = Some(PeakActive {
+                count: active,
+                at: SystemTime::now(),
+            });
+        }
+    }
+
+    pub fn peak_active(&self) -> Option<PeakActive> {
+        *self.peak.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Records that the collector is running.
+    pub fn beat(&self) {
+        let now = self.created.elapsed().as_millis() as u64;
+        self.heartbeat.store(now, Ordering::Relaxed);
+    }
+
+    pub fn since_heartbeat(&self) -> Duration {
+        let last = Duration::from_millis(self.heartbeat.load(Ordering::Relaxed));
+        self.created.elapsed().saturating_sub(last)
+    }
+
+    /// Records that the collector processed an event from `time`.
+    pub fn observe_event(&self, time: SystemTime) {
+        let micros = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_micros() as u64;
+        self.last_event_at.fetch_max(micros, Ordering::Relaxed);
+    }
+
+    pub fn last_event_at(&self) -> Option<SystemTime> {
+        match self.last_event_at.load(Ordering::Relaxed) {
+            0 => None,
+            micros => Some(UNIX_EPOCH + Duration::from_micros(micros)),
+        }
+    }
+
+    pub fn tick_interval(&self) -> Duration {
+        Duration::from_nanos(self.tick_interval.load(Ordering::Relaxed))
+    }
+
+    pub fn set_tick_interval(&self, interval: Duration) {
+        let nanos = interval.as_nanos().min(u64::MAX as u128) as u64;
+        self.tick_interval.store(nanos, Ordering::Relaxed);
+    }
+
+    pub fn set_allowlist(&self, names: Option<HashSet<String>>) {
+        if names.is_some() {
+            self.allowlist_used.store(true, Ordering::Relaxed);
+        }
+
+        let mut allowlist = self.allowlist.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+        *allowlist = names.map(Arc::new);
+    }
+
+    pub fn allowlist(&self) -> Option<Arc<HashSet<String>>> {
+        if !self.allowlist_used.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        self.allowlist.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+
+    pub fn allowlist_used(&self) -> bool {
+        self.allowlist_used.load(Ordering::Relaxed)
+    }
+
+    pub fn skip_own_span(&self) {
+        self.own_spans_skipped.store(true, Ordering::Relaxed);
+    }
+
+    pub fn own_spans_skipped(&self) -> bool {
+        self.own_spans_skipped.load(Ordering::Relaxed)
+    }
+
+    /// Whether a task with the given name should be tracked.
+    pub fn allows(&self, name: &str) -> bool {
+        match self.allowlist() {
+            Some(allowlist) => allowlist.contains(name),
+            None => true,
+        }
+    }
+
+    pub fn next_span_source(&self) -> u32 {
+        self.next_span_source.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub fn next_external_seq(&self) -> u64 {
+        self.next_external_seq.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// The settings the layer was built with, updated with the ones that
+    /// can change at runtime.
+    pub fn effective_config(&self) -> EffectiveConfig {
+        let mut allowlist = self.allowlist().map(|names| names.iter().cloned().collect::<Vec<_>>());
+        if let Some(names) = &mut allowlist {
+            names.sort();
+        }
+
+        EffectiveConfig {
+            tick_interval: self.tick_interval(),
+            name_allowlist: allowlist,
+            dropped_events: self.dropped_events(),
+            ..self.config.clone()
+        }
+    }
+
+    /// Events dropped so far, from the startup buffer, with the channel
+    /// full or evicted from the ring.
+    pub fn dropped_events(&self) -> u64 {
+        self.startup.stats().dropped + self.dropped_newest.total() + self.ring.as_ref().map_or(0, EventRing::dropped)
+    }
+
+    /// Events each event sink dropped so far, in the order they were added.
+    pub fn event_sinks_dropped(&self) -> Vec<u64> {
+        self.event_sinks.iter().map(|consumer| consumer.dropped.load(Ordering::Relaxed)).collect()
+    }
+
+    pub fn publish_summary(&self, summary: Summary) {
+        let mut published = self.summary.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        *published = Some(Arc::new(summary));
+    }
+
+    pub fn last_summary(&self) -> Option<Arc<Summary>> {
+        self.summary.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+}
+
+/// An [`EventSink`], with how many events it dropped.
+pub(crate) struct EventConsumer {
+    sink: Box<dyn EventSink>,
+    dropped: AtomicU64,
+}
+
+impl EventConsumer {
+    pub fn new(sink: Box<dyn EventSink>) -> Self {
+        Self {
+            sink,
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    fn send(&self, event: &Event) {
+        if !self.sink.try_send(event) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// A bounded queue that evicts its oldest event when pushed to while full,
+/// which tokio's channels can't do.
+pub(crate) struct EventRing {
+    capacity: usize,
+    events: Mutex<VecDeque<Event>>,
+    dropped: AtomicU64,
+}
+
+impl EventRing {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            events: Mutex::new(VecDeque::with_capacity(capacity)),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Queues an event, returning how many are now queued.
+    fn push(&self, event: Event) -> usize {
+        let mut events = self.events.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        if events.len() >= self.capacity && events.pop_front().is_some() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        events.push_back(event);
+
+        events.len()
+    }
+
+    /// Takes every queued event, oldest first.
+    pub fn drain(&self) -> VecDeque<Event> {
+        let mut events = self.events.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let capacity = events.capacity();
+
+        mem::replace(&mut *events, VecDeque::with_capacity(capacity))
+    }
+
+    /// Events evicted to make room for newer ones.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// The sending half of the collector's event channel, bounded unless
+/// [built otherwise](crate::Builder::unbounded_event_channel).
+#[derive(Clone)]
+pub(crate) enum EventChannel {
+    Bounded(Sender<Event>),
+    Unbounded(UnboundedSender<Event>),
+}
+
+impl EventChannel {
+    /// Sends `event` unless the channel is full, in which case it is handed
+    /// back. Events sent to a closed channel are dropped.
+    pub fn try_send(&self, event: Event) -> Option<Event> {
+        match self {
+            Self::Bounded(sender) => match sender.try_send(event) {
+                Err(TrySendError::Full(event)) => Some(event),
+                Ok(()) | Err(TrySendError::Closed(_)) => None,
+            },
+            Self::Unbounded(sender) => {
+                let _ = sender.send(event);
+                None
+            }
+        }
+    }
+
+    /// How many events the channel holds, or `None` if it is unbounded.
+    pub fn max_capacity(&self) -> Option<usize> {
+        match self {
+            Self::Bounded(sender) => Some(sender.max_capacity()),
+            Self::Unbounded(_) => None,
+        }
+    }
+
+    pub fn downgrade(&self) -> WeakEventChannel {
+        match self {
+            Self::Bounded(sender) => WeakEventChannel::Bounded(sender.downgrade()),
+            Self::Unbounded(sender) => WeakEventChannel::Unbounded(sender.downgrade()),
+        }
+    }
+}
+
+/// An [`EventChannel`] that doesn't keep the channel open.
+#[derive(Clone)]
+pub(crate) enum WeakEventChannel {
+    Bounded(WeakSender<Event>),
+    Unbounded(WeakUnboundedSender<Event>),
+}
+
+impl WeakEventChannel {
+    pub fn upgrade(&self) -> Option<EventChannel> {
+        match self {
+            Self::Bounded(sender) => sender.upgrade().map(EventChannel::Bounded),
+            Self::Unbounded(sender) => sender.upgrade().map(EventChannel::Unbounded),
+        }
+    }
+}
+
+/// The receiving half of an [`EventChannel`].
+pub(crate) enum EventReceiver {
+    Bounded(Receiver<Event>),
+    Unbounded(UnboundedReceiver<Event>),
+}
+
+impl EventReceiver {
+    pub fn try_recv(&mut self) -> Result<Event, TryRecvError> {
+        match self {
+            Self::Bounded(receiver) => receiver.try_recv(),
+            Self::Unbounded(receiver) => receiver.try_recv(),
+        }
+    }
+
+    /// Events waiting to be received.
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Bounded(receiver) => receiver.len(),
+            Self::Unbounded(receiver) => receiver.len(),
+        }
+    }
+}
+
+/// Opens the collector's event channel, holding `capacity` events or as
+/// many as are sent if `None`.
+pub(crate) fn event_channel(capacity: Option<usize>) -> (EventChannel, EventReceiver) {
+    match capacity {
+        Some(capacity) => {
+            let (tx, rx) = tokio::sync::mpsc::channel(capacity);
+            (EventChannel::Bounded(tx), EventReceiver::Bounded(rx))
+        }
+        None => {
+            let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+            (EventChannel::Unbounded(tx), EventReceiver::Unbounded(rx))
+        }
+    }
+}
+
+/// The sending half of the collector's event channel, shared by everything
+/// that produces events.
+#[derive(Clone)]
+pub(crate) struct EventSender {
+    pub sender: EventChannel,
+    pub shared: Arc<Shared>,
+    /// Declared after `sender`, so that it is dropped once the channel
+    /// sender is.
+    _dropped: Arc<SendersDropped>,
+}
+
+/// Wakes the collector once the last clone of an [`EventSender`] is gone.
+struct SendersDropped(Arc<Shared>);
+
+impl Drop for SendersDropped {
+    fn drop(&mut self) {
+        self.0.senders_dropped.notify_one();
+    }
+}
+
+impl EventSender {
+    /// How long to sleep between checks for room, when waiting on a runtime
+    /// thread that isn't allowed to block.
+    const WAIT_INTERVAL: Duration = Duration::from_micros(100);
+
+    pub fn new(sender: EventChannel, shared: Arc<Shared>) -> Self {
+        Self {
+            sender,
+            _dropped: Arc::new(SendersDropped(shared.clone())),
+            shared,
+        }
+    }
+
+    pub fn send(&self, event: Event) {
+        for consumer in &self.shared.event_sinks {
+            consumer.send(&event);
+        }
+
+        if !self.shared.is_collecting() {
+            return;
+        }
+
+        match event {
+            Event::Spawn { .. } => {
+                let active = self.shared.active.fetch_add(1, Ordering::Relaxed) + 1;
+                self.shared.observe_active(active);
+            }
+            Event::Close { .. } => {
+                let _ = self.shared.active.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |active| active.checked_sub(1));
+            }
+            _ => {}
+        }
+
+        let event = match self.shared.startup.send(event, &self.sender) {
+            Some(event) => event,
+            None => return,
+        };
+
+        if let Some(ring) = &self.shared.ring {
+            let len = ring.push(event);
+            self.shared.peak_queue_len.fetch_max(len, Ordering::Relaxed);
+            return;
+        }
+
+        let slot = DroppedCounts::slot(&event);
+        let sent = match &self.sender {
+            EventChannel::Bounded(sender) => match Self::reserve(sender, self.shared.send_retries) {
+                Ok(permit) => {
+                    permit.send(event);
+
+                    let len = sender.max_capacity() - sender.capacity();
+                    self.shared.peak_queue_len.fetch_max(len, Ordering::Relaxed);
+                    Ok(())
+                }
+                Err(TrySendError::Full(())) if !self.shared.lossy => self.wait_to_send(sender, event),
+                Err(e) => Err(e),
+            },
+            // Its queue length is taken by the collector as it drains it.
+            EventChannel::Unbounded(sender) => sender.send(event).map_err(|_| TrySendError::Closed(())),
+        };
+
+        match sent {
+            Ok(()) => {}
+            Err(TrySendError::Closed(())) => {
+                if self.shared.stop_collecting() {
+                    tracing::error!(target: INTERNAL_TARGET, "Receiver terminated, no longer sending events");
+                }
+            }
+            Err(TrySendError::Full(())) => {
+                self.shared.dropped_newest.count(slot);
+            }
+        }
+    }
+
+    /// Reserves room in the channel, spinning briefly if it is full in
+    /// case the collector is just about to drain it.
+    fn reserve(sender: &Sender<Event>, retries: u32) -> Result<Permit<'_, Event>, TrySendError<()>> {
+        let mut retries = retries;
+
+        loop {
+            match sender.try_reserve() {
+                Err(TrySendError::Full(())) if retries > 0 => {
+                    retries -= 1;
+                    hint::spin_loop();
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Sends `event` once the collector makes room for it, unless waiting
+    /// here could keep the collector from running, in which case the
+    /// channel is reported full. See [`Builder::lossy`](crate::Builder::lossy).
+    fn wait_to_send(&self, sender: &Sender<Event>, event: Event) -> Result<(), TrySendError<()>> {
+        let send = |event| {
+            // Woken from a thread that isn't a worker, the collector can be
+            // picked up by any worker rather than wait for this one.
+            self.shared.channel_full.notify_one();
+            sender.blocking_send(event).map_err(|_| TrySendError::Closed(()))
+        };
+
+        let flavor = match Handle::try_current() {
+            Ok(handle) => handle.runtime_flavor(),
+            Err(_) => return send(event),
+        };
+
+        match flavor {
+            RuntimeFlavor::MultiThread => task::block_in_place(|| send(event)),
+            // The runtime's only thread may be the one the collector needs.
+            _ if self.shared.on_collector_thread() => Err(TrySendError::Full(())),
+            // Blocking on a runtime thread panics, even when nothing else
+            // needs it, so poll for room instead.
+            _ => loop {
+                self.shared.channel_full.notify_one();
+                match sender.try_reserve() {
+                    Ok(permit) => {
+                        permit.send(event);
+                        return Ok(());
+                    }
+                    Err(TrySendError::Full(())) => thread::sleep(Self::WAIT_INTERVAL),
+                    Err(e) => return Err(e),
+                }
+            },
+        }
+    }
+
+    pub fn active_count(&self) -> usize {
+        self.shared.active.load(Ordering::Relaxed)
+    }
+
+    pub fn is_collecting(&self) -> bool {
+        self.shared.is_collecting()
+    }
+
+    pub fn peak_queue_len(&self) -> usize {
+        self.shared.peak_queue_len.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Running;
+    use crate::{event::EventId, BeeLayer, Builder, Summary};
+
+    #[test]
+    fn full_ring_keeps_the_newest_events() {
+        let ring = EventRing::new(3);
+
+        for id in 1..=5 {
+            ring.push(Event::close(EventId::external(id)));
+        }
+
+        let kept: Vec<_> = ring.drain().iter().map(|event| event.id().into_u64()).collect();
+        assert_eq!(kept, [3, 4, 5]);
+        assert_eq!(ring.dropped(), 2);
+        assert!(ring.drain().is_empty());
+    }
+
+    #[test]
+    fn peak_active_sticks_at_the_first_time_reached() {
+        let shared = Shared::new(0, OverflowPolicy::DropNewest, 1);
+
+        shared.observe_active(1);
+        shared.observe_active(2);
+        let before = SystemTime::now();
+        shared.observe_active(3);
+        let after = SystemTime::now();
+        for active in [2, 1, 2, 3] {
+            shared.observe_active(active);
+        }
+
+        let peak = shared.peak_active().unwrap();
+        assert_eq!(peak.count, 3);
+        assert!(before <= peak.at && peak.at <= after);
+    }
+
+    #[test]
+    fn retries_ride_out_momentary_fullness() {
+        let (sender, mut receiver) = tokio::sync::mpsc::channel(1);
+        sender.try_send(Event::close(EventId::external(1))).unwrap();
+        assert!(matches!(EventSender::reserve(&sender, 0), Err(TrySendError::Full(()))));
+
+        // The collector catches up while the producer is still retrying.
+        let drained = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(5));
+            receiver.blocking_recv();
+            receiver
+        });
+        let permit = EventSender::reserve(&sender, u32::MAX).expect("room was made before retries ran out");
+        permit.send(Event::close(EventId::external(2)));
+
+        let mut receiver = drained.join().unwrap();
+        assert_eq!(receiver.try_recv().unwrap().id().into_u64(), 2);
+    }
+
+    /// Spawns 10k tasks in one burst, without letting the collector run in
+    /// between, and returns what the collector made of them.
+    async fn burst_of_spawns(builder: Builder) -> Summary {
+        let running = Running::start(builder.build());
+        // Past the startup buffer, into the channel.
+        tokio::task::yield_now().await;
+
+        let spans: Vec<_> = (0..10_000).map(|n| tracing::info_span!("burst", n)).collect();
+        let summary = running.handle.debug_report(Duration::from_secs(5)).await.summary;
+        drop(spans);
+        running.finish().await;
+        summary
+    }
+
+    #[tokio::test]
+    async fn unbounded_channel_keeps_a_whole_burst() {
+        let summary = burst_of_spawns(BeeLayer::builder().unbounded_event_channel()).await;
+        assert_eq!(summary.tasks.len(), 10_000);
+        assert_eq!(summary.dropped_newest, 0);
+        assert!(summary.peak_queue_len >= 10_000);
+
+        let summary = burst_of_spawns(BeeLayer::builder()).await;
+        assert!(summary.dropped_newest > 0);
+        assert_eq!(summary.dropped_by_kind.spawn, summary.dropped_newest);
+        assert_eq!(summary.tasks.len() as u64 + summary.dropped_newest, 10_000);
+    }
+
+    #[tokio::test]
+    async fn waiting_for_a_collector_on_the_same_thread_drops_instead() {
+        // Waiting here would deadlock, with the collector stuck behind the sender.
+        let summary = burst_of_spawns(BeeLayer::builder().lossy(false)).await;
+        assert!(summary.dropped_newest > 0);
+        assert_eq!(summary.tasks.len() as u64 + summary.dropped_newest, 10_000);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn waiting_for_room_keeps_a_whole_burst() {
+        let summary = burst_of_spawns(BeeLayer::builder().lossy(false)).await;
+        assert_eq!(summary.tasks.len(), 10_000);
+        assert_eq!(summary.dropped_newest, 0);
+    }
+}
@@ -0,0 +1,127 @@
+use crate::event::EventId;
+
+use std::{
+    collections::{HashMap, VecDeque},
+    mem,
+};
+
+/// Gaps kept open per source before the oldest is taken as lost, however
+/// recently it opened. Gaps are otherwise only taken as lost once they
+/// outlast a flush: events racing each other through the channel from
+/// different threads, one of them preempted on the way, arrive out of
+/// sequence without anything being lost.
+const MAX_OPEN_GAPS: usize = 1024;
+
+/// A run of sequence numbers skipped by a source.
+struct Gap {
+    start: u64,
+    end: u64,
+    /// Events from the gap that arrived late after all.
+    arrived: u64,
+    /// Flushes the gap has been open across.
+    flushes: u32,
+    /// Tasks from the source that were being polled when the gap opened,
+    /// any of which may have lost the end of its poll.
+    polling: Vec<EventId>,
+}
+
+impl Gap {
+    fn missing(&self) -> u64 {
+        self.end - self.start - self.arrived
+    }
+}
+
+/// The next sequence number expected from a source, and the gaps it left.
+#[derive(Default)]
+struct Source {
+    next: u64,
+    /// Oldest first.
+    gaps: VecDeque<Gap>,
+}
+
+/// Tracks the sequence numbers each source stamps its events with, counting
+/// the events that never arrived.
+#[derive(Default)]
+pub(crate) struct SequenceTracker {
+    sources: HashMap<u32, Source>,
+    /// Events confirmed lost.
+    pub lost: u64,
+    /// Gaps that lost at least one event.
+    pub gaps: u64,
+}
+
+impl SequenceTracker {
+    /// Notes an event's sequence number. `polling` lists the source's tasks
+    /// being polled, and is only called if the event opens a gap. Returns
+    /// the tasks to mark as approximate, if too many gaps are open.
+    pub fn observe(&mut self, id: &EventId, seq: u64, polling: impl FnOnce() -> Vec<EventId>) -> Vec<EventId> {
+        let source = self.sources.entry(id.source()).or_default();
+
+        if seq >= source.next {
+            if seq > source.next {
+                source.gaps.push_back(Gap {
+                    start: source.next,
+                    end: seq,
+                    arrived: 0,
+                    flushes: 0,
+                    polling: polling(),
+                });
+            }
+            source.next = seq + 1;
+        } else if let Some(index) = source.gaps.iter().position(|gap| (gap.start..gap.end).contains(&seq)) {
+            let gap = &mut source.gaps[index];
+            gap.arrived += 1;
+            if gap.missing() == 0 {
+                source.gaps.remove(index);
+            }
+        }
+
+        let mut polling = Vec::new();
+        let excess = source.gaps.len().saturating_sub(MAX_OPEN_GAPS);
+        for gap in source.gaps.drain(..excess) {
+            self.lost += gap.missing();
+            self.gaps += 1;
+            polling.extend(gap.polling);
+        }
+
+        polling
+    }
+
+    /// Confirms the gaps left open since the previous flush, or every gap
+    /// if `all`, e.g. once no more events can arrive. Returns the tasks to
+    /// mark as approximate.
+    pub fn flush(&mut self, all: bool) -> Vec<EventId> {
+        let polling = self.confirm(|gap| all || gap.flushes > 0);
+        for gap in self.sources.values_mut().flat_map(|source| source.gaps.iter_mut()) {
+            gap.flushes += 1;
+        }
+
+        polling
+    }
+
+    /// Forgets the gaps and counts, but not where each source is up to.
+    pub fn clear(&mut self) {
+        for source in self.sources.values_mut() {
+            source.gaps.clear();
+        }
+        self.lost = 0;
+        self.gaps = 0;
+    }
+
+    fn confirm(&mut self, lost: impl Fn(&Gap) -> bool) -> Vec<EventId> {
+        let mut polling = Vec::new();
+
+        for source in self.sources.values_mut() {
+            let (confirmed, open): (VecDeque<_>, _) = mem::take(&mut source.gaps).into_iter().partition(|gap| lost(gap));
+            source.gaps = open;
+
+            for gap in confirmed {
+                self.lost += gap.missing();
+                self.gaps += 1;
+                polling.extend(gap.polling);
+            }
+        }
+
+        polling
+    }
+}
@@ -0,0 +1,507 @@
+use crate::{
+    event::Event,
+    export::ExportConfig,
+    lifecycle::{FinalReport, StartupReport},
+    reporting::INTERNAL_TARGET,
+    summary::Summary,
+};
+
+use serde_json::{Map, Value};
+use tokio::sync::mpsc;
+
+use std::{collections::HashMap, io::Write, sync::Mutex};
+
+/// Receives the collector's summary on every flush.
+pub trait SummarySink: Send + 'static {
+    /// `export` holds the units configured on the builder, which a sink may
+    /// override with its own.
+    fn flush(&mut self, summary: &Summary, export: &ExportConfig);
+
+    /// Called once as the collector starts, before the first flush.
+    fn start(&mut self, _report: &StartupReport, _export: &ExportConfig) {}
+
+    /// Called once as the collector stops, in place of a last flush. Flushes
+    /// the final summary unless overridden.
+    fn finish(&mut self, report: &FinalReport, export: &ExportConfig) {
+        self.flush(&report.summary, export)
+    }
+}
+
+impl<F> SummarySink for F
+where
+    F: FnMut(&Summary) + Send + 'static,
+{
+    fn flush(&mut self, summary: &Summary, _export: &ExportConfig) {
+        self(summary)
+    }
+}
+
+/// Receives every raw [`Event`] as it is produced, before the collector sees it.
+///
+/// Sinks are called inline on the thread producing the event, often from
+/// inside a poll, so they must return quickly and never block. The channel
+/// implementations drop events once full rather than wait, so that a slow
+/// consumer only loses events of its own, counted in
+/// [`Summary::event_sinks_dropped`].
+pub trait EventSink: Send + Sync + 'static {
+    fn send(&self, event: &Event);
+
+    /// Called once as the layer is built, before any event is sent.
+    /// `export` holds the units configured on the builder, which a sink may
+    /// override with its own.
+    fn configure(&mut self, _export: &ExportConfig) {}
+
+    /// Sends `event`, returning whether the sink took it rather than
+    /// dropping it. Defaults to [`send`](Self::send), taking every event.
+    fn try_send(&self, event: &Event) -> bool {
+        self.send(event);
+        true
+    }
+}
+
+impl<F> EventSink for F
+where
+    F: Fn(&Event) + Send + Sync + 'static,
+{
+    fn send(&self, event: &Event) {
+        self(event)
+    }
+}
+
+impl EventSink for mpsc::Sender<Event> {
+    fn send(&self, event: &Event) {
+        EventSink::try_send(self, event);
+    }
+
+    fn try_send(&self, event: &Event) -> bool {
+        mpsc::Sender::try_send(self, event.clone()).is_ok()
+    }
+}
+
+impl EventSink for mpsc::UnboundedSender<Event> {
+    fn send(&self, event: &Event) {
+        EventSink::try_send(self, event);
+    }
+
+    fn try_send(&self, event: &Event) -> bool {
+        mpsc::UnboundedSender::send(self, event.clone()).is_ok()
+    }
+}
+
+impl EventSink for std::sync::mpsc::SyncSender<Event> {
+    fn send(&self, event: &Event) {
+        EventSink::try_send(self, event);
+    }
+
+    fn try_send(&self, event: &Event) -> bool {
+        std::sync::mpsc::SyncSender::try_send(self, event.clone()).is_ok()
+    }
+}
+
+/// Handles standing in for the field strings already written to a stream,
+/// so that each is only written once.
+#[derive(Default)]
+struct Interner {
+    handles: HashMap<String, u64>,
+}
+
+impl Interner {
+    /// Returns the handle for `fields`, adding it to `new` if it hasn't been
+    /// written yet.
+    fn intern(&mut self, fields: &str, new: &mut Map<String, Value>) -> u64 {
+        if let Some(handle) = self.handles.get(fields) {
+            return *handle;
+        }
+
+        let handle = self.handles.len() as u64 + 1;
+        self.handles.insert(fields.to_string(), handle);
+        new.insert(handle.to_string(), fields.into());
+        handle
+    }
+
+    /// Swaps `json`'s `fields` for a `fields_ref` to its handle.
+    fn replace_fields(&mut self, json: &mut Value, new: &mut Map<String, Value>) {
+        let map = match json {
+            Value::Object(map) => map,
+            _ => return,
+        };
+        if let Some(Value::String(fields)) = map.remove("fields") {
+            let handle = self.intern(&fields, new);
+            map.insert("fields_ref".into(), handle.into());
+        }
+    }
+}
+
+/// The line introducing newly interned field strings, by handle.
+fn intern_line(new: Map<String, Value>) -> Value {
+    let mut map = Map::new();
+    map.insert("intern".into(), Value::Object(new));
+    Value::Object(map)
+}
+
+/// Records every raw event as a line of JSON, e.g. to a
+/// [`RotatingFile`](crate::RotatingFile).
+///
+/// Events are written from the threads producing them, under a lock; the
+/// writer should buffer rather than hit the disk on every write. It is
+/// flushed after every close.
+pub struct JsonlEventSink<W> {
+    writer: Mutex<W>,
+    /// The units set on the builder, unless overridden by `export`.
+    defaults: ExportConfig,
+    export: Option<ExportConfig>,
+    interner: Option<Mutex<Interner>>,
+}
+
+impl<W: Write + Send + 'static> JsonlEventSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: Mutex::new(writer),
+            defaults: Default::default(),
+            export: None,
+            interner: None,
+        }
+    }
+
+    /// Overrides the units set on the builder for this sink.
+    pub fn export_config(mut self, export: ExportConfig) -> Self {
+        self.export = Some(export);
+        self
+    }
+
+    /// Writes each distinct spawn field string once, on an
+    /// `{"intern": {"<handle>": "<fields>"}}` line ahead of the first event
+    /// using it, with events carrying a `fields_ref` handle in its place.
+    ///
+    /// Handles are only defined earlier in the same stream, so it has to be
+    /// read from its start, and every distinct string is kept for the life
+    /// of the sink.
+    pub fn intern_fields(mut self) -> Self {
+        self.interner = Some(Default::default());
+        self
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer.into_inner().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl<W: Write + Send + 'static> EventSink for JsonlEventSink<W> {
+    fn configure(&mut self, export: &ExportConfig) {
+        self.defaults = *export;
+    }
+
+    fn send(&self, event: &Event) {
+        let mut writer = self.writer.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let mut json = self.export.as_ref().unwrap_or(&self.defaults).event_json(event);
+        let mut new = Map::new();
+        if let Some(interner) = &self.interner {
+            interner.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).replace_fields(&mut json, &mut new);
+        }
+
+        let mut result = Ok(());
+        if !new.is_empty() {
+            result = serde_json::to_writer(&mut *writer, &intern_line(new))
+                .map_err(Into::into)
+                .and_then(|_| writer.write_all(b"\n"));
+        }
+        result = result
+            .and_then(|_| serde_json::to_writer(&mut *writer, &json).map_err(Into::into))
+            .and_then(|_| writer.write_all(b"\n"));
+        if let Event::Close { .. } = event {
+            result = result.and_then(|_| writer.flush());
+        }
+
+        if let Err(e) = result {
+            tracing::warn!(target: INTERNAL_TARGET, "Failed to record event: {}", e);
+        }
+    }
+}
+
+/// Writes one JSON object per flush: the summary's aggregates plus the
+/// busiest tasks under `top_tasks`, followed by a newline.
+pub struct JsonSummarySink<W> {
+    writer: W,
+    top: usize,
+    export: Option<ExportConfig>,
+    interner: Option<Interner>,
+}
+
+impl<W: Write + Send + 'static> JsonSummarySink<W> {
+    const DEFAULT_TOP: usize = 10;
+
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            top: Self::DEFAULT_TOP,
+            export: None,
+            interner: None,
+        }
+    }
+
+    /// How many of the busiest tasks to include. Defaults to 10.
+    pub fn top(mut self, n: usize) -> Self {
+        self.top = n;
+        self
+    }
+
+    /// Overrides the units set on the builder for this sink.
+    pub fn export_config(mut self, export: ExportConfig) -> Self {
+        self.export = Some(export);
+        self
+    }
+
+    /// Writes each distinct task field string once, on an
+    /// `{"intern": {"<handle>": "<fields>"}}` line ahead of the first
+    /// summary listing it, with tasks carrying a `fields_ref` handle in its
+    /// place, so that long-running tasks don't repeat their fields on every
+    /// flush.
+    ///
+    /// Handles are only defined earlier in the same stream, so it has to be
+    /// read from its start, and every distinct string is kept for the life
+    /// of the sink.
+    pub fn intern_fields(mut self) -> Self {
+        self.interner = Some(Default::default());
+        self
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> JsonSummarySink<W> {
+    fn write_line(&mut self, json: &Value) {
+        let result = serde_json::to_writer(&mut self.writer, json)
+            .map_err(Into::into)
+            .and_then(|_| self.writer.write_all(b"\n"))
+            .and_then(|_| self.writer.flush());
+
+        if let Err(e) = result {
+            tracing::warn!(target: INTERNAL_TARGET, "Failed to write JSON summary: {}", e);
+        }
+    }
+
+    /// Swaps the fields of `tasks` for handles if interning, first writing
+    /// the strings not written before.
+    fn intern_tasks(&mut self, tasks: &mut [Value]) {
+        let interner = match &mut self.interner {
+            Some(interner) => interner,
+            None => return,
+        };
+
+        let mut new = Map::new();
+        for task in tasks {
+            interner.replace_fields(task, &mut new);
+        }
+        if !new.is_empty() {
+            self.write_line(&intern_line(new));
+        }
+    }
+}
+
+/// The startup and final reports are written as lines of their own, see
+/// [`StartupReport::to_json`] and [`FinalReport::to_json`].
+impl<W: Write + Send + 'static> SummarySink for JsonSummarySink<W> {
+    fn flush(&mut self, summary: &Summary, export: &ExportConfig) {
+        let export = self.export.as_ref().unwrap_or(export);
+
+        let mut json = export.aggregates_json(summary);
+        let mut tasks: Vec<_> = summary.busiest(self.top).map(|task| export.task_json(task)).collect();
+        self.intern_tasks(&mut tasks);
+
+        json.insert("top_tasks".into(), tasks.into());
+        self.write_line(&Value::Object(json));
+    }
+
+    fn start(&mut self, report: &StartupReport, export: &ExportConfig) {
+        let json = report.to_json(self.export.as_ref().unwrap_or(export));
+        self.write_line(&json);
+    }
+
+    fn finish(&mut self, report: &FinalReport, export: &ExportConfig) {
+        let mut json = report.to_json(self.export.as_ref().unwrap_or(export));
+        if let Some(Value::Array(tasks)) = json.get_mut("top_tasks") {
+            self.intern_tasks(tasks);
+        }
+        self.write_line(&json);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Running;
+    use crate::{
+        event::EventId,
+        export::{DurationUnit, TimestampFormat},
+        BeeLayer, Stats, TaskSnapshot,
+    };
+
+    use std::{sync::Arc, time::Duration};
+
+    fn task(id: u64, busy_ms: u64) -> TaskSnapshot {
+        TaskSnapshot {
+            id,
+            stats: Stats {
+                busy_time: Duration::from_millis(busy_ms),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn json_summary_lists_the_busiest_tasks_first() {
+        let summary = Summary {
+            tasks: vec![task(1, 5), task(2, 30), task(3, 10), task(4, 20)],
+            ..Default::default()
+        };
+
+        let mut sink = JsonSummarySink::new(Vec::new()).top(3);
+        sink.flush(&summary, &ExportConfig::default());
+
+        let json: Value = serde_json::from_slice(&sink.into_inner()).unwrap();
+        let ids: Vec<_> = json["top_tasks"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|task| task["id"].as_u64().unwrap())
+            .collect();
+        assert_eq!(ids, [2, 4, 3]);
+    }
+
+    #[tokio::test]
+    async fn event_sink_receives_each_lifecycle_event_in_order() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let seen = events.clone();
+        let running = Running::start(BeeLayer::builder()
+            .event_sink(move |event: &Event| {
+                let kind = match event {
+                    Event::Spawn { .. } => "spawn",
+                    Event::Enter { .. } => "enter",
+                    Event::Exit { .. } => "exit",
+                    Event::Close { .. } => "close",
+                    _ => return,
+                };
+                seen.lock().unwrap().push((kind, event.id().into_u64(), event.seq()));
+            })
+            .build());
+
+        let first = tracing::info_span!("first");
+        let second = tracing::info_span!("second");
+        first.in_scope(|| second.in_scope(|| {}));
+        first.in_scope(|| {});
+        let ids = (first.id().unwrap().into_u64(), second.id().unwrap().into_u64());
+        drop(second);
+        drop(first);
+
+        running.finish().await;
+        let (first, second) = ids;
+        let events = events.lock().unwrap();
+        let kinds: Vec<_> = events.iter().map(|&(kind, id, _)| (kind, id)).collect();
+        assert_eq!(kinds, [
+            ("spawn", first),
+            ("spawn", second),
+            ("enter", first),
+            ("enter", second),
+            ("exit", second),
+            ("exit", first),
+            ("enter", first),
+            ("exit", first),
+            ("close", second),
+            ("close", first),
+        ]);
+        assert!(events.windows(2).all(|pair| pair[0].2 < pair[1].2));
+    }
+
+    fn lines(written: &[u8]) -> Vec<Value> {
+        written.split(|&byte| byte == b'\n').filter(|line| !line.is_empty()).map(|line| serde_json::from_slice(line).unwrap()).collect()
+    }
+
+    #[test]
+    fn summary_fields_are_interned_once() {
+        let with_fields = |id, fields: &str| TaskSnapshot {
+            fields: fields.into(),
+            ..task(id, id)
+        };
+        let mut sink = JsonSummarySink::new(Vec::new()).intern_fields();
+        let export = ExportConfig::default();
+
+        let summary = Summary {
+            tasks: vec![with_fields(1, "path=/a"), with_fields(2, "path=/b")],
+            ..Default::default()
+        };
+        sink.flush(&summary, &export);
+        sink.flush(&summary, &export);
+        let summary = Summary {
+            tasks: vec![with_fields(1, "path=/a"), with_fields(3, "path=/c")],
+            ..Default::default()
+        };
+        sink.flush(&summary, &export);
+
+        let lines = lines(&sink.into_inner());
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0], serde_json::json!({ "intern": { "1": "path=/b", "2": "path=/a" } }));
+        assert_eq!(lines[3], serde_json::json!({ "intern": { "3": "path=/c" } }));
+        for (line, refs) in [(1, [1, 2]), (2, [1, 2]), (4, [3, 2])].iter() {
+            let tasks = lines[*line]["top_tasks"].as_array().unwrap();
+            assert!(tasks.iter().all(|task| task.get("fields").is_none()));
+            let handles: Vec<_> = tasks.iter().map(|task| task["fields_ref"].as_u64().unwrap()).collect();
+            assert_eq!(handles, refs);
+        }
+    }
+
+    #[test]
+    fn event_fields_are_interned_once() {
+        let sink = JsonlEventSink::new(Vec::new()).intern_fields();
+        for (id, fields) in [(1, "user=ann"), (2, "user=bob"), (3, "user=ann")].iter() {
+            let id = EventId::external(*id);
+            sink.send(&Event::spawn(id, fields.to_string(), Default::default(), None, None));
+            sink.send(&Event::close(id));
+        }
+
+        let lines = lines(&sink.into_inner());
+        let interned: Vec<_> = lines.iter().filter_map(|line| line.get("intern")).collect();
+        assert_eq!(interned, [&serde_json::json!({ "1": "user=ann" }), &serde_json::json!({ "2": "user=bob" })]);
+        let refs: Vec<_> = lines.iter().filter_map(|line| line.get("fields_ref")).map(|handle| handle.as_u64().unwrap()).collect();
+        assert_eq!(refs, [1, 2, 1]);
+        assert!(lines.iter().all(|line| line.get("fields").is_none()));
+    }
+
+    #[test]
+    fn event_sink_takes_the_builders_units_unless_overridden() {
+        let micros = ExportConfig::new(DurationUnit::Micros, TimestampFormat::EpochMicros);
+        let nanos = ExportConfig::new(DurationUnit::Nanos, TimestampFormat::Rfc3339);
+        let close = Event::close(EventId::external(1));
+
+        let mut sink = JsonlEventSink::new(Vec::new());
+        sink.configure(&micros);
+        sink.send(&close);
+        assert!(lines(&sink.into_inner())[0].get("time_us").is_some());
+
+        let mut sink = JsonlEventSink::new(Vec::new()).export_config(nanos);
+        sink.configure(&micros);
+        sink.send(&close);
+        assert!(lines(&sink.into_inner())[0].get("time").is_some());
+    }
+
+    #[test]
+    fn builder_configures_event_sinks_with_its_units() {
+        struct Configured(Arc<Mutex<Option<ExportConfig>>>);
+
+        impl EventSink for Configured {
+            fn configure(&mut self, export: &ExportConfig) {
+                *self.0.lock().unwrap() = Some(*export);
+            }
+
+            fn send(&self, _event: &Event) {}
+        }
+
+        let configured = Arc::new(Mutex::new(None));
+        let micros = ExportConfig::new(DurationUnit::Micros, TimestampFormat::EpochMicros);
+        let _ = BeeLayer::builder().export_config(micros).event_sink(Configured(configured.clone())).build();
+        assert_eq!(*configured.lock().unwrap(), Some(micros));
+    }
+}
@@ -0,0 +1,309 @@
+use crate::{
+    callsite::Callsite,
+    event::EventId,
+    logs::TaskLogs,
+    fields::{Budget, FieldValue, TaskKind, TaskOutcome},
+    stats::Stats,
+};
+
+use std::{
+    any::Any,
+    collections::BTreeMap,
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// A point-in-time copy of everything the collector knows about a task.
+#[derive(Clone, Debug, Default)]
+pub struct TaskSnapshot {
+    pub id: u64,
+    /// The `task.name` field, as set by tokio's `task::Builder::name`.
+    pub name: Option<String>,
+    /// The task's fields as formatted by the layer's field formatter.
+    pub fields: String,
+    /// The task's fields as recorded, by name. Empty for tasks whose fields
+    /// only arrived formatted, e.g. [injected](crate::EventInjector) ones.
+    pub values: Vec<(&'static str, FieldValue)>,
+    pub callsite: Option<Callsite>,
+    pub kind: TaskKind,
+    pub stats: Stats,
+    pub outcome: Option<TaskOutcome>,
+    /// Memory the task last reported holding through `task.mem_bytes`.
+    pub mem_bytes: Option<u64>,
+    /// The most memory the task ever reported holding.
+    pub mem_bytes_max: Option<u64>,
+    pub budget: Budget,
+    pub over_budget: bool,
+    /// Whether the task was polled past the
+    /// [busy loop threshold](crate::Builder::busy_loop_polls) without a
+    /// single poll returning `Ready`.
+    pub busy_loop: bool,
+    pub weight: Option<f64>,
+    /// Values of the fields registered with
+    /// [`Builder::group_by_field`](crate::Builder::group_by_field), by field name.
+    pub groups: Vec<(String, String)>,
+    /// Totals of the `counter.<name>` fields recorded on events inside the
+    /// task, by name.
+    pub counters: BTreeMap<String, u64>,
+    /// How many times a second the task is being polled, from the polls
+    /// between flushes, smoothed over recent flushes. `None` until the
+    /// first flush after it was spawned, and once it has closed.
+    pub poll_rate: Option<f64>,
+    /// The id of the tracked task it was spawned inside of.
+    pub parent: Option<u64>,
+    /// The ids of the tasks spawned inside of it that are still kept,
+    /// oldest first.
+    pub children: Vec<u64>,
+    /// The events logged inside of it.
+    pub logs: TaskLogs,
+    /// The tasks it was marked as following from, in the order the links
+    /// were made.
+    pub follows: Vec<FollowedTask>,
+    /// Whether the task's `Spawn` has yet to reach the collector, so that
+    /// its fields, callsite and kind are still missing. Tasks whose spawn
+    /// still hasn't arrived a flush later are dropped.
+    pub provisional: bool,
+    /// Values accumulated by each [`TaskReducer`](crate::TaskReducer), in
+    /// the order they were added.
+    pub reduced: Vec<Arc<dyn Any + Send + Sync>>,
+}
+
+/// A task another follows from, see
+/// [`Span::follows_from`](tracing::Span::follows_from).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FollowedTask {
+    /// A task the collector was tracking when the link was made.
+    Task(u64),
+    /// A span the collector had no task for when the link was made, e.g.
+    /// one left untracked or already closed, by its raw id.
+    Span(EventId),
+}
+
+impl fmt::Display for FollowedTask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Task(id) => write!(f, "task {}", id),
+            Self::Span(id) => write!(f, "span {}", id.into_u64()),
+        }
+    }
+}
+
+/// The order snapshots and summaries list tasks in. Ties are broken by id.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SnapshotOrder {
+    /// Oldest task id first. This is the default.
+    #[default]
+    Id,
+    /// Most busy time first.
+    BusyTime,
+    /// Earliest created first.
+    CreatedAt,
+}
+
+impl SnapshotOrder {
+    pub(crate) fn sort(&self, tasks: &mut [TaskSnapshot]) {
+        match self {
+            Self::Id => tasks.sort_by_key(|task| task.id),
+            Self::BusyTime => tasks.sort_by(|a, b| b.stats.busy_time().cmp(&a.stats.busy_time()).then(a.id.cmp(&b.id))),
+            Self::CreatedAt => tasks.sort_by_key(|task| (task.stats.created_instant(), task.id)),
+        }
+    }
+}
+
+impl TaskSnapshot {
+    /// The value accumulated by the first [`TaskReducer`](crate::TaskReducer)
+    /// whose value is a `T`.
+    pub fn reduced<T: 'static>(&self) -> Option<&T> {
+        self.reduced.iter().find_map(|value| value.downcast_ref())
+    }
+
+    /// The task's value for a field registered with
+    /// [`Builder::group_by_field`](crate::Builder::group_by_field).
+    pub fn group(&self, field: &str) -> Option<&str> {
+        self.groups.iter().find(|(name, _)| name == field).map(|(_, value)| value.as_str())
+    }
+
+    /// Renders the recorded values, falling back to the formatted fields.
+    fn fmt_fields(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.values.is_empty() {
+            if self.fields.is_empty() {
+                return Ok(());
+            }
+            return write!(f, ", fields: {}", self.fields);
+        }
+
+        let values: Vec<_> = self.values.iter().map(|(name, value)| format!("{}={}", name, value)).collect();
+        write!(f, ", fields: {}", values.join(" "))
+    }
+
+    fn fmt_logs(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.logs.is_empty() {
+            return Ok(());
+        }
+
+        write!(f, ", {}", self.logs)
+    }
+
+    fn fmt_follows(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.follows.is_empty() {
+            return Ok(());
+        }
+
+        let follows: Vec<_> = self.follows.iter().map(ToString::to_string).collect();
+        write!(f, ", follows {}", follows.join(", "))
+    }
+
+    fn fmt_threads(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.stats.threads() == 0 {
+            return Ok(());
+        }
+
+        write!(f, ", threads={} migrations={}", self.stats.threads(), self.stats.migrations())
+    }
+
+    fn fmt_wakers(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stats = &self.stats;
+        if stats.wakes() + stats.waker_clones() + stats.waker_drops() == 0 {
+            return Ok(());
+        }
+
+        write!(f, ", wakes={}", stats.wakes())?;
+        if let Some(ratio) = stats.self_wake_ratio() {
+            write!(f, " ({:.1}% self)", ratio * 100.0)?;
+        }
+        write!(f, " waker_clones={} waker_drops={}", stats.waker_clones(), stats.waker_drops())?;
+        if let Some(mean) = stats.mean_scheduled_time() {
+            write!(f, " scheduled {:?} (mean {:?})", stats.scheduled_time(), mean)?;
+        }
+
+        Ok(())
+    }
+
+    fn fmt_counters(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.counters.is_empty() {
+            return Ok(());
+        }
+
+        let counters: Vec<_> = self.counters.iter().map(|(name, value)| format!("{} {}", name, value)).collect();
+        write!(f, ", counters: {}", counters.join(", "))
+    }
+
+    /// What the task is shown as: its name if it was given one, or else
+    /// the name and location of the span it was created with.
+    pub fn label(&self) -> Option<String> {
+        self.name.clone().or_else(|| self.callsite.map(|callsite| callsite.label()))
+    }
+
+    /// Time from creation to close, for tasks that have closed.
+    pub fn total_time(&self) -> Option<Duration> {
+        self.stats.total_time()
+    }
+
+    /// Splits a closed task's lifetime into the phases it spent its time in.
+    pub fn phases(&self) -> Option<TaskPhases> {
+        self.stats.phases()
+    }
+}
+
+impl fmt::Display for TaskSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flags: Vec<_> = IntoIterator::into_iter([
+            (self.over_budget, "over budget"),
+            (self.busy_loop, "busy loop"),
+            (self.stats.is_approximate(), "approximate"),
+        ])
+        .filter(|(set, _)| *set)
+        .map(|(_, flag)| flag)
+        .collect();
+        let flags = if flags.is_empty() { String::new() } else { format!(" ({})", flags.join(", ")) };
+
+        let label = self.label().map(|label| format!(" ({})", label)).unwrap_or_default();
+
+        if self.stats.is_active() {
+            write!(f, "Task {}{} running{}", self.id, label, flags)?;
+            if let Some(rate) = self.poll_rate {
+                write!(f, ", {:.1} polls/s", rate)?;
+            }
+            self.fmt_threads(f)?;
+            self.fmt_wakers(f)?;
+            self.fmt_fields(f)?;
+            self.fmt_follows(f)?;
+            self.fmt_logs(f)?;
+            return self.fmt_counters(f);
+        }
+
+        let outcome = self.outcome.map(|outcome| format!(" ({})", outcome)).unwrap_or_default();
+        let phases = self.phases().unwrap_or_default();
+
+        write!(
+            f,
+            "Task {}{} inactive{}: total time {:?} (scheduling {:?}, busy {:?}, idle {:?}, cleanup {:?}){}",
+            self.id,
+            label,
+            outcome,
+            self.total_time(),
+            phases.scheduling,
+            phases.busy,
+            phases.idle,
+            phases.cleanup,
+            flags,
+        )?;
+        self.fmt_threads(f)?;
+        self.fmt_wakers(f)?;
+        self.fmt_fields(f)?;
+        self.fmt_follows(f)?;
+        self.fmt_logs(f)?;
+        self.fmt_counters(f)
+    }
+}
+
+/// Where a task's lifetime went, from spawn to close.
+///
+/// The four phases add up to the task's total lifetime. A task that was never
+/// polled spends its whole lifetime in `scheduling`.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct TaskPhases {
+    /// Spawn to first poll.
+    pub scheduling: Duration,
+    /// Time spent inside polls.
+    pub busy: Duration,
+    /// Waiting between polls.
+    pub idle: Duration,
+    /// End of the last poll to close, i.e. drop latency.
+    pub cleanup: Duration,
+}
+
+impl TaskPhases {
+    pub(crate) fn new(
+        created_at: Instant,
+        first_poll: Option<Instant>,
+        last_poll_ended: Option<Instant>,
+        closed_at: Instant,
+        busy: Duration,
+    ) -> Option<Self> {
+        let total = closed_at.checked_duration_since(created_at)?;
+
+        let (first_poll, last_poll_ended) = match (first_poll, last_poll_ended) {
+            (Some(first), Some(last)) => (first, last),
+            _ => {
+                return Some(Self {
+                    scheduling: total,
+                    ..Default::default()
+                })
+            }
+        };
+
+        let scheduling = first_poll.saturating_duration_since(created_at).min(total);
+        let cleanup = closed_at.saturating_duration_since(last_poll_ended);
+        let busy = busy.min(total - scheduling);
+        let cleanup = cleanup.min(total - scheduling - busy);
+
+        Some(Self {
+            scheduling,
+            busy,
+            idle: total - scheduling - busy - cleanup,
+            cleanup,
+        })
+    }
+}
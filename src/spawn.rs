@@ -0,0 +1,207 @@
+use crate::fields::{OUTCOME_FIELD, TaskOutcome};
+
+use tokio::{runtime::Handle, task::JoinHandle};
+use tracing::{field, Instrument, Span};
+
+use std::{
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// The target of the events [`TrackFuture`] reports each poll with.
+pub(crate) const POLL_TARGET: &str = "bee_console::poll";
+
+/// Spawns `future` on the current runtime inside a span tracked by the
+/// collector, recording how the task ended.
+///
+/// The span carries the `bee.track` marker and a `task.outcome` field that is
+/// recorded as `ok`, `panicked` or `dropped` (aborted, or the runtime shut
+/// down before completion) right before the span closes. The outcome of each
+/// poll is reported as with [`TrackFuture`].
+///
+/// # Panics
+///
+/// Panics if called outside of a tokio runtime.
+pub fn spawn_tracked<T>(future: T) -> JoinHandle<T::Output>
+where
+    T: Future + Send + 'static,
+    T::Output: Send + 'static,
+{
+    spawn_tracked_on(&Handle::current(), future)
+}
+
+/// Like [`spawn_tracked`], but spawns onto the given runtime.
+pub fn spawn_tracked_on<T>(handle: &Handle, future: T) -> JoinHandle<T::Output>
+where
+    T: Future + Send + 'static,
+    T::Output: Send + 'static,
+{
+    let span = tracing::info_span!("tracked_task", bee.track = true, task.outcome = field::Empty);
+    let tracked = Tracked {
+        inner: Box::pin(future),
+        span: span.clone(),
+        finished: false,
+    };
+
+    handle.spawn(tracked.instrument(span))
+}
+
+struct Tracked<F> {
+    inner: Pin<Box<F>>,
+    span: Span,
+    finished: bool,
+}
+
+impl<F> Tracked<F> {
+    fn finish(&mut self, outcome: TaskOutcome) {
+        self.finished = true;
+        self.span.record(OUTCOME_FIELD, outcome.as_str());
+    }
+}
+
+impl<F: Future> Future for Tracked<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let inner = self.inner.as_mut();
+
+        match panic::catch_unwind(AssertUnwindSafe(|| inner.poll(cx))) {
+            Ok(Poll::Ready(output)) => {
+                record_poll(true);
+                self.finish(TaskOutcome::Completed);
+                Poll::Ready(output)
+            }
+            Ok(Poll::Pending) => {
+                record_poll(false);
+                Poll::Pending
+            }
+            Err(payload) => {
+                self.finish(TaskOutcome::Panicked);
+                panic::resume_unwind(payload)
+            }
+        }
+    }
+}
+
+impl<F> Drop for Tracked<F> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.finish(TaskOutcome::Dropped);
+        }
+    }
+}
+
+/// Reports whether each poll of a future returned `Ready` or `Pending` to the
+/// task it runs in, so the collector can count them.
+///
+/// Outcomes are sent as `TRACE` events with the `task.poll` field, under the
+/// `bee_console::poll` target, inside whichever span is current when the
+/// future is polled. A filter in front of the layer must let that target
+/// through for the counts to show up.
+///
+/// ```ignore
+/// let body = TrackFuture::new(stream.next());
+/// ```
+pub struct TrackFuture<F> {
+    inner: Pin<Box<F>>,
+}
+
+impl<F: Future> TrackFuture<F> {
+    pub fn new(future: F) -> Self {
+        Self {
+            inner: Box::pin(future),
+        }
+    }
+}
+
+impl<F: Future> Future for TrackFuture<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let poll = self.inner.as_mut().poll(cx);
+        record_poll(poll.is_ready());
+
+        poll
+    }
+}
+
+fn record_poll(ready: bool) {
+    let outcome = if ready { "ready" } else { "pending" };
+    tracing::trace!(target: POLL_TARGET, { task.poll = outcome }, "");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Running;
+    use crate::{BeeLayer, TaskSnapshot};
+
+    /// Returns `Pending` the given number of times before completing.
+    struct PendTimes(u32);
+
+    impl Future for PendTimes {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            if self.0 == 0 {
+                return Poll::Ready(());
+            }
+            self.0 -= 1;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+
+    /// Runs `track` under a layer, returning the task named `name`.
+    async fn tracked(name: &str, track: impl Future<Output = ()>) -> TaskSnapshot {
+        let running = Running::start(BeeLayer::pair());
+
+        track.await;
+
+        let tasks = running.finish().await;
+        tasks.into_iter().find(|task| task.callsite.is_some_and(|callsite| callsite.name == name)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn spawn_tracked_counts_ready_and_pending_polls() {
+        let task = tracked("tracked_task", async {
+            spawn_tracked(PendTimes(3)).await.unwrap();
+        })
+        .await;
+
+        assert_eq!(task.stats.pending_polls(), 3);
+        assert_eq!(task.stats.ready_polls(), 1);
+        assert_eq!(task.stats.ready_ratio(), Some(0.25));
+    }
+
+    #[tokio::test]
+    async fn track_future_counts_polls_to_the_enclosing_task() {
+        let task = tracked("job", async {
+            let job = tracing::info_span!("job");
+            // Queue spans aren't tasks, so the polls go to the job.
+            let queue = tracing::info_span!(parent: &job, "queue", queue.depth = 0u64);
+            TrackFuture::new(PendTimes(2)).instrument(queue).instrument(job).await;
+        })
+        .await;
+
+        assert_eq!(task.stats.pending_polls(), 2);
+        assert_eq!(task.stats.ready_polls(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn spawn_tracked_records_how_each_task_ended() {
+        let running = Running::start(BeeLayer::pair());
+
+        spawn_tracked(async {}).await.unwrap();
+        assert!(spawn_tracked(async { panic!("task panicked") }).await.unwrap_err().is_panic());
+        let aborted = spawn_tracked(std::future::pending::<()>());
+        aborted.abort();
+        assert!(aborted.await.unwrap_err().is_cancelled());
+
+        let mut outcomes: Vec<_> = running.finish().await.into_iter().filter_map(|task| task.outcome).collect();
+        outcomes.sort_by_key(TaskOutcome::as_str);
+        assert_eq!(outcomes, [TaskOutcome::Dropped, TaskOutcome::Completed, TaskOutcome::Panicked]);
+    }
+}
@@ -0,0 +1,103 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    thread::ThreadId,
+    time::Duration,
+};
+
+/// Busy time folded by the stack of tasks it was spent in, e.g.
+/// `root;child;leaf`, for flamegraphs. See
+/// [`Builder::flamegraph`](crate::Builder::flamegraph).
+///
+/// Each stack holds the time its innermost task spent polling itself, not
+/// polling the tasks entered inside of it, so that a frame's width is its
+/// own time plus that of the frames above it. Stacks follow the tasks a
+/// task was spawned inside of, so they are only approximate for tasks
+/// polled outside of their parent.
+///
+/// Displays as collapsed stacks in nanoseconds, one `stack busy_ns` line
+/// each, as read by `inferno-flamegraph` and `flamegraph.pl`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CollapsedStacks {
+    pub stacks: BTreeMap<String, Duration>,
+}
+
+impl CollapsedStacks {
+    pub fn is_empty(&self) -> bool {
+        self.stacks.is_empty()
+    }
+
+    /// Adds the busy time of every stack in `other`.
+    pub fn merge(&mut self, other: &CollapsedStacks) {
+        for (stack, busy) in &other.stacks {
+            *self.stacks.entry(stack.clone()).or_default() += *busy;
+        }
+    }
+}
+
+impl fmt::Display for CollapsedStacks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (stack, busy) in &self.stacks {
+            writeln!(f, "{} {}", stack, busy.as_nanos())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The frame a task shows up as: its name, with the separators collapsed
+/// stacks use replaced.
+pub(crate) fn frame(name: &str) -> String {
+    name.replace(';', ":").replace(['\n', '\r'], " ")
+}
+
+/// Folds poll time into [`CollapsedStacks`], keeping the polls currently
+/// open on each thread to take nested polls out of the outer ones.
+#[derive(Default)]
+pub(crate) struct StackTracker {
+    stacks: CollapsedStacks,
+    /// Time spent in polls nested inside each poll open on the thread,
+    /// innermost last.
+    open: HashMap<ThreadId, Vec<Duration>>,
+}
+
+impl StackTracker {
+    pub fn poll_started(&mut self, thread: ThreadId) {
+        self.open.entry(thread).or_default().push(Duration::ZERO);
+    }
+
+    /// Attributes a poll that took `poll_time` on `thread` to `stack`, less
+    /// the polls nested inside of it. Polls of provisional tasks, without a
+    /// stack yet, only count towards the poll they are nested in.
+    pub fn poll_ended(&mut self, thread: ThreadId, stack: Option<&str>, poll_time: Duration) {
+        let open = self.open.entry(thread).or_default();
+        let nested = open.pop().unwrap_or_default();
+        if let Some(outer) = open.last_mut() {
+            *outer += poll_time;
+        }
+        if open.is_empty() {
+            self.open.remove(&thread);
+        }
+
+        let stack = match stack {
+            Some(stack) => stack,
+            None => return,
+        };
+        let own = poll_time.saturating_sub(nested);
+        match self.stacks.stacks.get_mut(stack) {
+            Some(busy) => *busy += own,
+            None => {
+                self.stacks.stacks.insert(stack.to_string(), own);
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.stacks = Default::default();
+        self.open.clear();
+    }
+
+    pub fn summary(&self) -> CollapsedStacks {
+        self.stacks.clone()
+    }
+}
@@ -0,0 +1,140 @@
+use crate::{
+    event::Event,
+    sender::{EventChannel, EventReceiver},
+};
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Mutex,
+};
+
+/// Holds events that don't fit in the channel before the collector starts
+/// running, so tasks spawned during startup aren't lost.
+///
+/// Until the collector calls [`release`](Self::release), sends that find the
+/// channel full are queued here instead. Half of the buffer is reserved for
+/// spawn and close events, which can't be reconstructed if dropped.
+pub(crate) struct StartupBuffer {
+    ready: AtomicBool,
+    capacity: usize,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    events: Vec<Event>,
+    buffered: u64,
+    dropped: u64,
+}
+
+/// How many events the startup buffer absorbed or had to discard.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct StartupStats {
+    pub buffered: u64,
+    pub dropped: u64,
+}
+
+impl StartupBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            ready: AtomicBool::new(false),
+            capacity,
+            state: Default::default(),
+        }
+    }
+
+    /// Takes care of `event` while the collector hasn't started yet, or hands
+    /// it back once it has.
+    pub fn send(&self, event: Event, sender: &EventChannel) -> Option<Event> {
+        if self.ready.load(Ordering::Acquire) {
+            return Some(event);
+        }
+
+        let mut state = self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        // The collector may have released the buffer while we waited for the lock.
+        if self.ready.load(Ordering::Acquire) {
+            return Some(event);
+        }
+
+        // Once anything is buffered, later events have to queue behind it.
+        let event = if state.events.is_empty() {
+            // Handed back only if the channel is full.
+            sender.try_send(event)?
+        } else {
+            event
+        };
+
+        let limit = match event {
+            Event::Spawn { .. } | Event::Close { .. } => self.capacity,
+            _ => self.capacity / 2,
+        };
+
+        if state.events.len() < limit {
+            state.events.push(event);
+            state.buffered += 1;
+        } else {
+            state.dropped += 1;
+        }
+
+        None
+    }
+
+    /// Called once by the collector as it starts. Returns every event sent so
+    /// far in order, and switches senders over to the channel alone.
+    pub fn release(&self, receiver: &mut EventReceiver) -> Vec<Event> {
+        let mut state = self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut events = Vec::with_capacity(state.events.len());
+
+        while let Ok(event) = receiver.try_recv() {
+            events.push(event);
+        }
+        events.append(&mut state.events);
+        events.shrink_to_fit();
+
+        self.ready.store(true, Ordering::Release);
+        events
+    }
+
+    pub fn stats(&self) -> StartupStats {
+        let state = self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        StartupStats {
+            buffered: state.buffered,
+            dropped: state.dropped,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Running;
+    use crate::BeeLayer;
+
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn spawns_before_the_collector_starts_are_all_tracked() {
+        let startup = Arc::new(Mutex::new(StartupStats::default()));
+        let last = startup.clone();
+        let running = Running::start(BeeLayer::builder()
+            .summary_sink(move |summary: &crate::Summary| *last.lock().unwrap() = summary.startup)
+            .build());
+
+        // The collector only starts once the test first yields, in `finish`.
+        for job in 0..5000 {
+            tracing::info_span!("job", job).in_scope(|| {});
+        }
+
+        let tasks = running.finish().await;
+        assert_eq!(tasks.len(), 5000);
+        assert!(tasks.iter().all(|task| !task.stats.is_active()));
+        let startup = *startup.lock().unwrap();
+        // Of the 5000 spawns, enters, exits and closes, all but the 100 the
+        // channel holds were buffered or dropped, and only enters and exits
+        // were dropped.
+        assert_eq!(startup.buffered + startup.dropped, 4 * 5000 - 100);
+        assert!(startup.buffered >= 2 * 5000);
+    }
+}
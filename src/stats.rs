@@ -0,0 +1,369 @@
+use crate::{clock, snapshot::TaskPhases, waker::WakerOp};
+
+use std::{
+    convert::TryFrom,
+    thread::ThreadId,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// Poll and lifetime statistics for a single task.
+///
+/// Every duration is measured on the monotonic clock, so that the wall
+/// clock being stepped can't skew them. The wall-clock times, e.g.
+/// [`created_at`](Self::created_at), are derived from the monotonic ones,
+/// which are also available, e.g. as [`created_instant`](Self::created_instant).
+#[derive(Clone, Default, Debug)]
+pub struct Stats {
+    pub(crate) active: bool,
+    pub(crate) current_polls: u64,
+    pub(crate) polls: u64,
+    pub(crate) created_at: Option<Instant>,
+    pub(crate) first_poll: Option<Instant>,
+    pub(crate) last_poll: Option<Instant>,
+    pub(crate) last_exit: Option<Instant>,
+    pub(crate) closed_at: Option<Instant>,
+    pub(crate) busy_time: Duration,
+    pub(crate) poll_time: Duration,
+    pub(crate) ready_polls: u64,
+    pub(crate) pending_polls: u64,
+    /// Every thread the task was polled on, in the order first polled on.
+    pub(crate) threads: Vec<ThreadId>,
+    /// The thread the most recent poll started on.
+    pub(crate) last_thread: Option<ThreadId>,
+    pub(crate) migrations: u64,
+    pub(crate) wakes: u64,
+    pub(crate) waker_clones: u64,
+    pub(crate) waker_drops: u64,
+    pub(crate) self_wakes: u64,
+    /// When the task was first woken since its last poll, other than by
+    /// itself. Moved to the end of the poll for wakes during one.
+    pub(crate) woken_at: Option<Instant>,
+    pub(crate) scheduled_time: Duration,
+    pub(crate) scheduled_polls: u64,
+    pub(crate) approximate: bool,
+}
+
+impl Stats {
+    /// Whether the task has been spawned and not yet closed.
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Whether the task is inside a poll right now.
+    pub fn is_polling(&self) -> bool {
+        self.current_polls > 0
+    }
+
+    /// Whether events may have been lost while the task was being polled,
+    /// so that its poll counts and durations can't be fully trusted. See
+    /// [`Summary::events_lost`](crate::Summary::events_lost).
+    pub fn is_approximate(&self) -> bool {
+        self.approximate
+    }
+
+    /// Number of times the task has been polled. Nested entries into the
+    /// task's span count as a single poll.
+    pub fn polls(&self) -> u64 {
+        self.polls
+    }
+
+    /// Polls reported as returning `Ready`, by [`TrackFuture`](crate::TrackFuture)
+    /// wrappers inside the task.
+    pub fn ready_polls(&self) -> u64 {
+        self.ready_polls
+    }
+
+    /// Polls reported as returning `Pending`.
+    pub fn pending_polls(&self) -> u64 {
+        self.pending_polls
+    }
+
+    /// Fraction of reported poll outcomes that were `Ready`, or `None` if the
+    /// task reports none.
+    pub fn ready_ratio(&self) -> Option<f64> {
+        let reported = self.ready_polls + self.pending_polls;
+        if reported == 0 {
+            return None;
+        }
+
+        Some(self.ready_polls as f64 / reported as f64)
+    }
+
+    /// Wall-clock time during which the task was being polled. Polls
+    /// running at once on several threads overlap and count once, so this
+    /// never exceeds the task's lifetime and is what [`phases`](Self::phases)
+    /// splits it by.
+    pub fn busy_time(&self) -> Duration {
+        self.busy_time
+    }
+
+    /// Time spent in polls, summed over every thread the task was polled on.
+    /// Polls running at once on several threads each count, so this can
+    /// exceed [`busy_time`](Self::busy_time). Nested entries on one thread
+    /// count once.
+    pub fn poll_time(&self) -> Duration {
+        self.poll_time
+    }
+
+    /// Times the task was woken, by value or by reference. Only counted
+    /// when tokio is built with `tokio_unstable`, which logs waker events.
+    pub fn wakes(&self) -> u64 {
+        self.wakes
+    }
+
+    /// Times the task woke itself from inside its own poll, e.g. a stream
+    /// or loop that always has more work and yields without waiting. Wakes
+    /// from other threads while the task is being polled don't count.
+    pub fn self_wakes(&self) -> u64 {
+        self.self_wakes
+    }
+
+    /// The fraction of wakes that were [self-wakes](Self::self_wakes), if
+    /// the task was ever woken. Close to 1 means the task is busy-looping.
+    pub fn self_wake_ratio(&self) -> Option<f64> {
+        if self.wakes == 0 {
+            return None;
+        }
+
+        Some(self.self_wakes as f64 / self.wakes as f64)
+    }
+
+    /// Times the task's waker was cloned.
+    pub fn waker_clones(&self) -> u64 {
+        self.waker_clones
+    }
+
+    /// Times a waker of the task was dropped.
+    pub fn waker_drops(&self) -> u64 {
+        self.waker_drops
+    }
+
+    /// Total time the task spent runnable, from being woken to the start of
+    /// its next poll. Only wakes logged by tokio's waker events count, and
+    /// only from the first wake since the previous poll, or from the end of
+    /// that poll for a wake from another thread during it.
+    pub fn scheduled_time(&self) -> Duration {
+        self.scheduled_time
+    }
+
+    /// Mean time from a wake to the next poll, over the polls that followed
+    /// a wake. High values mean the runtime is starved.
+    pub fn mean_scheduled_time(&self) -> Option<Duration> {
+        let polls = u32::try_from(self.scheduled_polls).ok().filter(|&polls| polls > 0)?;
+        Some(self.scheduled_time / polls)
+    }
+
+    pub(crate) fn waker_op(&mut self, op: WakerOp, time: Instant, thread: ThreadId) {
+        match op {
+            WakerOp::Wake | WakerOp::WakeByRef => {
+                self.wakes += 1;
+                if self.current_polls > 0 && self.last_thread == Some(thread) {
+                    self.self_wakes += 1;
+                } else {
+                    self.woken_at.get_or_insert(time);
+                }
+            }
+            WakerOp::Clone => self.waker_clones += 1,
+            WakerOp::Drop => self.waker_drops += 1,
+        }
+    }
+
+    /// How many different threads the task was polled on.
+    pub fn threads(&self) -> usize {
+        self.threads.len()
+    }
+
+    /// How many times a poll started on a different thread than the one
+    /// before it. Entries nested in a poll, or made on another thread while
+    /// the task is already being polled, don't count.
+    pub fn migrations(&self) -> u64 {
+        self.migrations
+    }
+
+    /// Records a poll starting on `thread`.
+    pub(crate) fn polled_on(&mut self, thread: ThreadId) {
+        if self.last_thread.is_some_and(|last| last != thread) {
+            self.migrations += 1;
+        }
+        self.last_thread = Some(thread);
+
+        if !self.threads.contains(&thread) {
+            self.threads.push(thread);
+        }
+    }
+
+    /// When the task was created, on the wall clock.
+    pub fn created_at(&self) -> Option<SystemTime> {
+        self.created_at.map(clock::wall_time)
+    }
+
+    /// When the first poll started, on the wall clock.
+    pub fn first_poll(&self) -> Option<SystemTime> {
+        self.first_poll.map(clock::wall_time)
+    }
+
+    /// When the most recent poll started, on the wall clock.
+    pub fn last_poll(&self) -> Option<SystemTime> {
+        self.last_poll.map(clock::wall_time)
+    }
+
+    /// When the most recent poll ended, on the wall clock.
+    pub fn last_poll_ended(&self) -> Option<SystemTime> {
+        self.last_exit.map(clock::wall_time)
+    }
+
+    /// When the task was closed, on the wall clock.
+    pub fn closed_at(&self) -> Option<SystemTime> {
+        self.closed_at.map(clock::wall_time)
+    }
+
+    /// When the task was created, on the monotonic clock.
+    pub fn created_instant(&self) -> Option<Instant> {
+        self.created_at
+    }
+
+    /// When the first poll started, on the monotonic clock.
+    pub fn first_poll_instant(&self) -> Option<Instant> {
+        self.first_poll
+    }
+
+    /// When the most recent poll started, on the monotonic clock.
+    pub fn last_poll_instant(&self) -> Option<Instant> {
+        self.last_poll
+    }
+
+    /// When the most recent poll ended, on the monotonic clock.
+    pub fn last_poll_ended_instant(&self) -> Option<Instant> {
+        self.last_exit
+    }
+
+    /// When the task was closed, on the monotonic clock.
+    pub fn closed_instant(&self) -> Option<Instant> {
+        self.closed_at
+    }
+
+    /// Time from creation to close, for tasks that have closed.
+    pub fn total_time(&self) -> Option<Duration> {
+        self.closed_at.and_then(|end| {
+            self.created_at.and_then(|start| {
+                end.checked_duration_since(start)
+            })
+        })
+    }
+
+    /// Fraction of the task's lifetime spent inside polls, between 0 and 1.
+    /// For tasks still running, the lifetime so far is used.
+    pub fn utilization(&self) -> Option<f64> {
+        let lifetime = match self.total_time() {
+            Some(total) => total,
+            None => self.age(Instant::now())?,
+        };
+
+        if lifetime.is_zero() {
+            return None;
+        }
+
+        Some((self.busy_time.as_secs_f64() / lifetime.as_secs_f64()).min(1.0))
+    }
+
+    /// Splits a closed task's lifetime into the phases it spent its time in.
+    pub fn phases(&self) -> Option<TaskPhases> {
+        TaskPhases::new(
+            self.created_at?,
+            self.first_poll,
+            self.last_exit,
+            self.closed_at?,
+            self.busy_time,
+        )
+    }
+
+    pub(crate) fn age(&self, now: Instant) -> Option<Duration> {
+        self.created_at.and_then(|start| now.checked_duration_since(start))
+    }
+
+    /// Time since the last poll ended, or since the task was created if it
+    /// hasn't been polled; zero while it is being polled.
+    pub(crate) fn idle(&self, now: Instant) -> Option<Duration> {
+        if self.is_polling() {
+            return Some(Duration::ZERO);
+        }
+
+        self.last_exit.or(self.created_at).and_then(|since| now.checked_duration_since(since))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    #[test]
+    fn accessors_report_the_task_state() {
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+        let other = thread::spawn(|| thread::current().id()).join().unwrap();
+        let stats = Stats {
+            active: false,
+            polls: 3,
+            created_at: Some(start),
+            first_poll: Some(ms(10)),
+            last_poll: Some(ms(30)),
+            last_exit: Some(ms(35)),
+            closed_at: Some(ms(50)),
+            busy_time: Duration::from_millis(20),
+            poll_time: Duration::from_millis(25),
+            ready_polls: 1,
+            pending_polls: 3,
+            threads: vec![thread::current().id(), other],
+            migrations: 1,
+            wakes: 4,
+            self_wakes: 1,
+            waker_clones: 2,
+            waker_drops: 2,
+            scheduled_time: Duration::from_millis(9),
+            scheduled_polls: 3,
+            ..Default::default()
+        };
+
+        assert!(!stats.is_active());
+        assert!(!stats.is_polling());
+        assert!(!stats.is_approximate());
+        assert_eq!(stats.polls(), 3);
+        assert_eq!(stats.ready_polls(), 1);
+        assert_eq!(stats.pending_polls(), 3);
+        assert_eq!(stats.ready_ratio(), Some(0.25));
+        assert_eq!(stats.busy_time(), Duration::from_millis(20));
+        assert_eq!(stats.poll_time(), Duration::from_millis(25));
+        assert_eq!(stats.wakes(), 4);
+        assert_eq!(stats.self_wakes(), 1);
+        assert_eq!(stats.self_wake_ratio(), Some(0.25));
+        assert_eq!(stats.waker_clones(), 2);
+        assert_eq!(stats.waker_drops(), 2);
+        assert_eq!(stats.scheduled_time(), Duration::from_millis(9));
+        assert_eq!(stats.mean_scheduled_time(), Some(Duration::from_millis(3)));
+        assert_eq!(stats.threads(), 2);
+        assert_eq!(stats.migrations(), 1);
+        assert_eq!(stats.created_instant(), Some(start));
+        assert_eq!(stats.first_poll_instant(), Some(ms(10)));
+        assert_eq!(stats.last_poll_instant(), Some(ms(30)));
+        assert_eq!(stats.last_poll_ended_instant(), Some(ms(35)));
+        assert_eq!(stats.closed_instant(), Some(ms(50)));
+        assert_eq!(stats.created_at(), Some(clock::wall_time(start)));
+        assert_eq!(stats.closed_at(), Some(clock::wall_time(ms(50))));
+        assert_eq!(stats.total_time(), Some(Duration::from_millis(50)));
+        assert!((stats.utilization().unwrap() - 0.4).abs() < 1e-9);
+    }
+
+    #[test]
+    fn accessors_of_a_task_never_polled() {
+        let stats = Stats::default();
+
+        assert_eq!(stats.ready_ratio(), None);
+        assert_eq!(stats.self_wake_ratio(), None);
+        assert_eq!(stats.mean_scheduled_time(), None);
+        assert_eq!(stats.total_time(), None);
+        assert_eq!(stats.utilization(), None);
+        assert!(stats.phases().is_none());
+    }
+}
@@ -1,60 +1,342 @@
-use crate::{collector::Collector, event::Event};
+use crate::{
+    builder::Builder,
+    callsite::Callsite,
+    collector::Collector,
+    event::{Event, EventId},
+    fields::{FieldConfig, FieldUpdates, RecordVisitor, SpawnVisitor},
+    filter::{CallsiteAllowlist, CallsiteFilter, FilteredTraffic},
+    handle::CollectorHandle,
+    logs::{self, MessageVisitor},
+    replay,
+    reporting::{self, INTERNAL_TARGET},
+    sampling::Sampler,
+    sender::EventSender,
+    spawn::POLL_TARGET,
+    waker::{WakerVisitor, WAKER_TARGET},
+};
 
-use tokio::sync::mpsc::{self, error::TrySendError, Sender};
 use tracing_core::{
     span::{self, Id},
-    subscriber::Subscriber,
+    subscriber::{Interest, Subscriber},
+    Metadata,
 };
 use tracing_subscriber::{
     Layer,
     fmt::{
-        format::{DefaultFields, FormatFields}, 
+        format::{DefaultFields, FormatFields, Writer},
         FormattedFields,
     },
     layer::Context,
-    registry::LookupSpan,
+    registry::{LookupSpan, SpanRef},
 };
 
-use std::time::Duration;
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, ThreadId},
+};
 
-pub struct BeeLayer<F = DefaultFields> {
-    event_sender: Sender<Event>,
-    format: F,
-    collector: Option<Collector>,
+/// What to do with a span whose fields fail to format.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OnFormatError {
+    /// Track the task with empty fields. This is the default.
+    #[default]
+    TrackEmpty,
+    /// Don't track the span at all.
+    Skip,
 }
 
-impl BeeLayer {
-    pub fn new() -> Self {
-        let (tx, events) = mpsc::channel(100);
+/// Marks a span monitoring a queue rather than a task.
+struct QueueSpan;
 
+/// Whether the current thread is replaying tasks or reporting metrics, in
+/// which case the spans the layer sees are its own doing.
+fn is_own_activity() -> bool {
+    replay::is_replaying() || reporting::is_reporting()
+}
+
+/// Marks a span that isn't tracked, under [`OnFormatError::Skip`] or
+/// because it wasn't on an allowlist.
+struct Untracked;
+
+/// Whether `span` is tracked as a task, rather than as a queue or not at all.
+fn is_task<S>(span: &SpanRef<'_, S>) -> bool
+where
+    S: for<'a> LookupSpan<'a>,
+{
+    let extensions = span.extensions();
+    extensions.get::<Untracked>().is_none() && extensions.get::<QueueSpan>().is_none()
+}
+
+/// How many times a span is currently entered on each thread, kept when
+/// coalescing re-entries.
+#[derive(Default)]
+struct EntryDepths(Mutex<Vec<(ThreadId, usize)>>);
+
+impl EntryDepths {
+    /// Returns whether this is the span's outermost entry on this thread.
+    fn enter(&self) -> bool {
+        let thread = thread::current().id();
+        let mut depths = self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        match depths.iter_mut().find(|(id, _)| *id == thread) {
+            Some((_, depth)) => {
+                *depth += 1;
+                false
+            }
+            None => {
+                depths.push((thread, 1));
+                true
+            }
+        }
+    }
+
+    /// Returns whether this is the span's outermost exit on this thread.
+    fn exit(&self) -> bool {
+        let thread = thread::current().id();
+        let mut depths = self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let index = match depths.iter().position(|(id, _)| *id == thread) {
+            Some(index) => index,
+            // Unbalanced, so let the collector make what it can of it.
+            None => return true,
+        };
+        depths[index].1 -= 1;
+        if depths[index].1 > 0 {
+            return false;
+        }
+
+        depths.swap_remove(index);
+        true
+    }
+}
+
+/// Tracks task spans, sending their events to a collector.
+///
+/// Clones share the collector, channel and configuration of the layer they
+/// were cloned from, so that several subscriber stacks, e.g. one per test,
+/// can feed a single collector. Each clone keeps the span ids it sees apart
+/// from every other's, since ids from different registries overlap.
+pub struct BeeLayer<F = DefaultFields> {
+    pub(crate) events: EventSender,
+    pub(crate) format: Arc<F>,
+    pub(crate) fields: FieldConfig,
+    pub(crate) on_format_error: OnFormatError,
+    /// Whether to only send the outermost entry and exit of each span.
+    pub(crate) coalesce_reentrant: bool,
+    /// Namespaces the span ids this layer sees, see [`EventId`].
+    pub(crate) source: u32,
+    /// The sequence number of the next event sent, see [`Event::seq`].
+    pub(crate) seq: AtomicU64,
+    /// Only spans from these callsites are tracked, if set.
+    pub(crate) callsites: Option<Arc<CallsiteAllowlist>>,
+    /// Controls the collector built alongside the layer.
+    pub(crate) handle: CollectorHandle,
+}
+
+impl<F> Clone for BeeLayer<F> {
+    fn clone(&self) -> Self {
         Self {
-            event_sender: tx,
-            format: Default::default(),
-            collector: Some(Collector::new(events, Duration::from_secs(Self::TICK_INTERVAL))),
+            events: self.events.clone(),
+            format: self.format.clone(),
+            fields: self.fields.clone(),
+            on_format_error: self.on_format_error,
+            coalesce_reentrant: self.coalesce_reentrant,
+            source: self.events.shared.next_span_source(),
+            seq: AtomicU64::new(0),
+            callsites: self.callsites.clone(),
+            handle: self.handle.clone(),
         }
     }
+}
 
-    pub async fn run(self) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
-        let collector = self
-            .collector
-            .expect("No collector");
+impl BeeLayer {
+    /// Builds a layer with the default configuration, along with the
+    /// collector it sends events to. Register the layer with a subscriber
+    /// and run the collector on its own task:
+    ///
+    /// ```
+    /// use bee_console::BeeLayer;
+    /// use tracing_subscriber::prelude::*;
+    ///
+    /// #[tokio::main(flavor = "current_thread")]
+    /// async fn main() {
+    ///     let (layer, collector) = BeeLayer::pair();
+    ///     let handle = collector.handle();
+    ///     let _subscriber = tracing_subscriber::registry().with(layer).set_default();
+    ///
+    ///     let collector = tokio::spawn(collector.run());
+    ///
+    ///     tracing::info_span!("task").in_scope(|| {});
+    ///
+    ///     handle.shutdown().await.unwrap();
+    ///     let tasks = collector.await.unwrap();
+    ///     assert_eq!(tasks.len(), 1);
+    /// }
+    /// ```
+    pub fn pair() -> (Self, Collector) {
+        Self::builder().build()
+    }
 
-        let collector = tokio::spawn(async move { collector.run().await });
-        let res = collector.await;
-        res.map_err(Into::into)
+    pub fn builder() -> Builder {
+        Builder::default()
+    }
+
+    /// Like [`pair`](Self::pair), but formats span fields with `format`,
+    /// e.g. `JsonFields`, rather than [`DefaultFields`]. Short for
+    /// [`Builder::fields_format`] with the defaults otherwise.
+    pub fn with_format<F>(format: F) -> (BeeLayer<F>, Collector)
+    where
+        F: for<'writer> FormatFields<'writer> + 'static,
+    {
+        Self::builder().fields_format(format).build()
     }
 }
 
 impl<F> BeeLayer<F> {
-    const TICK_INTERVAL: u64 = 1;
+    /// Returns a handle to the collector the layer sends events to. Always
+    /// `Some`, as opposed to [`NoopBeeLayer::handle`](crate::NoopBeeLayer::handle).
+    pub fn handle(&self) -> Option<CollectorHandle> {
+        Some(self.handle.clone())
+    }
+
+    /// Number of tasks currently alive, updated as spans open and close.
+    ///
+    /// This is cheap to read from any thread and doesn't wait for the collector.
+    pub fn active_count(&self) -> usize {
+        self.events.active_count()
+    }
+
+    /// Counts of what was kept from the collector, to hand to a
+    /// [`filter`](crate::filter::TasksOnly::account_to) so that it counts
+    /// what it filters out too.
+    pub fn filtered_traffic(&self) -> FilteredTraffic {
+        self.events.shared.filtered.clone()
+    }
+
+    /// A per-layer filter leaving out the spans this layer doesn't track by
+    /// their callsite, i.e. those from
+    /// [targets not included](crate::Builder::include_targets), not
+    /// [tokio tasks](crate::Builder::tasks_only) or more verbose than the
+    /// [max level](crate::Builder::max_level), along with the collector's
+    /// own.
+    ///
+    /// The layer checks these itself for every new span. Filtered this way,
+    /// each callsite is decided once, when tracing registers it, and spans
+    /// left out never reach the layer at all, without affecting any other
+    /// layer next to it:
+    ///
+    /// ```
+    /// use bee_console::BeeLayer;
+    /// use tracing_subscriber::prelude::*;
+    ///
+    /// let (layer, _collector) = BeeLayer::builder().include_targets(["my_app"]).build();
+    /// let filter = layer.callsite_filter();
+    /// tracing_subscriber::registry()
+    ///     .with(layer.with_filter(filter))
+    ///     .with(tracing_subscriber::fmt::layer())
+    ///     .init();
+    /// ```
+    pub fn callsite_filter(&self) -> CallsiteFilter {
+        let traffic = &self.events.shared.filtered;
+
+        CallsiteFilter {
+            callsites: self.callsites.clone(),
+            traffic: traffic.is_enabled().then(|| traffic.clone()),
+        }
+    }
+
+    /// Whether events are still being sent to the collector. Turns `false`
+    /// for good once the collector stops, after which events are dropped
+    /// without complaint.
+    pub fn is_collecting(&self) -> bool {
+        self.events.is_collecting()
+    }
+
+    /// The most events ever waiting for the collector at once, since the
+    /// layer was built. Compare it against the channel's capacity, as set
+    /// with [`Builder::event_buffer_capacity`], to see how close the
+    /// collector came to dropping events.
+    pub fn peak_queue_len(&self) -> usize {
+        self.events.peak_queue_len()
+    }
 
     fn send(&self, event: Event) {
-        match self.event_sender.try_reserve() {
-            Ok(permit) => permit.send(event),
-            Err(TrySendError::Closed(_)) => tracing::error!("Receiver terminated"),
-            _ => tracing::error!("Unknown error"),
+        self.events.send(event.with_seq(self.seq.fetch_add(1, Ordering::Relaxed)));
+    }
+
+    /// Whether the span was skipped when it was created. Spans are only
+    /// looked up when skipping is enabled.
+    fn is_untracked<S>(&self, id: &Id, ctx: &Context<'_, S>) -> bool
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        (self.on_format_error == OnFormatError::Skip || self.callsites.is_some()
+            || self.fields.opt_in
+            || self.events.shared.sampler.is_some()
+            || self.events.shared.allowlist_used()
+            || self.events.shared.own_spans_skipped())
+            && ctx.span(id).is_some_and(|span| span.extensions().get::<Untracked>().is_some())
+    }
+
+    fn count_filtered(&self, metadata: &'static Metadata<'static>) {
+        if self.events.shared.filtered.is_enabled() {
+            self.events.shared.filtered.record(metadata, true);
+        }
+    }
+
+    /// Sends the waker operation tokio logged, if it names a task's span the
+    /// registry still knows. Events it can't make sense of are dropped.
+    fn on_waker_event<S>(&self, event: &tracing::Event<'_>, ctx: &Context<'_, S>)
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let mut visitor = WakerVisitor::default();
+        event.record(&mut visitor);
+
+        let (op, id) = match (visitor.op, visitor.task_id) {
+            // `Id::from_u64` panics on zero, which is never a span's id.
+            (Some(op), Some(id)) if id != 0 => (op, Id::from_u64(id)),
+            _ => return,
+        };
+        if ctx.span(&id).is_some_and(|span| is_task(&span)) {
+            self.send(Event::waker(EventId::span(self.source, &id), op));
         }
     }
+
+    /// Whether the span is untracked, counting the entry or exit if so.
+    fn is_untracked_entry<S>(&self, id: &Id, ctx: &Context<'_, S>) -> bool
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        if !self.is_untracked(id, ctx) {
+            return false;
+        }
+
+        if self.events.shared.filtered.is_enabled() {
+            if let Some(span) = ctx.span(id) {
+                self.events.shared.filtered.record(span.metadata(), false);
+            }
+        }
+        true
+    }
+
+    /// Steps the span's entry count on this thread, returning whether the
+    /// entry or exit is its outermost. Spans without a count always are.
+    fn entry_depths<S>(&self, id: &Id, ctx: &Context<'_, S>, step: fn(&EntryDepths) -> bool) -> bool
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let span = match ctx.span(id) {
+            Some(span) => span,
+            None => return true,
+        };
+        let extensions = span.extensions();
+
+        extensions.get::<EntryDepths>().is_none_or(step)
+    }
 }
 
 impl<S, F> Layer<S> for BeeLayer<F> 
@@ -62,37 +344,792 @@ where
     S: Subscriber + for<'a> LookupSpan<'a>,
     F: for<'writer> FormatFields<'writer> + 'static,
 {
-    fn new_span(&self, attrs: &span::Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+    // A plain layer can't turn a callsite off for itself alone: `never` or
+    // `false` here would turn it off for every layer next to this one. The
+    // allowlist's decision is only cached ahead of the callsite's first
+    // span; see `callsite_filter` for leaving callsites out early.
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        if let Some(callsites) = &self.callsites {
+            if metadata.is_span() {
+                callsites.allows(metadata);
+            }
+        }
+        Interest::always()
+    }
+
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        // The collector's own spans are never tasks, wherever they are opened.
+        if is_own_activity() {
+            return;
+        }
+
         let span = ctx.span(id).expect("span does not exist");
         let mut extensions = span.extensions_mut();
 
+        // Outside of reporting, their entries and close have to be skipped too.
+        if reporting::is_own_target(attrs.metadata().target()) {
+            extensions.insert(Untracked);
+            self.events.shared.skip_own_span();
+            return;
+        }
+
+        // Checked first, so that spans left out aren't formatted.
+        let allowed = self.callsites.as_ref().is_none_or(|callsites| callsites.allows(attrs.metadata()));
+        if !allowed || !self.fields.opts_in(attrs) {
+            extensions.insert(Untracked);
+            self.count_filtered(attrs.metadata());
+            return;
+        }
+
         let fields = match extensions.get_mut::<FormattedFields<F>>() {
             Some(fields) => fields.fields.clone(),
             None => {
                 let mut fields = String::new();
 
-                match self.format.format_fields(&mut fields, attrs) {
+                match self.format.format_fields(Writer::new(&mut fields), attrs) {
                     Ok(_) => extensions.insert(FormattedFields::<F>::new(fields.clone())),
                     Err(_) => {
-                        tracing::warn!("Error formatting span fields");
+                        tracing::warn!(target: INTERNAL_TARGET, "Error formatting span fields");
+
+                        if self.on_format_error == OnFormatError::Skip {
+                            extensions.insert(Untracked);
+                            self.count_filtered(attrs.metadata());
+                            return;
+                        }
                     },
                 }
                 fields
             }
         };
 
-        self.send(Event::spawn(id.clone(), fields));
+        let mut visitor = SpawnVisitor::new(&self.fields);
+        attrs.record(&mut visitor);
+
+        if self.fields.is_queue(attrs.metadata().fields()) {
+            extensions.insert(QueueSpan);
+            let name = attrs.metadata().name().to_string();
+            self.send(Event::queue_open(EventId::span(self.source, id), name, fields, visitor.attributes.queue_depth));
+            return;
+        }
+
+        let name = visitor.attributes.name.as_deref().unwrap_or_else(|| attrs.metadata().name());
+        if !self.events.shared.allows(name) {
+            extensions.insert(Untracked);
+            self.count_filtered(attrs.metadata());
+            return;
+        }
+        if !self.events.shared.sampler.as_ref().is_none_or(Sampler::sample) {
+            extensions.insert(Untracked);
+            return;
+        }
+
+        if self.coalesce_reentrant {
+            extensions.insert(EntryDepths::default());
+        }
+
+        // The innermost task the span opened inside of, seen through queue
+        // spans and spans left untracked.
+        let parent = span.scope().skip(1).find(is_task).map(|parent| EventId::span(self.source, &parent.id()));
+        let callsite = Callsite::from_metadata(attrs.metadata());
+        self.send(Event::spawn(EventId::span(self.source, id), fields, visitor.attributes, Some(callsite), parent));
+    }
+
+    fn on_record(&self, id: &Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+        if is_own_activity() || self.is_untracked(id, &ctx) {
+            return;
+        }
+
+        let span = match ctx.span(id) {
+            Some(span) => span,
+            None => return,
+        };
+
+        let mut visitor = RecordVisitor::keeping_values(&self.fields);
+        values.record(&mut visitor);
+
+        // Merged into the formatted fields the same way `fmt` does, so that a
+        // span created empty and filled in later shows what it was filled with.
+        let mut extensions = span.extensions_mut();
+        match extensions.get_mut::<FormattedFields<F>>() {
+            Some(fields) => match self.format.add_fields(fields, values) {
+                Ok(_) => visitor.updates.fields = Some(fields.fields.clone()),
+                Err(_) => tracing::warn!(target: INTERNAL_TARGET, "Error formatting recorded span fields"),
+            },
+            None => {
+                let mut fields = String::new();
+                match self.format.format_fields(Writer::new(&mut fields), values) {
+                    Ok(_) => {
+                        extensions.insert(FormattedFields::<F>::new(fields.clone()));
+                        visitor.updates.fields = Some(fields);
+                    }
+                    Err(_) => tracing::warn!(target: INTERNAL_TARGET, "Error formatting recorded span fields"),
+                }
+            }
+        }
+        drop(extensions);
+
+        if !visitor.updates.is_empty() {
+            self.send(Event::record(EventId::span(self.source, id), visitor.updates));
+        }
+    }
+
+    fn on_follows_from(&self, id: &Id, follows: &Id, ctx: Context<'_, S>) {
+        if is_own_activity() || self.is_untracked(id, &ctx) {
+            return;
+        }
+
+        self.send(Event::follows_from(EventId::span(self.source, id), EventId::span(self.source, follows)));
+    }
+
+    // Events count towards the logs of the innermost task they happen in.
+    // They can update their task's fields too, e.g. `task.mem_bytes`, and
+    // bump its counters with `counter.<name>` fields.
+    fn on_event(&self, event: &tracing::Event<'_>, ctx: Context<'_, S>) {
+        if is_own_activity() || event.metadata().target() == INTERNAL_TARGET {
+            return;
+        }
+        if event.metadata().target() == WAKER_TARGET {
+            return self.on_waker_event(event, &ctx);
+        }
+
+        let span = match ctx.event_span(event) {
+            Some(span) => span,
+            None => return,
+        };
+
+        // Polls reported by `TrackFuture` aren't logs.
+        if let Some(task) = span.scope().find(is_task).filter(|_| event.metadata().target() != POLL_TARGET) {
+            let level = *event.metadata().level();
+            let message = if logs::keeps_message(&level) {
+                let mut visitor = MessageVisitor::default();
+                event.record(&mut visitor);
+                visitor.message
+            } else {
+                None
+            };
+            self.send(Event::log(EventId::span(self.source, &task.id()), level, message));
+        }
+
+        if !self.fields.has_updates(event.metadata().fields()) {
+            return;
+        }
+
+        let mut visitor = RecordVisitor::new(&self.fields);
+        event.record(&mut visitor);
+        let mut updates = visitor.updates;
+
+        // A queue's depth goes to the span the event happened in.
+        if let Some(depth) = updates.queue_depth.take() {
+            if !self.is_untracked(&span.id(), &ctx) {
+                let depth = FieldUpdates {
+                    queue_depth: Some(depth),
+                    ..Default::default()
+                };
+                self.send(Event::record(EventId::span(self.source, &span.id()), depth));
+            }
+        }
+
+        // Everything else, e.g. counters, memory and poll outcomes, goes to
+        // the innermost task the event happened in, skipping queue spans and
+        // spans left untracked.
+        if updates.is_empty() {
+            return;
+        }
+        if let Some(task) = span.scope().find(is_task) {
+            self.send(Event::record(EventId::span(self.source, &task.id()), updates));
+        }
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        if is_own_activity() || self.is_untracked_entry(id, &ctx) {
+            return;
+        }
+        if self.coalesce_reentrant && !self.entry_depths(id, &ctx, EntryDepths::enter) {
+            return;
+        }
+
+        self.send(Event::enter(EventId::span(self.source, id)));
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        if is_own_activity() || self.is_untracked_entry(id, &ctx) {
+            return;
+        }
+        if self.coalesce_reentrant && !self.entry_depths(id, &ctx, EntryDepths::exit) {
+            return;
+        }
+
+        self.send(Event::exit(EventId::span(self.source, id)));
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        if is_own_activity() || self.is_untracked(&id, &ctx) {
+            return;
+        }
+
+        let is_queue = self.fields.queue_field.is_some()
+            && ctx.span(&id).is_some_and(|span| span.extensions().get::<QueueSpan>().is_some());
+        if is_queue {
+            self.send(Event::queue_close(EventId::span(self.source, &id)));
+        } else {
+            self.send(Event::close(EventId::span(self.source, &id)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Running;
+    use crate::{fields::TaskKind, FieldValue, Summary, TaskLogs, TaskSnapshot};
+
+    use tracing::Level;
+    use tracing_subscriber::{field::RecordFields, layer::SubscriberExt};
+
+    use std::{collections::BTreeMap, fmt, sync::atomic::AtomicUsize, time::Duration};
+
+    /// Formats fields as usual, but fails on any field named `bad`.
+    struct FailsOnBad;
+
+    impl<'writer> FormatFields<'writer> for FailsOnBad {
+        fn format_fields<R: RecordFields>(&self, mut writer: Writer<'writer>, fields: R) -> fmt::Result {
+            let mut formatted = String::new();
+            DefaultFields::new().format_fields(Writer::new(&mut formatted), fields)?;
+            if formatted.contains("bad") {
+                return Err(fmt::Error);
+            }
+            writer.write_str(&formatted)
+        }
+    }
+
+    /// Formats fields as usual, with their names in upper case.
+    struct Uppercase;
+
+    impl<'writer> FormatFields<'writer> for Uppercase {
+        fn format_fields<R: RecordFields>(&self, mut writer: Writer<'writer>, fields: R) -> fmt::Result {
+            struct Names(Vec<String>);
+
+            impl tracing::field::Visit for Names {
+                fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn fmt::Debug) {
+                    self.0.push(format!("{}={:?}", field.name().to_uppercase(), value));
+                }
+            }
+
+            let mut names = Names(Vec::new());
+            fields.record(&mut names);
+            writer.write_str(&names.0.join(" "))
+        }
+    }
+
+    async fn tasks_formatted_with(policy: OnFormatError) -> Vec<TaskSnapshot> {
+        let running = Running::start(BeeLayer::builder().fields_format(FailsOnBad).on_format_error(policy).build());
+
+        tracing::info_span!("good", ok = 1).in_scope(|| {});
+        tracing::info_span!("broken", bad = 1).in_scope(|| {});
+
+        running.finish().await
+    }
+
+    #[tokio::test]
+    async fn mem_bytes_keeps_the_current_value_the_max_and_the_total() {
+        let reported = Arc::new(Mutex::new(None));
+        let last = reported.clone();
+        let running = Running::start(BeeLayer::builder()
+            .summary_sink(move |summary: &Summary| {
+                *last.lock().unwrap() = summary.reported_mem_bytes;
+            })
+            .build());
+
+        let job = tracing::info_span!("job", task.mem_bytes = tracing::field::Empty);
+        job.record("task.mem_bytes", 100u64);
+        job.in_scope(|| {
+            // Queue spans aren't tasks, so the memory goes to the job.
+            let queue = tracing::info_span!("queue", queue.depth = 0u64);
+            queue.in_scope(|| tracing::info!(task.mem_bytes = 40u64));
+        });
+        let other = tracing::info_span!("other", task.mem_bytes = tracing::field::Empty);
+        other.record("task.mem_bytes", 10u64);
+
+        let tasks = running.finish().await;
+        let job = tasks.iter().find(|task| task.callsite.is_some_and(|callsite| callsite.name == "job")).unwrap();
+        assert_eq!(job.mem_bytes, Some(40));
+        assert_eq!(job.mem_bytes_max, Some(100));
+        assert_eq!(*reported.lock().unwrap(), Some(50));
+    }
+
+    #[tokio::test]
+    async fn weight_field_is_parsed_from_numbers_and_numeric_strings() {
+        let running = Running::start(BeeLayer::builder().weight_field("cost").build());
+
+        let _int = tracing::info_span!("int", cost = 2u64);
+        let _float = tracing::info_span!("float", cost = 0.5);
+        let _string = tracing::info_span!("string", cost = "1.5");
+        let _other = tracing::info_span!("other", cost = "heavy");
+
+        let mut weights: Vec<_> = running.finish().await
+            .into_iter()
+            .map(|task| (task.callsite.unwrap().name, task.weight))
+            .collect();
+        weights.sort_by_key(|(name, _)| *name);
+        assert_eq!(weights, [("float", Some(0.5)), ("int", Some(2.0)), ("other", None), ("string", Some(1.5))]);
+    }
+
+    #[tokio::test]
+    async fn active_count_follows_overlapping_tasks() {
+        let (layer, collector) = BeeLayer::pair();
+        let gauge = layer.clone();
+        let running = Running::start((layer, collector));
+
+        let first = tracing::info_span!("first");
+        assert_eq!(gauge.active_count(), 1);
+        let second = tracing::info_span!("second");
+        assert_eq!(gauge.active_count(), 2);
+        drop(first);
+        assert_eq!(gauge.active_count(), 1);
+        let third = tracing::info_span!("third");
+        assert_eq!(gauge.active_count(), 2);
+        drop(second);
+        drop(third);
+        assert_eq!(gauge.active_count(), 0);
+
+        running.finish().await;
+    }
+
+    #[tokio::test]
+    async fn kinds_are_classified_and_aggregated() {
+        let kinds = Arc::new(Mutex::new(Vec::new()));
+        let last = kinds.clone();
+        let running = Running::start(BeeLayer::builder()
+            .summary_sink(move |summary: &Summary| *last.lock().unwrap() = summary.kinds.clone())
+            .build());
+
+        let _running = tracing::info_span!("runtime.spawn", kind = "task");
+        tracing::info_span!("runtime.spawn", kind = "task").in_scope(|| {});
+        tracing::info_span!("runtime.spawn", kind = %"blocking").in_scope(|| {});
+
+        let tasks = running.finish().await;
+        let classified: Vec<_> = tasks.iter().map(|task| task.kind).collect();
+        assert_eq!(classified, [TaskKind::Task, TaskKind::Task, TaskKind::Blocking]);
+
+        let kinds: Vec<_> = kinds.lock().unwrap().iter().map(|kind| (kind.kind, kind.tasks, kind.active, kind.polls)).collect();
+        assert_eq!(kinds, [(TaskKind::Task, 2, 1, 1), (TaskKind::Blocking, 1, 0, 1)]);
+    }
+
+    #[tokio::test]
+    async fn unformattable_spans_are_tracked_empty_by_default() {
+        let mut fields: Vec<_> = tasks_formatted_with(OnFormatError::TrackEmpty)
+            .await
+            .into_iter()
+            .map(|task| task.fields)
+            .collect();
+        fields.sort();
+        assert_eq!(fields, ["", "ok=1"]);
+    }
+
+    #[tokio::test]
+    async fn unformattable_spans_are_skipped_if_asked() {
+        let tasks = tasks_formatted_with(OnFormatError::Skip).await;
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].fields, "ok=1");
+    }
+
+    #[tokio::test]
+    async fn peak_queue_len_outlasts_the_burst() {
+        let (layer, collector) = BeeLayer::pair();
+        let gauge = layer.clone();
+        let running = Running::start((layer, collector));
+        // Lets the collector start, so that events go to its channel.
+        running.handle.debug_report(Duration::from_secs(5)).await;
+        assert_eq!(gauge.peak_queue_len(), 0);
+
+        // The collector can't drain the channel while this task holds the
+        // runtime's only thread.
+        let spans: Vec<_> = (0..10).map(|_| tracing::info_span!("burst")).collect();
+        assert_eq!(gauge.peak_queue_len(), 10);
+
+        let report = running.handle.debug_report(Duration::from_secs(5)).await;
+        assert_eq!(report.summary.tasks.len(), 10);
+        assert_eq!(gauge.peak_queue_len(), 10);
+
+        drop(spans);
+        running.finish().await;
+    }
+
+    #[tokio::test]
+    async fn clones_in_two_registries_feed_one_collector() {
+        let (layer, collector) = BeeLayer::pair();
+        let other = layer.clone();
+        let handle = collector.handle();
+        let collector = tokio::spawn(collector.run());
+
+        let first = tracing::subscriber::with_default(tracing_subscriber::registry().with(layer), || {
+            tracing::info_span!("task", registry = "first")
+        });
+        let second = tracing::subscriber::with_default(tracing_subscriber::registry().with(other), || {
+            tracing::info_span!("task", registry = "second")
+        });
+        // Each registry numbers its spans from the start.
+        assert_eq!(first.id(), second.id());
+
+        first.in_scope(|| second.in_scope(|| {}));
+        second.in_scope(|| {});
+        drop(first);
+
+        let summary = handle.debug_report(Duration::from_secs(5)).await.summary;
+        assert_eq!(summary.active_tasks, 1);
+        // The last span held the last of the two subscribers, and so of the
+        // layers, which stops the collector.
+        drop(second);
+
+        let mut tasks = collector.await.unwrap();
+        tasks.sort_by(|a, b| a.fields.cmp(&b.fields));
+        let tasks: Vec<_> = tasks.iter().map(|task| (task.fields.as_str(), task.stats.polls(), task.stats.is_active())).collect();
+        assert_eq!(tasks, [("registry=\"first\"", 1, false), ("registry=\"second\"", 2, false)]);
+    }
+
+    /// Polls a task three times, re-entering its span within each poll, and
+    /// returns its polls along with the enters sent to the collector.
+    async fn reentered(coalesce: bool) -> (u64, usize) {
+        let enters = Arc::new(AtomicUsize::new(0));
+        let sent = enters.clone();
+        let running = Running::start(BeeLayer::builder()
+            .coalesce_reentrant(coalesce)
+            .event_sink(move |event: &Event| {
+                if let Event::Enter { .. } = event {
+                    sent.fetch_add(1, Ordering::Relaxed);
+                }
+            })
+            .build());
+
+        let span = tracing::info_span!("task");
+        for _ in 0..3 {
+            span.in_scope(|| span.in_scope(|| span.in_scope(|| {})));
+        }
+        drop(span);
+
+        let tasks = running.finish().await;
+        assert!(!tasks[0].stats.is_active());
+        (tasks[0].stats.polls(), enters.load(Ordering::Relaxed))
+    }
+
+    #[tokio::test]
+    async fn coalesced_reentry_sends_fewer_events_for_the_same_stats() {
+        assert_eq!(reentered(false).await, (3, 9));
+        assert_eq!(reentered(true).await, (3, 3));
+    }
+
+    /// Counts the errors logged.
+    struct Errors(Arc<AtomicUsize>);
+
+    impl<S: Subscriber> Layer<S> for Errors {
+        fn on_event(&self, event: &tracing::Event<'_>, _ctx: Context<'_, S>) {
+            if *event.metadata().level() == tracing::Level::ERROR {
+                self.0.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn spans_after_the_collector_stops_are_dropped_quietly() {
+        let errors = Arc::new(AtomicUsize::new(0));
+        let (layer, collector) = BeeLayer::pair();
+        let gauge = layer.clone();
+        let handle = collector.handle();
+        let subscriber = tracing_subscriber::registry().with(layer).with(Errors(errors.clone()));
+        let _default = tracing::subscriber::set_default(subscriber);
+        let collector = tokio::spawn(collector.run());
+        assert!(gauge.is_collecting());
+
+        handle.shutdown().await.unwrap();
+        collector.await.unwrap();
+        assert!(!gauge.is_collecting());
+
+        for _ in 0..100 {
+            tracing::info_span!("late").in_scope(|| {});
+        }
+        assert_eq!(errors.load(Ordering::Relaxed), 0);
+        assert_eq!(gauge.active_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn counter_events_add_up_in_their_task() {
+        let running = Running::start(BeeLayer::builder().max_counters_per_task(2).roll_up_counters().build());
+
+        for items in [3u64, 4] {
+            tracing::info_span!("worker").in_scope(|| {
+                tracing::info!(counter.items = items, counter.bytes = 100u64);
+                tracing::info!(counter.items = 2u64);
+                // Past the cap of two, or not a count: ignored.
+                tracing::info!(counter.retries = 1u64);
+                tracing::info!(counter.items = "many");
+                tracing::info!(counter.items = -1i64);
+            });
+        }
+        // Outside any task: counted nowhere.
+        tracing::info!(counter.items = 50u64);
+
+        let rollup = running.handle.debug_report(Duration::from_secs(5)).await.summary.counter_rollup;
+        let tasks = running.finish().await;
+
+        let counters: Vec<_> = tasks.iter().map(|task| task.counters.clone()).collect();
+        assert_eq!(counters, [
+            BTreeMap::from([("bytes".to_string(), 100), ("items".to_string(), 5)]),
+            BTreeMap::from([("bytes".to_string(), 100), ("items".to_string(), 6)]),
+        ]);
+        assert!(tasks[0].to_string().contains("counters: bytes 100, items 5"), "{}", tasks[0]);
+        assert_eq!(rollup.len(), 1);
+        assert_eq!(rollup[0].callsite.name, "worker");
+        assert_eq!(rollup[0].counters, BTreeMap::from([("bytes".to_string(), 200), ("items".to_string(), 11)]));
+    }
+
+    #[tokio::test]
+    async fn spans_sharing_a_stable_key_are_combined_under_it() {
+        let running = Running::start(BeeLayer::builder().stable_key_field("task.id").build());
+
+        let poll = |span: &tracing::Span| span.in_scope(|| std::thread::sleep(Duration::from_millis(2)));
+        let first = tracing::info_span!("job", task.id = "job-1");
+        poll(&first);
+        drop(first);
+        // The same logical task again, as a new span.
+        let second = tracing::info_span!("job", task.id = "job-1");
+        poll(&second);
+        poll(&second);
+        let other = tracing::info_span!("job", task.id = "job-2");
+        poll(&other);
+
+        let summary = running.handle.debug_report(Duration::from_secs(5)).await.summary;
+        drop((second, other));
+        running.finish().await;
+
+        let busy = |key: &str| {
+            summary
+                .tasks
+                .iter()
+                .filter(|task| task.fields == format!("task.id=\"{}\"", key))
+                .map(|task| task.stats.busy_time())
+                .sum::<Duration>()
+        };
+        let job = &summary.stable_keys["job-1"];
+        assert_eq!((job.tasks, job.active, job.polls), (2, 1, 3));
+        assert_eq!(job.busy_time, busy("job-1"));
+        assert!(job.busy_time >= Duration::from_millis(6));
+        assert_eq!(summary.stable_keys["job-2"].busy_time, busy("job-2"));
+        assert_eq!(summary.stable_keys.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn custom_field_format_reaches_the_spawn_event() {
+        let spawned = Arc::new(Mutex::new(Vec::new()));
+        let seen = spawned.clone();
+        let running = Running::start(BeeLayer::builder()
+            .fields_format(Uppercase)
+            .event_sink(move |event: &Event| {
+                if let Event::Spawn { fields, .. } = event {
+                    seen.lock().unwrap().push(fields.clone());
+                }
+            })
+            .build());
+
+        tracing::info_span!("job", user = "ann", attempt = 2).in_scope(|| {});
+
+        let tasks = running.finish().await;
+        assert_eq!(*spawned.lock().unwrap(), [r#"USER="ann" ATTEMPT=2"#]);
+        assert_eq!(tasks[0].fields, r#"USER="ann" ATTEMPT=2"#);
+    }
+
+    #[cfg(feature = "json")]
+    #[tokio::test]
+    async fn json_fields_are_an_escaped_object() {
+        let running = Running::start(BeeLayer::builder().json_fields().build());
+
+        tracing::info_span!("empty").in_scope(|| {});
+        tracing::info_span!("job", user = r#"ann "the admin""#, attempt = 2).in_scope(|| {});
+
+        let tasks = running.finish().await;
+        assert_eq!(tasks[0].fields, "{}");
+        assert_eq!(tasks[1].fields, r#"{"attempt":2,"user":"ann \"the admin\""}"#);
+        let parsed: serde_json::Value = serde_json::from_str(&tasks[1].fields).unwrap();
+        assert_eq!(parsed["user"], r#"ann "the admin""#);
+    }
+
+    /// Runs `spans` under a layer built by `builder`, returning the tasks
+    /// tracked and the lifecycle events the layer sent for them.
+    async fn tracked_with(builder: Builder, spans: impl FnOnce()) -> (Vec<&'static str>, Vec<&'static str>) {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let seen = events.clone();
+        let running = Running::start(builder
+            .event_sink(move |event: &Event| {
+                let kind = match event {
+                    Event::Spawn { .. } => "spawn",
+                    Event::Enter { .. } => "enter",
+                    Event::Exit { .. } => "exit",
+                    Event::Close { .. } => "close",
+                    _ => return,
+                };
+                seen.lock().unwrap().push(kind);
+            })
+            .build());
+
+        spans();
+
+        let tasks = running.finish().await.iter().map(|task| task.callsite.unwrap().name).collect();
+        let events = events.lock().unwrap().clone();
+        (tasks, events)
+    }
+
+    /// Runs a `runtime.spawn`, a `runtime.blocking` and a `my_span` span
+    /// inside the first, returning what [`tracked_with`] does.
+    async fn runtime_and_own_spans(tasks_only: bool) -> (Vec<&'static str>, Vec<&'static str>) {
+        tracked_with(BeeLayer::builder().tasks_only(tasks_only), || {
+            tracing::info_span!("runtime.spawn", kind = "task").in_scope(|| {
+                tracing::info_span!("my_span").in_scope(|| {});
+            });
+            tracing::info_span!("runtime.blocking", kind = "blocking").in_scope(|| {});
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn tasks_only_tracks_runtime_spans_alone() {
+        let (tasks, events) = runtime_and_own_spans(true).await;
+        assert_eq!(tasks, ["runtime.spawn", "runtime.blocking"]);
+        assert_eq!(events, ["spawn", "enter", "exit", "close", "spawn", "enter", "exit", "close"]);
+
+        let (tasks, events) = runtime_and_own_spans(false).await;
+        assert_eq!(tasks, ["runtime.spawn", "my_span", "runtime.blocking"]);
+        assert_eq!(events.len(), 12);
+    }
+
+    #[tokio::test]
+    async fn max_level_leaves_out_more_verbose_spans() {
+        let (tasks, events) = tracked_with(BeeLayer::builder().max_level(Level::DEBUG), || {
+            let hot = tracing::trace_span!("hot_loop");
+            // Left out by the layer alone, not by the subscriber.
+            assert!(!hot.is_disabled());
+            hot.in_scope(|| {});
+            tracing::info_span!("request").in_scope(|| {});
+        })
+        .await;
+        assert_eq!(tasks, ["request"]);
+        assert_eq!(events, ["spawn", "enter", "exit", "close"]);
+    }
+
+    #[tokio::test]
+    async fn opt_in_tracks_marked_spans_alone() {
+        let (tasks, events) = tracked_with(BeeLayer::builder().opt_in(true), || {
+            tracing::info_span!("unmarked").in_scope(|| {});
+            tracing::info_span!("declined", bee.track = false).in_scope(|| {});
+            let worker = tracing::info_span!("worker", bee.track = true);
+            worker.in_scope(|| tracing::info_span!("inner").in_scope(|| {}));
+            worker.in_scope(|| {});
+        })
+        .await;
+        assert_eq!(tasks, ["worker"]);
+        assert_eq!(events, ["spawn", "enter", "exit", "enter", "exit", "close"]);
     }
 
-    fn on_enter(&self, id: &Id, _ctx: Context<'_, S>) {
-        self.send(Event::enter(id.clone()));
+    #[tokio::test]
+    async fn tasks_are_labelled_by_task_name_or_else_their_span() {
+        let running = Running::start(BeeLayer::pair());
+
+        tracing::info_span!("runtime.spawn", kind = "task", task.name = "connection-handler").in_scope(|| {});
+        tracing::info_span!("runtime.spawn", kind = "task").in_scope(|| {});
+
+        let tasks = running.finish().await;
+        assert_eq!(tasks[0].name.as_deref(), Some("connection-handler"));
+        assert_eq!(tasks[0].label().as_deref(), Some("connection-handler"));
+        assert_eq!(tasks[0].fields, r#"kind="task" task.name="connection-handler""#);
+        assert_eq!(tasks[1].name, None);
+        assert!(tasks[1].label().unwrap().starts_with("runtime.spawn@"));
+        assert_eq!(tasks[1].fields, r#"kind="task""#);
+    }
+
+    #[tokio::test]
+    async fn events_are_counted_in_their_nearest_tracked_span() {
+        let running = Running::start(BeeLayer::builder().include_targets(["my_app"]).build());
+
+        tracing::info_span!(target: "my_app", "handler").in_scope(|| {
+            tracing::info!("accepted");
+            tracing::warn!("slow");
+            tracing::warn!("slower");
+            tracing::info_span!(target: "hyper::proto", "conn").in_scope(|| tracing::error!("reset"));
+            tracing::debug!("closing");
+        });
+        // Outside any tracked span: counted nowhere.
+        tracing::info_span!(target: "hyper::proto", "conn").in_scope(|| tracing::error!("refused"));
+        tracing::warn!("idle");
+
+        let tasks = running.finish().await;
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].logs, TaskLogs {
+            debug: 1,
+            info: 1,
+            warn: 2,
+            error: 1,
+            last_warn: Some("slower".into()),
+            last_error: Some("reset".into()),
+            ..Default::default()
+        });
+    }
+
+    #[tokio::test]
+    async fn fields_recorded_later_reach_the_task() {
+        let running = Running::start(BeeLayer::pair());
+
+        let conn = tracing::info_span!("conn", peer = tracing::field::Empty, attempt = 1u64);
+        conn.in_scope(|| {});
+        conn.record("peer", "10.0.0.1:443");
+        conn.record("attempt", 2u64);
+        drop(conn);
+
+        let tasks = running.finish().await;
+        assert_eq!(tasks[0].fields, r#"attempt=1 peer="10.0.0.1:443" attempt=2"#);
+        assert_eq!(tasks[0].values, [
+            ("attempt", FieldValue::U64(2)),
+            ("peer", FieldValue::Str("10.0.0.1:443".into())),
+        ]);
     }
 
-    fn on_exit(&self, id: &Id, _ctx: Context<'_, S>) {
-        self.send(Event::exit(id.clone()));
+    #[tokio::test]
+    async fn own_diagnostics_are_never_tracked() {
+        let running = Running::start(BeeLayer::builder()
+            .max_counters_per_task(1)
+            .summary_sink(|_: &Summary| {
+                tracing::info_span!("export").in_scope(|| tracing::warn!("exporting"));
+                panic!("sink failed");
+            })
+            .build());
+
+        tracing::info_span!("job").in_scope(|| {
+            tracing::warn!("slow");
+            // Each makes the crate log about itself, inside the task.
+            tracing::info!(counter.items = "many");
+            tracing::info!(counter.items = 1u64, counter.bytes = 1u64);
+            tracing::error!(target: INTERNAL_TARGET, "something went wrong");
+        });
+        tracing::info_span!(target: INTERNAL_TARGET, "internal").in_scope(|| {});
+        tracing::info_span!(target: reporting::METRICS_TARGET, "task_metrics").in_scope(|| {});
+        // Reported on, through the failing sink, with the crate's own spans and events.
+        running.handle.debug_report(Duration::from_secs(5)).await;
+
+        let tasks = running.finish().await;
+        let names: Vec<_> = tasks.iter().map(|task| task.callsite.unwrap().name).collect();
+        assert_eq!(names, ["job"]);
+        assert_eq!((tasks[0].logs.warn, tasks[0].logs.error), (1, 0));
     }
 
-    fn on_close(&self, id: Id, _ctx: Context<'_, S>) {
-        self.send(Event::close(id.clone()));
+    #[tokio::test]
+    async fn sampling_tracks_every_nth_task_alone() {
+        let running = Running::start(BeeLayer::builder().sample_one_in(4).build());
+
+        for job in 0..10u64 {
+            tracing::info_span!("job", job).in_scope(|| {});
+        }
+
+        let sampling = running.handle.debug_report(Duration::from_secs(5)).await.summary.sampling.unwrap();
+        assert_eq!((sampling.seen, sampling.sampled), (10, 3));
+        let mut jobs: Vec<_> = running.finish().await.iter().map(|task| task.fields.clone()).collect();
+        jobs.sort();
+        assert_eq!(jobs, ["job=0", "job=4", "job=8"]);
     }
-}
\ No newline at end of file
+}
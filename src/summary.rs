@@ -0,0 +1,568 @@
+use crate::{
+    callsite::{CallsiteCounters, CallsiteSummary},
+    census::AgeCensus,
+    filter::FilteredCallsite,
+    degrade::Degradation,
+    export::{rfc3339, ExportConfig},
+    fields::TaskKind,
+    histogram::DurationHistogram,
+    snapshot::TaskSnapshot,
+    stacks::CollapsedStacks,
+    startup::StartupStats,
+    queue::QueueSummary,
+    sampling::SampleStats,
+    sender::DroppedEvents,
+    threads::ThreadSummary,
+    variants::FieldVariants,
+};
+
+use serde_json::Value;
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Everything the collector reports on a flush.
+#[derive(Clone, Debug, Default)]
+pub struct Summary {
+    /// Identifies the process or runtime the summary came from, as set with
+    /// [`Builder::instance`](crate::Builder::instance).
+    pub instance: Option<String>,
+    /// Numbers the flush this summary was produced by, starting at 1. Ticks
+    /// the collector missed are skipped, leaving a gap.
+    pub seq: u64,
+    /// When the summary was flushed, or `None` if it was taken on demand.
+    pub flushed_at: Option<SystemTime>,
+    /// Ticks the collector has missed since it started, because it couldn't
+    /// keep up or the runtime was blocked.
+    pub ticks_missed: u64,
+    /// The names tasks were being tracked by, if an allowlist was set with
+    /// [`CollectorHandle::set_name_allowlist`](crate::CollectorHandle::set_name_allowlist).
+    /// Counts then only cover tasks spawned while it allowed them.
+    pub name_allowlist: Option<Vec<String>>,
+    pub tasks: Vec<TaskSnapshot>,
+    pub startup: StartupStats,
+    /// Tasks that closed within the [transient grace period](crate::Builder::transient_grace)
+    /// and so aren't listed in `tasks`.
+    pub transient_tasks: u64,
+    pub budget_violations: u64,
+    pub duplicate_closes: u64,
+    /// Events that arrived in an order they couldn't have happened in, if
+    /// [checked](crate::Builder::strict).
+    pub order_violations: u64,
+    /// Events that never reached the collector, going by the gaps in their
+    /// sequence numbers. Tasks being polled when events went missing are
+    /// marked [approximate](crate::Stats::is_approximate).
+    pub events_lost: u64,
+    /// Runs of consecutive events lost.
+    pub event_gaps: u64,
+    /// Panics the collector caught applying events or flushing sinks.
+    pub panics: u64,
+    /// Sinks no longer flushed because they kept panicking.
+    pub quarantined_sinks: usize,
+    /// Times the collector restarted after a panic, losing what it had
+    /// collected until then.
+    pub restarts: u32,
+    /// How long the collector took to produce its previous report.
+    pub report_cost: Option<Duration>,
+    /// Steps flushed reports are degraded by to keep within the
+    /// [`ReportBudget`](crate::ReportBudget), if any.
+    pub degradation: Vec<Degradation>,
+    /// The most tasks ever active at once, over the life of the process.
+    pub peak_active: Option<PeakActive>,
+    /// Tasks active when the summary was taken.
+    pub active_tasks: usize,
+    /// Tasks active at each flush so far.
+    pub active_concurrency: CountDistribution,
+    /// Running tasks bucketed by age and by time since their last poll.
+    pub census: AgeCensus,
+    /// Tasks alive per callsite, most first.
+    pub callsites: Vec<CallsiteSummary>,
+    /// Task counters summed per callsite, in callsite order, if
+    /// [rolled up](crate::Builder::roll_up_counters).
+    pub counter_rollup: Vec<CallsiteCounters>,
+    /// Distinct field strings tasks were spawned with per target, most first.
+    pub field_variants: Vec<FieldVariants>,
+    /// Busy time by task stack, if [folded](crate::Builder::flamegraph).
+    pub stacks: CollapsedStacks,
+    /// Totals over every task spawned, if the collector
+    /// [only counts](crate::Builder::aggregates_only), in which case no
+    /// tasks are listed.
+    pub counts: Option<AggregateStats>,
+    /// How many tasks were tracked out of how many were spawned, if only
+    /// [some are](crate::Builder::sample_one_in).
+    pub sampling: Option<SampleStats>,
+    /// Totals over every span sharing a value of the
+    /// [stable key field](crate::Builder::stable_key_field), by value.
+    pub stable_keys: BTreeMap<String, AggregateStats>,
+    /// Tasks of each kind, in [`TaskKind`] order.
+    pub kinds: Vec<KindSummary>,
+    /// Time spent polling on each worker thread since the previous flush,
+    /// busiest first.
+    pub threads: Vec<ThreadSummary>,
+    /// Spans monitoring queues, open or closed since the previous flush.
+    pub queues: Vec<QueueSummary>,
+    /// What was kept from the collector per callsite, most first, if
+    /// [tracked](crate::Builder::track_filtered_traffic).
+    pub filtered_traffic: Vec<FilteredCallsite>,
+    /// The most events ever waiting for the collector at once.
+    pub peak_queue_len: usize,
+    /// When the latest event the collector processed happened, or `None`
+    /// if it hasn't processed any. Stops moving if instrumentation goes
+    /// silent.
+    pub last_event_at: Option<SystemTime>,
+    /// Events discarded with the channel full under
+    /// [`OverflowPolicy::DropNewest`](crate::OverflowPolicy::DropNewest).
+    pub dropped_newest: u64,
+    /// The events behind `dropped_newest`, by kind.
+    pub dropped_by_kind: DroppedEvents,
+    /// Events dropped in any way since the previous flush, from the startup
+    /// buffer, with the channel full or evicted to make room.
+    pub dropped_since_flush: u64,
+    /// Events evicted under [`OverflowPolicy::DropOldest`](crate::OverflowPolicy::DropOldest).
+    pub dropped_oldest: Option<u64>,
+    /// Snapshots not sent to a close-events receiver because it was full.
+    pub dropped_close_events: u64,
+    /// Events each [event sink](crate::Builder::event_sink) dropped, e.g.
+    /// with its channel full, in the order they were added.
+    pub event_sinks_dropped: Vec<u64>,
+    pub weighted_busy_time: Option<Duration>,
+    /// Memory currently reported through `task.mem_bytes`, summed over
+    /// running tasks.
+    pub reported_mem_bytes: Option<u64>,
+    pub wake_to_poll: Option<LatencySummary>,
+    /// Time from spawn to first poll, across every task polled so far.
+    pub first_poll_delay: Option<LatencySummary>,
+    pub idle_gaps: Option<LatencySummary>,
+    /// How long polls took, from a sample of them if
+    /// [`Builder::poll_sample_every`](crate::Builder::poll_sample_every) is set.
+    pub poll_durations: Option<LatencySummary>,
+    pub phases: Option<PhaseSummary>,
+}
+
+/// A high-water mark of concurrently active tasks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PeakActive {
+    pub count: usize,
+    /// When the mark was first reached.
+    pub at: SystemTime,
+}
+
+/// A distribution of sampled counts, in cumulative buckets as Prometheus
+/// histograms expect.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CountDistribution {
+    /// Upper bounds, each with the number of samples at or below it.
+    pub buckets: Vec<(u64, u64)>,
+    pub sum: u64,
+    /// Every sample, including those above the last bound.
+    pub count: u64,
+}
+
+/// Totals across every task of one [`TaskKind`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KindSummary {
+    pub kind: TaskKind,
+    pub tasks: usize,
+    pub active: usize,
+    pub polls: u64,
+    /// Busy time of the tasks currently kept.
+    pub busy_time: Duration,
+    /// Busy time of every task ever tracked, including ones since dropped
+    /// as transient or lost to a restart. Unlike `busy_time`, it never goes
+    /// down.
+    pub busy_time_total: Duration,
+}
+
+impl KindSummary {
+    pub(crate) fn collect(tasks: &[TaskSnapshot], retired_busy_time: &BTreeMap<TaskKind, Duration>) -> Vec<Self> {
+        let mut kinds = BTreeMap::new();
+        let empty = |kind| Self {
+            kind,
+            tasks: 0,
+            active: 0,
+            polls: 0,
+            busy_time: Duration::ZERO,
+            busy_time_total: Duration::ZERO,
+        };
+
+        for task in tasks {
+            let summary = kinds.entry(task.kind).or_insert_with(|| empty(task.kind));
+
+            summary.tasks += 1;
+            summary.active += task.stats.is_active() as usize;
+            summary.polls += task.stats.polls();
+            summary.busy_time += task.stats.busy_time();
+            summary.busy_time_total += task.stats.busy_time();
+        }
+        for (kind, busy_time) in retired_busy_time {
+            kinds.entry(*kind).or_insert_with(|| empty(*kind)).busy_time_total += *busy_time;
+        }
+
+        kinds.into_values().collect()
+    }
+}
+
+/// Totals across the tasks sharing a value of a field, see
+/// [`Summary::aggregate_by_field`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AggregateStats {
+    pub tasks: usize,
+    pub active: usize,
+    pub polls: u64,
+    pub busy_time: Duration,
+}
+
+/// Percentiles of a latency distribution.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LatencySummary {
+    pub p50: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+    pub samples: u64,
+}
+
+impl LatencySummary {
+    pub(crate) fn from_histogram(histogram: &DurationHistogram) -> Option<Self> {
+        if histogram.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            p50: histogram.percentile(0.5),
+            p99: histogram.percentile(0.99),
+            max: histogram.max(),
+            samples: histogram.len(),
+        })
+    }
+}
+
+/// Distribution of each lifetime phase across closed tasks.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PhaseSummary {
+    pub scheduling: LatencySummary,
+    pub busy: LatencySummary,
+    pub idle: LatencySummary,
+    pub cleanup: LatencySummary,
+}
+
+impl Summary {
+    /// Targets listed with their field variant counts when displayed.
+    const FIELD_VARIANTS_SHOWN: usize = 5;
+    /// Stable keys listed, busiest first, when displayed.
+    const STABLE_KEYS_SHOWN: usize = 5;
+
+    /// Totals for the tasks in the summary, grouped by their value of a field
+    /// registered with [`Builder::group_by_field`](crate::Builder::group_by_field).
+    /// Tasks without the field are grouped under `"unknown"`.
+    pub fn aggregate_by_field(&self, field: &str) -> HashMap<String, AggregateStats> {
+        let mut groups: HashMap<String, AggregateStats> = HashMap::new();
+
+        for task in &self.tasks {
+            let group = groups.entry(task.group(field).unwrap_or("unknown").to_string()).or_default();
+            group.tasks += 1;
+            group.active += task.stats.is_active() as usize;
+            group.polls += task.stats.polls();
+            group.busy_time += task.stats.busy_time();
+        }
+
+        groups
+    }
+
+    /// The `n` tasks with the most busy time, busiest first.
+    pub fn busiest(&self, n: usize) -> impl Iterator<Item = &TaskSnapshot> {
+        let mut tasks: Vec<_> = self.tasks.iter().collect();
+        tasks.sort_by(|a, b| b.stats.busy_time().cmp(&a.stats.busy_time()).then(a.id.cmp(&b.id)));
+        tasks.truncate(n);
+
+        tasks.into_iter()
+    }
+
+    /// The summary as JSON in a canonical form, for comparing against golden
+    /// files: default units, tasks in id order, keys sorted and floats rounded
+    /// to a fixed precision. Equal summaries always give the same bytes.
+    pub fn canonical_json(&self) -> String {
+        let export = ExportConfig::default();
+        let mut map = export.aggregates_json(self);
+        let mut tasks: Vec<_> = self.tasks.iter().collect();
+        tasks.sort_by_key(|task| task.id);
+        map.insert("tasks".into(), tasks.into_iter().map(|task| export.task_json(task)).collect());
+
+        Value::Object(map).to_string()
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_aggregates(f)?;
+        self.fmt_task_tree(f)
+    }
+}
+
+/// A summary displayed without its task table, see [`Summary::aggregates`].
+struct Aggregates<'a>(&'a Summary);
+
+impl fmt::Display for Aggregates<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt_aggregates(f)
+    }
+}
+
+impl Summary {
+    /// Displays everything but the task table, e.g. to log the tasks
+    /// separately or not at all.
+    pub fn aggregates(&self) -> impl fmt::Display + '_ {
+        Aggregates(self)
+    }
+
+    /// Lists the tasks with each indented under the task it was spawned
+    /// inside of. Tasks whose parent isn't listed, e.g. because it closed and
+    /// was dropped, are shown at the top level.
+    fn fmt_task_tree(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let listed: HashMap<u64, &TaskSnapshot> = self.tasks.iter().map(|task| (task.id, task)).collect();
+        let mut shown = HashSet::new();
+
+        let roots = self.tasks.iter().filter(|task| !task.parent.is_some_and(|parent| listed.contains_key(&parent)));
+        for root in roots.chain(&self.tasks) {
+            // Tasks only reachable through a cycle of reused ids are shown
+            // at the top level after the rest.
+            if shown.contains(&root.id) {
+                continue;
+            }
+
+            let mut stack = vec![(root, 0)];
+            while let Some((task, depth)) = stack.pop() {
+                if !shown.insert(task.id) {
+                    continue;
+                }
+                writeln!(f, "{:indent$}{}", "", task, indent = depth * 2)?;
+                let children = task.children.iter().filter_map(|child| listed.get(child));
+                stack.extend(children.rev().map(|child| (*child, depth + 1)));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn fmt_aggregates(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(flushed_at) = self.flushed_at {
+            let at = rfc3339(flushed_at.duration_since(UNIX_EPOCH).unwrap_or_default());
+            writeln!(f, "Summary {} at {} ({} ticks missed)", self.seq, at, self.ticks_missed)?;
+        }
+        if let Some(names) = &self.name_allowlist {
+            writeln!(f, "Only tracking tasks named: {}", names.join(", "))?;
+        }
+        writeln!(f, "{} startup events buffered, {} dropped", self.startup.buffered, self.startup.dropped)?;
+        writeln!(f, "{} task budget violations", self.budget_violations)?;
+
+        if self.active_concurrency.count > 0 {
+            writeln!(
+                f,
+                "{} tasks active, {:.1} on average over {} flushes",
+                self.active_tasks,
+                self.active_concurrency.sum as f64 / self.active_concurrency.count as f64,
+                self.active_concurrency.count,
+            )?;
+        }
+        if let Some(counts) = &self.counts {
+            writeln!(
+                f,
+                "{} tasks spawned ({} running): {} polls, busy {:?}",
+                counts.tasks, counts.active, counts.polls, counts.busy_time,
+            )?;
+        }
+        if let Some(sampling) = &self.sampling {
+            writeln!(f, "{}", sampling)?;
+        }
+        if self.census.tasks() > 0 {
+            writeln!(f, "{}", self.census)?;
+        }
+        if let Some(peak) = self.peak_active {
+            let at = rfc3339(peak.at.duration_since(UNIX_EPOCH).unwrap_or_default());
+            writeln!(f, "Peak of {} concurrent tasks at {}", peak.count, at)?;
+        }
+        for callsite in self.callsites.iter().filter(|callsite| callsite.violated) {
+            writeln!(
+                f,
+                "Callsite {} exceeded its limit of {} alive tasks ({} alive now)",
+                callsite.callsite,
+                callsite.limit.unwrap_or_default(),
+                callsite.alive,
+            )?;
+        }
+        // Only the targets most likely to be over-instrumented.
+        for variants in self.field_variants.iter().take(Self::FIELD_VARIANTS_SHOWN).filter(|variants| variants.variants > 1) {
+            writeln!(f, "{}", variants)?;
+        }
+        if !self.stacks.is_empty() {
+            writeln!(f, "Busy time folded into {} stacks", self.stacks.stacks.len())?;
+        }
+        for rollup in &self.counter_rollup {
+            let counters: Vec<_> = rollup.counters.iter().map(|(name, value)| format!("{} {}", value, name)).collect();
+            writeln!(f, "Tasks from {} counted {}", rollup.callsite, counters.join(", "))?;
+        }
+        let mut stable_keys: Vec<_> = self.stable_keys.iter().collect();
+        stable_keys.sort_by(|(a_key, a), (b_key, b)| b.busy_time.cmp(&a.busy_time).then(a_key.cmp(b_key)));
+        for (key, stats) in stable_keys.into_iter().take(Self::STABLE_KEYS_SHOWN) {
+            writeln!(
+                f,
+                "Key {}: {} spans ({} running), {} polls, busy {:?}",
+                key, stats.tasks, stats.active, stats.polls, stats.busy_time,
+            )?;
+        }
+        for kind in &self.kinds {
+            writeln!(
+                f,
+                "{} tasks of kind {} ({} running): {} polls, busy {:?}",
+                kind.tasks, kind.kind, kind.active, kind.polls, kind.busy_time,
+            )?;
+        }
+        for thread in &self.threads {
+            writeln!(f, "{}", thread)?;
+        }
+        for queue in &self.queues {
+            writeln!(f, "{}", queue)?;
+        }
+        for filtered in &self.filtered_traffic {
+            writeln!(
+                f,
+                "Filtered out of {}: {} spans, {} events",
+                filtered.callsite, filtered.spans, filtered.events,
+            )?;
+        }
+        writeln!(f, "At most {} events queued for the collector", self.peak_queue_len)?;
+        if let Some(last_event_at) = self.last_event_at {
+            let at = rfc3339(last_event_at.duration_since(UNIX_EPOCH).unwrap_or_default());
+            writeln!(f, "Last event at {}", at)?;
+        }
+        if self.dropped_since_flush > 0 {
+            writeln!(f, "{} events dropped since the last flush", self.dropped_since_flush)?;
+        }
+        if self.dropped_newest > 0 {
+            writeln!(f, "{} events dropped with the queue full ({})", self.dropped_newest, self.dropped_by_kind)?;
+        }
+        if let Some(dropped) = self.dropped_oldest {
+            writeln!(f, "{} oldest events dropped to make room", dropped)?;
+        }
+        for (index, dropped) in self.event_sinks_dropped.iter().enumerate().filter(|(_, dropped)| **dropped > 0) {
+            writeln!(f, "Event sink {} dropped {} events", index + 1, dropped)?;
+        }
+        if self.dropped_close_events > 0 {
+            writeln!(f, "{} close events dropped by lagging receivers", self.dropped_close_events)?;
+        }
+        if self.transient_tasks > 0 {
+            writeln!(f, "{} transient tasks not listed", self.transient_tasks)?;
+        }
+        if !self.degradation.is_empty() {
+            let steps: Vec<_> = self.degradation.iter().map(ToString::to_string).collect();
+            writeln!(
+                f,
+                "Reports degraded to {} after taking {:?}",
+                steps.join(", "),
+                self.report_cost.unwrap_or_default(),
+            )?;
+        }
+        if self.restarts > 0 {
+            writeln!(f, "Collector restarted {} times after panicking, counts cover the time since", self.restarts)?;
+        }
+        if self.panics > 0 {
+            writeln!(f, "{} panics caught, {} sinks quarantined", self.panics, self.quarantined_sinks)?;
+        }
+        if self.duplicate_closes > 0 {
+            writeln!(f, "{} duplicate task closes ignored", self.duplicate_closes)?;
+        }
+        if self.order_violations > 0 {
+            writeln!(f, "{} events arrived out of order", self.order_violations)?;
+        }
+        if self.events_lost > 0 {
+            writeln!(f, "{} events lost in {} gaps", self.events_lost, self.event_gaps)?;
+        }
+        if let Some(weighted) = self.weighted_busy_time {
+            writeln!(f, "Weighted busy time {:?}", weighted)?;
+        }
+        if let Some(bytes) = self.reported_mem_bytes {
+            writeln!(f, "Reported task memory {} bytes", bytes)?;
+        }
+        if let Some(latency) = self.wake_to_poll {
+            writeln!(
+                f,
+                "Wake-to-poll latency: p50 {:?}, p99 {:?}, max {:?} ({} samples)",
+                latency.p50, latency.p99, latency.max, latency.samples,
+            )?;
+        }
+        if let Some(latency) = self.first_poll_delay {
+            writeln!(
+                f,
+                "Spawn-to-first-poll delay: p50 {:?}, p99 {:?}, max {:?} ({} samples)",
+                latency.p50, latency.p99, latency.max, latency.samples,
+            )?;
+        }
+        if let Some(latency) = self.idle_gaps {
+            writeln!(
+                f,
+                "Idle time between polls: p50 {:?}, p99 {:?}, max {:?} ({} samples)",
+                latency.p50, latency.p99, latency.max, latency.samples,
+            )?;
+        }
+        if let Some(latency) = self.poll_durations {
+            writeln!(
+                f,
+                "Poll durations: p50 {:?}, p99 {:?}, max {:?} ({} samples)",
+                latency.p50, latency.p99, latency.max, latency.samples,
+            )?;
+        }
+        if let Some(phases) = self.phases {
+            writeln!(
+                f,
+                "Task phases p50/p99: scheduling {:?}/{:?}, busy {:?}/{:?}, idle {:?}/{:?}, cleanup {:?}/{:?}",
+                phases.scheduling.p50,
+                phases.scheduling.p99,
+                phases.busy.p50,
+                phases.busy.p99,
+                phases.idle.p50,
+                phases.idle.p99,
+                phases.cleanup.p50,
+                phases.cleanup.p99,
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Running;
+    use crate::BeeLayer;
+
+    #[tokio::test]
+    async fn tasks_are_aggregated_by_tenant() {
+        let running = Running::start(BeeLayer::builder().group_by_field("tenant").build());
+
+        let polls = |span: tracing::Span, polls| (0..polls).for_each(|_| span.in_scope(|| {}));
+        polls(tracing::info_span!("request", tenant = "acme"), 2);
+        polls(tracing::info_span!("request", tenant = "acme"), 3);
+        polls(tracing::info_span!("request", tenant = "globex"), 1);
+        polls(tracing::info_span!("request"), 4);
+        let active = tracing::info_span!("request", tenant = "globex");
+
+        let summary = running.handle.debug_report(Duration::from_secs(5)).await.summary;
+        let groups = summary.aggregate_by_field("tenant");
+        let counts = |tenant: &str| {
+            let group = groups[tenant];
+            (group.tasks, group.active, group.polls)
+        };
+        assert_eq!(groups.len(), 3);
+        assert_eq!(counts("acme"), (2, 0, 5));
+        assert_eq!(counts("globex"), (2, 1, 1));
+        assert_eq!(counts("unknown"), (1, 0, 4));
+        let busy: Duration = groups.values().map(|group| group.busy_time).sum();
+        assert_eq!(busy, summary.tasks.iter().map(|task| task.stats.busy_time()).sum());
+
+        drop(active);
+        running.finish().await;
+    }
+}
@@ -0,0 +1,43 @@
+use crate::{BeeLayer, Collector, CollectorHandle, TaskSnapshot};
+
+use tokio::task::JoinHandle;
+use tracing::{subscriber::DefaultGuard, Subscriber};
+use tracing_subscriber::{layer::SubscriberExt, Layer, Registry};
+
+/// A collector running on a task of its own, with its layer registered as
+/// the current thread's default subscriber until the collector is finished.
+pub(crate) struct Running {
+    pub handle: CollectorHandle,
+    collector: JoinHandle<Vec<TaskSnapshot>>,
+    _default: DefaultGuard,
+}
+
+impl Running {
+    /// Registers the layer with a registry and runs its collector.
+    pub fn start<F>((layer, collector): (BeeLayer<F>, Collector)) -> Self
+    where
+        BeeLayer<F>: Layer<Registry> + Send + Sync + 'static,
+    {
+        Self::under(tracing_subscriber::registry().with(layer), collector)
+    }
+
+    /// Runs the collector with `subscriber` as the default, e.g. for a
+    /// layer behind a filter or next to another.
+    pub fn under(subscriber: impl Subscriber + Send + Sync + 'static, collector: Collector) -> Self {
+        let handle = collector.handle();
+        let _default = tracing::subscriber::set_default(subscriber);
+        let collector = tokio::spawn(collector.run());
+
+        Self {
+            handle,
+            collector,
+            _default,
+        }
+    }
+
+    /// Shuts the collector down, returning every task it saw.
+    pub async fn finish(self) -> Vec<TaskSnapshot> {
+        self.handle.shutdown().await.unwrap();
+        self.collector.await.unwrap()
+    }
+}
@@ -0,0 +1,118 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    thread::{Thread, ThreadId},
+    time::{Duration, Instant},
+};
+
+/// How busy one worker thread was over the last flush interval.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ThreadSummary {
+    pub id: ThreadId,
+    pub name: Option<String>,
+    /// Time spent polling tasks during the interval.
+    pub busy_time: Duration,
+    /// `busy_time` as a fraction of the interval, between 0 and 1.
+    pub utilization: f64,
+}
+
+impl ThreadSummary {
+    /// The thread's name, or its id if it has none.
+    pub fn label(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => format!("{:?}", self.id),
+        }
+    }
+}
+
+impl fmt::Display for ThreadSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Thread {} {:.1}% busy ({:?})",
+            self.label(),
+            self.utilization * 100.0,
+            self.busy_time,
+        )
+    }
+}
+
+struct ThreadBusy {
+    name: Option<String>,
+    busy_time: Duration,
+    /// Flushes in a row the thread hasn't polled anything.
+    idle_flushes: u32,
+}
+
+/// Busy time per thread, accumulated across every task polled on it.
+pub(crate) struct ThreadTracker {
+    threads: HashMap<ThreadId, ThreadBusy>,
+    window_start: Instant,
+}
+
+impl Default for ThreadTracker {
+    fn default() -> Self {
+        Self {
+            threads: HashMap::new(),
+            window_start: Instant::now(),
+        }
+    }
+}
+
+impl ThreadTracker {
+    /// Threads that haven't polled a task for this many flushes are
+    /// forgotten, so blocking pool threads that exit don't linger.
+    const IDLE_FLUSHES: u32 = 10;
+
+    pub fn record(&mut self, thread: &Thread, busy: Duration) {
+        let entry = self.threads.entry(thread.id()).or_insert_with(|| ThreadBusy {
+            name: thread.name().map(str::to_string),
+            busy_time: Duration::ZERO,
+            idle_flushes: 0,
+        });
+
+        entry.busy_time += busy;
+    }
+
+    /// Reports the interval since the last flush.
+    pub fn summary(&self) -> Vec<ThreadSummary> {
+        let window = self.window_start.elapsed();
+
+        let mut summaries: Vec<_> = self
+            .threads
+            .iter()
+            .map(|(id, thread)| ThreadSummary {
+                id: *id,
+                name: thread.name.clone(),
+                busy_time: thread.busy_time,
+                // Polls are attributed to the interval they end in, so one
+                // that straddles a flush can overshoot.
+                utilization: if window.is_zero() {
+                    0.0
+                } else {
+                    (thread.busy_time.as_secs_f64() / window.as_secs_f64()).min(1.0)
+                },
+            })
+            .collect();
+        summaries.sort_by(|a, b| b.busy_time.cmp(&a.busy_time).then_with(|| a.label().cmp(&b.label())));
+
+        summaries
+    }
+
+    /// Starts a new interval.
+    pub fn reset(&mut self) {
+        self.window_start = Instant::now();
+
+        self.threads.retain(|_, thread| {
+            if thread.busy_time.is_zero() {
+                thread.idle_flushes += 1;
+            } else {
+                thread.idle_flushes = 0;
+            }
+            thread.busy_time = Duration::ZERO;
+
+            thread.idle_flushes < Self::IDLE_FLUSHES
+        });
+    }
+}
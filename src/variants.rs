@@ -0,0 +1,117 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    fmt,
+    hash::{Hash, Hasher},
+};
+
+/// How many distinct formatted field strings the tasks of one target were
+/// spawned with. A count growing without bound means spans there capture
+/// unbounded values, e.g. user input through `#[instrument]`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FieldVariants {
+    pub target: &'static str,
+    pub variants: usize,
+    /// Whether the count stopped at [`FieldVariants::LIMIT`], so that there
+    /// are at least that many.
+    pub saturated: bool,
+}
+
+impl FieldVariants {
+    /// The most distinct field strings counted per target.
+    pub const LIMIT: usize = 10_000;
+}
+
+impl fmt::Display for FieldVariants {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Target {} spawned tasks with {}{} distinct field sets",
+            self.target,
+            if self.saturated { "at least " } else { "" },
+            self.variants,
+        )
+    }
+}
+
+/// Hashes of the field strings seen per target. Only the hashes are kept,
+/// so that counting doesn't hold on to the strings themselves.
+#[derive(Default)]
+pub(crate) struct FieldVariantCounts {
+    targets: HashMap<&'static str, HashSet<u64>>,
+}
+
+impl FieldVariantCounts {
+    pub fn spawned(&mut self, target: &'static str, fields: &str) {
+        let seen = self.targets.entry(target).or_default();
+        if seen.len() >= FieldVariants::LIMIT {
+            return;
+        }
+
+        let mut hasher = DefaultHasher::new();
+        fields.hash(&mut hasher);
+        seen.insert(hasher.finish());
+    }
+
+    pub fn clear(&mut self) {
+        self.targets.clear();
+    }
+
+    /// Every target seen so far, most variants first.
+    pub fn summary(&self) -> Vec<FieldVariants> {
+        let mut targets: Vec<_> = self
+            .targets
+            .iter()
+            .map(|(target, seen)| FieldVariants {
+                target,
+                variants: seen.len(),
+                saturated: seen.len() >= FieldVariants::LIMIT,
+            })
+            .collect();
+        targets.sort_by(|a, b| b.variants.cmp(&a.variants).then(a.target.cmp(b.target)));
+
+        targets
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Running;
+    use crate::BeeLayer;
+
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn distinct_field_strings_are_counted_per_target() {
+        let running = Running::start(BeeLayer::pair());
+
+        for user in ["ann", "bob", "ann", "cy", "bob", "ann"] {
+            tracing::info_span!(target: "app::api", "request", user).in_scope(|| {});
+        }
+        for _ in 0..3 {
+            tracing::info_span!(target: "app::db", "query", table = "users").in_scope(|| {});
+        }
+
+        let variants = running.handle.debug_report(Duration::from_secs(5)).await.summary.field_variants;
+        running.finish().await;
+
+        assert_eq!(variants, [
+            FieldVariants { target: "app::api", variants: 3, saturated: false },
+            FieldVariants { target: "app::db", variants: 1, saturated: false },
+        ]);
+        assert_eq!(variants[0].to_string(), "Target app::api spawned tasks with 3 distinct field sets");
+    }
+
+    #[test]
+    fn counting_stops_at_the_limit() {
+        let mut counts = FieldVariantCounts::default();
+        for n in 0..FieldVariants::LIMIT + 10 {
+            counts.spawned("app", &format!("id={}", n));
+        }
+
+        let variants = &counts.summary()[0];
+        assert_eq!(variants.variants, FieldVariants::LIMIT);
+        assert!(variants.saturated);
+        assert!(variants.to_string().contains("at least 10000"));
+    }
+}
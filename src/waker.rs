@@ -0,0 +1,86 @@
+use tracing_core::field::{Field, Visit};
+
+use std::{convert::TryFrom, fmt};
+
+/// The target of the events tokio logs about wakers when built with
+/// `tokio_unstable`.
+pub(crate) const WAKER_TARGET: &str = "tokio::task::waker";
+
+/// What was done with a task's waker, as logged by tokio.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum WakerOp {
+    Wake,
+    WakeByRef,
+    Clone,
+    Drop,
+}
+
+impl WakerOp {
+    /// Parses the `op` field of a waker event, e.g. `waker.wake_by_ref`.
+    fn parse(op: &str) -> Option<Self> {
+        match op.strip_prefix("waker.").unwrap_or(op) {
+            "wake" => Some(Self::Wake),
+            "wake_by_ref" => Some(Self::WakeByRef),
+            "clone" => Some(Self::Clone),
+            "drop" => Some(Self::Drop),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Wake => "wake",
+            Self::WakeByRef => "wake_by_ref",
+            Self::Clone => "clone",
+            Self::Drop => "drop",
+        }
+    }
+}
+
+impl fmt::Display for WakerOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Picks the operation and the id of the task's span out of a waker event.
+#[derive(Default)]
+pub(crate) struct WakerVisitor {
+    pub op: Option<WakerOp>,
+    pub task_id: Option<u64>,
+}
+
+impl Visit for WakerVisitor {
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        if field.name() == "task.id" {
+            self.task_id = Some(value);
+        }
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        if field.name() == "task.id" {
+            self.task_id = u64::try_from(value).ok();
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "op" => self.op = WakerOp::parse(value),
+            "task.id" => self.task_id = value.parse().ok(),
+            _ => {}
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        match field.name() {
+            "op" => self.op = WakerOp::parse(format!("{:?}", value).trim_matches('"')),
+            // Ids recorded with `?` print as e.g. `Id(4)`.
+            "task.id" => {
+                let formatted = format!("{:?}", value);
+                let digits = formatted.trim_start_matches(|c: char| !c.is_ascii_digit()).trim_end_matches(|c: char| !c.is_ascii_digit());
+                self.task_id = digits.parse().ok();
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use crate::sender::Shared;
+
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct WatchdogConfig {
+    /// How many flush intervals the collector may miss before it's reported.
+    pub stall_factor: u32,
+    pub dump_summary: bool,
+}
+
+/// Watches the collector from a dedicated OS thread, so that a runtime too
+/// blocked to run the collector still gets reported.
+///
+/// The collector bumps a heartbeat on every tick; the only steady-state cost
+/// is one atomic store per tick and a thread waking once per interval.
+pub(crate) struct Watchdog {
+    stop: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Watchdog {
+    /// The collector's tick interval is read on every wake, so that it can
+    /// change while the watchdog runs.
+    pub fn spawn(config: WatchdogConfig, shared: Arc<Shared>) -> io::Result<Self> {
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let thread = {
+            let stop = stop.clone();
+
+            thread::Builder::new()
+                .name("bee-console-watchdog".into())
+                .spawn(move || {
+                    let mut reported = false;
+
+                    loop {
+                        let interval = shared.tick_interval();
+                        let threshold = interval * config.stall_factor.max(1);
+
+                        thread::park_timeout(interval);
+                        if stop.load(Ordering::Acquire) {
+                            return;
+                        }
+
+                        let silent = shared.since_heartbeat();
+                        if silent <= threshold {
+                            reported = false;
+                            continue;
+                        }
+                        if reported {
+                            continue;
+                        }
+
+                        reported = true;
+                        shared.stalls.fetch_add(1, Ordering::Relaxed);
+                        eprintln!(
+                            "bee-console collector has not run for {}s - runtime likely blocked",
+                            silent.as_secs(),
+                        );
+
+                        if config.dump_summary {
+                            if let Some(summary) = shared.last_summary() {
+                                eprint!("Last summary:\n{}", summary);
+                            }
+                        }
+                    }
+                })?
+        };
+
+        Ok(Self {
+            stop,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::BeeLayer;
+
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn blocked_current_thread_runtime_is_reported() {
+        let (_layer, collector) = BeeLayer::builder().flush_interval(Duration::from_millis(10)).watchdog(5).build();
+        let handle = collector.handle();
+        tokio::spawn(collector.run());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        // The collector can't run while this runtime's only thread is blocked.
+        std::thread::sleep(Duration::from_millis(300));
+
+        let report = handle.debug_report(Duration::from_secs(1)).await;
+        assert!(report.collector.stalls >= 1);
+        handle.shutdown().await.unwrap();
+    }
+}